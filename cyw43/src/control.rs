@@ -6,7 +6,7 @@ use embassy_net_driver_channel::driver::{HardwareAddress, LinkState};
 use embassy_time::{Duration, Timer};
 
 use crate::consts::*;
-use crate::events::{Event, EventSubscriber, Events};
+use crate::events::{Event, EventSubscriber, Events, LinkEvent, LinkEventSubscriber};
 use crate::fmt::Bytes;
 use crate::ioctl::{IoctlState, IoctlType};
 use crate::structs::*;
@@ -19,6 +19,22 @@ pub struct Error {
     pub status: u32,
 }
 
+impl Error {
+    /// Whether a join may have failed because the network uses another security type, such as a
+    /// WPA2 join to a WPA3-only network. The firmware reports those as no matching network.
+    pub fn is_security_mismatch(&self) -> bool {
+        self.status == EStatus::NO_NETWORKS
+    }
+}
+
+const AUTH_OPEN: u32 = 0;
+const AUTH_SAE: u32 = 3;
+const MFP_NONE: u32 = 0;
+const MFP_CAPABLE: u32 = 1;
+const MFP_REQUIRED: u32 = 2;
+const WPA_AUTH_WPA2_PSK: u32 = 0x0080;
+const WPA_AUTH_WPA3_SAE_PSK: u32 = 0x4_0000;
+
 /// Multicast errors.
 #[derive(Debug)]
 pub enum AddMulticastAddressError {
@@ -33,6 +49,7 @@ pub struct Control<'a> {
     state_ch: ch::StateRunner<'a>,
     events: &'a Events,
     ioctl_state: &'a IoctlState,
+    join_bssid: Option<[u8; 6]>,
 }
 
 #[derive(Copy, Clone)]
@@ -80,6 +97,7 @@ impl<'a> Control<'a> {
             state_ch,
             events: event_sub,
             ioctl_state,
+            join_bssid: None,
         }
     }
 
@@ -236,26 +254,51 @@ impl<'a> Control<'a> {
 
     /// Join a protected network with the provided ssid and [`PassphraseInfo`].
     async fn join_wpa2_passphrase_info(&mut self, ssid: &str, passphrase_info: &PassphraseInfo) -> Result<(), Error> {
+        self.join_protected(ssid, Some(passphrase_info), None).await
+    }
+
+    /// Join a WPA2 network with `passphrase_info`, a WPA3 network with `sae_info`, or a mixed
+    /// network with both.
+    async fn join_protected(
+        &mut self,
+        ssid: &str,
+        passphrase_info: Option<&PassphraseInfo>,
+        sae_info: Option<&SaePassphraseInfo>,
+    ) -> Result<(), Error> {
+        let (auth, mfp, wpa_auth) = match (passphrase_info.is_some(), sae_info.is_some()) {
+            (_, false) => (AUTH_OPEN, MFP_NONE, WPA_AUTH_WPA2_PSK),
+            (false, true) => (AUTH_SAE, MFP_REQUIRED, WPA_AUTH_WPA3_SAE_PSK),
+            (true, true) => (AUTH_SAE, MFP_CAPABLE, WPA_AUTH_WPA3_SAE_PSK),
+        };
+
         self.set_iovar_u32("ampdu_ba_wsize", 8).await;
 
-        self.ioctl_set_u32(134, 0, 4).await; // wsec = wpa2
+        self.ioctl_set_u32(134, 0, 4).await; // wsec = aes
         self.set_iovar_u32x2("bsscfg:sup_wpa", 0, 1).await;
         self.set_iovar_u32x2("bsscfg:sup_wpa2_eapver", 0, 0xFFFF_FFFF).await;
         self.set_iovar_u32x2("bsscfg:sup_wpa_tmo", 0, 2500).await;
 
         Timer::after_millis(100).await;
 
-        self.ioctl(
-            IoctlType::Set,
-            IOCTL_CMD_SET_PASSPHRASE,
-            0,
-            &mut passphrase_info.to_bytes(),
-        )
-        .await; // WLC_SET_WSEC_PMK
+        if let Some(passphrase_info) = passphrase_info {
+            self.ioctl(
+                IoctlType::Set,
+                IOCTL_CMD_SET_PASSPHRASE,
+                0,
+                &mut passphrase_info.to_bytes(),
+            )
+            .await; // WLC_SET_WSEC_PMK
+        }
+        if let Some(sae_info) = sae_info {
+            self.set_iovar_v::<256>("sae_password", &sae_info.to_bytes()).await;
+        }
 
         self.ioctl_set_u32(20, 0, 1).await; // set_infra = 1
-        self.ioctl_set_u32(22, 0, 0).await; // set_auth = 0 (open)
-        self.ioctl_set_u32(165, 0, 0x80).await; // set_wpa_auth
+        self.ioctl_set_u32(22, 0, auth).await; // set_auth
+        if sae_info.is_some() {
+            self.set_iovar_u32("mfp", mfp).await;
+        }
+        self.ioctl_set_u32(165, 0, wpa_auth).await; // set_wpa_auth
 
         let mut i = SsidInfo {
             len: ssid.len() as _,
@@ -277,6 +320,31 @@ impl<'a> Control<'a> {
         self.join_wpa2_passphrase_info(ssid, &pfi).await
     }
 
+    /// Restrict the following joins to the access point with `bssid`, for example the strongest one
+    /// found by [`Control::scan`] when several share an SSID. `None` lets the firmware pick.
+    pub fn set_join_bssid(&mut self, bssid: Option<[u8; 6]>) {
+        self.join_bssid = bssid;
+    }
+
+    /// Join a WPA3-SAE network with the provided ssid and passphrase.
+    pub async fn join_wpa3(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        let sae = sae_passphrase_info(passphrase);
+        self.join_protected(ssid, None, Some(&sae)).await
+    }
+
+    /// Join a network in WPA2/WPA3 transition mode with the provided ssid and passphrase,
+    /// preferring WPA3-SAE.
+    pub async fn join_wpa2_wpa3(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        let mut pfi = PassphraseInfo {
+            len: passphrase.len() as _,
+            flags: 1,
+            passphrase: [0; 64],
+        };
+        pfi.passphrase[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
+        let sae = sae_passphrase_info(passphrase);
+        self.join_protected(ssid, Some(&pfi), Some(&sae)).await
+    }
+
     /// Join a protected network with the provided ssid and precomputed PSK.
     pub async fn join_wpa2_psk(&mut self, ssid: &str, psk: &[u8; 32]) -> Result<(), Error> {
         let mut pfi = PassphraseInfo {
@@ -295,8 +363,23 @@ impl<'a> Control<'a> {
         // we make sure to enable events before so we don't miss any
 
         // set_ssid
-        self.ioctl(IoctlType::Set, IOCTL_CMD_SET_SSID, 0, &mut i.to_bytes())
-            .await;
+        match self.join_bssid {
+            Some(bssid) => {
+                let params = JoinParams {
+                    ssid: i,
+                    bssid,
+                    bssid_cnt: 0,
+                    chanspec_num: 0,
+                    chanspec_list: [0],
+                };
+                self.ioctl(IoctlType::Set, IOCTL_CMD_SET_SSID, 0, &mut params.to_bytes())
+                    .await
+            }
+            None => {
+                self.ioctl(IoctlType::Set, IOCTL_CMD_SET_SSID, 0, &mut i.to_bytes())
+                    .await
+            }
+        };
 
         // to complete the join, we wait for a SET_SSID event
         // we also save the AUTH status for the user, it may be interesting
@@ -613,6 +696,15 @@ impl<'a> Control<'a> {
             events: &self.events,
         }
     }
+    /// Subscribe to association changes, such as the access point going away.
+    ///
+    /// Returns `None` if two subscriptions are already alive. Events are kept in a bounded queue,
+    /// the oldest ones are dropped if the subscriber falls behind.
+    pub fn link_events(&self) -> Option<LinkEvents<'a>> {
+        let subscriber = self.events.link.subscriber().ok()?;
+        Some(LinkEvents { subscriber })
+    }
+
     /// Leave the wifi, with which we are currently associated.
     pub async fn leave(&mut self) {
         self.ioctl(IoctlType::Set, IOCTL_CMD_DISASSOC, 0, &mut []).await;
@@ -625,6 +717,51 @@ impl<'a> Control<'a> {
         assert_eq!(self.get_iovar("cur_etheraddr", &mut mac_addr).await, 6);
         mac_addr
     }
+
+    /// Signal strength of the access point we are associated with, in dBm.
+    ///
+    /// Only valid while joined, the device rejects the request otherwise.
+    pub async fn rssi(&mut self) -> i32 {
+        // scb_val_t, the station address is left zero to query the associated access point
+        let mut buf = [0; 10];
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_RSSI, 0, &mut buf).await;
+        i32::from_le_bytes(buf[0..4].try_into().unwrap())
+    }
+
+    /// Current transmit PHY rate, in units of 500 kbit/s.
+    ///
+    /// Only valid while joined, the device rejects the request otherwise.
+    pub async fn tx_rate(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.ioctl(IoctlType::Get, IOCTL_CMD_GET_RATE, 0, &mut buf).await;
+        u32::from_le_bytes(buf)
+    }
+}
+
+fn sae_passphrase_info(passphrase: &str) -> SaePassphraseInfo {
+    let mut sae = SaePassphraseInfo {
+        len: passphrase.len() as _,
+        passphrase: [0; 128],
+    };
+    sae.passphrase[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
+    sae
+}
+
+/// Stream of link events, see [`Control::link_events`].
+pub struct LinkEvents<'a> {
+    subscriber: LinkEventSubscriber<'a>,
+}
+
+impl LinkEvents<'_> {
+    /// Wait for the next link event.
+    pub async fn next(&mut self) -> LinkEvent {
+        self.subscriber.next_message_pure().await
+    }
+
+    /// Take the next link event if one is already queued.
+    pub fn try_next(&mut self) -> Option<LinkEvent> {
+        self.subscriber.try_next_message_pure()
+    }
 }
 
 /// WiFi network scanner.
@@ -290,9 +290,14 @@ pub enum Event {
 pub type EventQueue = PubSubChannel<NoopRawMutex, Message, 2, 1, 1>;
 pub type EventSubscriber<'a> = Subscriber<'a, NoopRawMutex, Message, 2, 1, 1>;
 
+/// Link events are published regardless of `mask`, so applications see them between joins and scans.
+pub type LinkEventQueue = PubSubChannel<NoopRawMutex, LinkEvent, 4, 2, 1>;
+pub type LinkEventSubscriber<'a> = Subscriber<'a, NoopRawMutex, LinkEvent, 4, 2, 1>;
+
 pub struct Events {
     pub queue: EventQueue,
     pub mask: SharedEventMask,
+    pub link: LinkEventQueue,
 }
 
 impl Events {
@@ -300,10 +305,45 @@ impl Events {
         Self {
             queue: EventQueue::new(),
             mask: SharedEventMask::default(),
+            link: LinkEventQueue::new(),
         }
     }
 }
 
+/// Change of the association with an access point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkEvent {
+    /// The link came up.
+    Up,
+    /// The link went down.
+    Down,
+    /// Disassociated from the access point, with the 802.11 reason code.
+    Disassociated(u32),
+    /// Deauthenticated by the access point, with the 802.11 reason code.
+    Deauthenticated(u32),
+}
+
+impl LinkEvent {
+    /// `LINK` event flag set while the link is up.
+    const FLAG_LINK_UP: u16 = 0x01;
+
+    pub(crate) fn from_event(event: Event, flags: u16, reason: u32) -> Option<Self> {
+        match event {
+            Event::LINK if flags & Self::FLAG_LINK_UP != 0 => Some(LinkEvent::Up),
+            Event::LINK => Some(LinkEvent::Down),
+            Event::DISASSOC | Event::DISASSOC_IND => Some(LinkEvent::Disassociated(reason)),
+            Event::DEAUTH | Event::DEAUTH_IND => Some(LinkEvent::Deauthenticated(reason)),
+            _ => None,
+        }
+    }
+
+    /// Whether the link is usable after this event.
+    pub fn is_up(&self) -> bool {
+        matches!(self, LinkEvent::Up)
+    }
+}
+
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Status {
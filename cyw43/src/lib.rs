@@ -27,8 +27,11 @@ use events::Events;
 use ioctl::IoctlState;
 
 use crate::bus::Bus;
+#[cfg(feature = "bluetooth")]
+pub use crate::bluetooth::BtDriver;
 pub use crate::bus::SpiBusCyw43;
-pub use crate::control::{AddMulticastAddressError, Control, Error as ControlError, ScanOptions, Scanner};
+pub use crate::control::{AddMulticastAddressError, Control, Error as ControlError, LinkEvents, ScanOptions, Scanner};
+pub use crate::events::LinkEvent;
 pub use crate::runner::Runner;
 pub use crate::structs::BssInfo;
 
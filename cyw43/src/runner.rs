@@ -1,12 +1,13 @@
 use embassy_futures::select::{select4, Either4};
 use embassy_net_driver_channel as ch;
+use embassy_net_driver_channel::driver::LinkState;
 use embassy_time::{block_for, Duration, Timer};
 use embedded_hal_1::digital::OutputPin;
 
 use crate::bus::Bus;
 pub use crate::bus::SpiBusCyw43;
 use crate::consts::*;
-use crate::events::{Event, Events, Status};
+use crate::events::{Event, Events, LinkEvent, Status};
 use crate::fmt::Bytes;
 use crate::ioctl::{IoctlState, IoctlType, PendingIoctl};
 use crate::nvram::NVRAM;
@@ -496,6 +497,16 @@ where
                     Bytes(evt_data)
                 );
 
+                let (flags, reason) = (event_packet.msg.flags, event_packet.msg.reason);
+                if let Some(link_event) = LinkEvent::from_event(evt_type, flags, reason) {
+                    // Joins set the link up themselves once they complete. Disassociation and
+                    // deauthentication also report stations leaving our own AP, so only `LINK` counts.
+                    if link_event == LinkEvent::Down {
+                        self.ch.set_link_state(LinkState::Down);
+                    }
+                    self.events.link.immediate_publisher().publish_immediate(link_event);
+                }
+
                 if self.events.mask.is_enabled(evt_type) {
                     let status = event_packet.msg.status;
                     let event_payload = match evt_type {
@@ -384,6 +384,19 @@ pub struct SsidInfo {
 }
 impl_bytes!(SsidInfo);
 
+/// `SsidInfo` followed by association parameters restricting the join to one BSSID.
+#[derive(Clone, Copy)]
+#[repr(C, packed(2))]
+pub struct JoinParams {
+    pub ssid: SsidInfo,
+    pub bssid: [u8; 6],
+    pub bssid_cnt: u16,
+    /// 0 to look for the BSSID on every channel.
+    pub chanspec_num: u32,
+    pub chanspec_list: [u16; 1],
+}
+impl_bytes!(JoinParams);
+
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
@@ -394,6 +407,15 @@ pub struct PassphraseInfo {
 }
 impl_bytes!(PassphraseInfo);
 
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct SaePassphraseInfo {
+    pub len: u16,
+    pub passphrase: [u8; 128],
+}
+impl_bytes!(SaePassphraseInfo);
+
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
@@ -9,7 +9,7 @@ use embassy_net_driver::Driver;
 use smoltcp::iface::{Interface, SocketHandle};
 use smoltcp::socket::raw;
 pub use smoltcp::socket::raw::PacketMetadata;
-use smoltcp::wire::{IpProtocol, IpVersion};
+pub use smoltcp::wire::{IpProtocol, IpVersion};
 
 use crate::{SocketStack, Stack};
 
@@ -0,0 +1,23 @@
+use crate::KeyframeColor;
+
+/// Sends frames out to a chain of LEDs, so the same rendering drives whichever chip is fitted.
+///
+/// Frames are colors of any [`KeyframeColor`], the driver picks what its chip can show out of
+/// `to_rgbw`, e.g. an RGB chip drops white.
+#[allow(async_fn_in_trait)]
+pub trait LedDriver {
+    type Error;
+
+    /// Send `len` LEDs, first LED first, asking `pixel` for each color as it goes out so no
+    /// frame needs to be held for it.
+    async fn write_with<C: KeyframeColor>(&mut self, len: usize, pixel: impl FnMut(usize) -> C) -> Result<(), Self::Error>;
+
+    /// Send a whole frame, first LED first.
+    async fn write<C: KeyframeColor>(&mut self, frame: &[C]) -> Result<(), Self::Error> {
+        self.write_with(frame.len(), |i| frame[i]).await
+    }
+
+    /// Get the output going again after writes kept failing. Drivers that can't recover in
+    /// place leave it to their owner to build them again.
+    fn reinit(&mut self) {}
+}
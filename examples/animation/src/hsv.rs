@@ -0,0 +1,51 @@
+/// Red, green and blue of a hue out of the full `u16` circle, red at 0, with saturation and
+/// value out of 255.
+pub fn hsv_to_rgb(hue: u16, sat: u8, val: u8) -> [u8; 3] {
+    let hue = (((hue as u32) * 1530 + 32768) >> 16) as u16;
+
+    let (r, g, b) = if hue < 510 {
+        let (r, g) = if hue < 255 { (255, hue) } else { (510 - hue, 255) };
+        (r, g, 0)
+    } else if hue < 1020 {
+        let (g, b) = if hue < 765 { (255, hue - 510) } else { (1020 - hue, 255) };
+        (0, g, b)
+    } else if hue < 1530 {
+        let (r, b) = if hue < 1275 { (hue - 1020, 255) } else { (255, 1530 - hue) };
+        (r, 0, b)
+    } else {
+        (255, 0, 0)
+    };
+
+    let v1 = 1 + (val as u16);
+    let s1 = 1 + (sat as u16);
+    let s2 = 255 - (sat as u16);
+
+    let r = ((((r * s1) >> 8) + s2) * v1) >> 8;
+    let g = ((((g * s1) >> 8) + s2) * v1) >> 8;
+    let b = ((((b * s1) >> 8) + s2) * v1) >> 8;
+
+    [r as u8, g as u8, b as u8]
+}
+
+/// Inverse of `hsv_to_rgb`. Greys have no hue and come back with 0.
+pub fn rgb_to_hsv([r, g, b]: [u8; 3]) -> (u16, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if delta == 0 {
+        return (0, 0, max as u8);
+    }
+
+    let sat = delta * 255 / max;
+    let hue = if max == r {
+        (g - b) * 255 / delta
+    } else if max == g {
+        510 + (b - r) * 255 / delta
+    } else {
+        1020 + (r - g) * 255 / delta
+    }
+    .rem_euclid(1530);
+
+    ((hue * 65536 / 1530) as u16, sat as u8, max as u8)
+}
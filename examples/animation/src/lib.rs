@@ -0,0 +1,379 @@
+//! Keyframe animation shared by the examples.
+//!
+//! A [`KeyframeReader`] plays back a looping sequence of [`Keyframe`]s, blending the color
+//! between each pair of keyframes. It works with any color type implementing
+//! [`KeyframeColor`], RGB pads and RGBW strips alike, and can ease between keyframes or blend
+//! around the hue circle instead of straight through RGB, see [`Interpolation`].
+//!
+//! Rendered frames go out through a [`LedDriver`], implemented by each example for its LEDs.
+//!
+//! `cargo test --target <host triple>` runs the tests on the host.
+#![cfg_attr(not(test), no_std)]
+
+pub mod driver;
+pub mod hsv;
+
+pub use driver::LedDriver;
+
+/// A color a [`KeyframeReader`] can blend.
+pub trait KeyframeColor: Copy {
+    const BLACK: Self;
+
+    /// Red, green, blue and white, white 0 for colors without a white channel.
+    fn to_rgbw(self) -> [u8; 4];
+
+    /// Inverse of `to_rgbw`, dropping white for colors without a white channel.
+    fn from_rgbw(rgbw: [u8; 4]) -> Self;
+}
+
+#[derive(Copy, Clone)]
+pub struct Keyframe<C> {
+    pub frame: u32,
+    pub color: C,
+}
+
+impl<C: KeyframeColor> Keyframe<C> {
+    pub const EMPTY: Self = Keyframe { frame: 0, color: C::BLACK };
+}
+
+/// How the blend between two keyframes progresses over the frames between them.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Slow out of one keyframe and into the next, a smoothstep.
+    Smooth,
+    /// Hold each keyframe's color until the next keyframe.
+    Step,
+}
+
+impl Easing {
+    /// Where `instant` frames into a segment of `duration` frames lands on the curve, also out
+    /// of `duration`.
+    fn apply(self, instant: u32, duration: u32) -> u32 {
+        match self {
+            Easing::Linear => instant,
+            Easing::Smooth => {
+                let (t, d) = (instant as u128, duration as u128);
+                (t * t * (3 * d - 2 * t) / (d * d)) as u32
+            }
+            Easing::Step => 0,
+        }
+    }
+}
+
+/// The colors a blend passes through.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Blend {
+    /// Straight through RGB, which dims and greys towards the middle of distant hues.
+    #[default]
+    Rgb,
+    /// The short way around the hue circle, keeping colors saturated. White blends linearly.
+    Hsv,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Interpolation {
+    pub easing: Easing,
+    pub blend: Blend,
+}
+
+fn mix_channel(a: u8, b: u8, instant: u32, duration: u32) -> u8 {
+    ((b as u64 * instant as u64 + a as u64 * (duration - instant) as u64) / duration as u64) as u8
+}
+
+fn mix_rgb(a: [u8; 4], b: [u8; 4], instant: u32, duration: u32) -> [u8; 4] {
+    let mut mixed = [0; 4];
+    for (channel, (a, b)) in mixed.iter_mut().zip(a.into_iter().zip(b)) {
+        *channel = mix_channel(a, b, instant, duration);
+    }
+    mixed
+}
+
+fn mix_hsv(a: [u8; 4], b: [u8; 4], instant: u32, duration: u32) -> [u8; 4] {
+    let (mut hue_a, sat_a, val_a) = hsv::rgb_to_hsv([a[0], a[1], a[2]]);
+    let (mut hue_b, sat_b, val_b) = hsv::rgb_to_hsv([b[0], b[1], b[2]]);
+    // A grey has no hue of its own, so take the other end's rather than passing through red
+    if sat_a == 0 {
+        hue_a = hue_b;
+    } else if sat_b == 0 {
+        hue_b = hue_a;
+    }
+    let turn = hue_b.wrapping_sub(hue_a) as i16 as i64;
+    let hue = hue_a.wrapping_add((turn * instant as i64 / duration as i64) as u16);
+    let [r, g, b_] = hsv::hsv_to_rgb(
+        hue,
+        mix_channel(sat_a, sat_b, instant, duration),
+        mix_channel(val_a, val_b, instant, duration),
+    );
+    [r, g, b_, mix_channel(a[3], b[3], instant, duration)]
+}
+
+/// Plays back one sequence of a keyframe pool, addressed by `start` and `len`. Keyframes must
+/// start at frame 0 and increase strictly, the sequence loops at the last one.
+#[derive(Copy, Clone)]
+pub struct KeyframeReader {
+    start: usize,
+    len: usize,
+    last_frame: u32,
+    frame_a: u32,
+    frame_b: u32,
+    ib: usize,
+    interpolation: Interpolation,
+}
+
+impl Default for KeyframeReader {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            len: 0,
+            last_frame: 0,
+            frame_a: 0,
+            frame_b: 0,
+            ib: 1,
+            interpolation: Interpolation::default(),
+        }
+    }
+}
+
+impl KeyframeReader {
+    pub fn set_keyframes<C>(&mut self, pool: &[Keyframe<C>], start: usize, len: usize) {
+        self.start = start;
+        self.len = len;
+        let keyframes = &pool[start..start + len];
+
+        self.last_frame = if let Some(kf) = keyframes.last() { kf.frame } else { 0 };
+
+        self.frame_a = if let Some(kf) = keyframes.first() { kf.frame } else { 0 };
+
+        self.frame_b = if let Some(kf) = keyframes.get(1) {
+            kf.frame
+        } else {
+            self.frame_a
+        };
+
+        self.ib = 1;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    pub fn evaluate_color_at_frame<C: KeyframeColor>(&mut self, pool: &[Keyframe<C>], frame: u64) -> C {
+        let keyframes = &pool[self.start..self.start + self.len];
+        if keyframes.is_empty() {
+            return C::BLACK;
+        } else if keyframes.len() == 1 {
+            return keyframes[0].color;
+        }
+
+        let mod_frame = (frame % self.last_frame as u64) as u32;
+        if mod_frame < self.frame_a {
+            self.ib = 1;
+            self.frame_a = keyframes[self.ib - 1].frame;
+            self.frame_b = keyframes[self.ib].frame;
+        }
+        if mod_frame >= self.frame_b {
+            self.ib += 1;
+            while keyframes[self.ib].frame < mod_frame {
+                self.ib += 1;
+            }
+            self.frame_a = keyframes[self.ib - 1].frame;
+            self.frame_b = keyframes[self.ib].frame;
+        }
+
+        let ka = &keyframes[self.ib - 1];
+        let kb = &keyframes[self.ib];
+        let seg_duration = kb.frame - ka.frame;
+        assert!(seg_duration > 0);
+        let seg_instant = self.interpolation.easing.apply(mod_frame - ka.frame, seg_duration);
+
+        let (a, b) = (ka.color.to_rgbw(), kb.color.to_rgbw());
+        C::from_rgbw(match self.interpolation.blend {
+            Blend::Rgb => mix_rgb(a, b, seg_instant, seg_duration),
+            Blend::Hsv => mix_hsv(a, b, seg_instant, seg_duration),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Rgbw(u8, u8, u8, u8);
+
+    impl KeyframeColor for Rgbw {
+        const BLACK: Self = Rgbw(0, 0, 0, 0);
+
+        fn to_rgbw(self) -> [u8; 4] {
+            [self.0, self.1, self.2, self.3]
+        }
+
+        fn from_rgbw([r, g, b, w]: [u8; 4]) -> Self {
+            Rgbw(r, g, b, w)
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Rgb(u8, u8, u8);
+
+    impl KeyframeColor for Rgb {
+        const BLACK: Self = Rgb(0, 0, 0);
+
+        fn to_rgbw(self) -> [u8; 4] {
+            [self.0, self.1, self.2, 0]
+        }
+
+        fn from_rgbw([r, g, b, _]: [u8; 4]) -> Self {
+            Rgb(r, g, b)
+        }
+    }
+
+    const RED: Rgbw = Rgbw(200, 0, 0, 0);
+    const BLUE: Rgbw = Rgbw(0, 0, 200, 100);
+    const WHITE: Rgbw = Rgbw(0, 0, 0, 255);
+
+    fn reader<C>(pool: &[Keyframe<C>], start: usize, len: usize) -> KeyframeReader {
+        let mut reader = KeyframeReader::default();
+        reader.set_keyframes(pool, start, len);
+        reader
+    }
+
+    fn kf<C>(frame: u32, color: C) -> Keyframe<C> {
+        Keyframe { frame, color }
+    }
+
+    #[test]
+    fn empty_sequence_is_black() {
+        let mut reader = KeyframeReader::default();
+        assert_eq!(reader.evaluate_color_at_frame::<Rgbw>(&[], 123), Rgbw::BLACK);
+        assert_eq!(reader.evaluate_color_at_frame::<Rgb>(&[], 42), Rgb::BLACK);
+    }
+
+    #[test]
+    fn single_keyframe_holds_its_color() {
+        let pool = [kf(0, BLUE)];
+        let mut reader = reader(&pool, 0, 1);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 0), BLUE);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, u64::MAX), BLUE);
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let pool = [kf(0, RED), kf(100, BLUE)];
+        let mut reader = reader(&pool, 0, 2);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 0), RED);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 25), Rgbw(150, 0, 50, 25));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 99), Rgbw(2, 0, 198, 99));
+    }
+
+    #[test]
+    fn wraps_at_the_last_keyframe() {
+        let pool = [kf(0, RED), kf(100, BLUE)];
+        let mut reader = reader(&pool, 0, 2);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 100), RED);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 1050), Rgbw(100, 0, 100, 50));
+    }
+
+    #[test]
+    fn keyframe_boundaries_show_the_keyframe_color() {
+        let pool = [kf(0, RED), kf(10, BLUE), kf(20, WHITE), kf(30, RED)];
+        let mut reader = reader(&pool, 0, 4);
+        for (frame, color) in [(10, BLUE), (20, WHITE), (10, BLUE), (0, RED)] {
+            assert_eq!(reader.evaluate_color_at_frame(&pool, frame), color);
+        }
+    }
+
+    #[test]
+    fn skips_and_rewinds_across_segments() {
+        let pool = [kf(0, RED), kf(10, BLUE), kf(20, WHITE), kf(30, RED)];
+        let mut reader = reader(&pool, 0, 4);
+        // Straight from the first segment into the last one, then back to the first
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 2), Rgbw(160, 0, 40, 20));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 25), Rgbw(100, 0, 0, 127));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 5), Rgbw(100, 0, 100, 50));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 35), Rgbw(100, 0, 100, 50));
+    }
+
+    #[test]
+    fn reads_its_own_part_of_the_pool() {
+        let pool = [kf(0, WHITE), kf(0, RED), kf(50, BLUE)];
+        let mut reader = reader(&pool, 1, 2);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 0), RED);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 25), Rgbw(100, 0, 100, 50));
+    }
+
+    #[test]
+    fn rgb_interpolates_and_wraps() {
+        let pool = [kf(0, Rgb(200, 0, 0)), kf(100, Rgb(0, 0, 200))];
+        let mut reader = reader(&pool, 0, 2);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 0), Rgb(200, 0, 0));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 25), Rgb(150, 0, 50));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 100), Rgb(200, 0, 0));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 1075), Rgb(50, 0, 150));
+    }
+
+    #[test]
+    fn rgb_skips_and_rewinds_across_segments() {
+        let (red, green, blue) = (Rgb(200, 0, 0), Rgb(0, 200, 0), Rgb(0, 0, 200));
+        let pool = [kf(0, red), kf(10, green), kf(20, blue), kf(30, red)];
+        let mut reader = reader(&pool, 0, 4);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 5), Rgb(100, 100, 0));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 20), blue);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 25), Rgb(100, 0, 100));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 10), green);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 31), Rgb(180, 20, 0));
+    }
+
+    #[test]
+    fn smooth_easing() {
+        let pool = [kf(0, Rgbw(0, 0, 0, 0)), kf(100, Rgbw(200, 200, 200, 200))];
+        let mut reader = reader(&pool, 0, 2);
+        reader.set_interpolation(Interpolation { easing: Easing::Smooth, blend: Blend::Rgb });
+        // Slow at both ends, through the midpoint on time
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 10), Rgbw(4, 4, 4, 4));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 50), Rgbw(100, 100, 100, 100));
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 90), Rgbw(194, 194, 194, 194));
+    }
+
+    #[test]
+    fn step_easing_holds_until_the_next_keyframe() {
+        let pool = [kf(0, RED), kf(10, BLUE), kf(20, WHITE)];
+        let mut reader = reader(&pool, 0, 3);
+        reader.set_interpolation(Interpolation { easing: Easing::Step, blend: Blend::Rgb });
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 9), RED);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 10), BLUE);
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 19), BLUE);
+    }
+
+    #[test]
+    fn hsv_blend_stays_saturated() {
+        let pool = [kf(0, Rgb(255, 0, 0)), kf(100, Rgb(0, 255, 0))];
+        let mut reader = reader(&pool, 0, 2);
+        reader.set_interpolation(Interpolation { easing: Easing::Linear, blend: Blend::Hsv });
+        // Through yellow rather than a dim olive
+        assert_eq!(reader.evaluate_color_at_frame(&pool, 50), Rgb(255, 255, 0));
+    }
+
+    #[test]
+    fn hsv_blend_takes_the_short_way_around() {
+        // Magenta to orange crosses red, not green
+        let pool = [kf(0, Rgb(255, 0, 255)), kf(100, Rgb(255, 128, 0))];
+        let mut reader = reader(&pool, 0, 2);
+        reader.set_interpolation(Interpolation { easing: Easing::Linear, blend: Blend::Hsv });
+        let Rgb(r, g, _) = reader.evaluate_color_at_frame(&pool, 50);
+        assert_eq!(r, 255);
+        assert!(g < 16);
+    }
+
+    #[test]
+    fn hsv_blend_from_grey_keeps_the_target_hue() {
+        let pool = [kf(0, Rgbw(0, 0, 0, 40)), kf(100, Rgbw(0, 0, 255, 0))];
+        let mut reader = reader(&pool, 0, 2);
+        reader.set_interpolation(Interpolation { easing: Easing::Linear, blend: Blend::Hsv });
+        let Rgbw(r, g, b, w) = reader.evaluate_color_at_frame(&pool, 50);
+        assert!(r == g && b > r);
+        assert_eq!(w, 20);
+    }
+}
@@ -0,0 +1,103 @@
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use static_cell::StaticCell;
+
+const USB_CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
+const SUBCLASS_DFU: u8 = 0x01;
+const PROTOCOL_DFU_RUNTIME: u8 = 0x01;
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+
+const DFU_DETACH: u8 = 0x00;
+const DFU_GETSTATUS: u8 = 0x03;
+const DFU_GETSTATE: u8 = 0x05;
+
+const STATE_APP_IDLE: u8 = 0;
+const STATE_APP_DETACH: u8 = 1;
+/// bitWillDetach, the device leaves the bus by itself instead of waiting for a USB reset.
+const ATTRIBUTE_WILL_DETACH: u8 = 0x08;
+const DETACH_TIMEOUT_MS: u16 = 1000;
+/// Let the host see the detach request complete before the device drops off the bus.
+const DETACH_DELAY: Duration = Duration::from_millis(50);
+
+static DETACH: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// DFU runtime interface, so `dfu-util -e` can send the device to the ROM bootloader without
+/// anyone holding the BOOTSEL button.
+struct DfuRuntime {
+    interface: InterfaceNumber,
+    state: u8,
+}
+
+impl DfuRuntime {
+    fn accepts(&self, req: &Request) -> bool {
+        (req.request_type, req.recipient) == (RequestType::Class, Recipient::Interface)
+            && req.index == self.interface.0 as u16
+    }
+}
+
+impl Handler for DfuRuntime {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) || req.request != DFU_DETACH {
+            return None;
+        }
+        self.state = STATE_APP_DETACH;
+        DETACH.signal(());
+        Some(OutResponse::Accepted)
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match req.request {
+            DFU_GETSTATUS => {
+                // OK, no poll timeout, current state, no status string
+                buf[..6].copy_from_slice(&[0, 0, 0, 0, self.state, 0]);
+                Some(InResponse::Accepted(&buf[..6]))
+            }
+            DFU_GETSTATE => {
+                buf[0] = self.state;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Add the DFU runtime interface to the USB device being built.
+pub fn add_dfu_runtime<D: Driver<'static>>(builder: &mut Builder<'static, D>) {
+    static RUNTIME: StaticCell<DfuRuntime> = StaticCell::new();
+    let mut function = builder.function(0, 0, 0);
+    let mut interface = function.interface();
+    let number = interface.interface_number();
+    let mut alt = interface.alt_setting(USB_CLASS_APPLICATION_SPECIFIC, SUBCLASS_DFU, PROTOCOL_DFU_RUNTIME, None);
+    let [timeout_lo, timeout_hi] = DETACH_TIMEOUT_MS.to_le_bytes();
+    // Attributes, detach timeout, 64 byte transfers, DFU 1.1
+    alt.descriptor(DESCRIPTOR_DFU_FUNCTIONAL, &[ATTRIBUTE_WILL_DETACH, timeout_lo, timeout_hi, 64, 0, 0x10, 0x01]);
+    drop(function);
+    builder.handler(RUNTIME.init(DfuRuntime { interface: number, state: STATE_APP_IDLE }));
+}
+
+/// Reboot into BOOTSEL once the host sends a DFU detach.
+pub async fn run() -> ! {
+    DETACH.wait().await;
+    Timer::after(DETACH_DELAY).await;
+    reboot()
+}
+
+/// Reboot into the ROM's USB bootloader, which takes UF2 images as a mass storage device and
+/// speaks picoboot. Recovers a device whatever state its firmware or network is in.
+pub fn reboot() -> ! {
+    info!("rebooting into BOOTSEL");
+    embassy_rp::rom_data::reset_to_usb_boot(0, 0);
+    // The ROM resets the chip, it doesn't return
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
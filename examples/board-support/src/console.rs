@@ -0,0 +1,214 @@
+//! Serial console over the USB port, a CDC ACM interface next to the DFU runtime one of
+//! [`bootsel`](crate::bootsel). The application reads command lines with
+//! [`Console::read_line`] and answers each with [`Console::reply`].
+use defmt::info;
+use embassy_rp::interrupt::typelevel::{Binding, USBCTRL_IRQ};
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::{Driver, InterruptHandler};
+use embassy_time::Duration;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::{Handler, UsbDevice};
+use heapless::{String, Vec};
+use static_cell::StaticCell;
+use ufmt::uwrite;
+
+use crate::bootsel;
+
+const MAX_PACKET_SIZE: u16 = 64;
+/// Let the reply reach the host before a command resets the device.
+pub const RESET_DELAY: Duration = Duration::from_millis(100);
+
+pub type UsbDriver = Driver<'static, USB>;
+
+/// The host closed the port or the cable was pulled.
+struct Disconnected;
+
+impl From<EndpointError> for Disconnected {
+    fn from(_: EndpointError) -> Self {
+        Disconnected
+    }
+}
+
+/// Arguments of a command line, split at spaces unless inside double quotes.
+pub struct Args<'a>(pub &'a str);
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start_matches(' ');
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            self.0 = quoted.get(end + 1..).unwrap_or("");
+            Some(&quoted[..end])
+        } else if rest.is_empty() {
+            None
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            self.0 = &rest[end..];
+            Some(&rest[..end])
+        }
+    }
+}
+
+/// Write `data` in full-size packets, ending in a short one so the host passes it on right away.
+async fn write_all(class: &mut CdcAcmClass<'static, UsbDriver>, data: &[u8]) -> Result<(), Disconnected> {
+    for packet in data.chunks(MAX_PACKET_SIZE as usize) {
+        class.write_packet(packet).await?;
+    }
+    if data.len() % MAX_PACKET_SIZE as usize == 0 {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+/// Command lines of up to `LINE_LEN` characters from whichever host has the port open.
+pub struct Console<const LINE_LEN: usize> {
+    class: CdcAcmClass<'static, UsbDriver>,
+    /// Greets every host that connects.
+    name: &'static str,
+    connected: bool,
+    line: Vec<u8, LINE_LEN>,
+    packet: [u8; MAX_PACKET_SIZE as usize],
+    /// Bytes of `packet` past the end of the last line.
+    unread: core::ops::Range<usize>,
+    after_cr: bool,
+}
+
+impl<const LINE_LEN: usize> Console<LINE_LEN> {
+    /// Build the USB device with the console and the DFU runtime interface, identifying as
+    /// `product` with the device name as serial number. `handler` hears about the device's state.
+    /// The device has to run alongside [`bootsel::run`]. Only once, the buffers are static.
+    pub fn new(
+        usb: USB,
+        irqs: impl Binding<USBCTRL_IRQ, InterruptHandler<USB>>,
+        product: &'static str,
+        name: &'static str,
+        handler: Option<&'static mut dyn Handler>,
+    ) -> (UsbDevice<'static, UsbDriver>, Self) {
+        let mut usb_config = embassy_usb::Config::new(0xc0de, 0xcafe);
+        usb_config.manufacturer = Some("Embassy");
+        usb_config.product = Some(product);
+        usb_config.serial_number = Some(name);
+        usb_config.max_power = 100;
+        usb_config.max_packet_size_0 = 64;
+        // Interface association descriptors, Windows needs them to bind its CDC driver
+        usb_config.device_class = 0xEF;
+        usb_config.device_sub_class = 0x02;
+        usb_config.device_protocol = 0x01;
+        usb_config.composite_with_iads = true;
+
+        static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+        static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+        static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+        static STATE: StaticCell<State> = StaticCell::new();
+        let mut builder = embassy_usb::Builder::new(
+            Driver::new(usb, irqs),
+            usb_config,
+            CONFIG_DESCRIPTOR.init([0; 256]),
+            BOS_DESCRIPTOR.init([0; 256]),
+            &mut [],
+            CONTROL_BUF.init([0; 64]),
+        );
+        let class = CdcAcmClass::new(&mut builder, STATE.init(State::new()), MAX_PACKET_SIZE);
+        bootsel::add_dfu_runtime(&mut builder);
+        if let Some(handler) = handler {
+            builder.handler(handler);
+        }
+
+        let console = Self {
+            class,
+            name,
+            connected: false,
+            line: Vec::new(),
+            packet: [0; MAX_PACKET_SIZE as usize],
+            unread: 0..0,
+            after_cr: false,
+        };
+        (builder.build(), console)
+    }
+
+    fn disconnected(&mut self) {
+        if self.connected {
+            info!("console disconnected");
+            self.connected = false;
+        }
+    }
+
+    /// Wait for the next command line, echoing it as a terminal expects.
+    pub async fn read_line(&mut self) -> &str {
+        while self.next_line().await.is_err() {
+            self.disconnected();
+        }
+        core::str::from_utf8(&self.line).unwrap_or("")
+    }
+
+    async fn next_line(&mut self) -> Result<(), Disconnected> {
+        self.line.clear();
+        if !self.connected {
+            self.class.wait_connection().await;
+            info!("console connected");
+            self.connected = true;
+            self.unread = 0..0;
+            self.after_cr = false;
+            let mut hello = String::<64>::new();
+            uwrite!(hello, "{} console, try help\r\n> ", self.name).ok();
+            write_all(&mut self.class, hello.as_bytes()).await?;
+        }
+
+        loop {
+            if self.unread.is_empty() {
+                let len = self.class.read_packet(&mut self.packet).await?;
+                self.unread = 0..len;
+            }
+            let mut echo = Vec::<u8, { 3 * MAX_PACKET_SIZE as usize }>::new();
+            let mut ended = false;
+            for i in self.unread.by_ref() {
+                let byte = self.packet[i];
+                // Terminals end lines with CR, CR LF or LF
+                let skip = self.after_cr && byte == b'\n';
+                self.after_cr = byte == b'\r';
+                match byte {
+                    _ if skip => {}
+                    b'\r' | b'\n' => {
+                        echo.extend_from_slice(b"\r\n").ok();
+                        ended = true;
+                        break;
+                    }
+                    // Backspace and delete
+                    0x08 | 0x7F => {
+                        if self.line.pop().is_some() {
+                            echo.extend_from_slice(b"\x08 \x08").ok();
+                        }
+                    }
+                    b' '..=b'~' => {
+                        if self.line.push(byte).is_ok() {
+                            echo.push(byte).ok();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if !echo.is_empty() {
+                write_all(&mut self.class, &echo).await?;
+            }
+            if ended {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send `data`, dropped while no host is connected.
+    pub async fn write(&mut self, data: &[u8]) {
+        if self.connected && write_all(&mut self.class, data).await.is_err() {
+            self.disconnected();
+        }
+    }
+
+    /// Send the reply to the last command line and prompt for the next.
+    pub async fn reply(&mut self, reply: &[u8]) {
+        self.write(reply).await;
+        self.write(b"> ").await;
+    }
+}
@@ -0,0 +1,126 @@
+//! Panics and hard faults recorded in RAM that survives the reset, for the application to report
+//! on the next boot. The memory layout needs a CRASH region that startup leaves alone, with a
+//! `.crash` section in it.
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use cortex_m_rt::{exception, ExceptionFrame};
+use defmt::{error, Display2Format};
+
+/// Panic message with its location, truncated.
+pub const MAX_MESSAGE_LEN: usize = 96;
+/// Words copied from the top of the stack, return addresses in them locate the crash.
+pub const STACK_WORDS: usize = 8;
+const MAGIC: u32 = 0x4853_5243; // "CRSH"
+const RAM_END: usize = 0x2004_2000;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Record {
+    magic: u32,
+    pub core: u32,
+    /// Faulting instruction for hard faults, 0 for panics.
+    pub pc: u32,
+    pub lr: u32,
+    message_len: u32,
+    message: [u8; MAX_MESSAGE_LEN],
+    pub stack: [u32; STACK_WORDS],
+    checksum: u32,
+}
+
+/// In the CRASH region of the memory layout, which startup leaves alone so a record outlives
+/// the reset that follows it.
+#[link_section = ".crash"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+impl Record {
+    fn checksum(&self) -> u32 {
+        let words = [self.magic, self.core, self.pc, self.lr, self.message_len];
+        let message = self.message.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        words.into_iter().chain(message).chain(self.stack).fold(0, |sum, word| sum.rotate_left(5) ^ word)
+    }
+
+    pub fn message(&self) -> &str {
+        let message = &self.message[..(self.message_len as usize).min(MAX_MESSAGE_LEN)];
+        // Truncation can split a character
+        match core::str::from_utf8(message) {
+            Ok(message) => message,
+            Err(err) => core::str::from_utf8(&message[..err.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Copies what fits of a formatted message.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Whether this boot already recorded a crash, a panic ends in a hard fault as well.
+fn recorded() -> bool {
+    let record = unsafe { RECORD.assume_init_ref() };
+    record.magic == MAGIC && record.checksum == record.checksum()
+}
+
+fn record(pc: u32, lr: u32, sp: usize, info: Option<&PanicInfo>) {
+    let mut record = Record {
+        magic: MAGIC,
+        core: embassy_rp::pac::SIO.cpuid().read(),
+        pc,
+        lr,
+        message_len: 0,
+        message: [0; MAX_MESSAGE_LEN],
+        stack: [0; STACK_WORDS],
+        checksum: 0,
+    };
+    if let Some(info) = info {
+        let mut writer = Truncating { buf: &mut record.message, len: 0 };
+        write!(writer, "{}", info).ok();
+        record.message_len = writer.len as u32;
+    }
+    for (i, word) in record.stack.iter_mut().enumerate() {
+        let address = sp + i * 4;
+        if address + 4 <= RAM_END {
+            *word = unsafe { core::ptr::read_volatile(address as *const u32) };
+        }
+    }
+    record.checksum = record.checksum();
+    unsafe { RECORD.write(record) };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    error!("{}", Display2Format(info));
+    record(0, cortex_m::register::lr::read(), cortex_m::register::msp::read() as usize, Some(info));
+    // Halts under a debugger, otherwise the hard fault handler resets
+    cortex_m::asm::udf()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    if !recorded() {
+        let sp = frame as *const ExceptionFrame as usize + core::mem::size_of::<ExceptionFrame>();
+        record(frame.pc(), frame.lr(), sp, None);
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Take the crash recorded before the last reset, at boot. Clears the record so a crash of this
+/// boot gets recorded even if the previous one was never reported.
+pub fn take() -> Option<Record> {
+    if !recorded() {
+        return None;
+    }
+    let record = unsafe { RECORD.assume_init_read() };
+    unsafe { RECORD.write(Record { magic: 0, ..record }) };
+    Some(record)
+}
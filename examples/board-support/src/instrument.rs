@@ -0,0 +1,167 @@
+//! Per task poll counts, longest polls and core loads over fixed windows, to tell whether the LED
+//! core or the net core is the bottleneck.
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use ufmt::{uWrite, uwrite};
+
+const MAX_TASKS: usize = 12;
+/// Length of a measurement window, stats are logged and start over after each.
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Copy, Clone)]
+struct TaskStats {
+    name: &'static str,
+    core: u8,
+    polls: u32,
+    /// Time spent in polls, in timer ticks.
+    busy: u64,
+    max_poll: u64,
+}
+
+#[derive(Clone)]
+struct Window {
+    started: Instant,
+    length: u64,
+    tasks: Vec<TaskStats, MAX_TASKS>,
+}
+
+impl Window {
+    const fn new() -> Self {
+        Self { started: Instant::from_ticks(0), length: 0, tasks: Vec::new() }
+    }
+
+    /// Share of the window `busy` ticks make up, in tenths of a percent.
+    fn permille(&self, busy: u64) -> u64 {
+        busy * 1000 / self.length.max(1)
+    }
+
+    fn core_busy(&self, core: u8) -> u64 {
+        self.tasks.iter().filter(|task| task.core == core).map(|task| task.busy).sum()
+    }
+}
+
+static CURRENT: Mutex<CriticalSectionRawMutex, RefCell<Window>> = Mutex::new(RefCell::new(Window::new()));
+/// The last complete window, what the console shows.
+static LAST: Mutex<CriticalSectionRawMutex, RefCell<Window>> = Mutex::new(RefCell::new(Window::new()));
+
+/// A task's future, counting its polls and timing each one.
+pub struct Measured<F> {
+    slot: Option<usize>,
+    future: F,
+}
+
+/// Measure `future` as task `name`, on the core this is called from. Futures measured under
+/// the same name add up, so a loop can measure each pass.
+pub fn measured<F: Future>(name: &'static str, future: F) -> Measured<F> {
+    let core = embassy_rp::pac::SIO.cpuid().read() as u8;
+    let slot = CURRENT.lock(|current| {
+        let mut current = current.borrow_mut();
+        if let Some(slot) = current.tasks.iter().position(|task| (task.name, task.core) == (name, core)) {
+            return Some(slot);
+        }
+        let stats = TaskStats { name, core, polls: 0, busy: 0, max_poll: 0 };
+        current.tasks.push(stats).ok().map(|_| current.tasks.len() - 1)
+    });
+    if slot.is_none() {
+        warn!("no room to measure task {}", name);
+    }
+    Measured { slot, future }
+}
+
+impl<F: Future> Future for Measured<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // Safety: `future` is never moved out of the pinned struct
+        let this = unsafe { self.get_unchecked_mut() };
+        let started = Instant::now();
+        let result = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        let ticks = started.elapsed().as_ticks();
+        if let Some(slot) = this.slot {
+            CURRENT.lock(|current| {
+                let stats = &mut current.borrow_mut().tasks[slot];
+                stats.polls += 1;
+                stats.busy += ticks;
+                stats.max_poll = stats.max_poll.max(ticks);
+            });
+        }
+        result
+    }
+}
+
+fn micros(ticks: u64) -> u64 {
+    Duration::from_ticks(ticks).as_micros()
+}
+
+/// The last window's core loads and per task stats, one line each.
+pub fn write_summary<W: uWrite + ?Sized>(out: &mut W) {
+    let last = LAST.lock(|last| last.borrow().clone());
+    if last.length == 0 {
+        uwrite!(out, "no measurements yet\r\n").ok();
+        return;
+    }
+    for core in 0..2 {
+        let busy = last.permille(last.core_busy(core));
+        uwrite!(out, "core{} {}.{}% busy\r\n", core, busy / 10, busy % 10).ok();
+    }
+    for task in &last.tasks {
+        let busy = last.permille(task.busy);
+        uwrite!(
+            out,
+            "{} core{} {} polls, max {} us, {}.{}%\r\n",
+            task.name,
+            task.core,
+            task.polls,
+            micros(task.max_poll),
+            busy / 10,
+            busy % 10
+        )
+        .ok();
+    }
+}
+
+/// Close a measurement window every `WINDOW` and log it, to tell whether the LED core or the
+/// net core is the bottleneck.
+#[embassy_executor::task]
+pub async fn instrument_task() -> ! {
+    CURRENT.lock(|current| current.borrow_mut().started = Instant::now());
+    loop {
+        Timer::after(WINDOW).await;
+        let now = Instant::now();
+        let window = CURRENT.lock(|current| {
+            let mut current = current.borrow_mut();
+            let mut window = current.clone();
+            window.length = (now - window.started).as_ticks();
+            current.started = now;
+            for task in current.tasks.iter_mut() {
+                *task = TaskStats { polls: 0, busy: 0, max_poll: 0, ..*task };
+            }
+            window
+        });
+
+        for core in 0..2 {
+            let busy = window.permille(window.core_busy(core));
+            info!("core{} {}.{}% busy", core, busy / 10, busy % 10);
+        }
+        for task in &window.tasks {
+            let busy = window.permille(task.busy);
+            info!(
+                "task {} on core{}: {} polls, max {} us, {}.{}% busy",
+                task.name,
+                task.core,
+                task.polls,
+                micros(task.max_poll),
+                busy / 10,
+                busy % 10
+            );
+        }
+        LAST.lock(|last| *last.borrow_mut() = window);
+    }
+}
@@ -0,0 +1,14 @@
+//! RP2040 plumbing shared by the examples, apart from the network's in `net-setup`.
+//!
+//! [`bootsel`] gets a device back into the ROM's USB bootloader for flashing, [`watchdog`]
+//! reboots it when part of the application stalls and [`crash`] keeps what the last panic or
+//! hard fault left behind. [`instrument`] measures the load tasks put on the cores,
+//! [`logbuf`] keeps the latest log lines and [`console`] takes commands over the USB port.
+#![no_std]
+
+pub mod bootsel;
+pub mod console;
+pub mod crash;
+pub mod instrument;
+pub mod logbuf;
+pub mod watchdog;
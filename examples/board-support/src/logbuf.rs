@@ -0,0 +1,56 @@
+//! The last few KB of log lines in a RAM ring, for reading back over the network.
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+use heapless::String;
+use ufmt::uwrite;
+
+/// Bytes of log text kept, older lines are overwritten.
+pub const LOG_BUFFER_LEN: usize = 4096;
+
+struct Ring {
+    buf: [u8; LOG_BUFFER_LEN],
+    /// Bytes appended since boot, `buf` holds the last `LOG_BUFFER_LEN` of them.
+    written: u32,
+}
+
+impl Ring {
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.buf[self.written as usize % LOG_BUFFER_LEN] = *byte;
+            self.written += 1;
+        }
+    }
+}
+
+static RING: Mutex<CriticalSectionRawMutex, RefCell<Ring>> =
+    Mutex::new(RefCell::new(Ring { buf: [0; LOG_BUFFER_LEN], written: 0 }));
+
+/// Keep `message` as a line starting with the uptime in milliseconds and `level`.
+pub fn append(level: char, message: &str) {
+    let mut prefix = String::<24>::new();
+    uwrite!(prefix, "{} {} ", Instant::now().as_millis(), level).ok();
+    RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        ring.push(prefix.as_bytes());
+        ring.push(message.as_bytes());
+        ring.push(b"\n");
+    });
+}
+
+/// Copy kept bytes into `out` from stream position `from` on, or from the oldest byte still
+/// kept if `from` was overwritten already. Returns the position of the first byte copied, the
+/// number copied and the position the next append starts at.
+pub fn read(from: u32, out: &mut [u8]) -> (u32, usize, u32) {
+    RING.lock(|ring| {
+        let ring = ring.borrow();
+        let start = from.clamp(ring.written.saturating_sub(LOG_BUFFER_LEN as u32), ring.written);
+        let len = out.len().min((ring.written - start) as usize);
+        for (i, byte) in out[..len].iter_mut().enumerate() {
+            *byte = ring.buf[(start as usize + i) % LOG_BUFFER_LEN];
+        }
+        (start, len, ring.written)
+    })
+}
@@ -0,0 +1,85 @@
+//! The RP2040's hardware watchdog, fed only while every part of the application keeps checking
+//! in, so a lockup anywhere reboots the device.
+use core::cell::Cell;
+
+use defmt::{info, warn, Format};
+use embassy_rp::peripherals::WATCHDOG;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+/// The RP2040 watchdog counts down from at most about 8.3 s.
+const WATCHDOG_PERIOD: Duration = Duration::from_secs(8);
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+/// Scratch register holding `STALL_MARK` and the stalled subsystem across the reset.
+pub const STALL_SCRATCH: usize = 0;
+const STALL_MARK: u32 = 0x5741_7400;
+/// Most subsystems an application can have.
+pub const MAX_SUBSYSTEMS: usize = 8;
+
+/// Parts of an application that have to check in within their window for the watchdog to be
+/// fed, usually a fieldless enum.
+pub trait Subsystem: Copy + Format + 'static {
+    /// Every subsystem, each at its [`index`](Subsystem::index).
+    const ALL: &'static [Self];
+
+    /// Below [`MAX_SUBSYSTEMS`].
+    fn index(self) -> usize;
+
+    /// Longest the subsystem may go without checking in.
+    fn window(self) -> Duration;
+
+    /// Whether the subsystem has to check in at the moment.
+    fn judged(self) -> bool {
+        true
+    }
+}
+
+static CHECK_INS: Mutex<CriticalSectionRawMutex, Cell<[Instant; MAX_SUBSYSTEMS]>> =
+    Mutex::new(Cell::new([Instant::MIN; MAX_SUBSYSTEMS]));
+
+/// Record progress of `subsystem`. Cheap enough to call on every loop pass.
+pub fn check_in<S: Subsystem>(subsystem: S) {
+    CHECK_INS.lock(|cell| {
+        let mut check_ins = cell.get();
+        check_ins[subsystem.index()] = Instant::now();
+        cell.set(check_ins);
+    });
+}
+
+/// First subsystem that hasn't checked in within its window, counting from `started` at the
+/// earliest.
+fn stalled<S: Subsystem>(started: Instant) -> Option<S> {
+    let now = Instant::now();
+    let check_ins = CHECK_INS.lock(|cell| cell.get());
+    S::ALL.iter().copied().find(|subsystem| {
+        subsystem.judged() && now > check_ins[subsystem.index()].max(started) + subsystem.window()
+    })
+}
+
+/// Feed the hardware watchdog while every subsystem keeps checking in. When the watchdog reset
+/// the device over a stalled subsystem, `rebooted` is called first with it, `None` if it isn't
+/// one of `S` anymore.
+pub async fn run<S: Subsystem>(watchdog: WATCHDOG, rebooted: impl FnOnce(Option<S>)) -> ! {
+    let mut watchdog = Watchdog::new(watchdog);
+    let scratch = watchdog.get_scratch(STALL_SCRATCH);
+    if scratch & 0xFFFF_FF00 == STALL_MARK {
+        rebooted(S::ALL.iter().copied().find(|subsystem| subsystem.index() as u32 == scratch & 0xFF));
+    }
+    watchdog.set_scratch(STALL_SCRATCH, 0);
+
+    info!("starting watchdog");
+    let started = Instant::now();
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_PERIOD);
+    loop {
+        if let Some(subsystem) = stalled::<S>(started) {
+            warn!("{} stalled, letting the watchdog reset", subsystem);
+            watchdog.set_scratch(STALL_SCRATCH, STALL_MARK | subsystem.index() as u32);
+            core::future::pending::<()>().await;
+        }
+        watchdog.feed();
+        Timer::after(FEED_INTERVAL).await;
+    }
+}
@@ -1,5 +1,5 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! This build script copies the memory layout from the crate root into
+//! `memory.x` in a directory where the linker can always find it at build time.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -15,11 +15,19 @@ use std::path::PathBuf;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // on the linker search path. The `ota` feature links the firmware
+    // behind the bootloader instead. Neither layout may be named
+    // `memory.x` in the crate root, the linker searches its working
+    // directory first.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let memory_x: &[u8] = if env::var_os("CARGO_FEATURE_OTA").is_some() {
+        include_bytes!("memory-ota.x")
+    } else {
+        include_bytes!("memory-default.x")
+    };
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
@@ -27,7 +35,8 @@ fn main() {
     // any file in the project changes. By specifying `memory.x`
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory-default.x");
+    println!("cargo:rerun-if-changed=memory-ota.x");
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
@@ -0,0 +1,32 @@
+MEMORY {
+    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 64K
+    /* Settings storage, see storage.rs */
+    STORAGE : ORIGIN = 0x101F0000, LENGTH = 64K
+    /* FLASH : ORIGIN = 0x10000100, LENGTH = 0x100000 - 0x100 */
+
+    /* Pick one of the two options for RAM layout     */
+
+    /* OPTION A: Use all RAM banks as one big block   */
+    /* Reasonable, unless you are doing something     */
+    /* really particular with DMA or other concurrent */
+    /* access that would benefit from striping        */
+    RAM   : ORIGIN = 0x20000000, LENGTH = 264K - 2K
+    /* Crash record, see board-support/src/crash.rs. Below the stacks and clear of the boot ROM,
+       which uses the top of RAM for the second stage bootloader */
+    CRASH : ORIGIN = 0x20041800, LENGTH = 512
+
+    /* OPTION B: Keep the unstriped sections separate */
+    /* RAM: ORIGIN = 0x20000000, LENGTH = 256K        */
+    /* SCRATCH_A: ORIGIN = 0x20040000, LENGTH = 4K    */
+    /* SCRATCH_B: ORIGIN = 0x20041000, LENGTH = 4K    */
+}
+
+/* Crash record kept across resets, see board-support/src/crash.rs. Nothing initializes it at
+   startup. */
+SECTIONS {
+    .crash (NOLOAD) : ALIGN(4)
+    {
+        KEEP(*(.crash .crash.*));
+    } > CRASH
+} INSERT AFTER .uninit;
@@ -0,0 +1,105 @@
+use defmt::debug;
+use embassy_net::driver::Driver;
+use embassy_net::udp::{UdpMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::Duration;
+use nom::{IResult, branch::alt, bytes::complete::{tag, take}, combinator::map, number::complete::{be_u16, le_u16, u8}, sequence::{preceded, tuple}};
+use crate::{config, consts, dmx};
+use crate::leds::{self, LedSender};
+
+pub const ARTNET_PORT: u16 = 6454;
+
+const ARTNET_ID: [u8; 8] = *b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+const POLL_REPLY_LEN: usize = 239;
+/// Show the last frame this long after ArtDmx packets stop before resuming effects.
+const DATA_LOSS_TIMEOUT: Duration = Duration::from_millis(2500);
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+enum Packet<'a> {
+    Poll,
+    Dmx { port_address: u16, slots: &'a [u8] },
+}
+
+fn parse_op(op: u16) -> impl Fn(&[u8]) -> IResult<&[u8], &[u8]> {
+    move |input| preceded(tag(ARTNET_ID), tag(op.to_le_bytes()))(input)
+}
+
+fn parse_poll(input: &[u8]) -> IResult<&[u8], Packet> {
+    map(parse_op(OP_POLL), |_| Packet::Poll)(input)
+}
+
+fn parse_dmx(input: &[u8]) -> IResult<&[u8], Packet> {
+    // Protocol version, sequence, physical port, 15-bit port address, data length
+    let (input, (_, _, _, port_address, length)) = preceded(parse_op(OP_DMX), tuple((be_u16, u8, u8, le_u16, be_u16)))(input)?;
+    map(take(length as usize), move |slots| Packet::Dmx { port_address: port_address & 0x7FFF, slots })(input)
+}
+
+fn parse_packet(input: &[u8]) -> IResult<&[u8], Packet> {
+    alt((parse_poll, parse_dmx))(input)
+}
+
+fn put_str(dst: &mut [u8], s: &str) {
+    // Leave room for the terminating NUL
+    let len = s.len().min(dst.len() - 1);
+    dst[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+/// ArtPollReply describing one DMX output port on `consts::ARTNET_UNIVERSE`.
+fn poll_reply(ip: [u8; 4], mac: &[u8; 6]) -> [u8; POLL_REPLY_LEN] {
+    let universe = consts::ARTNET_UNIVERSE;
+    let mut reply = [0; POLL_REPLY_LEN];
+    reply[0..8].copy_from_slice(&ARTNET_ID);
+    reply[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+    reply[10..14].copy_from_slice(&ip);
+    reply[14..16].copy_from_slice(&ARTNET_PORT.to_le_bytes());
+    reply[16..18].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    // Net and sub-net switches
+    reply[18] = (universe >> 8) as u8 & 0x7F;
+    reply[19] = (universe >> 4) as u8 & 0x0F;
+    // OEM code: unknown
+    reply[20..22].copy_from_slice(&0x00FF_u16.to_be_bytes());
+    put_str(&mut reply[26..44], &config::get().hostname());
+    put_str(&mut reply[44..108], "brighty SK6812 sconce");
+    put_str(&mut reply[108..172], "#0001 [0000] Power On Tests successful");
+    reply[172..174].copy_from_slice(&1_u16.to_be_bytes());
+    // Port 0 outputs DMX512, data is being received
+    reply[174] = 0x80;
+    reply[182] = 0x80;
+    reply[190] = universe as u8 & 0x0F;
+    reply[201..207].copy_from_slice(mac);
+    reply[207..211].copy_from_slice(&ip);
+    reply[211] = 1;
+    reply
+}
+
+/// Answer ArtPoll and show ArtDmx data addressed to `consts::ARTNET_UNIVERSE`, on a socket
+/// bound to `ARTNET_PORT`.
+pub async fn run<'a, D: Driver>(socket: &mut UdpSocket<'a>, stack: &Stack<D>, mac: &[u8; 6]) -> ! {
+    loop {
+        let poll_from = socket.recv_from_with(|buffer, meta: UdpMetadata| match parse_packet(buffer) {
+            Ok((_, Packet::Poll)) => Some(meta.endpoint),
+            Ok((_, Packet::Dmx { port_address, slots })) => {
+                if port_address == consts::ARTNET_UNIVERSE {
+                    get_led_sender().set_realtime(dmx::frame_from_dmx(slots, DATA_LOSS_TIMEOUT));
+                }
+                None
+            }
+            Err(_) => None,
+        }).await;
+
+        if let Some(endpoint) = poll_from {
+            debug!("Answering ArtPoll from {}", endpoint);
+            let ip = stack.config_v4().map_or([0; 4], |config| config.address.address().0);
+            // Controllers listen for replies on the Art-Net port regardless of their source port
+            let dest = IpEndpoint::new(endpoint.addr, ARTNET_PORT);
+            socket.send_to(&poll_reply(ip, mac), dest).await.ok();
+        }
+    }
+}
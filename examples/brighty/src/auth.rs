@@ -0,0 +1,108 @@
+use core::hash::Hasher;
+use siphasher::sip::SipHasher24;
+
+// Only the command channel carries a tag, the other protocols would leave the LEDs open
+#[cfg(any(
+    feature = "wled",
+    feature = "sacn",
+    feature = "artnet",
+    feature = "ddp",
+    feature = "sync",
+    feature = "control-page",
+    feature = "mqtt",
+))]
+compile_error!("wled, sacn, artnet, ddp, sync, control-page and mqtt carry no tag, enable none of them with auth or ota");
+
+/// Pre-shared SipHash key, generate with `head -c 16 /dev/urandom > auth_key.bin`.
+pub(crate) const AUTH_KEY: &[u8; 16] = include_bytes!("../auth_key.bin");
+
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 8;
+/// Nonces this far below the highest one seen are still accepted once, to tolerate reordering.
+const REPLAY_WINDOW: u64 = 64;
+/// How far past the highest accepted nonce the stored floor is put, so it is written about once
+/// a minute of traffic rather than for every datagram.
+const FLOOR_MARGIN: u64 = 60_000;
+/// Nonces are Unix time in milliseconds, anything before November 2023 is a counter or a
+/// controller that never set its clock.
+const MIN_NONCE: u64 = 1_700_000_000_000;
+
+/// Sliding window of accepted nonces. Controllers must send the Unix time in milliseconds as
+/// the nonce, bumped by one when it would repeat so every datagram, retransmissions included,
+/// gets a fresh and increasing one. Counters are refused, see `MIN_NONCE`.
+///
+/// A floor kept in flash ahead of the highest accepted nonce carries the window across resets,
+/// so datagrams captured before one can't be replayed after it. The clock passes the floor at
+/// most `FLOOR_MARGIN` milliseconds after the last datagram, which is all a reset costs.
+pub struct ReplayGuard {
+    highest: u64,
+    /// Bit `n` is set if nonce `highest - n` was accepted.
+    seen: u64,
+    /// Stored floor, every nonce accepted so far is at or below it.
+    floor: u64,
+}
+
+impl ReplayGuard {
+    pub const fn new() -> Self {
+        // Nonce 0 is never valid
+        Self { highest: 0, seen: 1, floor: 0 }
+    }
+
+    /// Pick up after a reset from the stored `floor`, refusing every nonce up to it.
+    pub fn restore(&mut self, floor: u64) {
+        self.highest = floor;
+        self.seen = u64::MAX;
+        self.floor = floor;
+    }
+
+    /// Floor to store once an accepted nonce has passed the stored one.
+    pub fn next_floor(&self) -> Option<u64> {
+        (self.highest > self.floor).then(|| self.highest.saturating_add(FLOOR_MARGIN))
+    }
+
+    /// `floor` from `next_floor` is on its way to flash.
+    pub fn floor_stored(&mut self, floor: u64) {
+        self.floor = floor;
+    }
+
+    fn accept(&mut self, nonce: u64) -> bool {
+        if nonce > self.highest {
+            let shift = nonce - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = nonce;
+            return true;
+        }
+        let age = self.highest - nonce;
+        if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+/// Compare without an early exit so timing doesn't leak how much of the tag matched.
+pub(crate) fn tags_equal(expected: &[u8], tag: &[u8]) -> bool {
+    expected.len() == tag.len() && expected.iter().zip(tag).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Check the trailer of an authenticated datagram and return the payload before it.
+///
+/// The trailer is a little endian u64 nonce, the Unix time in milliseconds, followed by the
+/// SipHash-2-4 tag of the payload and nonce.
+pub fn verify<'a>(datagram: &'a [u8], guard: &mut ReplayGuard) -> Option<&'a [u8]> {
+    let signed_len = datagram.len().checked_sub(TAG_LEN)?;
+    let payload_len = signed_len.checked_sub(NONCE_LEN)?;
+    let (signed, tag) = datagram.split_at(signed_len);
+
+    let mut hasher = SipHasher24::new_with_key(AUTH_KEY);
+    hasher.write(signed);
+    let expected = hasher.finish().to_le_bytes();
+    if !tags_equal(&expected, tag) {
+        return None;
+    }
+
+    let nonce = u64::from_le_bytes(signed[payload_len..].try_into().unwrap());
+    (nonce >= MIN_NONCE && guard.accept(nonce)).then_some(&datagram[..payload_len])
+}
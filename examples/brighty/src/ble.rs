@@ -0,0 +1,141 @@
+use bt_hci::controller::ExternalController;
+use defmt::{info, warn};
+use embassy_futures::join::join3;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use static_cell::StaticCell;
+use trouble_host::advertise::{AdStructure, Advertisement, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE};
+use trouble_host::attribute::{AttributeTable, CharacteristicProp, Service, Uuid};
+use trouble_host::gatt::GattEvent;
+use trouble_host::{Address, BleHost, BleHostResources, PacketQos};
+use ufmt::uwrite;
+use crate::config;
+use crate::provision::{self, WifiCredentials};
+
+/// Provisioning service, 5b1e0000-8d3c-4f2e-9a57-6b7269676874 with the characteristics
+/// numbered in the second byte pair. UUIDs are stored little endian.
+const fn provision_uuid(id: u8) -> Uuid {
+    Uuid::Uuid128([
+        0x74, 0x68, 0x67, 0x69, 0x72, 0x62, 0x57, 0x9a, 0x2e, 0x4f, 0x3c, 0x8d, id, 0x00, 0x1e, 0x5b,
+    ])
+}
+const SERVICE_UUID: Uuid = provision_uuid(0);
+/// Length byte, then the SSID.
+const SSID_UUID: Uuid = provision_uuid(1);
+/// Length byte, then the passphrase, zero length for an open network.
+const PASSPHRASE_UUID: Uuid = provision_uuid(2);
+/// Any write saves the SSID and passphrase written so far and reboots into the network.
+const APPLY_UUID: Uuid = provision_uuid(3);
+
+/// Characteristic values carry a length byte since writes don't shrink the stored value. The
+/// passphrase needs an ATT MTU of 67, which phones negotiate when connecting.
+const L2CAP_MTU: usize = 72;
+const MAX_CONNECTIONS: usize = 1;
+const MAX_CHANNELS: usize = 2;
+const MAX_NAME_LEN: usize = 26;
+/// The host doesn't report disconnects, so an abandoned connection is dropped after this long
+/// and advertising starts over.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+
+fn copy_value(out: &mut [u8], value: &[u8]) {
+    let len = out.len().min(value.len());
+    out[..len].copy_from_slice(&value[..len]);
+}
+
+/// Credentials from the written characteristic values, `None` until both are valid.
+fn credentials(ssid: &[u8], passphrase: &[u8]) -> Option<WifiCredentials> {
+    let ssid = ssid.get(1..1 + *ssid.first()? as usize)?;
+    let passphrase = passphrase.get(1..1 + *passphrase.first()? as usize)?;
+    WifiCredentials::new(ssid, passphrase)
+}
+
+/// Advertise `<hostname>-setup` with a GATT service taking Wi-Fi credentials, so a phone can
+/// set up the device without joining its setup access point. Brighty has no Home Assistant
+/// connection of its own, so unlike squishy there's no token to provision.
+pub async fn run(bt_device: cyw43::BtDriver<'static>, mac: &[u8; 6]) -> ! {
+    let controller: ExternalController<_, 10> = ExternalController::new(bt_device);
+    static HOST_RESOURCES: StaticCell<BleHostResources<MAX_CONNECTIONS, MAX_CHANNELS, L2CAP_MTU>> = StaticCell::new();
+    let host_resources = HOST_RESOURCES.init(BleHostResources::new(PacketQos::None));
+    let mut ble: BleHost<'_, _> = BleHost::new(controller, host_resources);
+    // Random static address from the Wi-Fi MAC, the top two bits set
+    ble.set_random_address(Address::random([mac[5], mac[4], mac[3], mac[2], mac[1], mac[0] | 0xC0]));
+
+    let mut name = String::<MAX_NAME_LEN>::new();
+    uwrite!(name, "{}-setup", config::get().hostname().as_str()).ok();
+
+    let mut table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+    let appearance = [0x00, 0x00];
+    let (mut ssid, mut passphrase, mut apply) = ([0; 33], [0; 64], [0; 1]);
+    let (ssid_handle, passphrase_handle, apply_handle) = {
+        // Generic access and generic attribute services (mandatory)
+        let mut svc = table.add_service(Service::new(0x1800));
+        let _ = svc.add_characteristic_ro(0x2a00, name.as_bytes());
+        let _ = svc.add_characteristic_ro(0x2a01, &appearance[..]);
+        svc.build();
+        table.add_service(Service::new(0x1801));
+
+        let mut svc = table.add_service(Service::new(SERVICE_UUID));
+        let ssid_handle = svc.add_characteristic(SSID_UUID, &[CharacteristicProp::Write], &mut ssid).build();
+        let passphrase_handle =
+            svc.add_characteristic(PASSPHRASE_UUID, &[CharacteristicProp::Write], &mut passphrase).build();
+        let apply_handle = svc.add_characteristic(APPLY_UUID, &[CharacteristicProp::Write], &mut apply).build();
+        (ssid_handle, passphrase_handle, apply_handle)
+    };
+
+    let mut adv_data = [0; 31];
+    // Always fits, the name is at most MAX_NAME_LEN
+    AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::CompleteLocalName(name.as_bytes()),
+        ],
+        &mut adv_data[..],
+    )
+    .ok();
+
+    let server = ble.gatt_server(&table);
+    info!("advertising BLE provisioning service as {}", name.as_str());
+    join3(
+        ble.run(),
+        async {
+            loop {
+                match server.next().await {
+                    Ok(GattEvent::Write { handle, connection: _ }) if handle == apply_handle => {
+                        let (mut ssid, mut passphrase) = ([0; 33], [0; 64]);
+                        let _ = table.get(ssid_handle, |value| copy_value(&mut ssid, value));
+                        let _ = table.get(passphrase_handle, |value| copy_value(&mut passphrase, value));
+                        match credentials(&ssid, &passphrase) {
+                            Some(credentials) => provision::provision(credentials),
+                            None => warn!("invalid credentials written over BLE"),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("BLE GATT error: {:?}", err),
+                }
+            }
+        },
+        async {
+            loop {
+                let advertisement = Advertisement::ConnectableScannableUndirected { adv_data: &adv_data[..], scan_data: &[] };
+                let connection = match ble.advertise(&Default::default(), advertisement).await {
+                    Ok(mut advertiser) => advertiser.accept().await,
+                    Err(err) => Err(err),
+                };
+                match connection {
+                    Ok(connection) => {
+                        info!("BLE provisioning client connected");
+                        Timer::after(SESSION_TIMEOUT).await;
+                        drop(connection);
+                    }
+                    Err(err) => {
+                        warn!("BLE advertising failed: {:?}", err);
+                        Timer::after_secs(1).await;
+                    }
+                }
+            }
+        },
+    )
+    .await;
+    unreachable!()
+}
@@ -0,0 +1,160 @@
+//! Pin maps of the boards brighty is built for, one picked by a `board-*` feature:
+//!
+//! - `board-pico-w`, a Pico W on a breadboard or carrier, the default.
+//! - `board-sconce`, the sconce PCB, with `--no-default-features --features board-sconce`.
+//!
+//! The peripheral sets whose pins differ between boards are defined here rather than in the
+//! modules using them, which define their structs from these as usual.
+use net_setup::status_led::StatusLed;
+
+use crate::{define_peripheral_set, status_led_peripherals};
+
+#[cfg(all(feature = "board-pico-w", feature = "board-sconce"))]
+compile_error!("enable only one of the board-pico-w and board-sconce features");
+#[cfg(not(any(feature = "board-pico-w", feature = "board-sconce")))]
+compile_error!("enable one of the board-pico-w and board-sconce features");
+
+#[cfg(feature = "board-pico-w")]
+mod pico_w {
+    #[macro_export]
+    macro_rules! sk6812_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                SK6812Peripherals,
+                dio0: PIN_0,
+                dio1: Option<PIN_1> if feature = "strips-2",
+                dio2: Option<PIN_2> if feature = "strips-3",
+                dio3: Option<PIN_3> if feature = "strips-4",
+                pio: PIO1,
+                dma0: DMA_CH1,
+                dma1: Option<DMA_CH2> if feature = "strips-2",
+                dma2: Option<DMA_CH3> if feature = "strips-3",
+                dma3: Option<DMA_CH4> if feature = "strips-4",
+            }
+        };
+    }
+
+    /// Electret/MAX4466 microphone on ADC0.
+    #[macro_export]
+    macro_rules! mic_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                MicPeripherals,
+                mic: PIN_26,
+                dma: DMA_CH5,
+            }
+        };
+    }
+
+    /// Setup button to ground.
+    #[macro_export]
+    macro_rules! provision_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                ProvisionPeripherals,
+                button: PIN_15 => Input(Up),
+            }
+        };
+    }
+
+    /// Infrared receiver module, active low.
+    #[macro_export]
+    macro_rules! ir_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                IrPeripherals,
+                ir: PIN_16 => Input(Up),
+            }
+        };
+    }
+
+    /// None, the LED is on the cyw43's GPIO 0.
+    #[macro_export]
+    macro_rules! status_led_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                StatusLedPeripherals,
+            }
+        };
+    }
+}
+
+#[cfg(feature = "board-sconce")]
+mod sconce {
+    /// The four strip connectors along the bottom edge, all but the first taken with `strips-*`.
+    #[macro_export]
+    macro_rules! sk6812_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                SK6812Peripherals,
+                dio0: PIN_6,
+                dio1: Option<PIN_7> if feature = "strips-2",
+                dio2: Option<PIN_8> if feature = "strips-3",
+                dio3: Option<PIN_9> if feature = "strips-4",
+                pio: PIO1,
+                dma0: DMA_CH1,
+                dma1: Option<DMA_CH2> if feature = "strips-2",
+                dma2: Option<DMA_CH3> if feature = "strips-3",
+                dma3: Option<DMA_CH4> if feature = "strips-4",
+            }
+        };
+    }
+
+    /// MEMS microphone footprint on ADC1, fitted on some runs.
+    #[macro_export]
+    macro_rules! mic_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                MicPeripherals,
+                mic: PIN_27,
+                dma: DMA_CH5,
+            }
+        };
+    }
+
+    /// Recessed setup button to ground.
+    #[macro_export]
+    macro_rules! provision_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                ProvisionPeripherals,
+                button: PIN_14 => Input(Up),
+            }
+        };
+    }
+
+    /// Footprint for an infrared receiver module behind the diffuser, active low.
+    #[macro_export]
+    macro_rules! ir_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                IrPeripherals,
+                ir: PIN_13 => Input(Up),
+            }
+        };
+    }
+
+    /// Status LED next to the setup button, active high.
+    #[macro_export]
+    macro_rules! status_led_peripherals {
+        ($macro_name:ident $(,$arg:tt)*) => {
+            $macro_name!{$($arg,)*
+                StatusLedPeripherals,
+                led: PIN_22 => Output(Low),
+            }
+        };
+    }
+}
+
+status_led_peripherals!(define_peripheral_set);
+
+/// Where the board's status LED is wired.
+pub fn status_led(p: StatusLedPeripherals) -> StatusLed {
+    #[cfg(feature = "board-pico-w")]
+    {
+        let _ = p;
+        StatusLed::Cyw43
+    }
+    #[cfg(feature = "board-sconce")]
+    StatusLed::Pin(p.led)
+}
@@ -0,0 +1,107 @@
+/// UTC calendar time, in the fields the RP2040 RTC counts in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CivilTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    /// 0 is Sunday.
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, from Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (u16, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as u16, month as u8, day as u8)
+}
+
+impl CivilTime {
+    pub fn from_unix_seconds(seconds: u64) -> Self {
+        let days = (seconds / SECONDS_PER_DAY) as i64;
+        let second_of_day = seconds % SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 4) % 7) as u8,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60) as u8,
+        }
+    }
+
+    /// `None` before 1970.
+    pub fn unix_seconds(&self) -> Option<u64> {
+        let days = u64::try_from(days_from_civil(self.year, self.month, self.day)).ok()?;
+        Some(days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch() {
+        let epoch = CivilTime::from_unix_seconds(0);
+        assert_eq!(epoch, CivilTime { year: 1970, month: 1, day: 1, weekday: 4, hour: 0, minute: 0, second: 0 });
+        assert_eq!(epoch.unix_seconds(), Some(0));
+    }
+
+    #[test]
+    fn leap_day() {
+        // 2024-02-29 12:34:56, a Thursday
+        let time = CivilTime::from_unix_seconds(1_709_210_096);
+        assert_eq!(time, CivilTime { year: 2024, month: 2, day: 29, weekday: 4, hour: 12, minute: 34, second: 56 });
+        assert_eq!(time.unix_seconds(), Some(1_709_210_096));
+    }
+
+    #[test]
+    fn century_without_leap_day() {
+        // 2100-03-01, the day after 2100-02-28
+        let time = CivilTime::from_unix_seconds(4_107_542_400);
+        assert_eq!((time.year, time.month, time.day, time.weekday), (2100, 3, 1, 1));
+        let previous = CivilTime::from_unix_seconds(4_107_542_400 - SECONDS_PER_DAY);
+        assert_eq!((previous.month, previous.day), (2, 28));
+    }
+
+    #[test]
+    fn round_trip() {
+        for seconds in (0..4_200_000_000u64).step_by(7_777_777) {
+            assert_eq!(CivilTime::from_unix_seconds(seconds).unix_seconds(), Some(seconds));
+        }
+    }
+
+    #[test]
+    fn before_epoch_is_rejected() {
+        let time = CivilTime { year: 1969, month: 12, day: 31, weekday: 3, hour: 23, minute: 59, second: 59 };
+        assert_eq!(time.unix_seconds(), None);
+    }
+}
@@ -0,0 +1,211 @@
+use core::cell::{Cell, RefCell};
+use defmt::{info, warn};
+use embassy_rp::pac;
+use embassy_rp::pac::rtc::regs::{Rtc0, Rtc1};
+use embassy_rp::peripherals::RTC;
+use embassy_rp::rtc::{DateTime, DayOfWeek, Rtc};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+use crate::calendar::CivilTime;
+use crate::define_peripheral_set;
+
+/// Measured drift beyond this is a server stepping its own clock rather than our crystal.
+const MAX_DRIFT_PPM: i64 = 500;
+/// Server syncs closer together than this are too short to measure drift over.
+const MIN_DRIFT_INTERVAL_MS: u64 = 10 * 60 * 1000;
+
+#[macro_export]
+macro_rules! clock_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            ClockPeripherals,
+            rtc: RTC,
+        }
+    };
+}
+
+clock_peripherals!(define_peripheral_set);
+
+#[derive(Copy, Clone)]
+struct Clock {
+    /// `Instant` and Unix time in milliseconds when the clock was last set, unknown until a
+    /// controller or time server sets it.
+    reference: Option<(u64, u64)>,
+    /// Whether `reference` came from a time server, controller times are too coarse to measure
+    /// drift against.
+    server_reference: bool,
+    /// How much faster wall time runs than `Instant`, in parts per million.
+    drift_ppm: i64,
+}
+
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<Clock>> =
+    Mutex::new(Cell::new(Clock { reference: None, server_reference: false, drift_ppm: 0 }));
+
+/// Counts through resets, though not power loss, so a reboot starts with the time already set.
+static RTC_CLOCK: Mutex<CriticalSectionRawMutex, RefCell<Option<Rtc<'static, RTC>>>> = Mutex::new(RefCell::new(None));
+
+/// Time of the last server sync and the drift measured by then, persisted so a boot after a short
+/// power loss starts out approximately right rather than unset.
+#[derive(Copy, Clone)]
+pub struct StoredClock {
+    pub unix_s: u64,
+    pub drift_ppm: i32,
+}
+
+impl StoredClock {
+    pub const SERIALIZED_LEN: usize = 12;
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.unix_s.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.drift_ppm.to_le_bytes());
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let unix_s = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let drift_ppm = i32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        (unix_s != u64::MAX && drift_ppm.unsigned_abs() as i64 <= MAX_DRIFT_PPM).then_some(Self { unix_s, drift_ppm })
+    }
+}
+
+/// RTC registers as the previous boot left them.
+pub struct RtcSnapshot(Rtc1, Rtc0);
+
+/// Read the RTC before `embassy_rp::init` resets it. `None` after a power cycle, or if the
+/// previous image never started the RTC.
+pub fn snapshot_rtc() -> Option<RtcSnapshot> {
+    if !pac::RESETS.reset_done().read().rtc() || !pac::RTC.ctrl().read().rtc_active() {
+        return None;
+    }
+    // RTC_1 first as the datasheet asks, so the two halves belong together
+    let ymd = pac::RTC.rtc_1().read();
+    let hms = pac::RTC.rtc_0().read();
+    Some(RtcSnapshot(ymd, hms))
+}
+
+fn day_of_week(weekday: u8) -> DayOfWeek {
+    match weekday {
+        0 => DayOfWeek::Sunday,
+        1 => DayOfWeek::Monday,
+        2 => DayOfWeek::Tuesday,
+        3 => DayOfWeek::Wednesday,
+        4 => DayOfWeek::Thursday,
+        5 => DayOfWeek::Friday,
+        _ => DayOfWeek::Saturday,
+    }
+}
+
+fn set_rtc(unix_ms: u64) {
+    let time = CivilTime::from_unix_seconds(unix_ms / 1000);
+    let datetime = DateTime {
+        year: time.year,
+        month: time.month,
+        day: time.day,
+        day_of_week: day_of_week(time.weekday),
+        hour: time.hour,
+        minute: time.minute,
+        second: time.second,
+    };
+    RTC_CLOCK.lock(|rtc| {
+        if let Some(rtc) = rtc.borrow_mut().as_mut() {
+            if rtc.set_datetime(datetime).is_err() {
+                warn!("failed to set the RTC");
+            }
+        }
+    });
+}
+
+fn rtc_unix_seconds(rtc: &Rtc<'static, RTC>) -> Option<u64> {
+    let datetime = rtc.now().ok()?;
+    CivilTime {
+        year: datetime.year,
+        month: datetime.month,
+        day: datetime.day,
+        weekday: datetime.day_of_week as u8,
+        hour: datetime.hour,
+        minute: datetime.minute,
+        second: datetime.second,
+    }
+    .unix_seconds()
+}
+
+/// Take over the RTC and set the clock from it if it kept counting through a reset, or else from
+/// the time stored at the last server sync, which is behind by however long the power was off.
+/// Either way a time server still corrects it once the network is up.
+pub fn init(p: ClockPeripherals, snapshot: Option<RtcSnapshot>, stored: Option<StoredClock>) {
+    let mut rtc = Rtc::new(p.rtc);
+    let restored = snapshot.and_then(|RtcSnapshot(ymd, hms)| {
+        rtc.restore(ymd, hms);
+        rtc_unix_seconds(&rtc)
+    });
+    RTC_CLOCK.lock(|cell| cell.replace(Some(rtc)));
+    let drift_ppm = stored.map_or(0, |stored| stored.drift_ppm as i64);
+    let unix_ms = if let Some(unix_s) = restored {
+        info!("clock restored from the RTC");
+        unix_s * 1000
+    } else if let Some(stored) = stored {
+        info!("clock set from the last sync, until a time server answers");
+        set_rtc(stored.unix_s * 1000);
+        stored.unix_s * 1000
+    } else {
+        return;
+    };
+    let now = Instant::now().as_millis();
+    CLOCK.lock(|cell| cell.set(Clock { reference: Some((now, unix_ms)), server_reference: false, drift_ppm }));
+}
+
+fn unix_millis_at(clock: &Clock, instant_ms: u64) -> Option<u64> {
+    clock.reference.map(|(reference_ms, unix_ms)| {
+        let elapsed = instant_ms.saturating_sub(reference_ms);
+        (unix_ms + elapsed).saturating_add_signed(elapsed as i64 * clock.drift_ppm / 1_000_000)
+    })
+}
+
+pub fn set_unix_millis(unix_ms: u64) {
+    let now = Instant::now().as_millis();
+    CLOCK.lock(|cell| cell.set(Clock { reference: Some((now, unix_ms)), server_reference: false, ..cell.get() }));
+    set_rtc(unix_ms);
+}
+
+/// Set the clock from a time server, refining the drift estimate against the previous server
+/// sync. Returns how far the clock was off in milliseconds, `None` if it wasn't set before.
+pub fn sync_unix_millis(unix_ms: u64) -> Option<i64> {
+    let now = Instant::now().as_millis();
+    let error = CLOCK.lock(|cell| {
+        let mut clock = cell.get();
+        let error = unix_millis_at(&clock, now).map(|estimate| unix_ms as i64 - estimate as i64);
+        if let (true, Some((reference_ms, reference_unix_ms))) = (clock.server_reference, clock.reference) {
+            let elapsed = now - reference_ms;
+            if elapsed >= MIN_DRIFT_INTERVAL_MS {
+                let measured = (unix_ms as i64 - reference_unix_ms as i64 - elapsed as i64) * 1_000_000 / elapsed as i64;
+                // Halfway towards each measurement, so one delayed reply doesn't throw it off
+                if measured.abs() <= MAX_DRIFT_PPM {
+                    clock.drift_ppm += (measured - clock.drift_ppm) / 2;
+                }
+            }
+        }
+        clock.reference = Some((now, unix_ms));
+        clock.server_reference = true;
+        cell.set(clock);
+        error
+    });
+    set_rtc(unix_ms);
+    error
+}
+
+/// Current drift correction in parts per million.
+pub fn drift_ppm() -> i64 {
+    CLOCK.lock(|cell| cell.get().drift_ppm)
+}
+
+/// Current Unix time in milliseconds, `None` until the clock has been set.
+pub fn unix_millis() -> Option<u64> {
+    let now = Instant::now().as_millis();
+    CLOCK.lock(|cell| unix_millis_at(&cell.get(), now))
+}
+
+/// What to persist after a server sync, `None` until the clock has been set.
+pub fn stored() -> Option<StoredClock> {
+    let drift_ppm = drift_ppm() as i32;
+    unix_millis().map(|unix_ms| StoredClock { unix_s: unix_ms / 1000, drift_ppm })
+}
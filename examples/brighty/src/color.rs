@@ -1,4 +1,7 @@
-#[derive(Copy, Clone)]
+use animation::hsv;
+use animation::KeyframeColor;
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -16,31 +19,25 @@ impl Color {
     }
 
     pub fn from_hsv(hue: u16, sat: u8, val: u8) -> Self {
-        let hue = (((hue as u32) * 1530 + 32768) >> 16) as u16;
-
-        let (r, g, b) = if hue < 510 {
-            let (r, g) = if hue < 255 { (255, hue) } else { (510 - hue, 255) };
-            (r, g, 0)
-        } else if hue < 1020 {
-            let (g, b) = if hue < 765 { (255, hue - 510) } else { (1020 - hue, 255) };
-            (0, g, b)
-        } else if hue < 1530 {
-            let (r, b) = if hue < 1275 { (hue - 1020, 255) } else { (255, 1530 - hue) };
-            (r, 0, b)
-        } else {
-            (255, 0, 0)
-        };
-
-        let v1 = 1 + (val as u16);
-        let s1 = 1 + (sat as u16);
-        let s2 = 255 - (sat as u16);
+        let [r, g, b] = hsv::hsv_to_rgb(hue, sat, val);
+        Self::from_rgbw(r, g, b, 0)
+    }
 
-        let r = ((((r * s1) >> 8) + s2) * v1) >> 8;
-        let g = ((((g * s1) >> 8) + s2) * v1) >> 8;
-        let b = ((((b * s1) >> 8) + s2) * v1) >> 8;
+    /// Inverse of `from_hsv`, ignoring the white channel.
+    pub fn to_hsv(&self) -> (u16, u8, u8) {
+        hsv::rgb_to_hsv([self.r, self.g, self.b])
+    }
 
-        Self {
-            r: r as _, g: g as _, b: b as _, w: 0
+    /// Black body style ramp used by the fire effect: black, red, yellow, white.
+    pub fn from_heat(heat: u8) -> Self {
+        let t192 = ((heat as u16 * 191) / 255) as u8;
+        let ramp = (t192 & 0x3F) << 2;
+        if t192 & 0x80 != 0 {
+            Self::from_rgbw(255, 255, ramp, 0)
+        } else if t192 & 0x40 != 0 {
+            Self::from_rgbw(255, ramp, 0, 0)
+        } else {
+            Self::from_rgbw(ramp, 0, 0, 0)
         }
     }
 
@@ -55,7 +52,91 @@ impl Color {
         }
     }
 
+    /// Apply `f` to each channel.
+    pub fn map_channels(&self, f: impl Fn(u8) -> u8) -> Color {
+        Self::from_rgbw(f(self.r), f(self.g), f(self.b), f(self.w))
+    }
+
+    /// Blend `t` out of 256 of the way from `self` to `other`.
+    pub fn blend(&self, other: &Color, t: u32) -> Color {
+        let mix = |a: u8, b: u8| ((a as u32 * (256 - t) + b as u32 * t) >> 8) as u8;
+        Self::from_rgbw(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b), mix(self.w, other.w))
+    }
+
     pub fn encode_for_sk6812(&self) -> u32 {
         ((self.r as u32) << 16) | ((self.g as u32) << 24) | ((self.b as u32) << 8) | (self.w as u32)
     }
 }
+
+impl KeyframeColor for Color {
+    const BLACK: Self = Color::BLACK;
+
+    fn to_rgbw(self) -> [u8; 4] {
+        [self.r, self.g, self.b, self.w]
+    }
+
+    fn from_rgbw([r, g, b, w]: [u8; 4]) -> Self {
+        Self::from_rgbw(r, g, b, w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgbw(color: Color) -> (u8, u8, u8, u8) {
+        (color.r, color.g, color.b, color.w)
+    }
+
+    #[test]
+    fn hsv_primaries() {
+        assert_eq!(rgbw(Color::from_hsv(0, 255, 255)), (255, 0, 0, 0));
+        assert_eq!(rgbw(Color::from_hsv(21845, 255, 255)), (0, 255, 0, 0));
+        assert_eq!(rgbw(Color::from_hsv(43690, 255, 255)), (0, 0, 255, 0));
+        // The top of the hue range wraps back to red
+        assert_eq!(rgbw(Color::from_hsv(u16::MAX, 255, 255)), (255, 0, 0, 0));
+    }
+
+    #[test]
+    fn hsv_without_saturation_is_grey() {
+        let (r, g, b, _) = rgbw(Color::from_hsv(12345, 0, 128));
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for hue in (0..u16::MAX).step_by(997) {
+            let (h, s, v) = Color::from_hsv(hue, 255, 255).to_hsv();
+            assert!((h as i32 - hue as i32).abs() <= 64 || (h as i32 - hue as i32).abs() >= 65536 - 64);
+            assert_eq!((s, v), (255, 255));
+        }
+    }
+
+    #[test]
+    fn heat_ramp_ends() {
+        assert_eq!(rgbw(Color::from_heat(0)), (0, 0, 0, 0));
+        assert_eq!(rgbw(Color::from_heat(255)), (255, 255, 252, 0));
+    }
+
+    #[test]
+    fn brightness_scaling() {
+        let color = Color::from_rgbw(255, 128, 1, 64);
+        assert_eq!(rgbw(color.with_brightness(255)), rgbw(color));
+        assert_eq!(rgbw(color.with_brightness(0)), (0, 0, 0, 0));
+        assert_eq!(rgbw(color.with_brightness(127)), (127, 64, 0, 32));
+    }
+
+    #[test]
+    fn blend_ends() {
+        let from = Color::from_rgbw(255, 0, 100, 8);
+        let to = Color::from_rgbw(0, 255, 100, 0);
+        assert_eq!(rgbw(from.blend(&to, 0)), rgbw(from));
+        assert_eq!(rgbw(from.blend(&to, 256)), rgbw(to));
+        assert_eq!(rgbw(from.blend(&to, 128)), (127, 127, 100, 4));
+    }
+
+    #[test]
+    fn sk6812_wire_order() {
+        assert_eq!(Color::from_rgbw(0x11, 0x22, 0x33, 0x44).encode_for_sk6812(), 0x2211_3344);
+    }
+}
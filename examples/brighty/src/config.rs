@@ -0,0 +1,246 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::String;
+use num::FromPrimitive;
+use num_derive::FromPrimitive;
+use ufmt::uwrite;
+use crate::color::Color;
+use crate::consts;
+use crate::leds::{Effect, NUM_LEDS};
+
+pub const MAX_HOSTNAME_LEN: usize = 32;
+pub const MAX_NTP_SERVER_LEN: usize = 32;
+
+/// Order the strip expects the color bytes in, W always comes last.
+#[derive(Copy, Clone, PartialEq, FromPrimitive)]
+pub enum ColorOrder {
+    /// Native SK6812 order.
+    Grb = 0,
+    Rgb = 1,
+    Brg = 2,
+    Rbg = 3,
+    Gbr = 4,
+    Bgr = 5,
+}
+
+impl ColorOrder {
+    /// Swap the channels of a color so a strip in this order shows it as intended once sent in
+    /// the native order.
+    pub fn apply(self, color: Color) -> Color {
+        let Color { r, g, b, w } = color;
+        let [c0, c1, c2] = match self {
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Bgr => [b, g, r],
+        };
+        // The native order sends green first, then red
+        Color::from_rgbw(c1, c0, c2, w)
+    }
+}
+
+/// Fixed IPv4 configuration for networks without DHCP reservations.
+#[derive(Copy, Clone, PartialEq)]
+pub struct StaticIp {
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+    /// 0.0.0.0 for none.
+    pub gateway: [u8; 4],
+    /// 0.0.0.0 for none.
+    pub dns: [u8; 4],
+}
+
+impl StaticIp {
+    pub fn is_valid(&self) -> bool {
+        (1..=32).contains(&self.prefix_len)
+    }
+}
+
+/// Syslog collector that `syslog` sends remote log messages to.
+#[derive(Copy, Clone, PartialEq)]
+pub struct LogCollector {
+    pub address: [u8; 4],
+    pub port: u16,
+}
+
+impl LogCollector {
+    pub fn is_valid(&self) -> bool {
+        self.port != 0 && self.address != [0; 4] && self.address != [0xFF; 4]
+    }
+}
+
+/// Per-installation settings, so one firmware binary serves every sconce variant. LED output
+/// settings apply immediately, the rest at the next boot.
+#[derive(Copy, Clone, PartialEq)]
+pub struct DeviceConfig {
+    hostname: [u8; MAX_HOSTNAME_LEN],
+    hostname_len: u8,
+    /// LEDs actually fitted, up to `NUM_LEDS`. The rest of the output stays dark.
+    pub led_count: u16,
+    pub color_order: ColorOrder,
+    /// Estimated strip current limit in milliamps, 0 for unlimited.
+    pub max_power_ma: u16,
+    /// Effect shown at power-on, `None` restores the last one.
+    pub default_effect: Option<Effect>,
+    /// Groups this device answers group addressed datagrams for, bit n for group n.
+    pub groups: u32,
+    /// Static IPv4 configuration, `None` uses DHCP.
+    pub static_ip: Option<StaticIp>,
+    ntp_server: [u8; MAX_NTP_SERVER_LEN],
+    ntp_server_len: u8,
+    /// Where remote log messages go, `None` keeps them on the device.
+    pub log_collector: Option<LogCollector>,
+}
+
+impl DeviceConfig {
+    pub const DEFAULT: DeviceConfig = DeviceConfig {
+        hostname: [0; MAX_HOSTNAME_LEN],
+        hostname_len: 0,
+        led_count: NUM_LEDS as u16,
+        color_order: ColorOrder::Grb,
+        max_power_ma: 0,
+        default_effect: None,
+        groups: 0,
+        static_ip: None,
+        ntp_server: [0; MAX_NTP_SERVER_LEN],
+        ntp_server_len: 0,
+        log_collector: None,
+    };
+
+    pub const SERIALIZED_LEN: usize = 1 + MAX_HOSTNAME_LEN + 2 + 1 + 2 + 1 + 4 + 13 + 1 + MAX_NTP_SERVER_LEN + 6;
+
+    /// Configured hostname, `<consts::HOSTNAME>-xxxx` with the end of the flash unique ID if
+    /// none was set, so devices on one network don't all share a name.
+    pub fn hostname(&self) -> String<MAX_HOSTNAME_LEN> {
+        let hostname = core::str::from_utf8(&self.hostname[..self.hostname_len as usize]).unwrap_or("");
+        let mut out = String::new();
+        if hostname.is_empty() {
+            let [a, b] = ID_SUFFIX.lock(|cell| cell.get());
+            uwrite!(out, "{}-{:02x}{:02x}", consts::HOSTNAME, a, b).ok();
+        } else {
+            out.push_str(hostname).ok();
+        }
+        out
+    }
+
+    /// Configured time server name or address, `consts::NTP_SERVER` if none was set.
+    pub fn ntp_server(&self) -> String<MAX_NTP_SERVER_LEN> {
+        let server = core::str::from_utf8(&self.ntp_server[..self.ntp_server_len as usize]).unwrap_or("");
+        let mut out = String::new();
+        out.push_str(if server.is_empty() { consts::NTP_SERVER } else { server }).ok();
+        out
+    }
+
+    /// Host names or IPv4 addresses: letters, digits, hyphens and dots. Empty restores the default.
+    pub fn set_ntp_server(&mut self, server: &[u8]) -> bool {
+        let valid = server.len() <= MAX_NTP_SERVER_LEN
+            && server.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'.');
+        if valid {
+            self.ntp_server = [0; MAX_NTP_SERVER_LEN];
+            self.ntp_server[..server.len()].copy_from_slice(server);
+            self.ntp_server_len = server.len() as u8;
+        }
+        valid
+    }
+
+    pub fn in_group(&self, group: u8) -> bool {
+        group < 32 && self.groups & (1 << group) != 0
+    }
+
+    /// Hostnames are DNS labels: letters, digits and inner hyphens. Empty restores the default.
+    pub fn set_hostname(&mut self, hostname: &[u8]) -> bool {
+        let valid = hostname.len() <= MAX_HOSTNAME_LEN
+            && hostname.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-')
+            && hostname.first() != Some(&b'-')
+            && hostname.last() != Some(&b'-');
+        if valid {
+            self.hostname = [0; MAX_HOSTNAME_LEN];
+            self.hostname[..hostname.len()].copy_from_slice(hostname);
+            self.hostname_len = hostname.len() as u8;
+        }
+        valid
+    }
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0] = self.hostname_len;
+        bytes[1..33].copy_from_slice(&self.hostname);
+        bytes[33..35].copy_from_slice(&self.led_count.to_le_bytes());
+        bytes[35] = self.color_order as u8;
+        bytes[36..38].copy_from_slice(&self.max_power_ma.to_le_bytes());
+        bytes[38] = self.default_effect.map_or(0xFF, |effect| effect as u8);
+        bytes[39..43].copy_from_slice(&self.groups.to_le_bytes());
+        match self.static_ip {
+            Some(ip) => {
+                bytes[43..47].copy_from_slice(&ip.address);
+                bytes[47] = ip.prefix_len;
+                bytes[48..52].copy_from_slice(&ip.gateway);
+                bytes[52..56].copy_from_slice(&ip.dns);
+            }
+            None => bytes[43..56].fill(0xFF),
+        }
+        bytes[56] = self.ntp_server_len;
+        bytes[57..89].copy_from_slice(&self.ntp_server);
+        match self.log_collector {
+            Some(collector) => {
+                bytes[89..93].copy_from_slice(&collector.address);
+                bytes[93..95].copy_from_slice(&collector.port.to_le_bytes());
+            }
+            None => bytes[89..95].fill(0xFF),
+        }
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let mut config = Self::DEFAULT;
+        if !config.set_hostname(&bytes[1..1 + (bytes[0] as usize).min(MAX_HOSTNAME_LEN)]) {
+            return None;
+        }
+        config.led_count = u16::from_le_bytes([bytes[33], bytes[34]]).min(NUM_LEDS as u16);
+        config.color_order = ColorOrder::from_u8(bytes[35])?;
+        config.max_power_ma = u16::from_le_bytes([bytes[36], bytes[37]]);
+        config.default_effect = Effect::from_u8(bytes[38]);
+        // Configs saved before groups existed read erased flash here
+        config.groups = match u32::from_le_bytes([bytes[39], bytes[40], bytes[41], bytes[42]]) {
+            u32::MAX => 0,
+            groups => groups,
+        };
+        // As are configs saved before static addresses existed, an invalid prefix length means DHCP
+        let static_ip = StaticIp {
+            address: bytes[43..47].try_into().unwrap(),
+            prefix_len: bytes[47],
+            gateway: bytes[48..52].try_into().unwrap(),
+            dns: bytes[52..56].try_into().unwrap(),
+        };
+        config.static_ip = static_ip.is_valid().then_some(static_ip);
+        // Erased flash fails validation and leaves the default server
+        config.set_ntp_server(&bytes[57..57 + (bytes[56] as usize).min(MAX_NTP_SERVER_LEN)]);
+        // Erased flash reads as the broadcast address, which is no collector
+        let log_collector = LogCollector {
+            address: bytes[89..93].try_into().unwrap(),
+            port: u16::from_le_bytes([bytes[93], bytes[94]]),
+        };
+        config.log_collector = log_collector.is_valid().then_some(log_collector);
+        Some(config)
+    }
+}
+
+/// Configuration loaded at boot, updated by configuration commands.
+pub static DEVICE_CONFIG: Mutex<CriticalSectionRawMutex, Cell<DeviceConfig>> = Mutex::new(Cell::new(DeviceConfig::DEFAULT));
+
+pub fn get() -> DeviceConfig {
+    DEVICE_CONFIG.lock(|cell| cell.get())
+}
+
+pub fn set(config: DeviceConfig) {
+    DEVICE_CONFIG.lock(|cell| cell.set(config));
+}
+
+/// Last bytes of the flash unique ID, for the default hostname.
+static ID_SUFFIX: Mutex<CriticalSectionRawMutex, Cell<[u8; 2]>> = Mutex::new(Cell::new([0; 2]));
+
+/// Record the flash unique ID, before anything asks for the hostname.
+pub fn set_unique_id(id: [u8; 8]) {
+    ID_SUFFIX.lock(|cell| cell.set([id[6], id[7]]));
+}
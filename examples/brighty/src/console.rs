@@ -0,0 +1,191 @@
+use board_support::bootsel;
+use board_support::console::{Args, Console, RESET_DELAY};
+use embassy_futures::join::join3;
+use embassy_time::{Instant, Timer};
+use heapless::{String, Vec};
+use static_cell::StaticCell;
+use ufmt::uwrite;
+use crate::config::{self, MAX_HOSTNAME_LEN};
+use crate::instrument;
+#[cfg(feature = "ir")]
+use crate::ir::{self, IrAction};
+use crate::leds::{self, NUM_LEDS};
+use crate::link::{self, NetHealth};
+use crate::provision::{self, WifiCredentials};
+use crate::storage::{self, StorageSender};
+use crate::{define_peripheral_set, Irqs};
+
+const MAX_LINE_LEN: usize = 128;
+const MAX_REPLY_LEN: usize = 512;
+
+#[cfg(feature = "ir")]
+const IR_HELP: &str = "ir                          list the learned remote keys\r\n\
+                       ir learn <action>           map the next remote key pressed to on, off, brighter or dimmer\r\n\
+                       ir learn color <rrggbb[ww]> map the next remote key pressed to a static color\r\n\
+                       ir learn effect <n>         map the next remote key pressed to an effect\r\n\
+                       ir learn preset <n>         map the next remote key pressed to a preset\r\n\
+                       ir forget                   forget every learned remote key\r\n";
+#[cfg(not(feature = "ir"))]
+const IR_HELP: &str = "";
+const HELP: &str = "status                      show the device state\r\n\
+                    tasks                       show core loads and task poll stats\r\n\
+                    wifi <ssid> [passphrase]    store network credentials and rejoin\r\n\
+                    leds <count>                set the number of LEDs fitted\r\n\
+                    reboot                      restart the device\r\n\
+                    bootsel                     restart into the USB bootloader for flashing\r\n\
+                    factory-reset               erase all settings and restart\r\n\
+                    Quote arguments containing spaces, \"like this\".\r\n";
+
+#[macro_export]
+macro_rules! console_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            ConsolePeripherals,
+            usb: USB,
+        }
+    };
+}
+
+console_peripherals!(define_peripheral_set);
+
+fn get_storage_sender() -> StorageSender {
+    storage::STORAGE_CHANNEL.sender()
+}
+
+/// What to do once the reply went out.
+enum Then {
+    Continue,
+    Reboot,
+    Bootsel,
+    FactoryReset,
+    Provision(WifiCredentials),
+}
+
+fn write_status(out: &mut String<MAX_REPLY_LEN>) {
+    let config = config::get();
+    let status = leds::LED_STATUS.lock(|cell| cell.get());
+    let quality = link::quality();
+    let net = match link::net_health() {
+        NetHealth::Up => "up",
+        NetHealth::Outage => "outage",
+    };
+    uwrite!(out, "name {}\r\nfirmware {}\r\nuptime {} s\r\nnet {}, rssi {} dBm\r\n",
+        config.hostname().as_str(), env!("CARGO_PKG_VERSION"), Instant::now().as_secs(), net, quality.rssi).ok();
+    uwrite!(out, "leds {} of {}, brightness {}, effect {}\r\n",
+        config.led_count, NUM_LEDS, status.brightness, status.effect as u8).ok();
+    let led_errors = leds::output_errors();
+    if led_errors.failed_writes != 0 {
+        uwrite!(out, "led frames failed {}, restarts {}\r\n", led_errors.failed_writes, led_errors.reinits).ok();
+    }
+}
+
+fn execute(line: &str, out: &mut String<MAX_REPLY_LEN>) -> Then {
+    // One more than any command takes, to reject extra arguments
+    let args: Vec<&str, 5> = Args(line).take(5).collect();
+    let reply = match args.as_slice() {
+        [] => "",
+        ["help"] => {
+            out.push_str(IR_HELP).ok();
+            HELP
+        }
+        ["status"] => {
+            write_status(out);
+            return Then::Continue;
+        }
+        ["tasks"] => {
+            instrument::write_summary(out);
+            return Then::Continue;
+        }
+        ["wifi", ssid, passphrase @ ..] if passphrase.len() <= 1 => {
+            let passphrase = passphrase.first().copied().unwrap_or("");
+            match WifiCredentials::new(ssid.as_bytes(), passphrase.as_bytes()) {
+                Some(credentials) => {
+                    out.push_str("credentials stored, rebooting to join\r\n").ok();
+                    return Then::Provision(credentials);
+                }
+                None => "SSID of 1 to 32 bytes and passphrase of 8 to 63 characters expected\r\n",
+            }
+        }
+        ["leds", count] => match count.parse::<u16>() {
+            Ok(count) if (1..=NUM_LEDS).contains(&(count as usize)) => {
+                let mut new_config = config::get();
+                new_config.led_count = count;
+                config::set(new_config);
+                get_storage_sender().save_config(new_config);
+                "LED count stored\r\n"
+            }
+            _ => "LED count out of range\r\n",
+        },
+        #[cfg(feature = "ir")]
+        ["ir"] => {
+            ir::write_bindings(out);
+            return Then::Continue;
+        }
+        #[cfg(feature = "ir")]
+        ["ir", "learn", action @ ..] => match IrAction::parse(action) {
+            Some(action) => {
+                ir::learn(action);
+                "press the remote key to map\r\n"
+            }
+            None => "unknown action, try help\r\n",
+        },
+        #[cfg(feature = "ir")]
+        ["ir", "forget"] => {
+            ir::forget_all();
+            "remote keys forgotten\r\n"
+        }
+        ["reboot"] => {
+            out.push_str("rebooting\r\n").ok();
+            return Then::Reboot;
+        }
+        ["bootsel"] => {
+            out.push_str("rebooting into BOOTSEL\r\n").ok();
+            return Then::Bootsel;
+        }
+        ["factory-reset"] => {
+            out.push_str("erasing settings and rebooting\r\n").ok();
+            return Then::FactoryReset;
+        }
+        _ => "unknown command or wrong arguments, try help\r\n",
+    };
+    out.push_str(reply).ok();
+    Then::Continue
+}
+
+/// Serial console over the USB port, for configuring a device from a laptop without
+/// rebuilding the firmware, next to a DFU runtime interface for getting into BOOTSEL.
+#[embassy_executor::task]
+pub async fn console_task(p: ConsolePeripherals) -> ! {
+    static SERIAL_NUMBER: StaticCell<String<MAX_HOSTNAME_LEN>> = StaticCell::new();
+    let serial_number: &'static String<MAX_HOSTNAME_LEN> = SERIAL_NUMBER.init(config::get().hostname());
+    let (mut usb, mut console) =
+        Console::<MAX_LINE_LEN>::new(p.usb, Irqs, "brighty console", serial_number.as_str(), None);
+
+    let session = async {
+        loop {
+            let mut reply = String::<MAX_REPLY_LEN>::new();
+            let then = execute(console.read_line().await, &mut reply);
+            console.reply(reply.as_bytes()).await;
+            match then {
+                Then::Continue => {}
+                Then::Reboot => {
+                    Timer::after(RESET_DELAY).await;
+                    get_storage_sender().reboot();
+                }
+                Then::Bootsel => {
+                    Timer::after(RESET_DELAY).await;
+                    bootsel::reboot();
+                }
+                Then::FactoryReset => {
+                    Timer::after(RESET_DELAY).await;
+                    get_storage_sender().factory_reset();
+                }
+                Then::Provision(credentials) => {
+                    Timer::after(RESET_DELAY).await;
+                    provision::provision(credentials);
+                }
+            }
+        }
+    };
+    instrument::measured("console", join3(usb.run(), session, bootsel::run())).await.0
+}
@@ -1,2 +1,20 @@
 pub const CMD_PORT: u16 = 6721;
 pub const DISCOVER_PORT: u16 = 6722;
+/// Advertised over mDNS as `<hostname>._mowsconce._udp.local`, for apps to find the command port.
+pub const MDNS_SERVICE: net_setup::mdns::Service =
+    net_setup::mdns::Service { name: ["_mowsconce", "_udp", "local"], port: CMD_PORT, txt: b"proto=1" };
+pub const HOSTNAME: &str = "brighty";
+/// Time server used until one is configured.
+pub const NTP_SERVER: &str = "pool.ntp.org";
+/// sACN universe, counted from 1.
+#[cfg(feature = "sacn")]
+pub const DMX_UNIVERSE: u16 = 1;
+/// DMX start address (1-based) of the first LED, shared by sACN and Art-Net.
+#[cfg(any(feature = "sacn", feature = "artnet"))]
+pub const DMX_START_ADDRESS: u16 = 1;
+/// Art-Net 15-bit port address (net, sub-net, universe), counted from 0.
+#[cfg(feature = "artnet")]
+pub const ARTNET_UNIVERSE: u16 = 0;
+/// Broker the `mqtt` feature connects to for Home Assistant discovery.
+#[cfg(feature = "mqtt")]
+pub const MQTT_BROKER: embassy_net::Ipv4Address = embassy_net::Ipv4Address::new(192, 168, 1, 10);
@@ -0,0 +1,80 @@
+//! Status JSON and a control page for browsers, served by [`http::run`](crate::http::run).
+use embassy_time::Instant;
+use heapless::String;
+use net_setup::http::{form_value, CONTENT_HTML, CONTENT_JSON};
+use num::FromPrimitive;
+use ufmt::uwrite;
+use crate::{clock, config};
+use crate::json::json_u8;
+use crate::leds::{self, Effect, LedSender};
+use crate::link::{self, NetHealth};
+
+/// The control page up to its effect options, which come from `Effect`.
+const CONTROL_PAGE_HEAD: &str = "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+<title>brighty</title></head><body><h1 id=\"n\">brighty</h1><p id=\"s\"></p><form method=\"POST\" action=\"/control\">\
+<p>Effect <select name=\"fx\">";
+const CONTROL_PAGE_TAIL: &str = "</select></p>\
+<p>Brightness <input name=\"bri\" type=\"range\" max=\"255\"></p><p><input type=\"submit\" value=\"Apply\"></p></form>\
+<script>fetch('/status').then(r=>r.json()).then(s=>{document.getElementById('n').textContent=s.name;\
+document.getElementById('s').textContent='Network '+s.net+', '+s.rssi+' dBm, up '+s.uptime+' s';\
+document.forms[0].fx.value=s.fx;document.forms[0].bri.value=s.bri})</script></body></html>";
+
+fn write_control_page<const N: usize>(out: &mut String<N>) {
+    out.push_str(CONTROL_PAGE_HEAD).ok();
+    for effect in Effect::ALL.into_iter().filter(|effect| effect.standalone()) {
+        uwrite!(out, "<option value=\"{}\">{}</option>", effect as u8, effect.name()).ok();
+    }
+    out.push_str(CONTROL_PAGE_TAIL).ok();
+}
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+fn form_u8(body: &[u8], name: &[u8]) -> Option<u8> {
+    let mut value = [0; 3];
+    json_u8(form_value(body, name, &mut value)?)
+}
+
+fn write_status<const N: usize>(out: &mut String<N>) {
+    let status = leds::LED_STATUS.lock(|cell| cell.get());
+    let quality = link::quality();
+    let net = match link::net_health() {
+        NetHealth::Up => "up",
+        NetHealth::Outage => "outage",
+    };
+    uwrite!(out, "{{\"name\":\"{}\",\"uptime\":{},\"net\":\"{}\",\"rssi\":{},\"rate\":{},\"clock\":{},\"on\":{},\"bri\":{},\"fx\":{}}}",
+        config::get().hostname().as_str(), Instant::now().as_secs(), net, quality.rssi, quality.tx_rate as u32 * 500,
+        clock::unix_millis().is_some(), status.brightness > 0, status.brightness, status.effect as u8).ok();
+}
+
+fn apply_control(body: &[u8]) {
+    let mut led_sender = get_led_sender();
+    if let Some(effect) = form_u8(body, b"fx").and_then(Effect::from_u8) {
+        led_sender.set_effect(effect);
+    }
+    if let Some(brightness) = form_u8(body, b"bri") {
+        led_sender.set_brightness(brightness);
+    }
+}
+
+/// Status JSON at `/status` and the control page at `/`, submitting to `/control`. Writes the
+/// response body to `out` and returns its content type, `None` for unknown paths.
+pub fn handle<const N: usize>(method: &[u8], path: &[u8], body: &[u8], out: &mut String<N>) -> Option<&'static str> {
+    match (method, path) {
+        (b"GET", b"/status") => {
+            write_status(out);
+            Some(CONTENT_JSON)
+        }
+        (b"POST", b"/control") => {
+            apply_control(body);
+            write_control_page(out);
+            Some(CONTENT_HTML)
+        }
+        (b"GET", b"/") => {
+            write_control_page(out);
+            Some(CONTENT_HTML)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,21 @@
+use board_support::crash::{self, STACK_WORDS};
+use heapless::String;
+use ufmt::uwrite;
+use crate::remote_log;
+
+/// Report a crash recorded before the last reset through the remote log, at boot.
+pub fn report() {
+    let Some(record) = crash::take() else {
+        return;
+    };
+
+    remote_log!(Error, "crashed on core {}, pc {:08x} lr {:08x}", record.core, record.pc, record.lr);
+    if !record.message().is_empty() {
+        remote_log!(Error, "{}", record.message());
+    }
+    let mut stack = String::<{ STACK_WORDS * 9 }>::new();
+    for word in record.stack {
+        uwrite!(stack, " {:08x}", word).ok();
+    }
+    remote_log!(Error, "stack{}", stack.as_str());
+}
@@ -0,0 +1,93 @@
+use defmt::debug;
+use embassy_net::udp::UdpSocket;
+use embassy_time::Duration;
+use nom::{IResult, bytes::complete::take, combinator::{cond, verify}, number::complete::{be_u16, be_u32, u8}, sequence::tuple};
+use crate::color::Color;
+use crate::leds::{self, LedSender, RealtimeFrame, NUM_LEDS};
+
+pub const DDP_PORT: u16 = 4048;
+
+const FLAGS_VERSION_MASK: u8 = 0xC0;
+const FLAGS_VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+/// Pixel type bits of the data type byte.
+const DATA_TYPE_MASK: u8 = 0x38;
+const DATA_TYPE_RGBW: u8 = 0x18;
+/// Destination ids 1 (default output) and 0 (reserved, sent by some senders) address the strip.
+const MAX_DISPLAY_ID: u8 = 1;
+/// Keep showing the last pushed frame this long after packets stop before resuming effects.
+const DATA_LOSS_TIMEOUT: Duration = Duration::from_millis(2500);
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+struct DataPacket<'a> {
+    flags: u8,
+    data_type: u8,
+    /// Byte offset of `data` into the display's pixel data.
+    offset: u32,
+    data: &'a [u8],
+}
+
+fn parse_packet(input: &[u8]) -> IResult<&[u8], DataPacket> {
+    // Flags, sequence, data type, destination id, offset, length
+    let (input, (flags, _, data_type, _, offset, length)) = tuple((
+        verify(u8, |flags| flags & FLAGS_VERSION_MASK == FLAGS_VERSION_1),
+        u8,
+        u8,
+        verify(u8, |id| *id <= MAX_DISPLAY_ID),
+        be_u32,
+        be_u16,
+    ))(input)?;
+    // Timecode is only present when flagged. Frames are shown on push as they arrive, there is no
+    // shared clock to schedule them against.
+    let (input, _timecode) = cond(flags & FLAG_TIMECODE != 0, be_u32)(input)?;
+    let (input, data) = take(length as usize)(input)?;
+    Ok((input, DataPacket { flags, data_type, offset, data }))
+}
+
+/// Pixel data collected from packets until one carries the push flag.
+struct Receiver {
+    pending: RealtimeFrame,
+}
+
+impl Receiver {
+    fn on_packet(&mut self, packet: DataPacket) -> Option<RealtimeFrame> {
+        if packet.flags & FLAG_QUERY != 0 {
+            return None;
+        }
+        let bytes_per_pixel = if packet.data_type & DATA_TYPE_MASK == DATA_TYPE_RGBW { 4 } else { 3 };
+        let first = packet.offset as usize / bytes_per_pixel;
+        for (i, pixel) in packet.data.chunks_exact(bytes_per_pixel).enumerate() {
+            if let Some(led) = self.pending.colors.get_mut(first + i) {
+                *led = Some(Color::from_rgbw(pixel[0], pixel[1], pixel[2], pixel.get(3).copied().unwrap_or(0)));
+            }
+        }
+        if packet.flags & FLAG_PUSH == 0 {
+            return None;
+        }
+        let frame = self.pending;
+        self.pending.colors = [None; NUM_LEDS];
+        Some(frame)
+    }
+}
+
+/// Receive DDP pixel data on a socket bound to `DDP_PORT`.
+pub async fn run<'a>(socket: &mut UdpSocket<'a>) -> ! {
+    let mut receiver = Receiver {
+        pending: RealtimeFrame { colors: [None; NUM_LEDS], timeout: DATA_LOSS_TIMEOUT },
+    };
+    loop {
+        socket.recv_from_with(|buffer, endpoint| match parse_packet(buffer) {
+            Ok((_, packet)) => {
+                if let Some(frame) = receiver.on_packet(packet) {
+                    get_led_sender().set_realtime(frame);
+                }
+            }
+            Err(_) => debug!("Discarding invalid DDP packet from {}", endpoint),
+        }).await;
+    }
+}
@@ -0,0 +1,19 @@
+use embassy_time::Duration;
+use crate::color::Color;
+use crate::consts;
+use crate::leds::{RealtimeFrame, NUM_LEDS};
+
+/// Each LED takes consecutive R, G, B, W channels.
+pub const CHANNELS_PER_LED: usize = 4;
+
+/// Map the slots of a DMX universe (without start code) to LED colors, starting at
+/// `consts::DMX_START_ADDRESS`. LEDs past the end of the universe are left untouched.
+pub fn frame_from_dmx(slots: &[u8], timeout: Duration) -> RealtimeFrame {
+    let mut frame = RealtimeFrame { colors: [None; NUM_LEDS], timeout };
+    let start = (consts::DMX_START_ADDRESS as usize).saturating_sub(1);
+    let channels = slots.get(start..).unwrap_or(&[]).chunks_exact(CHANNELS_PER_LED);
+    for (led, channels) in frame.colors.iter_mut().zip(channels) {
+        *led = Some(Color::from_rgbw(channels[0], channels[1], channels[2], channels[3]));
+    }
+    frame
+}
@@ -0,0 +1,52 @@
+//! The web server on `HTTP_PORT`, shared by the WLED JSON API and the control page.
+use defmt::warn;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_time::Duration;
+use heapless::String;
+use net_setup::http::{read_request, respond, CONTENT_TEXT, HTTP_PORT};
+#[cfg(feature = "control-page")]
+use crate::control_page;
+#[cfg(feature = "wled")]
+use crate::wled;
+
+const MAX_REQUEST_LEN: usize = 1024;
+
+/// Serve the WLED JSON API with the `wled` feature and the status JSON and control page with
+/// `control-page`, one connection at a time.
+pub async fn run<D: Driver>(stack: &Stack<D>, mac: [u8; 6]) -> ! {
+    let mut rx_buffer = [0; MAX_REQUEST_LEN];
+    let mut tx_buffer = [0; MAX_REQUEST_LEN];
+    let mut request = [0; MAX_REQUEST_LEN];
+    #[cfg(feature = "wled")]
+    let mut api = wled::JsonApi::new(mac);
+    #[cfg(not(feature = "wled"))]
+    let _ = mac;
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if socket.accept(HTTP_PORT).await.is_err() {
+            warn!("HTTP accept failed");
+            continue;
+        }
+
+        let parsed = read_request(&mut socket, &mut request).await;
+        let mut body = String::<MAX_REQUEST_LEN>::new();
+        let content_type = parsed.and_then(|(method, path, request_body)| {
+            #[cfg(feature = "wled")]
+            if api.handle(method, path, request_body, &mut body) {
+                return Some(net_setup::http::CONTENT_JSON);
+            }
+            #[cfg(feature = "control-page")]
+            if let Some(content_type) = control_page::handle(method, path, request_body, &mut body) {
+                return Some(content_type);
+            }
+            None
+        });
+        match content_type {
+            Some(content_type) => respond(&mut socket, "200 OK", content_type, body.as_bytes()).await,
+            None => respond(&mut socket, "404 Not Found", CONTENT_TEXT, &[]).await,
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use embassy_net::{ConfigV6, Ipv6Address, Ipv6Cidr, StaticConfigV6};
+use heapless::Vec;
+
+/// Ethernet address the cyw43 has to accept for link-local all-nodes traffic such as router
+/// advertisements.
+pub const ALL_NODES_MAC: [u8; 6] = [0x33, 0x33, 0x00, 0x00, 0x00, 0x01];
+
+/// Link-local address with the modified EUI-64 interface id of `mac` (RFC 4291 appendix A).
+pub fn link_local_address(mac: &[u8; 6]) -> Ipv6Address {
+    let mut bytes = [0; 16];
+    bytes[0..2].copy_from_slice(&[0xFE, 0x80]);
+    bytes[8..16].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xFF, 0xFE, mac[3], mac[4], mac[5]]);
+    Ipv6Address::from_bytes(&bytes)
+}
+
+/// Link-local only configuration, enough for controllers and mDNS on the same link. There is
+/// no SLAAC in the stack, so global addresses aren't configured.
+pub fn config(mac: &[u8; 6]) -> ConfigV6 {
+    ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local_address(mac), 64),
+        gateway: None,
+        dns_servers: Vec::new(),
+    })
+}
+
+/// Solicited-node group neighbor solicitations for `address` are sent to (RFC 4291 section 2.7.1).
+pub fn solicited_node(address: Ipv6Address) -> Ipv6Address {
+    let mut bytes = [0; 16];
+    bytes[0..2].copy_from_slice(&[0xFF, 0x02]);
+    bytes[11..13].copy_from_slice(&[0x01, 0xFF]);
+    bytes[13..16].copy_from_slice(&address.as_bytes()[13..16]);
+    Ipv6Address::from_bytes(&bytes)
+}
+
+/// Ethernet address an IPv6 multicast group maps to (RFC 2464 section 7).
+pub fn multicast_mac(group: Ipv6Address) -> [u8; 6] {
+    let b = group.as_bytes();
+    [0x33, 0x33, b[12], b[13], b[14], b[15]]
+}
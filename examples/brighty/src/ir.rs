@@ -0,0 +1,267 @@
+use core::cell::Cell;
+use defmt::{debug, info};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use brighty::nec::{Code, Decoder, Event};
+use heapless::String;
+use num::FromPrimitive;
+use ufmt::uwrite;
+use crate::{define_peripheral_set, instrument, ir_peripherals, remote_log};
+use crate::color::Color;
+use crate::leds::{self, Effect, LedSender};
+use crate::presets::{self, NUM_PRESETS};
+use crate::storage;
+
+/// Keys of the remote that can be learned, enough for a 24-key RGB remote.
+pub const NUM_IR_BINDINGS: usize = 24;
+const BRIGHTNESS_STEP: u8 = 16;
+/// Repeat codes come every 110 ms while a key is held, a longer gap means it was let go.
+const REPEAT_WINDOW: Duration = Duration::from_millis(150);
+
+ir_peripherals!(define_peripheral_set);
+
+/// What a learned key does.
+#[derive(Copy, Clone, PartialEq)]
+pub enum IrAction {
+    /// Back to the brightness before `Off`.
+    On,
+    Off,
+    Brighter,
+    Dimmer,
+    /// The static effect in this color.
+    Color(Color),
+    Effect(Effect),
+    Preset(u8),
+}
+
+impl IrAction {
+    /// From the console arguments after `ir learn`.
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        match args {
+            ["on"] => Some(IrAction::On),
+            ["off"] => Some(IrAction::Off),
+            ["brighter"] => Some(IrAction::Brighter),
+            ["dimmer"] => Some(IrAction::Dimmer),
+            ["color", hex] if hex.len() == 6 || hex.len() == 8 => {
+                let channel = |i: usize| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok());
+                let w = if hex.len() == 8 { channel(6)? } else { 0 };
+                Some(IrAction::Color(Color::from_rgbw(channel(0)?, channel(2)?, channel(4)?, w)))
+            }
+            ["effect", effect] => Effect::from_u8(effect.parse().ok()?).map(IrAction::Effect),
+            ["preset", index] => index.parse::<u8>().ok().filter(|index| (*index as usize) < NUM_PRESETS).map(IrAction::Preset),
+            _ => None,
+        }
+    }
+
+    /// Held keys only step the brightness further.
+    fn repeats(&self) -> bool {
+        matches!(self, IrAction::Brighter | IrAction::Dimmer)
+    }
+}
+
+/// A learned key and its action.
+#[derive(Copy, Clone, PartialEq)]
+pub struct IrBinding {
+    pub code: Code,
+    pub action: IrAction,
+}
+
+pub type IrBindings = [Option<IrBinding>; NUM_IR_BINDINGS];
+
+impl IrBinding {
+    /// le_u16 address, command, action, then up to 4 bytes of its argument.
+    pub const SERIALIZED_LEN: usize = 8;
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0..2].copy_from_slice(&self.code.address.to_le_bytes());
+        bytes[2] = self.code.command;
+        bytes[3..8].fill(0);
+        match self.action {
+            IrAction::On => bytes[3] = 0,
+            IrAction::Off => bytes[3] = 1,
+            IrAction::Brighter => bytes[3] = 2,
+            IrAction::Dimmer => bytes[3] = 3,
+            IrAction::Color(c) => {
+                bytes[3] = 4;
+                bytes[4..8].copy_from_slice(&[c.r, c.g, c.b, c.w]);
+            }
+            IrAction::Effect(effect) => {
+                bytes[3] = 5;
+                bytes[4] = effect as u8;
+            }
+            IrAction::Preset(index) => {
+                bytes[3] = 6;
+                bytes[4] = index;
+            }
+        }
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let code = Code { address: u16::from_le_bytes([bytes[0], bytes[1]]), command: bytes[2] };
+        let action = match bytes[3] {
+            0 => IrAction::On,
+            1 => IrAction::Off,
+            2 => IrAction::Brighter,
+            3 => IrAction::Dimmer,
+            4 => IrAction::Color(Color::from_rgbw(bytes[4], bytes[5], bytes[6], bytes[7])),
+            5 => IrAction::Effect(Effect::from_u8(bytes[4])?),
+            6 if (bytes[4] as usize) < NUM_PRESETS => IrAction::Preset(bytes[4]),
+            _ => return None,
+        };
+        Some(Self { code, action })
+    }
+}
+
+/// Bindings loaded at boot, updated alongside their flash copy.
+static BINDINGS: Mutex<CriticalSectionRawMutex, Cell<IrBindings>> = Mutex::new(Cell::new([None; NUM_IR_BINDINGS]));
+/// Action for the next key received, set from the console.
+static LEARN: Signal<CriticalSectionRawMutex, IrAction> = Signal::new();
+
+pub fn set_bindings(bindings: IrBindings) {
+    BINDINGS.lock(|cell| cell.set(bindings));
+}
+
+/// Map the next key pressed to `action`, replacing what it did before.
+pub fn learn(action: IrAction) {
+    LEARN.signal(action);
+}
+
+pub fn forget_all() {
+    LEARN.reset();
+    set_bindings([None; NUM_IR_BINDINGS]);
+    storage::STORAGE_CHANNEL.sender().forget_ir_bindings();
+}
+
+/// One line per learned key, for the console.
+pub fn write_bindings<const N: usize>(out: &mut String<N>) {
+    let bindings = BINDINGS.lock(|cell| cell.get());
+    for IrBinding { code, action } in bindings.iter().flatten() {
+        uwrite!(out, "{:04x}:{:02x} ", code.address, code.command).ok();
+        match action {
+            IrAction::On => uwrite!(out, "on"),
+            IrAction::Off => uwrite!(out, "off"),
+            IrAction::Brighter => uwrite!(out, "brighter"),
+            IrAction::Dimmer => uwrite!(out, "dimmer"),
+            IrAction::Color(c) => uwrite!(out, "color {:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.w),
+            IrAction::Effect(effect) => uwrite!(out, "effect {}", *effect as u8),
+            IrAction::Preset(index) => uwrite!(out, "preset {}", index),
+        }
+        .ok();
+        out.push_str("\r\n").ok();
+    }
+}
+
+fn bind(code: Code, action: IrAction) {
+    let slot = BINDINGS.lock(|cell| {
+        let mut bindings = cell.get();
+        let slot = bindings
+            .iter()
+            .position(|binding| binding.is_some_and(|binding| binding.code == code))
+            .or_else(|| bindings.iter().position(Option::is_none))?;
+        bindings[slot] = Some(IrBinding { code, action });
+        cell.set(bindings);
+        Some(slot)
+    });
+    match slot {
+        Some(slot) => {
+            remote_log!(Info, "learned remote key {:04x}:{:02x}", code.address, code.command);
+            storage::STORAGE_CHANNEL.sender().save_ir_binding(slot as u8, IrBinding { code, action });
+        }
+        None => remote_log!(Warn, "no room to learn another remote key, forget them first"),
+    }
+}
+
+fn lookup(code: Code) -> Option<IrAction> {
+    BINDINGS.lock(|cell| cell.get().iter().flatten().find(|binding| binding.code == code).map(|binding| binding.action))
+}
+
+struct Remote {
+    sender: LedSender,
+    brightness_before_off: u8,
+    /// Action of the last key and when it or its last repeat arrived.
+    held: Option<(IrAction, Instant)>,
+}
+
+impl Remote {
+    fn apply(&mut self, action: IrAction) {
+        let brightness = leds::LED_STATUS.lock(|cell| cell.get()).brightness;
+        match action {
+            IrAction::On => {
+                if brightness == 0 {
+                    self.sender.set_brightness(self.brightness_before_off);
+                }
+            }
+            IrAction::Off => {
+                if brightness != 0 {
+                    self.brightness_before_off = brightness;
+                    self.sender.set_brightness(0);
+                }
+            }
+            IrAction::Brighter => self.sender.set_brightness(brightness.saturating_add(BRIGHTNESS_STEP)),
+            // Dimming stops short of off, which has its own key
+            IrAction::Dimmer if brightness != 0 => {
+                self.sender.set_brightness(brightness.saturating_sub(BRIGHTNESS_STEP).max(1));
+            }
+            IrAction::Dimmer => {}
+            IrAction::Color(color) => {
+                self.sender.set_effect(Effect::Static);
+                self.sender.set_primary_color(color);
+            }
+            IrAction::Effect(effect) => self.sender.set_effect(effect),
+            IrAction::Preset(index) => {
+                presets::recall(index as usize, &mut self.sender);
+            }
+        }
+    }
+
+    fn on_event(&mut self, event: Event) {
+        let now = Instant::now();
+        match event {
+            Event::Code(code) => {
+                debug!("remote key {:04x}:{:02x}", code.address, code.command);
+                self.held = None;
+                if let Some(action) = LEARN.try_take() {
+                    bind(code, action);
+                } else if let Some(action) = lookup(code) {
+                    self.apply(action);
+                    self.held = Some((action, now));
+                }
+            }
+            Event::Repeat => {
+                if let Some((action, last)) = self.held {
+                    if now - last < REPEAT_WINDOW && action.repeats() {
+                        self.apply(action);
+                        self.held = Some((action, now));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn run(&mut self, p: &mut IrPeripherals) -> ! {
+        let mut decoder = Decoder::new();
+        let mut last_edge = Instant::now();
+        loop {
+            p.ir.wait_for_any_edge().await;
+            let now = Instant::now();
+            let micros = (now - last_edge).as_micros().min(u32::MAX as u64) as u32;
+            last_edge = now;
+            // The output is low during a mark, so going high ends one
+            if let Some(event) = decoder.pulse(p.ir.is_high(), micros) {
+                self.on_event(event);
+            }
+        }
+    }
+}
+
+/// Decode NEC codes from an infrared receiver by timing the edges of its output, and run the
+/// actions learned for them.
+#[embassy_executor::task]
+pub async fn ir_task(mut p: IrPeripherals) -> ! {
+    info!("set up infrared receiver");
+    let mut remote = Remote { sender: leds::LED_CHANNEL.sender(), brightness_before_off: u8::MAX, held: None };
+    instrument::measured("ir", remote.run(&mut p)).await
+}
+
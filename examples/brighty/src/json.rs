@@ -0,0 +1,17 @@
+//! Just enough JSON reading for the flat documents of the WLED and Home Assistant APIs.
+
+/// Value following `"key":` in a JSON document, with leading whitespace removed. Only good
+/// enough for the flat keys of the WLED state subset, nested objects aren't tracked.
+#[cfg(any(feature = "wled", feature = "mqtt"))]
+pub fn json_value<'a>(json: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let pos = json
+        .windows(key.len() + 2)
+        .position(|w| w[0] == b'"' && &w[1..w.len() - 1] == key.as_bytes() && w[w.len() - 1] == b'"')?;
+    let value = json[pos + key.len() + 2..].trim_ascii_start().strip_prefix(b":")?;
+    Some(value.trim_ascii_start())
+}
+
+pub fn json_u8(value: &[u8]) -> Option<u8> {
+    let digits = value.iter().take_while(|c| c.is_ascii_digit()).count();
+    core::str::from_utf8(&value[..digits]).ok()?.parse::<u16>().ok().map(|v| v.min(255) as u8)
+}
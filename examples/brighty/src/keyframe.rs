@@ -0,0 +1,17 @@
+use crate::color::Color;
+
+pub use animation::KeyframeReader;
+
+/// Maximum number of keyframes in a single uploaded sequence.
+pub const MAX_SEQUENCE_KEYFRAMES: usize = 16;
+
+pub type Keyframe = animation::Keyframe<Color>;
+
+/// Keyframe sequence applied to LEDs `first_led..first_led + led_count`.
+#[derive(Copy, Clone)]
+pub struct KeyframeSequence {
+    pub first_led: u8,
+    pub led_count: u8,
+    pub keyframes: [Keyframe; MAX_SEQUENCE_KEYFRAMES],
+    pub len: u8,
+}
@@ -1,52 +1,231 @@
-use defmt::{assert, info, debug};
+use defmt::{assert, info, debug, warn};
 use embassy_futures::select;
-use embassy_rp::{dma, pio};
+use embassy_rp::dma::Channel as _;
+use embassy_rp::pio;
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Timer};
+use num::FromPrimitive;
 use num_derive::FromPrimitive;
-use crate::{consts, define_peripheral_set, Irqs};
-use crate::sk6812::PioSK6812;
+use sk6812::{PioSK6812, PioSK6812Multi, PioSK6812Program};
+use crate::{consts, define_peripheral_set, remote_log, sk6812_peripherals, Irqs};
+use animation::LedDriver;
 use crate::color::Color;
+use crate::keyframe::{Keyframe, KeyframeReader, KeyframeSequence};
+use crate::config;
+use crate::instrument;
+use crate::link::{self, NetHealth};
+use crate::palette::{Palette, Palettes, NUM_PALETTES};
+use crate::playlist::Playlist;
+use crate::power;
+use crate::schedule::{Schedule, ScheduledCommand};
+use crate::storage::{self, StorageSender};
+use crate::temperature;
+use crate::watchdog::{self, Subsystem};
 
-const LED_PERIOD: Duration = Duration::from_millis(20); // 50 Hz
+/// Time base of effect animations, independent of the frame rate.
+const ANIMATION_PERIOD: Duration = Duration::from_millis(20);
+/// Frames timed out in a row before the state machines are restarted.
+const MAX_FAILED_WRITES: u32 = 5;
+const DEFAULT_FRAME_RATE: u8 = 50;
+pub const MAX_FRAME_RATE: u8 = 100;
+const DEFAULT_BRIGHTNESS_FADE: Duration = Duration::from_millis(400);
+/// Sync clock errors beyond this are jumped instead of slewed, e.g. when first following.
+#[cfg(feature = "sync")]
+const CLOCK_STEP_THRESHOLD_MS: i64 = 1000;
+/// Fraction of the remaining clock error corrected per sync packet.
+#[cfg(feature = "sync")]
+const CLOCK_SLEW_DIVISOR: i64 = 4;
+/// Rough SK6812 supply current per color channel at full value, and per LED when dark.
+const CHANNEL_FULL_MA: u32 = 15;
+const LED_IDLE_MA: u32 = 1;
 
-pub const NUM_LEDS: usize = 10;
+/// Number of strips driven in parallel from PIO1, one per state machine, picked by the
+/// `strips-*` features.
+pub const NUM_STRIPS: usize = if cfg!(feature = "strips-4") {
+    4
+} else if cfg!(feature = "strips-3") {
+    3
+} else if cfg!(feature = "strips-2") {
+    2
+} else {
+    1
+};
+/// Capacity, `DeviceConfig::led_count` sets how many LEDs are actually fitted.
+pub const LEDS_PER_STRIP: usize = 10;
+pub const NUM_LEDS: usize = NUM_STRIPS * LEDS_PER_STRIP;
+/// Bytes of an LED bitmask, bit `i % 8` of byte `i / 8` selects LED `i`.
+pub const LED_MASK_LEN: usize = (NUM_LEDS + 7) / 8;
 
+/// Total number of keyframes held in RAM across all uploaded sequences.
+const KEYFRAME_POOL_LEN: usize = 256;
+
+/// Maximum number of bands in a streamed audio level frame.
+pub const MAX_AUDIO_BANDS: usize = 16;
+/// Audio reactive mode falls back to the previous effect when no levels arrive for this long.
+const AUDIO_HOLDOVER: Duration = Duration::from_millis(500);
+/// Per-tick decay of the displayed audio levels, giving a peak-hold look between frames.
+const AUDIO_DECAY: u8 = 12;
+
+/// Distance between lit LEDs in the chase effect.
+const CHASE_SPACING: usize = 4;
+/// Fire sparks ignite within this many LEDs of the start of the strip.
+const FIRE_SPARK_ZONE: usize = 7;
+
+/// Half period of the identify blink, alternate LEDs swap between white and dark.
+const IDENTIFY_BLINK: Duration = Duration::from_millis(200);
+const IDENTIFY_COLOR: Color = Color::from_rgbw(0, 0, 0, 255);
+
+/// Period and peak red level of the breathing shown while the network is out.
+const NET_OUTAGE_BREATH: Duration = Duration::from_millis(4000);
+const NET_OUTAGE_MAX_RED: u8 = 32;
+
+/// Sunrise duration when started through `SetEffect` instead of `StartSunrise`.
+const DEFAULT_SUNRISE_DURATION: Duration = Duration::from_secs(30 * 60);
+/// Sunrise color ramp, frames are progress in 1/10000ths of the duration.
+const SUNRISE_KEYFRAMES: [Keyframe; 5] = [
+    Keyframe { frame: 0, color: Color::from_rgbw(0, 0, 0, 0) },
+    Keyframe { frame: 2000, color: Color::from_rgbw(96, 4, 0, 0) },
+    Keyframe { frame: 4500, color: Color::from_rgbw(255, 60, 0, 0) },
+    Keyframe { frame: 7000, color: Color::from_rgbw(255, 150, 40, 40) },
+    Keyframe { frame: 10000, color: Color::from_rgbw(255, 190, 110, 255) },
+];
 
-#[macro_export]
-macro_rules! sk6812_peripherals {
-    ($macro_name:ident $(,$arg:tt)*) => {
-        $macro_name!{$($arg,)*
-            SK6812Peripherals,
-            dio: PIN_0,
-            pio: PIO1,
-            dma: DMA_CH1,
-        }
-    };
-}
 
 sk6812_peripherals!(define_peripheral_set);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 #[derive(FromPrimitive)]
 pub enum Effect {
     Static = 0,
     Rainbow = 1,
+    Keyframes = 2,
+    AudioReactive = 3,
+    Chase = 4,
+    Twinkle = 5,
+    Fire = 6,
+    Sunrise = 7,
+    Sunset = 8,
+}
+
+impl Effect {
+    /// Every effect, in the order of their numbers.
+    pub const ALL: [Effect; 9] = [
+        Effect::Static,
+        Effect::Rainbow,
+        Effect::Keyframes,
+        Effect::AudioReactive,
+        Effect::Chase,
+        Effect::Twinkle,
+        Effect::Fire,
+        Effect::Sunrise,
+        Effect::Sunset,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Static => "Static",
+            Effect::Rainbow => "Rainbow",
+            Effect::Keyframes => "Keyframes",
+            Effect::AudioReactive => "Audio reactive",
+            Effect::Chase => "Chase",
+            Effect::Twinkle => "Twinkle",
+            Effect::Fire => "Fire",
+            Effect::Sunrise => "Sunrise",
+            Effect::Sunset => "Sunset",
+        }
+    }
+
+    /// Whether the effect runs by itself once picked, rather than on a stream it waits for.
+    pub fn standalone(self) -> bool {
+        !matches!(self, Effect::AudioReactive)
+    }
+}
+
+/// Tuning shared by all effects, each effect interprets the fields its own way:
+///
+/// * `speed`: animation rate for Rainbow, Chase and Twinkle fade
+/// * `intensity`: Twinkle density, Fire sparking
+/// * `option`: Fire cooling, `STATIC_COLOR_CYCLE` flag for Static
+/// * `palette`: palette to sample instead of the raw HSV wheel
+#[derive(Copy, Clone, PartialEq)]
+pub struct EffectParams {
+    pub speed: u16,
+    pub intensity: u8,
+    pub option: u8,
+    pub palette: Option<u8>,
+}
+
+/// Static effect option flag: rotate the primary color's hue at `speed`.
+pub const STATIC_COLOR_CYCLE: u8 = 0x01;
+
+impl EffectParams {
+    pub const DEFAULT: EffectParams = EffectParams {
+        speed: 32768,
+        intensity: 128,
+        option: 64,
+        palette: None,
+    };
+}
+
+/// Externally streamed LED colors that override effects until `timeout` passes without a new
+/// frame. LEDs left as `None` keep their previous realtime color.
+#[derive(Copy, Clone)]
+pub struct RealtimeFrame {
+    pub colors: [Option<Color>; NUM_LEDS],
+    pub timeout: Duration,
+}
+
+impl RealtimeFrame {
+    /// Ends the realtime override right away, e.g. when a stream is terminated by its source.
+    pub const STOP: RealtimeFrame = RealtimeFrame {
+        colors: [None; NUM_LEDS],
+        timeout: Duration::from_ticks(0),
+    };
+}
+
+/// One frame of streamed audio band magnitudes, lowest band first.
+#[derive(Copy, Clone)]
+pub struct AudioLevels {
+    pub bands: [u8; MAX_AUDIO_BANDS],
+    pub len: u8,
 }
 
 #[derive(Copy, Clone)]
 pub enum LedCommand {
     SetColorList([Color; NUM_LEDS]),
+    /// Set the LEDs selected by the mask, leaving the others untouched.
+    SetMaskedColor([u8; LED_MASK_LEN], Color),
+    SetRealtime(RealtimeFrame),
     ShiftColor(Color),
     SetPrimaryColor(Color),
     SetEffect(Effect),
     SetEffectSpeed(u16),
+    SetEffectParams(EffectParams),
     SetBrightness(u8),
+    SetBrightnessFade(Duration),
+    SetFrameRate(u8),
+    SetKeyframes(KeyframeSequence),
+    ClearKeyframes,
+    SetAudioLevels(AudioLevels),
+    StartSunrise { sunset: bool, duration: Duration },
+    /// Animation clock offset from `Instant::now()` a sync leader asks for, in milliseconds.
+    #[cfg(feature = "sync")]
+    SyncClock(i64),
+    SetPlaylist(Playlist),
+    SetPalette(u8, Palette),
+    SelectPalette(Option<u8>),
+    Schedule(ScheduledCommand),
+    ClearSchedule,
+    /// Blink a pattern over the current effect for this long, zero stops it early.
+    Identify(Duration),
+    /// Render again, e.g. after the output settings in `config::DEVICE_CONFIG` changed.
+    Refresh,
 }
 
-unsafe impl Send for LedCommand {}
-
 pub type LedReceiver = Receiver<'static, CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>;
 
 pub struct LedSender(Sender<'static, CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>);
@@ -59,6 +238,15 @@ impl LedSender {
         self.0.try_send(LedCommand::SetColorList(color_list)).ok();
     }
 
+    pub fn set_masked_color(&mut self, mask: [u8; LED_MASK_LEN], color: Color) {
+        self.0.try_send(LedCommand::SetMaskedColor(mask, color)).ok();
+    }
+
+    pub fn set_realtime(&mut self, frame: RealtimeFrame) {
+        power::note_activity();
+        self.0.try_send(LedCommand::SetRealtime(frame)).ok();
+    }
+
     pub fn shift_color(&mut self, color: Color) {
         self.0.try_send(LedCommand::ShiftColor(color)).ok();
     }
@@ -75,9 +263,71 @@ impl LedSender {
         self.0.try_send(LedCommand::SetEffectSpeed(effect_speed)).ok();
     }
 
+    pub fn set_effect_params(&mut self, params: EffectParams) {
+        self.0.try_send(LedCommand::SetEffectParams(params)).ok();
+    }
+
     pub fn set_brightness(&mut self, brightness: u8) {
         self.0.try_send(LedCommand::SetBrightness(brightness)).ok();
     }
+
+    pub fn set_brightness_fade(&mut self, fade: Duration) {
+        self.0.try_send(LedCommand::SetBrightnessFade(fade)).ok();
+    }
+
+    pub fn set_frame_rate(&mut self, fps: u8) {
+        self.0.try_send(LedCommand::SetFrameRate(fps)).ok();
+    }
+
+    pub fn set_keyframes(&mut self, sequence: KeyframeSequence) {
+        self.0.try_send(LedCommand::SetKeyframes(sequence)).ok();
+    }
+
+    pub fn clear_keyframes(&mut self) {
+        self.0.try_send(LedCommand::ClearKeyframes).ok();
+    }
+
+    pub fn set_audio_levels(&mut self, levels: AudioLevels) {
+        power::note_activity();
+        self.0.try_send(LedCommand::SetAudioLevels(levels)).ok();
+    }
+
+    pub fn start_sunrise(&mut self, sunset: bool, duration: Duration) {
+        self.0.try_send(LedCommand::StartSunrise { sunset, duration }).ok();
+    }
+
+    #[cfg(feature = "sync")]
+    pub fn sync_clock(&mut self, offset_ms: i64) {
+        self.0.try_send(LedCommand::SyncClock(offset_ms)).ok();
+    }
+
+    pub fn set_playlist(&mut self, playlist: Playlist) {
+        self.0.try_send(LedCommand::SetPlaylist(playlist)).ok();
+    }
+
+    pub fn set_palette(&mut self, index: u8, palette: Palette) {
+        self.0.try_send(LedCommand::SetPalette(index, palette)).ok();
+    }
+
+    pub fn select_palette(&mut self, index: Option<u8>) {
+        self.0.try_send(LedCommand::SelectPalette(index)).ok();
+    }
+
+    pub fn schedule(&mut self, command: ScheduledCommand) {
+        self.0.try_send(LedCommand::Schedule(command)).ok();
+    }
+
+    pub fn clear_schedule(&mut self) {
+        self.0.try_send(LedCommand::ClearSchedule).ok();
+    }
+
+    pub fn identify(&mut self, duration: Duration) {
+        self.0.try_send(LedCommand::Identify(duration)).ok();
+    }
+
+    pub fn refresh(&mut self) {
+        self.0.try_send(LedCommand::Refresh).ok();
+    }
 }
 
 pub struct LedChannel(Channel<CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>);
@@ -87,217 +337,774 @@ impl LedChannel {
         Self(Channel::new())
     }
 
-    pub fn sender(&'static mut self) -> LedSender {
+    pub fn sender(&'static self) -> LedSender {
         LedSender(self.0.sender())
     }
 
-    pub fn receiver(&'static mut self) -> LedReceiver {
+    pub fn receiver(&'static self) -> LedReceiver {
         self.0.receiver()
     }
 }
 
 const CHANNEL_BUF_LEN: usize = 64;
-pub(crate) static mut LED_CHANNEL: LedChannel = LedChannel::new();
-
+pub(crate) static LED_CHANNEL: LedChannel = LedChannel::new();
 
-#[derive(Copy, Clone)]
-pub struct Keyframe {
-    pub(crate) frame: u32,
-    pub(crate) color: Color,
+/// Snapshot of the LED state, published by the LED task for status queries from core0 and
+/// persisted so it survives a power cycle.
+#[derive(Copy, Clone, PartialEq)]
+pub struct LedStatus {
+    pub effect: Effect,
+    pub primary_color: Color,
+    /// Brightness being faded towards, not the momentary value.
+    pub brightness: u8,
+    pub params: EffectParams,
 }
 
-#[derive(Copy, Clone)]
-struct KeyframeReader {
-    keyframes: &'static [Keyframe],
-    last_frame: u32,
-    frame_a: u32,
-    frame_b: u32,
-    ib: usize,
-}
+impl LedStatus {
+    pub const SERIALIZED_LEN: usize = 11;
 
-impl Default for KeyframeReader {
-    fn default() -> Self {
-        static DEFAULT_KEYFRAMES: [Keyframe; 0] = [];
-        Self {
-            keyframes: &DEFAULT_KEYFRAMES,
-            last_frame: 0,
-            frame_a: 0,
-            frame_b: 0,
-            ib: 1,
-        }
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        let c = self.primary_color;
+        bytes[0] = self.effect as u8;
+        bytes[1..5].copy_from_slice(&[c.r, c.g, c.b, c.w]);
+        bytes[5] = self.brightness;
+        bytes[6..8].copy_from_slice(&self.params.speed.to_le_bytes());
+        bytes[8] = self.params.intensity;
+        bytes[9] = self.params.option;
+        bytes[10] = self.params.palette.unwrap_or(0xFF);
     }
-}
 
-impl KeyframeReader {
-    pub fn set_keyframes(&mut self, keyframes: &'static [Keyframe]) {
-        self.keyframes = keyframes;
-
-        self.last_frame = if let Some(kf) = keyframes.last() { kf.frame } else { 0 };
-
-        self.frame_a = if let Some(kf) = keyframes.get(0) { kf.frame } else { 0 };
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            effect: Effect::from_u8(bytes[0])?,
+            primary_color: Color::from_rgbw(bytes[1], bytes[2], bytes[3], bytes[4]),
+            brightness: bytes[5],
+            params: EffectParams {
+                speed: u16::from_le_bytes([bytes[6], bytes[7]]),
+                intensity: bytes[8],
+                option: bytes[9],
+                palette: (bytes[10] != 0xFF).then_some(bytes[10]),
+            },
+        })
+    }
+}
 
-        self.frame_b = if let Some(kf) = keyframes.get(1) {
-            kf.frame
-        } else {
-            self.frame_a
-        };
+pub static LED_STATUS: Mutex<CriticalSectionRawMutex, Cell<LedStatus>> = Mutex::new(Cell::new(LedStatus {
+    effect: Effect::Static,
+    primary_color: Color::BLACK,
+    brightness: 0,
+    params: EffectParams::DEFAULT,
+}));
 
-        self.ib = 1;
-    }
+/// Failed LED frames and state machine restarts since boot.
+#[derive(Copy, Clone, Default)]
+pub struct OutputErrors {
+    pub failed_writes: u32,
+    pub reinits: u32,
+}
 
-    pub fn evaluate_color_at_frame(&mut self, frame: u64) -> Color {
-        if self.keyframes.is_empty() {
-            return Color { r: 0, g: 0, b: 0, w: 0 };
-        } else if self.keyframes.len() == 1 {
-            return unsafe { self.keyframes.get_unchecked(0).color };
-        }
+static OUTPUT_ERRORS: Mutex<CriticalSectionRawMutex, Cell<OutputErrors>> =
+    Mutex::new(Cell::new(OutputErrors { failed_writes: 0, reinits: 0 }));
 
-        let mod_frame = (frame % self.last_frame as u64) as u32;
-        if mod_frame < self.frame_a {
-            self.ib = 1;
-            self.frame_a = self.keyframes[self.ib - 1].frame;
-            self.frame_b = self.keyframes[self.ib].frame;
-        }
-        if mod_frame >= self.frame_b {
-            self.ib += 1;
-            while self.keyframes[self.ib].frame < mod_frame {
-                self.ib += 1;
-            }
-            self.frame_a = self.keyframes[self.ib - 1].frame;
-            self.frame_b = self.keyframes[self.ib].frame;
-        }
+pub fn output_errors() -> OutputErrors {
+    OUTPUT_ERRORS.lock(|cell| cell.get())
+}
 
-        let ka = &self.keyframes[self.ib - 1];
-        let kb = &self.keyframes[self.ib];
-        let seg_duration = kb.frame - ka.frame;
-        core::assert!(seg_duration > 0);
-        let seg_instant = mod_frame - ka.frame;
+/// Signalled whenever `LED_STATUS` changes, whichever source caused it.
+pub static STATUS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-        let r = (kb.color.r as u32 * seg_instant + ka.color.r as u32 * (seg_duration - seg_instant)) / seg_duration;
-        let g = (kb.color.g as u32 * seg_instant + ka.color.g as u32 * (seg_duration - seg_instant)) / seg_duration;
-        let b = (kb.color.b as u32 * seg_instant + ka.color.b as u32 * (seg_duration - seg_instant)) / seg_duration;
-        let w = (kb.color.w as u32 * seg_instant + ka.color.w as u32 * (seg_duration - seg_instant)) / seg_duration;
-        debug!("{} [{},{}]: ({} {} {} {})", mod_frame, self.ib - 1, self.ib, r, g, b, w);
+/// Offset of the animation clock from `Instant::now()`, moved by multicast sync.
+static CLOCK_OFFSET_MS: Mutex<CriticalSectionRawMutex, Cell<i64>> = Mutex::new(Cell::new(0));
 
-        Color {
-            r: r as u8,
-            g: g as u8,
-            b: b as u8,
-            w: w as u8,
-        }
-    }
+/// Animation clock in milliseconds, the time base effects are rendered from.
+pub fn animation_millis() -> i64 {
+    Instant::now().as_millis() as i64 + CLOCK_OFFSET_MS.lock(|cell| cell.get())
 }
 
-struct Leds<'d, PIO: pio::Instance, const SM: usize, DMA: dma::Channel> {
-    sk6812: PioSK6812<'d, PIO, SM, DMA>,
+
+struct Leds {
     keyframe_readers: [KeyframeReader; NUM_LEDS],
-    buffer: [u32; NUM_LEDS],
+    keyframe_pool: heapless::Vec<Keyframe, KEYFRAME_POOL_LEN>,
+    /// Colors from realtime streams and color list commands, shown instead of the effect while
+    /// `realtime_active`.
+    realtime: [Color; NUM_LEDS],
     primary_color: Color,
+    primary_hsv: (u16, u8, u8),
     effect: Effect,
-    effect_speed: u16,
+    params: EffectParams,
+    /// Output brightness, ramped towards `brightness_target` over `brightness_fade`.
     brightness: u8,
+    brightness_from: u8,
+    brightness_target: u8,
+    brightness_fade_start: Instant,
+    brightness_fade: Duration,
+    frame_period: Duration,
+    /// Effect time and wall time of the frame being rendered, the same for every LED in it.
+    cur_period: u64,
+    frame_time: Instant,
+    realtime_active: bool,
+    realtime_until: Instant,
+    identify_until: Instant,
+    /// Whether the network outage overlay is shown, tracking `link::net_health`.
+    net_outage: bool,
+    /// Scale applied while running hot, tracking `temperature::derating`.
+    derating: u8,
+    /// Set by any command so the next tick renders even if the effect is idle.
+    frame_dirty: bool,
+    last_frame_hash: u32,
+    audio_levels: [u8; MAX_AUDIO_BANDS],
+    audio_bands: usize,
+    last_audio_instant: Instant,
+    audio_fallback_effect: Effect,
+    palettes: Palettes,
+    storage_sender: StorageSender,
+    /// Last state handed to storage, which debounces the actual flash writes.
+    saved_status: Option<LedStatus>,
+    twinkle_levels: [u8; NUM_LEDS],
+    twinkle_hues: [u16; NUM_LEDS],
+    fire_heat: [u8; NUM_LEDS],
+    sunrise_reader: KeyframeReader,
+    sunrise_start: Instant,
+    sunrise_duration: Duration,
+    playlist: Playlist,
+    /// Cleared when the controller picks an effect directly, until a new playlist is sent.
+    playlist_active: bool,
+    playlist_index: usize,
+    playlist_entry_start: Instant,
+    /// Effect of the previous playlist entry, still rendered and blended out over `crossfade`.
+    crossfade_effect: Effect,
+    crossfade_params: EffectParams,
+    crossfade_start: Instant,
+    crossfade: Duration,
+    schedule: Schedule,
+    rng: u32,
+    /// Frames timed out in a row, the state machines are restarted after `MAX_FAILED_WRITES`.
+    failed_writes: u32,
 }
 
 const BRIGHTNESS_INTERP_MUL: u32 = 1;
 const BRIGHTNESS_MAX: u32 = 31;
 const BRIGHTNESS_MIN: u32 = 1;
 
-impl<'d, PIO: pio::Instance, const SM: usize, DMA: dma::Channel> Leds<'d, PIO, SM, DMA> {
-    pub fn new(sk6812: PioSK6812<'d, PIO, SM, DMA>) -> Self {
+impl Leds {
+    pub fn new(palettes: Palettes, playlist: Playlist, saved: Option<LedStatus>) -> Self {
         let mut keyframe_readers: [KeyframeReader; NUM_LEDS] = [Default::default(); NUM_LEDS];
+        let mut sunrise_reader = KeyframeReader::default();
+        sunrise_reader.set_keyframes(&SUNRISE_KEYFRAMES, 0, SUNRISE_KEYFRAMES.len());
 
-        Self {
-            sk6812,
+        let mut leds = Self {
             keyframe_readers,
-            buffer: [0; NUM_LEDS],
+            keyframe_pool: heapless::Vec::new(),
+            realtime: [Color::BLACK; NUM_LEDS],
             primary_color: Color::BLACK,
+            primary_hsv: (0, 0, 0),
             effect: Effect::Static,
-            effect_speed: 32768,
+            params: EffectParams::DEFAULT,
             brightness: 255,
+            brightness_from: 255,
+            brightness_target: 255,
+            brightness_fade_start: Instant::MIN,
+            brightness_fade: DEFAULT_BRIGHTNESS_FADE,
+            frame_period: Duration::from_hz(DEFAULT_FRAME_RATE as u64),
+            cur_period: 0,
+            frame_time: Instant::MIN,
+            realtime_active: false,
+            realtime_until: Instant::MIN,
+            identify_until: Instant::MIN,
+            net_outage: false,
+            derating: 255,
+            frame_dirty: true,
+            last_frame_hash: 0,
+            audio_levels: [0; MAX_AUDIO_BANDS],
+            audio_bands: 1,
+            last_audio_instant: Instant::MIN,
+            audio_fallback_effect: Effect::Static,
+            palettes,
+            storage_sender: storage::STORAGE_CHANNEL.sender(),
+            saved_status: saved,
+            twinkle_levels: [0; NUM_LEDS],
+            twinkle_hues: [0; NUM_LEDS],
+            fire_heat: [0; NUM_LEDS],
+            sunrise_reader,
+            sunrise_start: Instant::MIN,
+            sunrise_duration: DEFAULT_SUNRISE_DURATION,
+            playlist,
+            playlist_active: playlist.len > 0,
+            playlist_index: 0,
+            playlist_entry_start: Instant::MIN,
+            crossfade_effect: Effect::Static,
+            crossfade_params: EffectParams::DEFAULT,
+            crossfade_start: Instant::MIN,
+            crossfade: Duration::from_millis(playlist.crossfade_ms as u64),
+            schedule: Schedule::new(),
+            rng: 0x2545F491,
+            failed_writes: 0,
+        };
+        if let Some(saved) = saved {
+            leds.restore(&saved);
+        }
+        if let Some(effect) = config::get().default_effect {
+            leds.set_effect(effect);
+        }
+        if leds.playlist_active {
+            leds.play_entry(0);
         }
+        leds
+    }
+
+    fn restore(&mut self, saved: &LedStatus) {
+        self.primary_color = saved.primary_color;
+        self.primary_hsv = saved.primary_color.to_hsv();
+        self.params = saved.params;
+        // Fade in from black like a manual brightness change
+        self.brightness = 0;
+        self.brightness_from = 0;
+        self.brightness_target = saved.brightness;
+        self.brightness_fade_start = Instant::now();
+        self.set_effect(saved.effect);
     }
 
     pub async fn process_command(&mut self, cmd: &LedCommand) {
+        self.frame_dirty = true;
         match cmd {
+            LedCommand::SetRealtime(frame) => {
+                for (led, color) in self.realtime.iter_mut().zip(frame.colors.iter()) {
+                    if let Some(color) = color {
+                        *led = color.with_brightness(self.brightness);
+                    }
+                }
+                self.realtime_active = true;
+                self.realtime_until = Instant::now().checked_add(frame.timeout).unwrap_or(Instant::MAX);
+            }
             LedCommand::SetColorList(color_list) => {
                 for (idx, color) in color_list.iter().enumerate() {
-                    self.buffer[idx] = color.encode_for_sk6812();
+                    self.realtime[idx] = *color;
+                }
+            }
+            LedCommand::SetMaskedColor(mask, color) => {
+                for (idx, led) in self.realtime.iter_mut().enumerate() {
+                    if mask[idx / 8] & (1 << (idx % 8)) != 0 {
+                        *led = *color;
+                    }
                 }
             }
             LedCommand::ShiftColor(color) => {
                 for i in (1..NUM_LEDS).rev() {
-                    self.buffer[i] = self.buffer[i-1];
+                    self.realtime[i] = self.realtime[i-1];
                 }
-                self.buffer[0] = color.encode_for_sk6812();
+                self.realtime[0] = *color;
             }
             LedCommand::SetPrimaryColor(color) => {
                 self.primary_color = *color;
+                self.primary_hsv = color.to_hsv();
             }
             LedCommand::SetEffect(effect) => {
-                self.effect = *effect;
+                self.playlist_active = false;
+                self.realtime_active = false;
+                self.set_effect(*effect);
+            }
+            LedCommand::StartSunrise { sunset, duration } => {
+                self.playlist_active = false;
+                self.start_sunrise(*sunset, *duration);
+            }
+            #[cfg(feature = "sync")]
+            LedCommand::SyncClock(offset_ms) => {
+                let current = CLOCK_OFFSET_MS.lock(|cell| cell.get());
+                let error = offset_ms - current;
+                // Slewing keeps followers from visibly jumping on every packet's network jitter
+                let offset = if error.abs() > CLOCK_STEP_THRESHOLD_MS { *offset_ms } else { current + error / CLOCK_SLEW_DIVISOR };
+                CLOCK_OFFSET_MS.lock(|cell| cell.set(offset));
+            }
+            LedCommand::SetPlaylist(playlist) => {
+                self.playlist = *playlist;
+                self.playlist_active = playlist.len > 0;
+                self.crossfade = Duration::from_millis(playlist.crossfade_ms as u64);
+                if self.playlist_active {
+                    self.play_entry(0);
+                }
             }
             LedCommand::SetEffectSpeed(effect_speed) => {
-                self.effect_speed = *effect_speed;
+                self.params.speed = *effect_speed;
+            }
+            LedCommand::SetEffectParams(params) => {
+                self.params = *params;
+                self.params.palette = params.palette.filter(|index| (*index as usize) < NUM_PALETTES);
             }
             LedCommand::SetBrightness(brightness) => {
-                self.brightness = *brightness;
+                self.brightness_from = self.brightness;
+                self.brightness_target = *brightness;
+                self.brightness_fade_start = Instant::now();
+            }
+            LedCommand::SetBrightnessFade(fade) => {
+                self.brightness_fade = *fade;
+            }
+            LedCommand::SetFrameRate(fps) => {
+                self.frame_period = Duration::from_hz((*fps).clamp(1, MAX_FRAME_RATE) as u64);
             }
+            LedCommand::SetKeyframes(sequence) => {
+                let start = self.keyframe_pool.len();
+                let len = sequence.len as usize;
+                if self.keyframe_pool.extend_from_slice(&sequence.keyframes[..len]).is_err() {
+                    warn!("keyframe pool full, clear it before uploading more sequences");
+                    return;
+                }
+                let first_led = sequence.first_led as usize;
+                let last_led = NUM_LEDS.min(first_led + sequence.led_count as usize);
+                for reader in self.keyframe_readers[first_led.min(last_led)..last_led].iter_mut() {
+                    reader.set_keyframes(&self.keyframe_pool, start, len);
+                }
+            }
+            LedCommand::ClearKeyframes => {
+                self.keyframe_pool.clear();
+                self.keyframe_readers = [Default::default(); NUM_LEDS];
+            }
+            LedCommand::SetAudioLevels(levels) => {
+                // A live stream switches to audio reactive mode on its own
+                self.enter_audio_reactive();
+                self.audio_bands = (levels.len as usize).clamp(1, MAX_AUDIO_BANDS);
+                for (level, new_level) in self.audio_levels.iter_mut().zip(&levels.bands[..levels.len as usize]) {
+                    *level = (*level).max(*new_level);
+                }
+                self.last_audio_instant = Instant::now();
+            }
+            LedCommand::SetPalette(index, palette) => {
+                if let Some(stored) = self.palettes.get_mut(*index as usize) {
+                    *stored = *palette;
+                }
+            }
+            LedCommand::SelectPalette(index) => {
+                self.params.palette = index.filter(|index| (*index as usize) < NUM_PALETTES);
+            }
+            LedCommand::Schedule(command) => {
+                self.schedule.add(*command);
+            }
+            LedCommand::ClearSchedule => {
+                self.schedule.clear();
+            }
+            LedCommand::Identify(duration) => {
+                self.identify_until = Instant::now().checked_add(*duration).unwrap_or(Instant::MAX);
+            }
+            LedCommand::Refresh => {}
+        }
+    }
+
+    /// Effect color at `hue`, taken from `palette` or the raw HSV wheel.
+    fn sample_color(&self, palette: Option<u8>, hue: u16, val: u8) -> Color {
+        match palette {
+            Some(index) => self.palettes[index as usize].sample(hue).with_brightness(val),
+            None => Color::from_hsv(hue, 255, val),
         }
     }
 
-    pub async fn tick(&mut self) {
-        let cur_period = Instant::now().as_ticks() / LED_PERIOD.as_ticks();
+    fn update_brightness(&mut self) {
+        let elapsed = self.brightness_fade_start.elapsed().as_millis();
+        let fade = self.brightness_fade.as_millis();
+        self.brightness = if elapsed >= fade {
+            self.brightness_target
+        } else {
+            let from = self.brightness_from as i64;
+            let to = self.brightness_target as i64;
+            (from + (to - from) * elapsed as i64 / fade as i64) as u8
+        };
+    }
 
-        match self.effect {
-            Effect::Static => {
-                let encoded_color = self.primary_color.with_brightness(self.brightness).encode_for_sk6812();
+    /// True when rendering again would produce the same frame as last tick.
+    fn is_idle(&self) -> bool {
+        matches!(self.effect, Effect::Static)
+            && self.params.option & STATIC_COLOR_CYCLE == 0
+            && self.brightness == self.brightness_target
+            && !self.playlist_active
+            && Instant::now() >= self.identify_until
+            && !self.net_outage
+    }
+
+    fn hash_color(hash: u32, color: Color) -> u32 {
+        // FNV-1a, a word per LED
+        (hash ^ u32::from_be_bytes([color.r, color.g, color.b, color.w])).wrapping_mul(0x01000193)
+    }
+
+    /// Channel scale as (numerator, denominator) bringing a frame whose channels sum to
+    /// `channel_sum` within the configured power limit, `None` when it already is.
+    fn power_limit(config: &config::DeviceConfig, led_count: usize, channel_sum: u32) -> Option<(u32, u32)> {
+        if config.max_power_ma == 0 {
+            return None;
+        }
+        let idle_ma = LED_IDLE_MA * led_count as u32;
+        let channel_ma = channel_sum * CHANNEL_FULL_MA / 255;
+        let budget_ma = (config.max_power_ma as u32).saturating_sub(idle_ma);
+        (channel_ma > budget_ma).then_some((budget_ma, channel_ma))
+    }
+
+    /// A rendered color as the fitted strip wants it: scaled down to the power limit and while
+    /// running hot, channels in the strip's color order.
+    fn output_color(color: Color, power_limit: Option<(u32, u32)>, derating: u8, color_order: config::ColorOrder) -> Color {
+        let mut color = color;
+        if let Some((budget_ma, channel_ma)) = power_limit {
+            color = color.map_channels(|c| (c as u32 * budget_ma / channel_ma) as u8);
+        }
+        if derating < 255 {
+            color = color.map_channels(|c| (c as u32 * derating as u32 / 255) as u8);
+        }
+        if color_order != config::ColorOrder::Grb {
+            color = color_order.apply(color);
+        }
+        color
+    }
+
+    fn publish_status(&mut self) {
+        let status = LedStatus {
+            effect: self.effect,
+            primary_color: self.primary_color,
+            brightness: self.brightness_target,
+            params: self.params,
+        };
+        if LED_STATUS.lock(|cell| cell.replace(status)) != status {
+            STATUS_CHANGED.signal(());
+        }
+
+        // Audio streams are transient, keep the effect they interrupted
+        if !matches!(status.effect, Effect::AudioReactive) && self.saved_status != Some(status) {
+            self.storage_sender.save_led_state(status);
+            self.saved_status = Some(status);
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn set_effect(&mut self, effect: Effect) {
+        match effect {
+            Effect::AudioReactive => self.enter_audio_reactive(),
+            Effect::Sunrise | Effect::Sunset => {
+                self.start_sunrise(matches!(effect, Effect::Sunset), DEFAULT_SUNRISE_DURATION)
+            }
+            _ => self.effect = effect,
+        }
+    }
+
+    fn play_entry(&mut self, index: usize) {
+        let entry = self.playlist.entries[index];
+        self.playlist_index = index;
+        self.playlist_entry_start = Instant::now();
+        self.crossfade_effect = self.effect;
+        self.crossfade_params = self.params;
+        self.crossfade_start = Instant::now();
+        self.params = entry.params;
+        if matches!(self.effect, Effect::AudioReactive) {
+            // Keep reacting to sound, the entry takes over once the stream stops
+            self.audio_fallback_effect = entry.effect;
+        } else {
+            self.set_effect(entry.effect);
+        }
+    }
+
+    fn advance_playlist(&mut self) {
+        if !self.playlist_active {
+            return;
+        }
+        let entry = &self.playlist.entries[self.playlist_index];
+        if self.playlist_entry_start.elapsed() >= Duration::from_secs(entry.duration_s as u64) {
+            self.play_entry((self.playlist_index + 1) % self.playlist.len as usize);
+        }
+    }
+
+    /// How far this frame is into the crossfade from the previous playlist entry, out of 256.
+    fn crossfade_mix(&self) -> Option<u32> {
+        let elapsed = self.frame_time.saturating_duration_since(self.crossfade_start).as_millis();
+        let crossfade = self.crossfade.as_millis();
+        (elapsed < crossfade).then(|| (elapsed * 256 / crossfade) as u32)
+    }
+
+    fn start_sunrise(&mut self, sunset: bool, duration: Duration) {
+        self.effect = if sunset { Effect::Sunset } else { Effect::Sunrise };
+        self.sunrise_start = Instant::now();
+        self.sunrise_duration = duration;
+    }
+
+    fn enter_audio_reactive(&mut self) {
+        if !matches!(self.effect, Effect::AudioReactive) {
+            self.audio_fallback_effect = self.effect;
+            self.effect = Effect::AudioReactive;
+            self.last_audio_instant = Instant::now();
+        }
+    }
+
+    /// Advance the state `effect` keeps between frames, before its LEDs are rendered.
+    fn step_effect(&mut self, effect: Effect, params: EffectParams) {
+        match effect {
+            Effect::Twinkle => {
+                let fade = (params.speed >> 11) as u8 + 1;
+                for level in self.twinkle_levels.iter_mut() {
+                    *level = level.saturating_sub(fade);
+                }
+                if self.next_random() % 1024 < params.intensity as u32 {
+                    let i = self.next_random() as usize % NUM_LEDS;
+                    if self.twinkle_levels[i] == 0 {
+                        self.twinkle_levels[i] = 255;
+                        self.twinkle_hues[i] = self.next_random() as u16;
+                    }
+                }
+            }
+            Effect::Fire => {
+                // Fire2012: cool every cell, let heat drift up the strip, then spark near the base
+                let max_cooling = (params.option as u32 * 10) / NUM_LEDS as u32 + 2;
                 for i in 0..NUM_LEDS {
-                    self.buffer[i] = encoded_color;
+                    let cooling = (self.next_random() % max_cooling) as u8;
+                    self.fire_heat[i] = self.fire_heat[i].saturating_sub(cooling);
+                }
+                for i in (2..NUM_LEDS).rev() {
+                    self.fire_heat[i] =
+                        ((self.fire_heat[i - 1] as u16 + 2 * self.fire_heat[i - 2] as u16) / 3) as u8;
                 }
+                if self.next_random() % 256 < params.intensity as u32 {
+                    let i = self.next_random() as usize % FIRE_SPARK_ZONE.min(NUM_LEDS);
+                    let spark = 160 + (self.next_random() % 96) as u8;
+                    self.fire_heat[i] = self.fire_heat[i].saturating_add(spark);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Color of LED `i` under `effect` this frame.
+    fn effect_color(&mut self, effect: Effect, params: EffectParams, i: usize) -> Color {
+        const LED_OFFSET: u32 = 0x10000_u32 / NUM_LEDS as u32;
+        match effect {
+            Effect::Static => {
+                let color = if params.option & STATIC_COLOR_CYCLE != 0 {
+                    let (hue, sat, val) = self.primary_hsv;
+                    // 16x slower than Rainbow for a low-key ambient drift
+                    let offset = ((self.cur_period * params.speed as u64 / 1024) % 0x10000) as u16;
+                    Color { w: self.primary_color.w, ..Color::from_hsv(hue.wrapping_add(offset), sat, val) }
+                } else {
+                    self.primary_color
+                };
+                color.with_brightness(self.brightness)
             }
             Effect::Rainbow => {
-                let base = ((cur_period * self.effect_speed as u64 / 64) % 0x10000) as u32;
-                const LED_OFFSET: u32 = 0x10000_u32 / NUM_LEDS as u32;
-                for i in 0..NUM_LEDS {
-                    self.buffer[i] = Color::from_hsv(((base + LED_OFFSET * i as u32) % 0x10000) as u16, 255, self.brightness).encode_for_sk6812();
+                let base = ((self.cur_period * params.speed as u64 / 64) % 0x10000) as u32;
+                self.sample_color(params.palette, ((base + LED_OFFSET * i as u32) % 0x10000) as u16, self.brightness)
+            }
+            Effect::Keyframes => {
+                // Keyframe frame numbers are milliseconds
+                let frame = self.cur_period * ANIMATION_PERIOD.as_millis();
+                self.keyframe_readers[i]
+                    .evaluate_color_at_frame(&self.keyframe_pool, frame)
+                    .with_brightness(self.brightness)
+            }
+            Effect::AudioReactive => {
+                // Bands are spread evenly across the strip, hue follows LED position
+                let level = self.audio_levels[i * self.audio_bands / NUM_LEDS] as u16;
+                let val = ((level * (self.brightness as u16 + 1)) >> 8) as u8;
+                Color::from_hsv((LED_OFFSET * i as u32) as u16, 255, val)
+            }
+            Effect::Chase => {
+                let pos = self.cur_period * params.speed as u64 / 64;
+                let step = (pos / 0x1000) as usize % CHASE_SPACING;
+                if (i + CHASE_SPACING - step) % CHASE_SPACING == 0 {
+                    self.sample_color(params.palette, ((pos as u32 + LED_OFFSET * i as u32) % 0x10000) as u16, self.brightness)
+                } else {
+                    Color::BLACK
                 }
             }
+            Effect::Twinkle => {
+                let val = ((self.twinkle_levels[i] as u16 * (self.brightness as u16 + 1)) >> 8) as u8;
+                self.sample_color(params.palette, self.twinkle_hues[i], val)
+            }
+            Effect::Fire => {
+                let heat = self.fire_heat[i];
+                let color = match params.palette {
+                    // Stay below the wrap back to the first palette entry
+                    Some(index) => self.palettes[index as usize].sample(heat as u16 * 240),
+                    None => Color::from_heat(heat),
+                };
+                color.with_brightness(self.brightness)
+            }
+            Effect::Sunrise | Effect::Sunset => {
+                // Hold the final color once the duration has passed
+                let duration = self.sunrise_duration.as_millis().max(1);
+                let elapsed = self.frame_time.saturating_duration_since(self.sunrise_start).as_millis();
+                let progress = (elapsed * 10000 / duration).min(9999);
+                let frame = if matches!(effect, Effect::Sunset) { 9999 - progress } else { progress };
+                self.sunrise_reader
+                    .evaluate_color_at_frame(&SUNRISE_KEYFRAMES, frame)
+                    .with_brightness(self.brightness)
+            }
         }
+    }
 
-        self.sk6812.write(&self.buffer).await;
+    /// Color of LED `i` this frame, rendered when asked for so no frame is kept. The identify
+    /// and outage overlays are drawn over the output so the effect carries on underneath and
+    /// shows again after.
+    fn pixel(&mut self, i: usize) -> Color {
+        if self.frame_time < self.identify_until {
+            let phase = (self.frame_time.as_millis() / IDENTIFY_BLINK.as_millis()) as usize;
+            return if (i + phase) % 2 == 0 { IDENTIFY_COLOR } else { Color::BLACK };
+        }
+        if self.net_outage {
+            // Dim enough to live with until the access point is back
+            let period = NET_OUTAGE_BREATH.as_millis();
+            let phase = self.frame_time.as_millis() % period;
+            let ramp = if phase < period / 2 { phase } else { period - phase };
+            let red = (ramp * 2 * NET_OUTAGE_MAX_RED as u64 / period) as u8;
+            return Color::from_rgbw(red, 0, 0, 0);
+        }
+        if self.realtime_active {
+            return self.realtime[i];
+        }
+        let color = self.effect_color(self.effect, self.params, i);
+        match self.crossfade_mix() {
+            Some(t) => self.effect_color(self.crossfade_effect, self.crossfade_params, i).blend(&color, t),
+            None => color,
+        }
+    }
+
+    pub async fn tick(&mut self, driver: &mut impl LedDriver) {
+        if self.realtime_active && Instant::now() >= self.realtime_until {
+            debug!("realtime stream timed out, resuming effects");
+            self.realtime_active = false;
+            self.frame_dirty = true;
+        }
+        if self.identify_until != Instant::MIN && Instant::now() >= self.identify_until {
+            self.identify_until = Instant::MIN;
+            self.frame_dirty = true;
+        }
+        let net_outage = link::net_health() == NetHealth::Outage;
+        if net_outage != self.net_outage {
+            self.net_outage = net_outage;
+            self.frame_dirty = true;
+        }
+        // A static effect only renders again when something changes, heating up included
+        let derating = temperature::derating();
+        if derating != self.derating {
+            self.derating = derating;
+            self.frame_dirty = true;
+        }
+        if !self.frame_dirty && self.is_idle() {
+            return;
+        }
+
+        self.frame_time = Instant::now();
+        self.cur_period = animation_millis().max(0) as u64 / ANIMATION_PERIOD.as_millis();
+        self.update_brightness();
+
+        if matches!(self.effect, Effect::AudioReactive) && Instant::now() > self.last_audio_instant + AUDIO_HOLDOVER {
+            debug!("audio stream stopped, falling back to previous effect");
+            self.effect = self.audio_fallback_effect;
+            self.audio_levels = [0; MAX_AUDIO_BANDS];
+        }
+        self.advance_playlist();
+
+        if !self.realtime_active {
+            self.step_effect(self.effect, self.params);
+            if self.crossfade_mix().is_some() && self.crossfade_effect != self.effect {
+                self.step_effect(self.crossfade_effect, self.crossfade_params);
+            }
+        }
+
+        // A first pass over the fitted LEDs for the power draw, and to leave the strip latched
+        // instead of resending an identical frame
+        let config = config::get();
+        let led_count = (config.led_count as usize).min(NUM_LEDS);
+        let mut hash = 0x811C9DC5;
+        let mut channel_sum = 0;
+        for i in 0..led_count {
+            let color = self.pixel(i);
+            hash = Self::hash_color(hash, color);
+            channel_sum += color.r as u32 + color.g as u32 + color.b as u32 + color.w as u32;
+        }
+        let result = if self.frame_dirty || hash != self.last_frame_hash {
+            let power_limit = Self::power_limit(&config, led_count, channel_sum);
+            let (derating, color_order) = (self.derating, config.color_order);
+            // Rendered again LED by LED as the driver streams it out, unfitted LEDs dark
+            driver
+                .write_with(NUM_LEDS, |i| {
+                    if i < led_count {
+                        Self::output_color(self.pixel(i), power_limit, derating, color_order)
+                    } else {
+                        Color::BLACK
+                    }
+                })
+                .await
+        } else {
+            Ok(())
+        };
+        if matches!(self.effect, Effect::AudioReactive) && !self.realtime_active {
+            for level in self.audio_levels.iter_mut() {
+                *level = level.saturating_sub(AUDIO_DECAY);
+            }
+        }
+        if result.is_err() {
+            // Left dirty to be sent again on the next tick
+            self.on_write_failed(driver);
+            return;
+        }
+        self.failed_writes = 0;
+        self.last_frame_hash = hash;
+        self.frame_dirty = false;
+    }
+
+    fn on_write_failed(&mut self, driver: &mut impl LedDriver) {
+        warn!("LED frame timed out");
+        self.frame_dirty = true;
+        self.failed_writes += 1;
+        let reinit = self.failed_writes >= MAX_FAILED_WRITES;
+        OUTPUT_ERRORS.lock(|cell| {
+            let mut errors = cell.get();
+            errors.failed_writes += 1;
+            errors.reinits += reinit as u32;
+            cell.set(errors);
+        });
+        if reinit {
+            remote_log!(Warning, "LED frames keep timing out, restarting the state machines");
+            driver.reinit();
+            self.failed_writes = 0;
+        }
     }
 
-    pub async fn run(&mut self, receiver: LedReceiver) -> ! {
+    pub async fn run(&mut self, driver: &mut impl LedDriver, receiver: LedReceiver) -> ! {
         loop {
-            let next_tick = (Instant::now().as_ticks() + LED_PERIOD.as_ticks() - 1) / LED_PERIOD.as_ticks()
-                * LED_PERIOD.as_ticks();
+            watchdog::check_in(Subsystem::Leds);
+            let next_tick = (Instant::now().as_ticks() + self.frame_period.as_ticks() - 1) / self.frame_period.as_ticks()
+                * self.frame_period.as_ticks();
             match select::select(
                 Timer::at(Instant::from_ticks(next_tick)),
                 receiver.receive(),
             ).await {
                 select::Either::First(_) => {
                     // Update timer has expired
-                    self.tick().await;
+                    self.tick(driver).await;
                 }
                 select::Either::Second(command) => {
                     // Led command
                     self.process_command(&command).await;
                 }
             }
+            // Due commands are queued back to this task and applied on the next iterations
+            self.schedule.run_due();
+            self.publish_status();
         }
     }
 }
 
 
 #[embassy_executor::task]
-pub async fn led_task(receiver: LedReceiver, p: SK6812Peripherals) -> ! {
+pub async fn led_task(receiver: LedReceiver, p: SK6812Peripherals, palettes: Palettes, playlist: Playlist, saved: Option<LedStatus>) -> ! {
     info!("set up SK6812 peripherals");
-    let mut sk6812_pio = pio::Pio::new(p.pio, Irqs);
-    let sk6812 = PioSK6812::new(
-        &mut sk6812_pio.common,
-        sk6812_pio.sm0,
-        p.dio,
-        p.dma,
+    let pio::Pio { mut common, sm0, sm1, sm2, sm3, .. } = pio::Pio::new(p.pio, Irqs);
+    let program = PioSK6812Program::new(&mut common);
+    let mut sk6812 = PioSK6812Multi::new(
+        Some(PioSK6812::new(&mut common, &program, sm0, p.dio0, p.dma0.degrade())),
+        p.dio1.zip(p.dma1).map(|(dio, dma)| PioSK6812::new(&mut common, &program, sm1, dio, dma.degrade())),
+        p.dio2.zip(p.dma2).map(|(dio, dma)| PioSK6812::new(&mut common, &program, sm2, dio, dma.degrade())),
+        p.dio3.zip(p.dma3).map(|(dio, dma)| PioSK6812::new(&mut common, &program, sm3, dio, dma.degrade())),
+        LEDS_PER_STRIP,
     );
-    Leds::new(sk6812).run(receiver).await
+    let mut leds = Leds::new(palettes, playlist, saved);
+    instrument::measured("leds", leds.run(&mut sk6812, receiver)).await
 }
@@ -0,0 +1,10 @@
+//! Parts of brighty that don't touch the hardware, in a library of their own so they also build
+//! for the host. With the `std` feature `cargo test --lib --features std --target <host triple>`
+//! runs their tests, the target in `.cargo/config.toml` otherwise applies.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod calendar;
+pub mod color;
+pub mod keyframe;
+pub mod nec;
+pub mod parse;
@@ -0,0 +1,207 @@
+use core::cell::Cell;
+use defmt::{debug, info, unwrap, warn, Format};
+use cyw43::LinkEvents;
+use embassy_futures::select::{select, select3, Either3};
+use embassy_net::driver::Driver;
+use embassy_net::{ConfigV4, DhcpConfig, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use net_setup::link::{probe_until_lost, Probe, MAX_PROBE_FAILURES};
+use net_setup::ping::PingId;
+use net_setup::status_led::{self, LinkStatus, StatusLed};
+use crate::config::{self, StaticIp};
+use crate::power::PowerManager;
+use crate::remote_log;
+use crate::provision::{self, WifiCredentials};
+use crate::watchdog::{self, Subsystem};
+
+const PING_ID: PingId = PingId { identifier: 0x4252, payload: *b"brighty\0" };
+const REJOIN_DELAY: Duration = Duration::from_secs(5);
+/// How often signal strength and PHY rate are sampled while joined.
+const QUALITY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Copy, Clone)]
+pub struct LinkQuality {
+    /// Signal strength in dBm, 0 while unknown.
+    pub rssi: i8,
+    /// Transmit PHY rate in units of 500 kbit/s, 0 while unknown.
+    pub tx_rate: u16,
+}
+
+impl LinkQuality {
+    const UNKNOWN: LinkQuality = LinkQuality { rssi: 0, tx_rate: 0 };
+}
+
+/// Network state as seen by the link supervisor, shown on the LEDs during outages.
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum NetHealth {
+    Up,
+    /// The DHCP lease ran out or the gateway stopped answering, until the link is back.
+    Outage,
+}
+
+static NET_HEALTH: Mutex<CriticalSectionRawMutex, Cell<NetHealth>> = Mutex::new(Cell::new(NetHealth::Up));
+
+pub fn net_health() -> NetHealth {
+    NET_HEALTH.lock(|cell| cell.get())
+}
+
+fn set_net_health(health: NetHealth) {
+    status_led::set_fault(health != NetHealth::Up);
+    if NET_HEALTH.lock(|cell| cell.replace(health)) != health {
+        match health {
+            NetHealth::Up => remote_log!(Info, "network up"),
+            NetHealth::Outage => remote_log!(Warning, "network outage"),
+        }
+    }
+}
+
+static QUALITY: Mutex<CriticalSectionRawMutex, Cell<LinkQuality>> = Mutex::new(Cell::new(LinkQuality::UNKNOWN));
+
+/// Last sampled link quality, for diagnosing weak-signal installations remotely.
+pub fn quality() -> LinkQuality {
+    QUALITY.lock(|cell| cell.get())
+}
+
+fn dhcp_config() -> DhcpConfig {
+    let mut dhcp_config: DhcpConfig = Default::default();
+    dhcp_config.hostname = Some(unwrap!(config::get().hostname().as_str().try_into()));
+    dhcp_config
+}
+
+fn static_config(ip: StaticIp) -> StaticConfigV4 {
+    let mut dns_servers = heapless::Vec::new();
+    if ip.dns != [0; 4] {
+        dns_servers.push(Ipv4Address(ip.dns)).ok();
+    }
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address(ip.address), ip.prefix_len),
+        gateway: (ip.gateway != [0; 4]).then_some(Ipv4Address(ip.gateway)),
+        dns_servers,
+    }
+}
+
+/// DHCP unless the device config holds a static address.
+pub fn ipv4_config() -> ConfigV4 {
+    match config::get().static_ip {
+        Some(ip) => {
+            info!("using static address {}/{}", Ipv4Address(ip.address), ip.prefix_len);
+            ConfigV4::Static(static_config(ip))
+        }
+        None => ConfigV4::Dhcp(dhcp_config()),
+    }
+}
+
+/// Track the health of the network by what the link checks find.
+fn on_probe(probe: Probe) {
+    match probe {
+        Probe::Reachable => {
+            watchdog::check_in(Subsystem::Net);
+            set_net_health(NetHealth::Up);
+        }
+        Probe::Unanswered { gateway, failures } => {
+            set_net_health(NetHealth::Outage);
+            let [a, b, c, d] = gateway.0;
+            remote_log!(Warning, "gateway {}.{}.{}.{} not answering ({}/{})", a, b, c, d, failures, MAX_PROBE_FAILURES);
+        }
+        Probe::LeaseLost => {
+            remote_log!(Warning, "DHCP lease lost");
+            set_net_health(NetHealth::Outage);
+        }
+    }
+}
+
+/// Resolve once the connection to the access point is lost, either reported by the cyw43 or
+/// detected by probing the gateway, as when the access point reboots.
+pub async fn wait_lost<D: Driver>(stack: &Stack<D>, link_events: &mut LinkEvents<'_>) {
+    // Left over from the previous join or leave
+    while link_events.try_next().is_some() {}
+    let link_down = async {
+        loop {
+            let event = link_events.next().await;
+            if !event.is_up() {
+                warn!("wifi link event {}", event);
+                set_net_health(NetHealth::Outage);
+                return;
+            }
+        }
+    };
+    select(link_down, probe_until_lost(stack, &PING_ID, |probe| async move { on_probe(probe) })).await;
+}
+
+async fn sample_quality<D: Driver>(control: &mut cyw43::Control<'_>, stack: &Stack<D>) {
+    // The cyw43 rejects both requests while not associated
+    if stack.is_link_up() {
+        let quality = LinkQuality {
+            rssi: control.rssi().await.clamp(i8::MIN as i32, 0) as i8,
+            tx_rate: control.tx_rate().await.min(u16::MAX as u32) as u16,
+        };
+        debug!("link quality rssi {} dBm, rate {} kbit/s", quality.rssi, quality.tx_rate as u32 * 500);
+        QUALITY.lock(|cell| cell.set(quality));
+        watchdog::check_in(Subsystem::Net);
+    }
+}
+
+/// Drive the cyw43 while the link is up: sample signal strength and PHY rate every
+/// `QUALITY_INTERVAL`, switch power management with stream activity and blink the status LED.
+pub async fn run_control<D: Driver>(
+    control: &mut cyw43::Control<'_>,
+    stack: &Stack<D>,
+    led: &mut StatusLed,
+) {
+    let mut power = PowerManager::new();
+    control.set_power_management(power.mode()).await;
+    let mut next_sample = Instant::now();
+    loop {
+        match select3(Timer::at(next_sample), power.next_mode(), status_led::changed()).await {
+            Either3::First(_) => {
+                sample_quality(control, stack).await;
+                next_sample = Instant::now() + QUALITY_INTERVAL;
+            }
+            Either3::Second(mode) => control.set_power_management(mode).await,
+            Either3::Third(on) => led.set(control, on).await,
+        }
+    }
+}
+
+/// Wait for the DHCP lease, or return right away with a static address, blinking the status LED
+/// meanwhile.
+pub async fn wait_config_up<D: Driver>(
+    control: &mut cyw43::Control<'_>,
+    stack: &Stack<D>,
+    led: &mut StatusLed,
+) {
+    status_led::set_status(LinkStatus::WaitingDhcp);
+    info!("waiting for network config...");
+    select(stack.wait_config_up(), status_led::drive(led, control)).await;
+    status_led::set_status(LinkStatus::Up);
+}
+
+/// Leave the network, join it again and wait for a fresh DHCP lease.
+pub async fn reconnect<D: Driver>(
+    control: &mut cyw43::Control<'_>,
+    stack: &Stack<D>,
+    credentials: &WifiCredentials,
+    led: &mut StatusLed,
+) {
+    remote_log!(Info, "reconnecting to wifi");
+    QUALITY.lock(|cell| cell.set(LinkQuality::UNKNOWN));
+    control.leave().await;
+    status_led::show_joining(led, control).await;
+    loop {
+        match provision::join(control, credentials).await {
+            Ok(_) => break,
+            Err(err) => {
+                remote_log!(Warning, "rejoin failed with status={}", err.status);
+                Timer::after(REJOIN_DELAY).await;
+            }
+        }
+    }
+
+    // Restart DHCP, the old lease may not survive the access point's reboot
+    stack.set_config_v4(ipv4_config());
+    wait_config_up(control, stack, led).await;
+    info!("network config is up again");
+    set_net_health(NetHealth::Up);
+}
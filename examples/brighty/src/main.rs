@@ -2,46 +2,94 @@
 #![no_main]
 
 mod consts;
+mod clock;
+mod config;
+mod console;
+#[cfg(feature = "control-page")]
+mod control_page;
+mod crash;
+#[cfg(feature = "artnet")]
+mod artnet;
+#[cfg(feature = "auth")]
+mod auth;
+#[cfg(feature = "ble-provision")]
+mod ble;
+mod board;
 mod peripheral_macros;
-mod sk6812;
 mod udplisten;
-mod color;
+#[cfg(feature = "ddp")]
+mod ddp;
+#[cfg(any(feature = "sacn", feature = "artnet"))]
+mod dmx;
+#[cfg(any(feature = "wled", feature = "control-page"))]
+mod http;
+mod ipv6;
+#[cfg(feature = "ir")]
+mod ir;
+#[cfg(any(feature = "wled", feature = "control-page", feature = "mqtt"))]
+mod json;
 mod leds;
+mod link;
+#[cfg(feature = "mic")]
+mod mic;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "ota")]
+mod ota;
+mod palette;
+mod playlist;
+mod power;
+mod presets;
+mod provision;
+mod reassembly;
+#[cfg(feature = "sacn")]
+mod sacn;
+mod schedule;
+mod sntp;
+mod storage;
+#[cfg(feature = "sync")]
+mod sync;
+mod syslog;
+mod temperature;
+#[cfg(feature = "tcp-control")]
+mod tcp_control;
+mod watchdog;
+#[cfg(feature = "wled")]
+mod wled;
 
-use cyw43_pio::PioSpi;
-use defmt::{debug, info, unwrap};
+use board_support::{instrument, logbuf};
+use brighty::{calendar, color, keyframe, parse};
+use defmt::{debug, info, unwrap, warn};
 use embassy_executor::{Executor, Spawner};
-use embassy_net::{Config, DhcpConfig, IpAddress, IpEndpoint, Ipv4Address, Stack, StackResources};
+use embassy_futures::join::join;
+use embassy_futures::select::select3;
+use embassy_net::{Config, IpAddress, IpEndpoint, Ipv4Address, StackResources};
 use embassy_net::udp::{UdpSocket, PacketMetadata};
-use embassy_rp::gpio::{Level, Output};
 use embassy_rp::multicore;
 use embassy_rp::multicore::spawn_core1;
-use embassy_rp::peripherals::{DMA_CH0, I2C0, PIO0, PIO1};
-use embassy_rp::{bind_interrupts, i2c, pio};
+use embassy_rp::peripherals::{I2C0, PIO0, PIO1, USB};
+use embassy_rp::{adc, bind_interrupts, i2c, pio, usb};
 use embassy_time::{Duration, Timer};
 use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
+use defmt_rtt as _;
+use board::StatusLedPeripherals;
 use leds::{led_task, SK6812Peripherals};
-
-const WIFI_SSID: &str = include_str!("../wifi_ssid.txt");
-const WIFI_PSK: &[u8; 32] = include_bytes!("../wifi_psk.bin");
+use net_setup::{mdns, status_led};
+use net_setup::{Firmware, NetConfig, WifiPeripherals};
+#[cfg(feature = "mic")]
+use mic::MicPeripherals;
+use provision::{ProvisionPeripherals, WifiCredentials};
+use storage::{storage_task, Storage, StoragePeripherals};
+use watchdog::{watchdog_task, WatchdogPeripherals};
 
 bind_interrupts!(struct Irqs {
     I2C0_IRQ => i2c::InterruptHandler<I2C0>;
     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
     PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
 });
 
-#[embassy_executor::task]
-async fn wifi_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
-    runner.run().await
-}
-
-#[embassy_executor::task]
-async fn net_task(stack: &'static Stack<cyw43::NetDriver<'static>>) -> ! {
-    stack.run().await
-}
-
 macro_rules! wifi_peripherals {
     ($macro_name:ident $(,$arg:tt)*) => {
         $macro_name!{$($arg,)*
@@ -56,74 +104,61 @@ macro_rules! wifi_peripherals {
     };
 }
 
-wifi_peripherals!(define_peripheral_set);
-
-
 #[embassy_executor::task]
 async fn core0_task(
     spawner: Spawner,
     wifi_peripherals: WifiPeripherals,
+    wifi_credentials: Option<WifiCredentials>,
+    watchdog_peripherals: WatchdogPeripherals,
+    status_led_peripherals: StatusLedPeripherals,
 ) {
-    let fw = include_bytes!("../../../cyw43-firmware/43439A0.bin");
-    let clm = include_bytes!("../../../cyw43-firmware/43439A0_clm.bin");
-
-    info!("set up wifi peripherals");
-    let pwr = Output::new(wifi_peripherals.pwr, Level::Low);
-    let cs = Output::new(wifi_peripherals.cs, Level::High);
-    let mut pio = pio::Pio::new(wifi_peripherals.pio, Irqs);
-    let spi = PioSpi::new(
-        &mut pio.common,
-        pio.sm0,
-        pio.irq0,
-        cs,
-        wifi_peripherals.dio,
-        wifi_peripherals.clk,
-        wifi_peripherals.dma0,
-    );
-
-    info!("set up cyw43");
-    static STATE: StaticCell<cyw43::State> = StaticCell::new();
-    let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-    unwrap!(spawner.spawn(wifi_task(runner)));
-
-    info!("init cyw43");
-    control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
-
-    let mut dhcp_config: DhcpConfig = Default::default();
-    dhcp_config.hostname = Some(unwrap!("squishy".try_into()));
-    let config = Config::dhcpv4(dhcp_config);
-
-    // Generate random seed
-    let seed = {
-        use rand_core::RngCore;
-        embassy_rp::clocks::RoscRng.next_u64()
+    let mut led = board::status_led(status_led_peripherals);
+    // Without credentials the device serves its setup access point instead of joining
+    let ip = if wifi_credentials.is_some() {
+        Config { ipv4: link::ipv4_config(), ..Default::default() }
+    } else {
+        provision::ap_config()
     };
-    debug!("rand seed {}", seed);
-
-    // Init network stack
-    static STACK: StaticCell<Stack<cyw43::NetDriver<'static>>> = StaticCell::new();
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
-    let stack = &*STACK.init(Stack::new(
-        net_device,
-        config,
-        RESOURCES.init(StackResources::<3>::new()),
-        seed,
-    ));
-
-    info!("set up net");
-    unwrap!(spawner.spawn(net_task(stack)));
+    static RESOURCES: StaticCell<StackResources<16>> = StaticCell::new();
+    let config = NetConfig {
+        firmware: Firmware::BUNDLED,
+        power_management: cyw43::PowerManagementMode::PowerSave,
+        ip,
+        resources: RESOURCES.init(StackResources::<16>::new()),
+    };
+    #[cfg(not(feature = "ble-provision"))]
+    let (stack, mut control) = net_setup::bring_up(spawner, wifi_peripherals, Irqs, config).await;
+    #[cfg(feature = "ble-provision")]
+    let (stack, bt_device, mut control) = net_setup::bring_up_with_bluetooth(spawner, wifi_peripherals, Irqs, config).await;
 
     let mac = control.address().await;
     debug!("mac: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
         mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+    stack.set_config_v6(ipv6::config(&mac));
+
+    let Some(wifi_credentials) = wifi_credentials else {
+        // The setup access point is up, the rest waits on a person
+        #[cfg(feature = "ota")]
+        ota::confirm_boot();
+        #[cfg(feature = "ble-provision")]
+        {
+            join(
+                provision::run(&mut control, stack, &mac, &mut led),
+                ble::run(bt_device, &mac),
+            )
+            .await;
+            unreachable!()
+        }
+        #[cfg(not(feature = "ble-provision"))]
+        provision::run(&mut control, stack, &mac, &mut led).await
+    };
 
+    // Setup mode waits on a person rather than the network, so only guard station mode
+    unwrap!(spawner.spawn(watchdog_task(watchdog_peripherals)));
+
+    status_led::show_joining(&mut led, &mut control).await;
     loop {
-        //control.join_open(WIFI_NETWORK).await;
-        match control.join_wpa2_psk(WIFI_SSID, WIFI_PSK).await {
+        match provision::join(&mut control, &wifi_credentials).await {
             Ok(_) => break,
             Err(err) => {
                 info!("join failed with status={}", err.status);
@@ -131,20 +166,22 @@ async fn core0_task(
         }
     }
 
-    // Wait for DHCP, not necessary when using static IP
-    info!("waiting for DHCP...");
-    stack.wait_config_up().await;
-    info!("DHCP is now up!");
+    // Returns right away with a static address
+    link::wait_config_up(&mut control, stack, &mut led).await;
+    info!("network config is now up!");
+    // Joined and addressed, a new image that gets this far is kept
+    #[cfg(feature = "ota")]
+    ota::confirm_boot();
 
     let mut cmd_socket = {
         static RX_META: StaticCell<[PacketMetadata; 128]> = StaticCell::new();
         let rx_meta = RX_META.init([PacketMetadata::EMPTY; 128]);
         static RX_BUFFER: StaticCell<[u8; 4096]> = StaticCell::new();
         let rx_buffer = RX_BUFFER.init([0; 4096]);
-        static TX_META: StaticCell<[PacketMetadata; 0]> = StaticCell::new();
-        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 0]);
-        static TX_BUFFER: StaticCell<[u8; 0]> = StaticCell::new();
-        let tx_buffer = TX_BUFFER.init([0; 0]);
+        static TX_META: StaticCell<[PacketMetadata; 16]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 16]);
+        static TX_BUFFER: StaticCell<[u8; 512]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 512]);
 
         UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
     };
@@ -162,29 +199,248 @@ async fn core0_task(
         UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
     };
 
+    let mut mdns_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 8]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 8]);
+        static RX_BUFFER: StaticCell<[u8; 1024]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 1024]);
+        static TX_META: StaticCell<[PacketMetadata; 4]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 4]);
+        static TX_BUFFER: StaticCell<[u8; 1024]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 1024]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    #[cfg(feature = "wled")]
+    let mut wled_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 16]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 16]);
+        static RX_BUFFER: StaticCell<[u8; 2048]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 2048]);
+        static TX_META: StaticCell<[PacketMetadata; 0]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 0]);
+        static TX_BUFFER: StaticCell<[u8; 0]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 0]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    #[cfg(feature = "sacn")]
+    let mut sacn_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 8]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 8]);
+        static RX_BUFFER: StaticCell<[u8; 2048]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 2048]);
+        static TX_META: StaticCell<[PacketMetadata; 0]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 0]);
+        static TX_BUFFER: StaticCell<[u8; 0]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 0]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    #[cfg(feature = "artnet")]
+    let mut artnet_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 8]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 8]);
+        static RX_BUFFER: StaticCell<[u8; 2048]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 2048]);
+        static TX_META: StaticCell<[PacketMetadata; 2]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 2]);
+        static TX_BUFFER: StaticCell<[u8; 512]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 512]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    #[cfg(feature = "sync")]
+    let mut sync_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 4]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 4]);
+        static RX_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 256]);
+        static TX_META: StaticCell<[PacketMetadata; 2]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 2]);
+        static TX_BUFFER: StaticCell<[u8; 128]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 128]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    #[cfg(feature = "ddp")]
+    let mut ddp_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 8]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 8]);
+        static RX_BUFFER: StaticCell<[u8; 2048]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 2048]);
+        static TX_META: StaticCell<[PacketMetadata; 0]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 0]);
+        static TX_BUFFER: StaticCell<[u8; 0]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 0]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+
+    // Bound by port only, so IPv4 and IPv6 datagrams are both accepted
     unwrap!(cmd_socket.bind(consts::CMD_PORT));
     unwrap!(discover_socket.bind(consts::DISCOVER_PORT));
+    unwrap!(mdns_socket.bind(mdns::MDNS_PORT));
+    #[cfg(feature = "wled")]
+    unwrap!(wled_socket.bind(wled::REALTIME_PORT));
+    #[cfg(feature = "sacn")]
+    unwrap!(sacn_socket.bind(sacn::SACN_PORT));
+    #[cfg(feature = "artnet")]
+    unwrap!(artnet_socket.bind(artnet::ARTNET_PORT));
+    #[cfg(feature = "ddp")]
+    unwrap!(ddp_socket.bind(ddp::DDP_PORT));
+    #[cfg(feature = "sync")]
+    unwrap!(sync_socket.bind(sync::SYNC_PORT));
 
-    udplisten::run(&mut cmd_socket, &mut discover_socket, &mac).await;
+    if control.add_multicast_address(mdns::MDNS_MAC).await.is_err()
+        || stack.join_multicast_group(mdns::MDNS_GROUP).await.is_err()
+    {
+        warn!("failed to join mDNS group");
+    }
+    let solicited_node = ipv6::solicited_node(ipv6::link_local_address(&mac));
+    if control.add_multicast_address(ipv6::ALL_NODES_MAC).await.is_err()
+        || control.add_multicast_address(ipv6::multicast_mac(solicited_node)).await.is_err()
+    {
+        warn!("failed to accept IPv6 neighbor discovery");
+    }
+    if control.add_multicast_address(ipv6::multicast_mac(mdns::MDNS_GROUP_V6)).await.is_err()
+        || stack.join_multicast_group(mdns::MDNS_GROUP_V6).await.is_err()
+    {
+        warn!("failed to join IPv6 mDNS group");
+    }
+    #[cfg(feature = "sacn")]
+    if control.add_multicast_address(sacn::universe_mac(consts::DMX_UNIVERSE)).await.is_err()
+        || stack.join_multicast_group(sacn::universe_group(consts::DMX_UNIVERSE)).await.is_err()
+    {
+        warn!("failed to join sACN universe group");
+    }
+    #[cfg(feature = "sync")]
+    if control.add_multicast_address(sync::SYNC_MAC).await.is_err()
+        || stack.join_multicast_group(sync::SYNC_GROUP).await.is_err()
+    {
+        warn!("failed to join sync group");
+    }
+
+    let hostname = config::get().hostname();
+    let mut link_events = unwrap!(control.link_events());
+    loop {
+        let services = join(
+            udplisten::run(&mut cmd_socket, &mut discover_socket, stack, &mac),
+            mdns::run(&mut mdns_socket, stack, &hostname, Some(&consts::MDNS_SERVICE)),
+        );
+        let services = join(services, join(sntp::run(stack), syslog::run(stack)));
+        #[cfg(any(feature = "wled", feature = "control-page"))]
+        let services = join(services, http::run(stack, mac));
+        #[cfg(feature = "wled")]
+        let services = join(services, wled::run_realtime(&mut wled_socket));
+        #[cfg(feature = "sacn")]
+        let services = join(services, sacn::run(&mut sacn_socket));
+        #[cfg(feature = "artnet")]
+        let services = join(services, artnet::run(&mut artnet_socket, stack, &mac));
+        #[cfg(feature = "ddp")]
+        let services = join(services, ddp::run(&mut ddp_socket));
+        #[cfg(feature = "sync")]
+        let services = join(services, sync::run(&mut sync_socket, &mac));
+        #[cfg(feature = "tcp-control")]
+        let services = join(services, tcp_control::run(stack));
+        #[cfg(feature = "mqtt")]
+        let services = join(services, mqtt::run(stack, mac));
+        // Dropping the services closes their TCP connections, they reopen on the next pass
+        let services = select3(
+            services,
+            link::wait_lost(stack, &mut link_events),
+            link::run_control(&mut control, stack, &mut led),
+        );
+        instrument::measured("services", services).await;
+        link::reconnect(&mut control, stack, &wifi_credentials, &mut led).await;
+    }
 }
 
+// Fails the build naming any peripheral two of the sets taken in main both claim
+const _: () = {
+    wifi_peripherals!(claim_peripheral_set);
+    sk6812_peripherals!(claim_peripheral_set);
+    storage_peripherals!(claim_peripheral_set);
+    provision_peripherals!(claim_peripheral_set);
+    watchdog_peripherals!(claim_peripheral_set);
+    console_peripherals!(claim_peripheral_set);
+    #[cfg(feature = "mic")]
+    mic_peripherals!(claim_peripheral_set);
+    temperature_peripherals!(claim_peripheral_set);
+    clock_peripherals!(claim_peripheral_set);
+    status_led_peripherals!(claim_peripheral_set);
+    #[cfg(feature = "ir")]
+    ir_peripherals!(claim_peripheral_set);
+};
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
+    // Before init resets the RTC, so a reboot keeps the time
+    let rtc_snapshot = clock::snapshot_rtc();
     let p = embassy_rp::init(Default::default());
 
     let wifi_peripherals = wifi_peripherals!(take_peripheral_set, p);
     let sk6812_peripherals = sk6812_peripherals!(take_peripheral_set, p);
+    let storage_peripherals = storage_peripherals!(take_peripheral_set, p);
+    let provision_peripherals = provision_peripherals!(take_peripheral_set, p);
+    let watchdog_peripherals = watchdog_peripherals!(take_peripheral_set, p);
+    let console_peripherals = console_peripherals!(take_peripheral_set, p);
+    #[cfg(feature = "mic")]
+    let mic_peripherals = mic_peripherals!(take_peripheral_set, p);
+    let temperature_peripherals = temperature_peripherals!(take_peripheral_set, p);
+    let clock_peripherals = clock_peripherals!(take_peripheral_set, p);
+    let status_led_peripherals = status_led_peripherals!(take_peripheral_set, p);
+    #[cfg(feature = "ir")]
+    let ir_peripherals = ir_peripherals!(take_peripheral_set, p);
+
+    // Stored settings are read before core1 starts using them
+    crash::report();
+    let mut storage = Storage::new(storage_peripherals);
+    config::set_unique_id(storage.load_unique_id());
+    config::set(storage.load_config());
+    let palettes = storage.load_palettes();
+    let playlist = storage.load_playlist();
+    let led_state = storage.load_led_state();
+    presets::set_all(storage.load_presets());
+    #[cfg(feature = "ir")]
+    ir::set_bindings(storage.load_ir_bindings());
+    clock::init(clock_peripherals, rtc_snapshot, storage.load_clock());
+    #[cfg(feature = "auth")]
+    udplisten::restore_nonce_floor(storage.load_nonce_floor());
+    let wifi_credentials = if provision::setup_requested(provision_peripherals) {
+        info!("setup button held, starting setup access point");
+        None
+    } else {
+        storage.load_wifi_credentials()
+    };
 
     static mut CORE1_STACK: multicore::Stack<4096> = multicore::Stack::new();
     spawn_core1(p.CORE1, unsafe { &mut CORE1_STACK }, move || {
         static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
         let executor1 = EXECUTOR1.init(Executor::new());
-        let led_receiver = unsafe { leds::LED_CHANNEL.receiver() };
-        executor1.run(|spawner| unwrap!(spawner.spawn(led_task(led_receiver, sk6812_peripherals))));
+        let led_receiver = leds::LED_CHANNEL.receiver();
+        executor1.run(|spawner| unwrap!(spawner.spawn(led_task(led_receiver, sk6812_peripherals, palettes, playlist, led_state))));
     });
 
     static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
     let executor0 = EXECUTOR0.init(Executor::new());
-    executor0
-        .run(|spawner| unwrap!(spawner.spawn(core0_task(spawner, wifi_peripherals))));
+    executor0.run(|spawner| {
+        let storage_receiver = storage::STORAGE_CHANNEL.receiver();
+        unwrap!(spawner.spawn(storage_task(storage_receiver, storage)));
+        unwrap!(spawner.spawn(console::console_task(console_peripherals)));
+        unwrap!(spawner.spawn(instrument::instrument_task()));
+        unwrap!(spawner.spawn(status_led::status_led_task()));
+        #[cfg(feature = "mic")]
+        unwrap!(spawner.spawn(mic::mic_task(leds::LED_CHANNEL.sender(), mic_peripherals, temperature_peripherals)));
+        #[cfg(not(feature = "mic"))]
+        unwrap!(spawner.spawn(temperature::temperature_task(temperature_peripherals)));
+        #[cfg(feature = "ir")]
+        unwrap!(spawner.spawn(ir::ir_task(ir_peripherals)));
+        unwrap!(spawner.spawn(core0_task(spawner, wifi_peripherals, wifi_credentials, watchdog_peripherals, status_led_peripherals)));
+    });
 }
@@ -0,0 +1,119 @@
+use defmt::{info, warn};
+use embassy_rp::{adc, dma, Peripheral, PeripheralRef};
+use embassy_rp::gpio::Pull;
+use crate::{define_peripheral_set, instrument, mic_peripherals, Irqs};
+use crate::leds::{self, AudioLevels, Effect, LedSender, MAX_AUDIO_BANDS};
+use crate::temperature::{Sensor, TemperaturePeripherals};
+
+/// ADC clock divider for an 8 kHz sample rate: 48 MHz / 8 kHz - 1.
+const SAMPLE_DIV: u16 = 5999;
+/// Samples per envelope window, 200 samples at 8 kHz gives a level frame at 40 Hz.
+const WINDOW_LEN: usize = 200;
+/// Envelope amplitude (in ADC counts) below which the room is considered quiet.
+const NOISE_FLOOR: u32 = 24;
+/// Smallest tracked peak so that quiet rooms aren't amplified to full scale.
+const MIN_PEAK: u32 = 256;
+
+mic_peripherals!(define_peripheral_set);
+
+struct Mic<'d, DMA: dma::Channel> {
+    adc: adc::Adc<'d, adc::Async>,
+    channel: adc::Channel<'d>,
+    dma: PeripheralRef<'d, DMA>,
+    led_sender: LedSender,
+    /// Shares the ADC, read between windows.
+    temperature: Sensor,
+    samples: [u16; WINDOW_LEN],
+    /// DC offset of the microphone bias, 8 fractional bits.
+    dc_offset: u32,
+    envelope: u32,
+    peak: u32,
+}
+
+impl<'d, DMA: dma::Channel> Mic<'d, DMA> {
+    pub fn new(
+        adc: adc::Adc<'d, adc::Async>,
+        channel: adc::Channel<'d>,
+        dma: impl Peripheral<P = DMA> + 'd,
+        led_sender: LedSender,
+        temperature: Sensor,
+    ) -> Self {
+        Self {
+            adc,
+            channel,
+            dma: dma.into_ref(),
+            led_sender,
+            temperature,
+            samples: [0; WINDOW_LEN],
+            dc_offset: 2048 << 8,
+            envelope: 0,
+            peak: MIN_PEAK,
+        }
+    }
+
+    /// Mean absolute deviation of one window from the tracked DC offset.
+    fn window_amplitude(&mut self) -> u32 {
+        let mut sum = 0;
+        for sample in self.samples.iter() {
+            let sample = (*sample as u32) << 8;
+            self.dc_offset = self.dc_offset - (self.dc_offset >> 10) + (sample >> 10);
+            sum += sample.abs_diff(self.dc_offset) >> 8;
+        }
+        sum / WINDOW_LEN as u32
+    }
+
+    fn update_envelope(&mut self, amplitude: u32) -> u8 {
+        // Fast attack, slow release
+        if amplitude > self.envelope {
+            self.envelope = amplitude;
+        } else {
+            self.envelope -= self.envelope >> 3;
+        }
+
+        // Automatic gain: normalize against a slowly decaying peak
+        if self.envelope > self.peak {
+            self.peak = self.envelope;
+        } else {
+            self.peak = (self.peak - (self.peak >> 8)).max(MIN_PEAK);
+        }
+
+        (self.envelope * 255 / self.peak) as u8
+    }
+
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.temperature.sample_if_due(&mut self.adc).await;
+            if self
+                .adc
+                .read_many(&mut self.channel, &mut self.samples, SAMPLE_DIV, self.dma.reborrow())
+                .await
+                .is_err()
+            {
+                warn!("mic sampling failed");
+                continue;
+            }
+
+            let amplitude = self.window_amplitude();
+            let level = self.update_envelope(amplitude);
+
+            // Only stream while audio reactive is the picked effect, so room noise doesn't
+            // take over from any other. Quiet rooms stream silence to keep it picked.
+            if matches!(leds::LED_STATUS.lock(|cell| cell.get()).effect, Effect::AudioReactive) {
+                let mut bands = [0; MAX_AUDIO_BANDS];
+                if self.envelope > NOISE_FLOOR {
+                    bands[0] = level;
+                }
+                self.led_sender.set_audio_levels(AudioLevels { bands, len: 1 });
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn mic_task(led_sender: LedSender, p: MicPeripherals, temperature: TemperaturePeripherals) -> ! {
+    info!("set up mic");
+    let adc = adc::Adc::new(temperature.adc, Irqs, adc::Config::default());
+    let channel = adc::Channel::new_pin(p.mic, Pull::None);
+    let mut mic = Mic::new(adc, channel, p.dma, led_sender, Sensor::new(temperature.sensor));
+    instrument::measured("mic", mic.run()).await
+}
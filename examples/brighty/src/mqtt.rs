@@ -0,0 +1,343 @@
+use defmt::{debug, info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+use ufmt::uwrite;
+use crate::color::Color;
+use crate::config::{self, MAX_HOSTNAME_LEN};
+use crate::consts;
+use crate::json::{json_u8, json_value};
+use crate::leds::{self, Effect, LedSender, LedStatus};
+use crate::temperature;
+
+pub const MQTT_PORT: u16 = 1883;
+
+/// Sent to the broker in CONNECT, pings go out at half of it.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// How often `LED_STATUS` is compared against the last published state.
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Between temperature updates, it drifts slowly and HA records every one.
+const TEMPERATURE_INTERVAL: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// Largest packet sent or received, the discovery config being the biggest one.
+const MAX_PACKET_LEN: usize = 1024;
+const MAX_TOPIC_LEN: usize = 64;
+/// Brightness restored by `"state":"ON"` when the LEDs were switched off before any brightness was sent.
+const DEFAULT_ON_BRIGHTNESS: u8 = 128;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBLISH_RETAIN: u8 = 0x01;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+/// Clean session, will flag and will retain.
+const CONNECT_FLAGS: u8 = 0x26;
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// Effects offered in Home Assistant's effect list, the rest need parameters HA can't send.
+const EFFECTS: [Effect; 5] = [Effect::Static, Effect::Rainbow, Effect::Chase, Effect::Twinkle, Effect::Fire];
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+/// Topics of one device, all below `brighty/<hostname>` except the discovery configs.
+struct Topics {
+    config: String<MAX_TOPIC_LEN>,
+    state: String<MAX_TOPIC_LEN>,
+    command: String<MAX_TOPIC_LEN>,
+    availability: String<MAX_TOPIC_LEN>,
+    temperature_config: String<MAX_TOPIC_LEN>,
+    temperature: String<MAX_TOPIC_LEN>,
+}
+
+impl Topics {
+    fn new(hostname: &str) -> Self {
+        let topic = |prefix: &str, suffix: &str| {
+            let mut topic = String::new();
+            uwrite!(topic, "{}/{}/{}", prefix, hostname, suffix).ok();
+            topic
+        };
+        Self {
+            config: topic("homeassistant/light", "config"),
+            state: topic(consts::HOSTNAME, "state"),
+            command: topic(consts::HOSTNAME, "set"),
+            availability: topic(consts::HOSTNAME, "availability"),
+            temperature_config: topic("homeassistant/sensor", "config"),
+            temperature: topic(consts::HOSTNAME, "temperature"),
+        }
+    }
+}
+
+/// Append an MQTT remaining length varint.
+fn push_len(packet: &mut Vec<u8, MAX_PACKET_LEN>, mut len: usize) {
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte }).ok();
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+/// Append a u16 length prefixed string.
+fn push_str(body: &mut Vec<u8, MAX_PACKET_LEN>, s: &[u8]) {
+    body.extend_from_slice(&(s.len() as u16).to_be_bytes()).ok();
+    body.extend_from_slice(s).ok();
+}
+
+fn encode_packet(kind: u8, body: &[u8]) -> Vec<u8, MAX_PACKET_LEN> {
+    let mut packet = Vec::new();
+    packet.push(kind).ok();
+    push_len(&mut packet, body.len());
+    packet.extend_from_slice(body).ok();
+    packet
+}
+
+fn encode_connect(client_id: &str, topics: &Topics) -> Vec<u8, MAX_PACKET_LEN> {
+    let mut body = Vec::new();
+    push_str(&mut body, b"MQTT");
+    body.push(4).ok();
+    body.push(CONNECT_FLAGS).ok();
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes()).ok();
+    push_str(&mut body, client_id.as_bytes());
+    push_str(&mut body, topics.availability.as_bytes());
+    push_str(&mut body, b"offline");
+    encode_packet(CONNECT, &body)
+}
+
+fn encode_subscribe(topic: &str) -> Vec<u8, MAX_PACKET_LEN> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&SUBSCRIBE_PACKET_ID.to_be_bytes()).ok();
+    push_str(&mut body, topic.as_bytes());
+    body.push(0).ok();
+    encode_packet(SUBSCRIBE, &body)
+}
+
+/// QoS 0 publish, retained so HA picks the state up after it restarts.
+fn encode_publish(topic: &str, payload: &[u8]) -> Vec<u8, MAX_PACKET_LEN> {
+    let mut body = Vec::new();
+    push_str(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload).ok();
+    encode_packet(PUBLISH | PUBLISH_RETAIN, &body)
+}
+
+/// Split the first complete packet off `buffer`, returning its type byte, body and total length.
+fn split_packet(buffer: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let mut len = 0;
+    for (i, byte) in buffer.iter().skip(1).take(4).enumerate() {
+        len |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            let start = 2 + i;
+            let body = buffer.get(start..start + len)?;
+            return Some((buffer[0], body, start + len));
+        }
+    }
+    None
+}
+
+/// Body of a PUBLISH packet on `topic`, skipping the packet id of QoS 1 and 2 publishes.
+fn publish_payload<'a>(kind: u8, body: &'a [u8], topic: &str) -> Option<&'a [u8]> {
+    let topic_len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let rest = body.get(2..)?;
+    if rest.get(..topic_len)? != topic.as_bytes() {
+        return None;
+    }
+    let rest = &rest[topic_len..];
+    if kind & 0x06 != 0 {
+        rest.get(2..)
+    } else {
+        Some(rest)
+    }
+}
+
+/// `"key":"value"` string value without the quotes.
+fn json_str<'a>(json: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let value = json_value(json, key)?.strip_prefix(b"\"")?;
+    Some(&value[..value.iter().position(|c| *c == b'"')?])
+}
+
+fn write_discovery(out: &mut String<MAX_PACKET_LEN>, hostname: &str, mac: &[u8; 6], topics: &Topics) {
+    let m = mac;
+    uwrite!(out, "{{\"schema\":\"json\",\"name\":null,\"uniq_id\":\"{}_{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}\",",
+        consts::HOSTNAME, m[0], m[1], m[2], m[3], m[4], m[5]).ok();
+    uwrite!(out, "\"cmd_t\":\"{}\",\"stat_t\":\"{}\",\"avty_t\":\"{}\",",
+        topics.command.as_str(), topics.state.as_str(), topics.availability.as_str()).ok();
+    out.push_str("\"brightness\":true,\"supported_color_modes\":[\"rgbw\"],\"effect\":true,\"effect_list\":[").ok();
+    for (i, effect) in EFFECTS.iter().enumerate() {
+        uwrite!(out, "{}\"{}\"", if i > 0 { "," } else { "" }, effect.name()).ok();
+    }
+    uwrite!(out, "],\"dev\":{{\"ids\":[\"{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}\"],\"name\":\"{}\",\"mf\":\"{}\",\"sw\":\"{}\"}}}}",
+        m[0], m[1], m[2], m[3], m[4], m[5], hostname, consts::HOSTNAME, env!("CARGO_PKG_VERSION")).ok();
+}
+
+/// Die temperature as a diagnostic sensor of the same device.
+fn write_temperature_discovery(out: &mut String<MAX_PACKET_LEN>, mac: &[u8; 6], topics: &Topics) {
+    let m = mac;
+    uwrite!(out, "{{\"name\":\"Temperature\",\"uniq_id\":\"{}_{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}_temperature\",",
+        consts::HOSTNAME, m[0], m[1], m[2], m[3], m[4], m[5]).ok();
+    uwrite!(out, "\"stat_t\":\"{}\",\"avty_t\":\"{}\",\"dev_cla\":\"temperature\",\"unit_of_meas\":\"°C\",",
+        topics.temperature.as_str(), topics.availability.as_str()).ok();
+    uwrite!(out, "\"stat_cla\":\"measurement\",\"ent_cat\":\"diagnostic\",\"dev\":{{\"ids\":[\"{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}\"]}}}}",
+        m[0], m[1], m[2], m[3], m[4], m[5]).ok();
+}
+
+/// Tenths of a degree as a decimal number.
+fn write_decicelsius(out: &mut String<MAX_PACKET_LEN>, decicelsius: i16) {
+    let sign = if decicelsius < 0 { "-" } else { "" };
+    let abs = decicelsius.unsigned_abs();
+    uwrite!(out, "{}{}.{}", sign, abs / 10, abs % 10).ok();
+}
+
+fn write_state(out: &mut String<MAX_PACKET_LEN>, status: &LedStatus) {
+    let c = status.primary_color;
+    uwrite!(out, "{{\"state\":\"{}\",\"brightness\":{},\"color_mode\":\"rgbw\",\"color\":{{\"r\":{},\"g\":{},\"b\":{},\"w\":{}}}",
+        if status.brightness > 0 { "ON" } else { "OFF" }, status.brightness, c.r, c.g, c.b, c.w).ok();
+    if EFFECTS.contains(&status.effect) {
+        uwrite!(out, ",\"effect\":\"{}\"", status.effect.name()).ok();
+    }
+    out.push('}').ok();
+}
+
+/// Maps HA JSON schema light commands onto the LED task.
+struct Light {
+    /// Brightness to restore on `"state":"ON"`.
+    on_brightness: u8,
+}
+
+impl Light {
+    fn apply_command(&mut self, body: &[u8]) {
+        let mut led_sender = get_led_sender();
+        let current = leds::LED_STATUS.lock(|cell| cell.get()).brightness;
+        let brightness = json_value(body, "brightness").and_then(json_u8);
+        if let Some(bri) = brightness {
+            self.on_brightness = bri.max(1);
+        }
+        match json_str(body, "state") {
+            Some(b"OFF") => {
+                if current > 0 {
+                    self.on_brightness = current;
+                }
+                led_sender.set_brightness(0);
+            }
+            Some(b"ON") => led_sender.set_brightness(brightness.unwrap_or(if current > 0 { current } else { self.on_brightness })),
+            _ => {}
+        }
+        if let Some(color) = json_value(body, "color") {
+            let channel = |key| json_value(color, key).and_then(json_u8).unwrap_or(0);
+            led_sender.set_primary_color(Color::from_rgbw(channel("r"), channel("g"), channel("b"), channel("w")));
+        }
+        if let Some(name) = json_str(body, "effect") {
+            match EFFECTS.iter().find(|effect| effect.name().as_bytes() == name) {
+                Some(effect) => led_sender.set_effect(*effect),
+                None => debug!("Ignoring unknown HA effect {}", name),
+            }
+        }
+    }
+}
+
+/// Keep a connection to the MQTT broker at `consts::MQTT_BROKER`, announce the LEDs as a
+/// Home Assistant light and the die temperature as a sensor through MQTT discovery, publish
+/// state changes and apply commands.
+pub async fn run<D: Driver>(stack: &Stack<D>, mac: [u8; 6]) -> ! {
+    let mut rx_buffer = [0; MAX_PACKET_LEN];
+    let mut tx_buffer = [0; MAX_PACKET_LEN];
+    let mut buffer = [0; MAX_PACKET_LEN];
+    let hostname: String<MAX_HOSTNAME_LEN> = config::get().hostname();
+    let topics = Topics::new(&hostname);
+    let mut light = Light { on_brightness: DEFAULT_ON_BRIGHTNESS };
+    let broker = IpEndpoint::new(IpAddress::Ipv4(consts::MQTT_BROKER), MQTT_PORT);
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(KEEP_ALIVE * 2));
+        if socket.connect(broker).await.is_err() {
+            warn!("MQTT connect to {} failed", broker);
+            Timer::after(RECONNECT_DELAY).await;
+            continue;
+        }
+
+        let mut discovery = String::<MAX_PACKET_LEN>::new();
+        write_discovery(&mut discovery, &hostname, &mac, &topics);
+        let mut temperature_discovery = String::<MAX_PACKET_LEN>::new();
+        write_temperature_discovery(&mut temperature_discovery, &mac, &topics);
+        let connected = socket.write_all(&encode_connect(&hostname, &topics)).await.is_ok()
+            && socket.write_all(&encode_publish(&topics.config, discovery.as_bytes())).await.is_ok()
+            && socket.write_all(&encode_publish(&topics.temperature_config, temperature_discovery.as_bytes())).await.is_ok()
+            && socket.write_all(&encode_publish(&topics.availability, b"online")).await.is_ok()
+            && socket.write_all(&encode_subscribe(&topics.command)).await.is_ok();
+        if connected {
+            info!("MQTT connected to {}", broker);
+        }
+
+        let mut len = 0;
+        let mut published = None;
+        let mut published_temperature = None;
+        let mut next_temperature = Instant::now();
+        let mut last_ping = Instant::now();
+        while connected {
+            match select(socket.read(&mut buffer[len..]), Timer::after(STATE_POLL_INTERVAL)).await {
+                Either::First(Ok(0) | Err(_)) => break,
+                Either::First(Ok(n)) => len += n,
+                Either::Second(_) => {}
+            }
+
+            while let Some((kind, body, packet_len)) = split_packet(&buffer[..len]) {
+                match kind & 0xF0 {
+                    CONNACK if body.get(1) != Some(&0) => {
+                        warn!("MQTT connection refused with code {}", body.get(1));
+                    }
+                    PUBLISH => match publish_payload(kind, body, &topics.command) {
+                        Some(payload) => light.apply_command(payload),
+                        None => debug!("Ignoring MQTT publish on another topic"),
+                    },
+                    _ => {}
+                }
+                buffer.copy_within(packet_len..len, 0);
+                len -= packet_len;
+            }
+            if len == buffer.len() {
+                warn!("MQTT packet too long");
+                break;
+            }
+
+            let status = leds::LED_STATUS.lock(|cell| cell.get());
+            if published != Some(status) {
+                let mut state = String::<MAX_PACKET_LEN>::new();
+                write_state(&mut state, &status);
+                if socket.write_all(&encode_publish(&topics.state, state.as_bytes())).await.is_err() {
+                    break;
+                }
+                published = Some(status);
+            }
+            let temperature = temperature::decicelsius();
+            if temperature != published_temperature && Instant::now() >= next_temperature {
+                if let Some(temperature) = temperature {
+                    let mut payload = String::<MAX_PACKET_LEN>::new();
+                    write_decicelsius(&mut payload, temperature);
+                    if socket.write_all(&encode_publish(&topics.temperature, payload.as_bytes())).await.is_err() {
+                        break;
+                    }
+                }
+                published_temperature = temperature;
+                next_temperature = Instant::now() + TEMPERATURE_INTERVAL;
+            }
+            if last_ping.elapsed() >= KEEP_ALIVE / 2 {
+                if socket.write_all(&[PINGREQ, 0]).await.is_err() {
+                    break;
+                }
+                last_ping = Instant::now();
+            }
+        }
+
+        warn!("MQTT connection to {} lost", broker);
+        socket.abort();
+        socket.flush().await.ok();
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,149 @@
+//! Decoder for the NEC infrared protocol most cheap remotes speak. A frame is a 9 ms mark and a
+//! 4.5 ms space, then 32 bits LSB first, each a 560 µs mark followed by a 560 µs space for 0 or
+//! a 1690 µs space for 1, and a closing mark. The bits are the address, the inverted address
+//! or the high byte of an extended address, the command and the inverted command. A key held
+//! down sends a repeat code every 110 ms instead: the 9 ms mark, a 2.25 ms space and a mark.
+
+const LEADER_MARK_US: u32 = 9000;
+const LEADER_SPACE_US: u32 = 4500;
+const REPEAT_SPACE_US: u32 = 2250;
+const BIT_MARK_US: u32 = 560;
+const ZERO_SPACE_US: u32 = 560;
+const ONE_SPACE_US: u32 = 1690;
+
+/// A key of a remote.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Code {
+    /// 8-bit addresses, sent with their inverse, are widened to 16 bits.
+    pub address: u16,
+    pub command: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Code(Code),
+    /// The last key is still held.
+    Repeat,
+}
+
+#[derive(Copy, Clone)]
+enum State {
+    Idle,
+    /// After the leader mark.
+    Leader,
+    /// After the space of bit `count`, or the leader space for 0.
+    Data { bits: u32, count: u8 },
+    /// After the repeat space.
+    Repeat,
+}
+
+/// Feed it the pulses from an active low receiver module, such as a TSOP38238, as they end.
+pub struct Decoder {
+    state: State,
+}
+
+/// Within 25% of `nominal`, receivers stretch marks and shorten spaces by about 100 µs.
+fn near(micros: u32, nominal: u32) -> bool {
+    (nominal * 3 / 4..=nominal * 5 / 4).contains(&micros)
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { state: State::Idle }
+    }
+
+    /// A pulse of `micros` ended, a mark if the receiver output was low. Anything out of place
+    /// drops the frame.
+    pub fn pulse(&mut self, mark: bool, micros: u32) -> Option<Event> {
+        let (state, event) = match (self.state, mark) {
+            (_, true) if near(micros, LEADER_MARK_US) => (State::Leader, None),
+            (State::Leader, false) if near(micros, LEADER_SPACE_US) => (State::Data { bits: 0, count: 0 }, None),
+            (State::Leader, false) if near(micros, REPEAT_SPACE_US) => (State::Repeat, None),
+            (State::Repeat, true) if near(micros, BIT_MARK_US) => (State::Idle, Some(Event::Repeat)),
+            (State::Data { bits, count: 32 }, true) if near(micros, BIT_MARK_US) => {
+                (State::Idle, Self::decode(bits).map(Event::Code))
+            }
+            (state @ State::Data { .. }, true) if near(micros, BIT_MARK_US) => (state, None),
+            (State::Data { bits, count }, false) if count < 32 && near(micros, ZERO_SPACE_US) => {
+                (State::Data { bits, count: count + 1 }, None)
+            }
+            (State::Data { bits, count }, false) if count < 32 && near(micros, ONE_SPACE_US) => {
+                (State::Data { bits: bits | 1 << count, count: count + 1 }, None)
+            }
+            _ => (State::Idle, None),
+        };
+        self.state = state;
+        event
+    }
+
+    fn decode(bits: u32) -> Option<Code> {
+        let [address, address_high, command, inverted_command] = bits.to_le_bytes();
+        if command != !inverted_command {
+            return None;
+        }
+        let address = if address_high == !address {
+            address as u16
+        } else {
+            u16::from_le_bytes([address, address_high])
+        };
+        Some(Code { address, command })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pulses of a frame carrying `bits`, mark first.
+    fn frame(bits: u32) -> Vec<(bool, u32)> {
+        let mut pulses = vec![(true, 9000), (false, 4500)];
+        for i in 0..32 {
+            pulses.push((true, 560));
+            pulses.push((false, if bits & 1 << i != 0 { 1690 } else { 560 }));
+        }
+        pulses.push((true, 560));
+        pulses
+    }
+
+    fn feed(decoder: &mut Decoder, pulses: &[(bool, u32)]) -> Vec<Event> {
+        pulses.iter().filter_map(|(mark, micros)| decoder.pulse(*mark, *micros)).collect()
+    }
+
+    #[test]
+    fn standard_frame() {
+        let mut decoder = Decoder::new();
+        // Address 0x00, command 0x45
+        let events = feed(&mut decoder, &frame(u32::from_le_bytes([0x00, 0xFF, 0x45, 0xBA])));
+        assert_eq!(events, [Event::Code(Code { address: 0x00, command: 0x45 })]);
+    }
+
+    #[test]
+    fn extended_address() {
+        let mut decoder = Decoder::new();
+        let events = feed(&mut decoder, &frame(u32::from_le_bytes([0x00, 0xEF, 0x03, 0xFC])));
+        assert_eq!(events, [Event::Code(Code { address: 0xEF00, command: 0x03 })]);
+    }
+
+    #[test]
+    fn corrupt_command_is_dropped() {
+        let mut decoder = Decoder::new();
+        assert!(feed(&mut decoder, &frame(u32::from_le_bytes([0x00, 0xFF, 0x45, 0xBB]))).is_empty());
+    }
+
+    #[test]
+    fn repeat_code() {
+        let mut decoder = Decoder::new();
+        assert_eq!(feed(&mut decoder, &[(true, 9100), (false, 2200), (true, 600)]), [Event::Repeat]);
+    }
+
+    #[test]
+    fn glitch_restarts_at_next_leader() {
+        let mut decoder = Decoder::new();
+        let mut pulses = frame(u32::from_le_bytes([0x00, 0xFF, 0x45, 0xBA]));
+        // A stray long space halfway through, then the full frame again
+        pulses.truncate(20);
+        pulses.push((false, 3000));
+        pulses.extend(frame(u32::from_le_bytes([0x00, 0xFF, 0x16, 0xE9])));
+        assert_eq!(feed(&mut decoder, &pulses), [Event::Code(Code { address: 0x00, command: 0x16 })]);
+    }
+}
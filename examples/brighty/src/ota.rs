@@ -0,0 +1,252 @@
+use core::cell::{Cell, RefCell};
+use core::cmp::min;
+use defmt::{info, warn, Format};
+use embassy_boot_rp::{AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_rp::flash::{ERASE_SIZE, WRITE_SIZE};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_time::Duration;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use siphasher::sip128::{Hasher128, SipHasher24};
+use core::hash::Hasher;
+use config_store::crc32;
+use crate::auth;
+use crate::storage::{self, StorageFlash, StorageSender};
+
+/// Largest slice of the image carried by one chunk command.
+pub const MAX_CHUNK_LEN: usize = 256;
+/// Length of the image tag, a SipHash-2-4 128 bit tag of the whole image keyed with
+/// `auth_key.bin`.
+pub const TAG_LEN: usize = 16;
+/// A freshly swapped in image has this long to call `confirm_boot`, otherwise the device resets
+/// and the bootloader swaps the previous image back.
+pub const BOOT_WINDOW: Duration = Duration::from_secs(180);
+
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum OtaState {
+    Idle = 0,
+    Receiving = 1,
+    /// All chunks received, checking the image tag.
+    Verifying = 2,
+    /// Image marked for swap, the device resets shortly.
+    Rebooting = 3,
+    Failed = 4,
+}
+
+/// Outcome of the OTA command a progress reply answers.
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum OtaResult {
+    Ok = 0,
+    /// Command not valid in the current state.
+    BadState = 1,
+    BadCrc = 2,
+    /// Chunk doesn't start at `received`, resume from there.
+    OutOfOrder = 3,
+    /// Storage queue full, retry the command.
+    Busy = 4,
+    TooLarge = 5,
+}
+
+#[derive(Copy, Clone)]
+pub struct OtaProgress {
+    pub state: OtaState,
+    pub size: u32,
+    /// Image bytes accepted so far, chunks count once queued for writing.
+    pub received: u32,
+}
+
+/// Progress of the current update, advanced by the command side and failed or completed by
+/// the storage task doing the flash writes.
+static PROGRESS: Mutex<CriticalSectionRawMutex, Cell<OtaProgress>> =
+    Mutex::new(Cell::new(OtaProgress { state: OtaState::Idle, size: 0, received: 0 }));
+
+pub fn progress() -> OtaProgress {
+    PROGRESS.lock(|cell| cell.get())
+}
+
+fn set_state(state: OtaState) {
+    PROGRESS.lock(|cell| cell.set(OtaProgress { state, ..cell.get() }));
+}
+
+#[derive(Copy, Clone)]
+pub enum OtaCommand {
+    Begin { size: u32, tag: [u8; TAG_LEN] },
+    Chunk { offset: u32, len: u16, data: [u8; MAX_CHUNK_LEN] },
+    Finish,
+}
+
+fn get_storage_sender() -> StorageSender {
+    storage::STORAGE_CHANNEL.sender()
+}
+
+/// Start receiving an image of `size` bytes, abandoning any update in progress.
+pub fn begin(size: u32, tag: [u8; TAG_LEN]) -> OtaResult {
+    if matches!(progress().state, OtaState::Verifying | OtaState::Rebooting) {
+        return OtaResult::BadState;
+    }
+    if !get_storage_sender().ota(OtaCommand::Begin { size, tag }) {
+        return OtaResult::Busy;
+    }
+    info!("OTA update of {} bytes started", size);
+    PROGRESS.lock(|cell| cell.set(OtaProgress { state: OtaState::Receiving, size, received: 0 }));
+    OtaResult::Ok
+}
+
+/// Queue the next slice of the image, chunks must arrive in order.
+pub fn chunk(offset: u32, crc: u32, data: &[u8]) -> OtaResult {
+    let progress = progress();
+    if progress.state != OtaState::Receiving {
+        return OtaResult::BadState;
+    }
+    if crc32(data) != crc {
+        return OtaResult::BadCrc;
+    }
+    if offset != progress.received {
+        return OtaResult::OutOfOrder;
+    }
+    if offset + data.len() as u32 > progress.size {
+        return OtaResult::TooLarge;
+    }
+    let mut buf = [0; MAX_CHUNK_LEN];
+    buf[..data.len()].copy_from_slice(data);
+    if !get_storage_sender().ota(OtaCommand::Chunk { offset, len: data.len() as u16, data: buf }) {
+        return OtaResult::Busy;
+    }
+    PROGRESS.lock(|cell| cell.set(OtaProgress { received: offset + data.len() as u32, ..progress }));
+    OtaResult::Ok
+}
+
+/// Verify the complete image and swap to it on the next boot.
+pub fn finish() -> OtaResult {
+    let progress = progress();
+    if progress.state != OtaState::Receiving || progress.received != progress.size {
+        return OtaResult::BadState;
+    }
+    if !get_storage_sender().ota(OtaCommand::Finish) {
+        return OtaResult::Busy;
+    }
+    set_state(OtaState::Verifying);
+    OtaResult::Ok
+}
+
+type Partition<'a, 'f> = BlockingPartition<'a, NoopRawMutex, &'f mut StorageFlash>;
+
+/// Report the running image healthy, it stays installed once the storage task marked it booted.
+pub fn confirm_boot() {
+    get_storage_sender().confirm_boot();
+}
+
+/// Whether the bootloader just swapped this image in, it rolls back on the next reset unless
+/// marked booted first.
+pub fn is_trial(flash: &mut StorageFlash) -> bool {
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(flash));
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut state = BlockingFirmwareState::new(config.state, &mut aligned.0);
+    state.get_state().is_ok_and(|state| state == State::Swap)
+}
+
+/// Tell the bootloader the running image is good, so it doesn't roll back on the next reset.
+/// Also required before another update can be written.
+pub fn mark_booted(flash: &mut StorageFlash) {
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(flash));
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut state = BlockingFirmwareState::new(config.state, &mut aligned.0);
+    if state.get_state().is_ok_and(|state| state != State::Boot) && state.mark_booted().is_err() {
+        warn!("failed to mark firmware as booted");
+    }
+}
+
+/// Writes an image received through `OtaCommand`s into the DFU partition, runs in the
+/// storage task which owns the flash.
+pub struct OtaWriter {
+    size: u32,
+    tag: [u8; TAG_LEN],
+    /// DFU sectors are erased lazily as chunks reach them.
+    erased_until: u32,
+}
+
+impl OtaWriter {
+    pub const fn new() -> Self {
+        Self { size: 0, tag: [0; TAG_LEN], erased_until: 0 }
+    }
+
+    pub fn process(&mut self, flash: &mut StorageFlash, cmd: &OtaCommand) {
+        // Chunks queued before a failure was noticed are dropped until the next begin
+        if progress().state == OtaState::Failed && !matches!(cmd, OtaCommand::Begin { .. }) {
+            return;
+        }
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(flash));
+        let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+        let mut dfu = config.dfu;
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+        let mut state = BlockingFirmwareState::new(config.state, &mut aligned.0);
+        let ok = match cmd {
+            OtaCommand::Begin { size, tag } => self.begin(&dfu, &mut state, *size, *tag),
+            OtaCommand::Chunk { offset, len, data } => self.write(&mut dfu, *offset, &data[..*len as usize]),
+            OtaCommand::Finish => self.finish(&mut dfu, &mut state),
+        };
+        if !ok {
+            set_state(OtaState::Failed);
+        }
+    }
+
+    fn begin(&mut self, dfu: &Partition<'_, '_>, state: &mut BlockingFirmwareState<Partition<'_, '_>>, size: u32, tag: [u8; TAG_LEN]) -> bool {
+        if !state.get_state().is_ok_and(|state| state == State::Boot) {
+            warn!("OTA rejected, the running image isn't marked booted");
+            return false;
+        }
+        // The bootloader needs one spare sector in the DFU partition to track the swap
+        if size as usize > dfu.capacity() - ERASE_SIZE {
+            warn!("OTA image of {} bytes doesn't fit the DFU partition", size);
+            return false;
+        }
+        *self = Self { size, tag, erased_until: 0 };
+        true
+    }
+
+    fn write(&mut self, dfu: &mut Partition<'_, '_>, offset: u32, data: &[u8]) -> bool {
+        let end = offset + data.len() as u32;
+        while self.erased_until < end {
+            if dfu.erase(self.erased_until, self.erased_until + ERASE_SIZE as u32).is_err() {
+                warn!("failed to erase DFU sector at {}", self.erased_until);
+                return false;
+            }
+            self.erased_until += ERASE_SIZE as u32;
+        }
+        if dfu.write(offset, data).is_err() {
+            warn!("failed to write OTA chunk at {}", offset);
+            return false;
+        }
+        true
+    }
+
+    /// Check the tag against what actually landed in flash, then mark the image for swap.
+    fn finish(&mut self, dfu: &mut Partition<'_, '_>, state: &mut BlockingFirmwareState<Partition<'_, '_>>) -> bool {
+        let mut hasher = SipHasher24::new_with_key(auth::AUTH_KEY);
+        let mut buf = [0; MAX_CHUNK_LEN];
+        let mut offset = 0;
+        while offset < self.size {
+            let len = min(MAX_CHUNK_LEN, (self.size - offset) as usize);
+            if dfu.read(offset, &mut buf[..len]).is_err() {
+                warn!("failed to read back DFU partition");
+                return false;
+            }
+            hasher.write(&buf[..len]);
+            offset += len as u32;
+        }
+        if !auth::tags_equal(&hasher.finish128().as_bytes(), &self.tag) {
+            warn!("OTA image tag mismatch");
+            return false;
+        }
+        if state.mark_updated().is_err() {
+            warn!("failed to mark OTA image for swap");
+            return false;
+        }
+        info!("OTA image verified, rebooting into it");
+        set_state(OtaState::Rebooting);
+        true
+    }
+}
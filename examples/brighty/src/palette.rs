@@ -0,0 +1,50 @@
+use crate::color::Color;
+
+pub const NUM_PALETTES: usize = 8;
+pub const PALETTE_LEN: usize = 16;
+
+/// Gradient of 16 colors sampled with linear interpolation, wrapping from the last entry back to the first.
+#[derive(Copy, Clone)]
+pub struct Palette {
+    pub colors: [Color; PALETTE_LEN],
+}
+
+impl Palette {
+    pub const SERIALIZED_LEN: usize = PALETTE_LEN * 4;
+
+    /// Palette spanning the full hue circle, matching the raw HSV rainbow.
+    pub fn rainbow() -> Self {
+        let mut colors = [Color::BLACK; PALETTE_LEN];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = Color::from_hsv((i * 0x10000 / PALETTE_LEN) as u16, 255, 255);
+        }
+        Self { colors }
+    }
+
+    /// Sample the palette at `pos`, where the full `u16` range covers all entries once.
+    pub fn sample(&self, pos: u16) -> Color {
+        let scaled = pos as u32 * PALETTE_LEN as u32;
+        let ia = (scaled >> 16) as usize;
+        let ib = (ia + 1) % PALETTE_LEN;
+        let t = (scaled >> 8) & 0xFF;
+        let (a, b) = (&self.colors[ia], &self.colors[ib]);
+        let lerp = |a: u8, b: u8| ((a as u32 * (256 - t) + b as u32 * t) >> 8) as u8;
+        Color::from_rgbw(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b), lerp(a.w, b.w))
+    }
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        for (color, chunk) in self.colors.iter().zip(bytes.chunks_exact_mut(4)) {
+            chunk.copy_from_slice(&[color.r, color.g, color.b, color.w]);
+        }
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Self {
+        let mut colors = [Color::BLACK; PALETTE_LEN];
+        for (color, chunk) in colors.iter_mut().zip(bytes.chunks_exact(4)) {
+            *color = Color::from_rgbw(chunk[0], chunk[1], chunk[2], chunk[3]);
+        }
+        Self { colors }
+    }
+}
+
+pub type Palettes = [Palette; NUM_PALETTES];
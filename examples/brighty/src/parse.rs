@@ -0,0 +1,125 @@
+use core::cmp::min;
+use nom::{IResult, bytes::complete::take, combinator::{map, verify}, number::complete::{le_u32, u8}, sequence::tuple};
+use crate::color::Color;
+use crate::keyframe::{Keyframe, KeyframeSequence, MAX_SEQUENCE_KEYFRAMES};
+
+/// Color count, then that many RGBW colors. Colors past `N` are skipped, LEDs past the count
+/// are black.
+pub fn parse_color_list<const N: usize>(input: &[u8]) -> IResult<&[u8], [Color; N]> {
+    let (input, color_count) = u8(input)?;
+    let num_color_bytes = color_count as usize * 4;
+    map(take(num_color_bytes), |color_bytes: &[u8]| {
+        let mut colors = [Color::BLACK; N];
+        for i in 0..min(N, color_bytes.len() / 4) {
+            colors[i] = Color::from_rgbw(color_bytes[i * 4],
+                                         color_bytes[i * 4 + 1],
+                                         color_bytes[i * 4 + 2],
+                                         color_bytes[i * 4 + 3]);
+        }
+        colors
+    })(input)
+}
+
+pub fn parse_color(input: &[u8]) -> IResult<&[u8], Color> {
+    map(take(4usize), |color_bytes: &[u8]| {
+        Color::from_rgbw(color_bytes[0],
+                         color_bytes[1],
+                         color_bytes[2],
+                         color_bytes[3])
+    })(input)
+}
+
+pub fn parse_keyframe(input: &[u8]) -> IResult<&[u8], Keyframe> {
+    map(tuple((le_u32, parse_color)), |(frame, color)| Keyframe { frame, color })(input)
+}
+
+/// Keyframes must start at frame 0 and be strictly increasing, as `KeyframeReader` expects.
+pub fn parse_keyframe_sequence(input: &[u8]) -> IResult<&[u8], KeyframeSequence> {
+    let (mut input, (first_led, led_count, len)) =
+        tuple((u8, u8, verify(u8, |len| *len as usize <= MAX_SEQUENCE_KEYFRAMES)))(input)?;
+    let mut keyframes = [Keyframe::EMPTY; MAX_SEQUENCE_KEYFRAMES];
+    for i in 0..len as usize {
+        let (rest, keyframe) = verify(parse_keyframe, |kf: &Keyframe| {
+            if i == 0 { kf.frame == 0 } else { kf.frame > keyframes[i - 1].frame }
+        })(input)?;
+        keyframes[i] = keyframe;
+        input = rest;
+    }
+    Ok((input, KeyframeSequence { first_led, led_count, keyframes, len }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_list_fills_missing_leds_with_black() {
+        let (rest, colors) = parse_color_list::<3>(&[1, 10, 20, 30, 40, 0xAA]).unwrap();
+        assert_eq!(rest, &[0xAA]);
+        assert!(colors == [Color::from_rgbw(10, 20, 30, 40), Color::BLACK, Color::BLACK]);
+    }
+
+    #[test]
+    fn color_list_skips_colors_past_capacity() {
+        let (rest, colors) = parse_color_list::<1>(&[2, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert!(rest.is_empty());
+        assert!(colors == [Color::from_rgbw(1, 2, 3, 4)]);
+    }
+
+    #[test]
+    fn truncated_color_list_is_rejected() {
+        assert!(parse_color_list::<4>(&[]).is_err());
+        assert!(parse_color_list::<4>(&[2, 1, 2, 3, 4, 5, 6, 7]).is_err());
+    }
+
+    #[test]
+    fn truncated_color_is_rejected() {
+        assert!(parse_color(&[1, 2, 3]).is_err());
+    }
+
+    fn keyframe_bytes(frame: u32, color: [u8; 4]) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&frame.to_le_bytes());
+        bytes[4..].copy_from_slice(&color);
+        bytes
+    }
+
+    #[test]
+    fn keyframe_sequence() {
+        let mut input = vec![4, 2, 2];
+        input.extend_from_slice(&keyframe_bytes(0, [255, 0, 0, 0]));
+        input.extend_from_slice(&keyframe_bytes(1000, [0, 0, 255, 0]));
+        let (rest, sequence) = parse_keyframe_sequence(&input).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!((sequence.first_led, sequence.led_count, sequence.len), (4, 2, 2));
+        assert_eq!(sequence.keyframes[1].frame, 1000);
+        assert!(sequence.keyframes[1].color == Color::from_rgbw(0, 0, 255, 0));
+    }
+
+    #[test]
+    fn keyframe_sequence_not_starting_at_zero_is_rejected() {
+        let mut input = vec![0, 1, 1];
+        input.extend_from_slice(&keyframe_bytes(5, [0; 4]));
+        assert!(parse_keyframe_sequence(&input).is_err());
+    }
+
+    #[test]
+    fn keyframe_sequence_not_increasing_is_rejected() {
+        let mut input = vec![0, 1, 2];
+        input.extend_from_slice(&keyframe_bytes(0, [0; 4]));
+        input.extend_from_slice(&keyframe_bytes(0, [0; 4]));
+        assert!(parse_keyframe_sequence(&input).is_err());
+    }
+
+    #[test]
+    fn overlong_keyframe_sequence_is_rejected() {
+        assert!(parse_keyframe_sequence(&[0, 1, MAX_SEQUENCE_KEYFRAMES as u8 + 1]).is_err());
+    }
+
+    #[test]
+    fn truncated_keyframe_sequence_is_rejected() {
+        let mut input = vec![0, 1, 2];
+        input.extend_from_slice(&keyframe_bytes(0, [0; 4]));
+        assert!(parse_keyframe_sequence(&input).is_err());
+    }
+}
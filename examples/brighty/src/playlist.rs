@@ -0,0 +1,91 @@
+use num::FromPrimitive;
+use crate::leds::{Effect, EffectParams};
+
+pub const MAX_PLAYLIST_ENTRIES: usize = 8;
+
+/// One step of a playlist: an effect and its params, shown for `duration_s` seconds.
+#[derive(Copy, Clone)]
+pub struct PlaylistEntry {
+    pub effect: Effect,
+    pub params: EffectParams,
+    pub duration_s: u16,
+}
+
+impl PlaylistEntry {
+    pub const EMPTY: PlaylistEntry = PlaylistEntry {
+        effect: Effect::Static,
+        params: EffectParams::DEFAULT,
+        duration_s: 0,
+    };
+
+    pub const SERIALIZED_LEN: usize = 8;
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0] = self.effect as u8;
+        bytes[1..3].copy_from_slice(&self.params.speed.to_le_bytes());
+        bytes[3] = self.params.intensity;
+        bytes[4] = self.params.option;
+        bytes[5] = self.params.palette.unwrap_or(0xFF);
+        bytes[6..8].copy_from_slice(&self.duration_s.to_le_bytes());
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let palette = bytes[5];
+        Some(Self {
+            effect: Effect::from_u8(bytes[0])?,
+            params: EffectParams {
+                speed: u16::from_le_bytes([bytes[1], bytes[2]]),
+                intensity: bytes[3],
+                option: bytes[4],
+                palette: (palette != 0xFF).then_some(palette),
+            },
+            duration_s: u16::from_le_bytes([bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// Effects cycled automatically, crossfading over `crossfade_ms` between entries.
+/// An empty playlist leaves effect selection to the controller.
+#[derive(Copy, Clone)]
+pub struct Playlist {
+    pub entries: [PlaylistEntry; MAX_PLAYLIST_ENTRIES],
+    pub len: u8,
+    pub crossfade_ms: u16,
+}
+
+impl Playlist {
+    pub const EMPTY: Playlist = Playlist {
+        entries: [PlaylistEntry::EMPTY; MAX_PLAYLIST_ENTRIES],
+        len: 0,
+        crossfade_ms: 0,
+    };
+
+    pub const SERIALIZED_LEN: usize = 3 + MAX_PLAYLIST_ENTRIES * PlaylistEntry::SERIALIZED_LEN;
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0..2].copy_from_slice(&self.crossfade_ms.to_le_bytes());
+        bytes[2] = self.len;
+        for (entry, chunk) in self.entries.iter().zip(bytes[3..].chunks_exact_mut(PlaylistEntry::SERIALIZED_LEN)) {
+            entry.serialize(chunk);
+        }
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let len = bytes[2];
+        if len as usize > MAX_PLAYLIST_ENTRIES {
+            return None;
+        }
+        let mut playlist = Playlist {
+            len,
+            crossfade_ms: u16::from_le_bytes([bytes[0], bytes[1]]),
+            ..Playlist::EMPTY
+        };
+        for (entry, chunk) in playlist.entries[..len as usize]
+            .iter_mut()
+            .zip(bytes[3..].chunks_exact(PlaylistEntry::SERIALIZED_LEN))
+        {
+            *entry = PlaylistEntry::deserialize(chunk)?;
+        }
+        Some(playlist)
+    }
+}
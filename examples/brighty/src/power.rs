@@ -0,0 +1,67 @@
+use core::cell::Cell;
+use cyw43::PowerManagementMode;
+use defmt::debug;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+/// Performance mode is kept this long after the last stream frame.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static LAST_ACTIVITY: Mutex<CriticalSectionRawMutex, Cell<Instant>> = Mutex::new(Cell::new(Instant::MIN));
+static ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Record latency sensitive traffic, such as a realtime stream frame. Cheap enough to call for
+/// every frame.
+pub fn note_activity() {
+    LAST_ACTIVITY.lock(|cell| cell.set(Instant::now()));
+    ACTIVITY.signal(());
+}
+
+fn idle_at() -> Instant {
+    LAST_ACTIVITY.lock(|cell| cell.get()).checked_add(IDLE_TIMEOUT).unwrap_or(Instant::MIN)
+}
+
+/// Power save while idle, performance while streams are running. Power save delays received
+/// frames by up to a beacon interval, too much for a stream but fine for the odd command.
+pub struct PowerManager {
+    performance: bool,
+}
+
+impl PowerManager {
+    pub const fn new() -> Self {
+        Self { performance: false }
+    }
+
+    pub fn mode(&self) -> PowerManagementMode {
+        if self.performance {
+            PowerManagementMode::Performance
+        } else {
+            PowerManagementMode::PowerSave
+        }
+    }
+
+    /// Resolve with the mode to switch to once activity starts or stops. Cancel safe.
+    pub async fn next_mode(&mut self) -> PowerManagementMode {
+        loop {
+            if self.performance {
+                let idle_at = idle_at();
+                if Instant::now() >= idle_at {
+                    debug!("idle, returning to power save");
+                    self.performance = false;
+                    return self.mode();
+                }
+                Timer::at(idle_at).await;
+            } else {
+                ACTIVITY.wait().await;
+                // The signal may be left over from activity that has already timed out
+                if Instant::now() < idle_at() {
+                    debug!("activity, switching to performance mode");
+                    self.performance = true;
+                    return self.mode();
+                }
+            }
+        }
+    }
+}
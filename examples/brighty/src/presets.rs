@@ -0,0 +1,91 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use crate::leds::{LedSender, LedStatus};
+
+pub const NUM_PRESETS: usize = 8;
+pub const MAX_PRESET_NAME_LEN: usize = 16;
+
+/// Saved look recalled with a single index, so simple remotes don't need to resend every
+/// parameter.
+#[derive(Copy, Clone)]
+pub struct Preset {
+    name: [u8; MAX_PRESET_NAME_LEN],
+    name_len: u8,
+    pub status: LedStatus,
+}
+
+pub type Presets = [Option<Preset>; NUM_PRESETS];
+
+impl Preset {
+    /// Name length, name, then the `LedStatus`. A name length of 0xFF marks an empty slot,
+    /// which is also what erased flash reads as.
+    pub const SERIALIZED_LEN: usize = 1 + MAX_PRESET_NAME_LEN + LedStatus::SERIALIZED_LEN;
+
+    /// Names longer than `MAX_PRESET_NAME_LEN` are truncated.
+    pub fn new(name: &[u8], status: LedStatus) -> Self {
+        let name_len = name.len().min(MAX_PRESET_NAME_LEN);
+        let mut preset = Self { name: [0; MAX_PRESET_NAME_LEN], name_len: name_len as u8, status };
+        preset.name[..name_len].copy_from_slice(&name[..name_len]);
+        preset
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    pub fn serialize(preset: &Option<Preset>, bytes: &mut [u8]) {
+        let Some(preset) = preset else {
+            bytes[0] = 0xFF;
+            return;
+        };
+        bytes[0] = preset.name_len;
+        bytes[1..1 + MAX_PRESET_NAME_LEN].copy_from_slice(&preset.name);
+        preset.status.serialize(&mut bytes[1 + MAX_PRESET_NAME_LEN..]);
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Preset> {
+        if bytes[0] as usize > MAX_PRESET_NAME_LEN {
+            return None;
+        }
+        let status = LedStatus::deserialize(&bytes[1 + MAX_PRESET_NAME_LEN..])?;
+        Some(Self::new(&bytes[1..1 + bytes[0] as usize], status))
+    }
+}
+
+/// Presets loaded at boot, read by recall commands and updated alongside their flash copy.
+static PRESETS: Mutex<CriticalSectionRawMutex, Cell<Presets>> = Mutex::new(Cell::new([None; NUM_PRESETS]));
+
+pub fn get(index: usize) -> Option<Preset> {
+    PRESETS.lock(|cell| cell.get().get(index).copied().flatten())
+}
+
+pub fn all() -> Presets {
+    PRESETS.lock(|cell| cell.get())
+}
+
+pub fn set_all(presets: Presets) {
+    PRESETS.lock(|cell| cell.set(presets));
+}
+
+pub fn set(index: usize, preset: Option<Preset>) {
+    PRESETS.lock(|cell| {
+        let mut presets = cell.get();
+        if let Some(slot) = presets.get_mut(index) {
+            *slot = preset;
+        }
+        cell.set(presets);
+    });
+}
+
+/// Switch the LEDs to the preset at `index`, returns false if the slot is empty.
+pub fn recall(index: usize, sender: &mut LedSender) -> bool {
+    let Some(preset) = get(index) else {
+        return false;
+    };
+    sender.set_effect(preset.status.effect);
+    sender.set_effect_params(preset.status.params);
+    sender.set_primary_color(preset.status.primary_color);
+    sender.set_brightness(preset.status.brightness);
+    true
+}
@@ -0,0 +1,355 @@
+use defmt::{debug, info, warn};
+use embassy_futures::join::join4;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_time::{block_for, Duration};
+use heapless::String;
+use net_setup::http;
+use net_setup::status_led::{self, LinkStatus, StatusLed};
+use nom::{IResult, bytes::complete::{tag, take}, combinator::map_opt, number::complete::u8, sequence::{preceded, tuple}};
+use ufmt::uwrite;
+use crate::{config, consts, define_peripheral_set, provision_peripherals};
+use crate::storage::{self, StorageSender};
+use crate::watchdog::{self, Subsystem};
+
+/// Channel of the setup access point.
+const SETUP_AP_CHANNEL: u8 = 6;
+const AP_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+const AP_PREFIX_LEN: u8 = 24;
+/// Clients get addresses from AP_ADDRESS + 1 on.
+const MAX_LEASES: usize = 8;
+const LEASE_TIME_S: u32 = 3600;
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+/// BOOTP header up to and including the magic cookie.
+const DHCP_HEADER_LEN: usize = 240;
+/// Replies are padded to the minimum BOOTP message size, some clients drop shorter ones.
+const DHCP_REPLY_LEN: usize = 300;
+const UDP_PROVISION_MAGIC: [u8; 4] = *b"WIFI";
+const MAX_REQUEST_LEN: usize = 1024;
+
+const SETUP_PAGE: &str = "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+<title>brighty setup</title></head><body><h1>brighty setup</h1><form method=\"POST\" action=\"/\">\
+<p>Network <input name=\"ssid\" maxlength=\"32\"></p><p>Password <input name=\"psk\" type=\"password\" maxlength=\"63\"></p>\
+<p><input type=\"submit\" value=\"Save\"></p></form></body></html>";
+const SAVED_PAGE: &str = "<!DOCTYPE html><html><body><h1>Saved</h1><p>Rebooting into the network.</p></body></html>";
+const INVALID_PAGE: &str = "<!DOCTYPE html><html><body><h1>Invalid network</h1><p><a href=\"/\">Back</a></p></body></html>";
+
+provision_peripherals!(define_peripheral_set);
+
+/// True if the setup button (to ground) is held at power-on.
+pub fn setup_requested(p: ProvisionPeripherals) -> bool {
+    // Let the pull-up charge the line
+    block_for(Duration::from_millis(1));
+    p.button.is_low()
+}
+
+/// Station credentials, an empty passphrase joins an open network.
+#[derive(Copy, Clone)]
+pub struct WifiCredentials {
+    ssid: [u8; 32],
+    ssid_len: u8,
+    passphrase: [u8; 63],
+    passphrase_len: u8,
+}
+
+impl WifiCredentials {
+    pub const SERIALIZED_LEN: usize = 1 + 32 + 1 + 63;
+
+    pub fn new(ssid: &[u8], passphrase: &[u8]) -> Option<Self> {
+        let valid_passphrase = passphrase.is_empty() || (8..=63).contains(&passphrase.len());
+        if ssid.is_empty() || ssid.len() > 32 || !valid_passphrase {
+            return None;
+        }
+        core::str::from_utf8(ssid).ok()?;
+        core::str::from_utf8(passphrase).ok()?;
+        let mut credentials = Self { ssid: [0; 32], ssid_len: ssid.len() as u8, passphrase: [0; 63], passphrase_len: passphrase.len() as u8 };
+        credentials.ssid[..ssid.len()].copy_from_slice(ssid);
+        credentials.passphrase[..passphrase.len()].copy_from_slice(passphrase);
+        Some(credentials)
+    }
+
+    pub fn ssid(&self) -> &str {
+        core::str::from_utf8(&self.ssid[..self.ssid_len as usize]).unwrap_or("")
+    }
+
+    pub fn passphrase(&self) -> &str {
+        core::str::from_utf8(&self.passphrase[..self.passphrase_len as usize]).unwrap_or("")
+    }
+
+    pub fn serialize(&self, bytes: &mut [u8]) {
+        bytes[0] = self.ssid_len;
+        bytes[1..33].copy_from_slice(&self.ssid);
+        bytes[33] = self.passphrase_len;
+        bytes[34..97].copy_from_slice(&self.passphrase);
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let ssid_len = (bytes[0] as usize).min(32);
+        let passphrase_len = (bytes[33] as usize).min(63);
+        Self::new(&bytes[1..1 + ssid_len], &bytes[34..34 + passphrase_len])
+    }
+}
+
+/// Scan all channels, logging every network heard, and return the BSSID of `ssid` with the
+/// strongest signal.
+async fn strongest_bssid(control: &mut cyw43::Control<'_>, ssid: &str) -> Option<[u8; 6]> {
+    let mut scanner = control.scan(Default::default()).await;
+    let mut best: Option<([u8; 6], i16)> = None;
+    while let Some(bss) = scanner.next().await {
+        let (bssid, rssi, channel) = (bss.bssid, bss.rssi, bss.ctl_ch);
+        let name = &bss.ssid[..(bss.ssid_len as usize).min(32)];
+        info!("scan: {} {:02x} channel {} rssi {}", core::str::from_utf8(name).unwrap_or("?"), bssid, channel, rssi);
+        if name == ssid.as_bytes() && best.map_or(true, |(_, best_rssi)| rssi > best_rssi) {
+            best = Some((bssid, rssi));
+        }
+    }
+    best.map(|(bssid, _)| bssid)
+}
+
+/// Join the network in `credentials` as a station through its strongest access point, falling
+/// back to WPA3 for networks that don't accept WPA2.
+pub async fn join(control: &mut cyw43::Control<'_>, credentials: &WifiCredentials) -> Result<(), cyw43::ControlError> {
+    // Without a pin the firmware sticks to whichever access point it finds first
+    let bssid = strongest_bssid(control, credentials.ssid()).await;
+    control.set_join_bssid(bssid);
+    let result = if credentials.passphrase().is_empty() {
+        control.join_open(credentials.ssid()).await
+    } else {
+        match control.join_wpa2(credentials.ssid(), credentials.passphrase()).await {
+            Err(err) if err.is_security_mismatch() => {
+                info!("WPA2 join failed with status={}, trying WPA3", err.status);
+                control.join_wpa3(credentials.ssid(), credentials.passphrase()).await
+            }
+            result => result,
+        }
+    };
+    // Even a refused join shows the cyw43 is answering
+    watchdog::check_in(Subsystem::Net);
+    result
+}
+
+/// Static address of the device on its setup access point.
+pub fn ap_config() -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_ADDRESS, AP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: heapless::Vec::new(),
+    })
+}
+
+fn get_storage_sender() -> StorageSender {
+    storage::STORAGE_CHANNEL.sender()
+}
+
+/// Store the credentials and reboot into station mode once storage has written them.
+pub fn provision(credentials: WifiCredentials) {
+    info!("provisioned network {}", credentials.ssid());
+    let mut sender = get_storage_sender();
+    sender.save_wifi_credentials(credentials);
+    sender.reboot();
+}
+
+/// Addresses handed out by the setup AP, by client hardware address.
+struct Leases {
+    clients: [Option<[u8; 6]>; MAX_LEASES],
+    next_evict: usize,
+}
+
+impl Leases {
+    fn address_for(&mut self, client: [u8; 6]) -> Ipv4Address {
+        let index = match self.clients.iter().position(|lease| *lease == Some(client)) {
+            Some(index) => index,
+            None => {
+                let index = self.clients.iter().position(Option::is_none).unwrap_or_else(|| {
+                    let index = self.next_evict;
+                    self.next_evict = (self.next_evict + 1) % MAX_LEASES;
+                    index
+                });
+                self.clients[index] = Some(client);
+                index
+            }
+        };
+        let [a, b, c, d] = AP_ADDRESS.0;
+        Ipv4Address::new(a, b, c, d + 1 + index as u8)
+    }
+}
+
+struct DhcpRequest<'a> {
+    xid: &'a [u8],
+    flags: &'a [u8],
+    client: [u8; 6],
+    message_type: u8,
+}
+
+fn parse_dhcp_request(input: &[u8]) -> IResult<&[u8], DhcpRequest> {
+    // op, htype, hlen, hops, xid, secs, flags, ciaddr/yiaddr/siaddr/giaddr, chaddr, sname and file
+    let (options, (_, _, _, _, xid, _, flags, _, chaddr, _, _)) = tuple((
+        tag([1]), u8, u8, u8, take(4_usize), take(2_usize), take(2_usize), take(16_usize), take(16_usize), take(192_usize),
+        tag(DHCP_MAGIC_COOKIE),
+    ))(input)?;
+    let message_type = dhcp_option(options, 53).and_then(|value| value.first().copied()).unwrap_or(0);
+    Ok((&[], DhcpRequest { xid, flags, client: chaddr[..6].try_into().unwrap(), message_type }))
+}
+
+/// Value of option `code` in a DHCP options field.
+fn dhcp_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match options {
+            [] | [255, ..] => return None,
+            [0, rest @ ..] => options = rest,
+            [c, len, rest @ ..] => {
+                let value = rest.get(..*len as usize)?;
+                if *c == code {
+                    return Some(value);
+                }
+                options = &rest[*len as usize..];
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn encode_dhcp_reply(request: &DhcpRequest, message_type: u8, address: Ipv4Address) -> [u8; DHCP_REPLY_LEN] {
+    let mut reply = [0; DHCP_REPLY_LEN];
+    reply[0..4].copy_from_slice(&[2, 1, 6, 0]);
+    reply[4..8].copy_from_slice(request.xid);
+    reply[10..12].copy_from_slice(request.flags);
+    reply[16..20].copy_from_slice(&address.0);
+    reply[20..24].copy_from_slice(&AP_ADDRESS.0);
+    reply[28..34].copy_from_slice(&request.client);
+    reply[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+    let mut options = &mut reply[DHCP_HEADER_LEN..];
+    let mut put = |code: u8, value: &[u8]| {
+        options[0] = code;
+        options[1] = value.len() as u8;
+        options[2..2 + value.len()].copy_from_slice(value);
+        options = &mut core::mem::take(&mut options)[2 + value.len()..];
+    };
+    put(53, &[message_type]);
+    put(54, &AP_ADDRESS.0);
+    put(51, &LEASE_TIME_S.to_be_bytes());
+    put(1, &[255, 255, 255, 0]);
+    put(3, &AP_ADDRESS.0);
+    put(255, &[]);
+    reply
+}
+
+/// Minimal DHCP server so phones joining the setup AP get an address.
+async fn run_dhcp_server<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * DHCP_REPLY_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(DHCP_SERVER_PORT).is_err() {
+        warn!("failed to bind DHCP server");
+    }
+    let mut leases = Leases { clients: [None; MAX_LEASES], next_evict: 0 };
+    let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), DHCP_CLIENT_PORT);
+    loop {
+        let reply = socket.recv_from_with(|packet, _| {
+            let (_, request) = parse_dhcp_request(packet).ok()?;
+            let reply_type = match request.message_type {
+                DHCP_DISCOVER => DHCP_OFFER,
+                DHCP_REQUEST => DHCP_ACK,
+                _ => return None,
+            };
+            let address = leases.address_for(request.client);
+            debug!("DHCP {} for {} gets {}", request.message_type, request.client, address);
+            Some(encode_dhcp_reply(&request, reply_type, address))
+        }).await;
+        if let Some(reply) = reply {
+            socket.send_to(&reply, broadcast).await.ok();
+        }
+    }
+}
+
+fn credentials_from_form(body: &[u8]) -> Option<WifiCredentials> {
+    let (mut ssid, mut passphrase) = ([0; 32], [0; 63]);
+    let ssid = http::form_value(body, b"ssid", &mut ssid)?;
+    let passphrase = http::form_value(body, b"psk", &mut passphrase).unwrap_or(&[]);
+    WifiCredentials::new(ssid, passphrase)
+}
+
+/// Serve the setup form on port 80, one connection at a time.
+async fn run_http<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_buffer = [0; MAX_REQUEST_LEN];
+    let mut tx_buffer = [0; MAX_REQUEST_LEN];
+    let mut request = [0; MAX_REQUEST_LEN];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if socket.accept(http::HTTP_PORT).await.is_err() {
+            continue;
+        }
+
+        let parsed = http::read_request(&mut socket, &mut request).await;
+
+        // Anything but a valid POST gets the form, so captive portal probes land on it too
+        let credentials = parsed.filter(|(method, _, _)| *method == b"POST").map(|(_, _, body)| credentials_from_form(body));
+        let page = match credentials {
+            Some(Some(_)) => SAVED_PAGE,
+            Some(None) => INVALID_PAGE,
+            None => SETUP_PAGE,
+        };
+        http::respond(&mut socket, "200 OK", http::CONTENT_HTML, page.as_bytes()).await;
+        if let Some(Some(credentials)) = credentials {
+            provision(credentials);
+        }
+    }
+}
+
+/// Magic, then the SSID and passphrase each prefixed with their length.
+fn parse_provision_packet(input: &[u8]) -> IResult<&[u8], WifiCredentials> {
+    let (input, ssid_len) = preceded(tag(UDP_PROVISION_MAGIC), u8)(input)?;
+    let (input, ssid) = take(ssid_len as usize)(input)?;
+    let (input, passphrase_len) = u8(input)?;
+    map_opt(take(passphrase_len as usize), |passphrase| WifiCredentials::new(ssid, passphrase))(input)
+}
+
+/// Accept credentials from apps in a single datagram on `consts::CMD_PORT`, acknowledged with
+/// the magic.
+async fn run_udp<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 32];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(consts::CMD_PORT).is_err() {
+        warn!("failed to bind provisioning socket");
+    }
+    loop {
+        let received = socket.recv_from_with(|packet, meta| {
+            parse_provision_packet(packet).ok().map(|(_, credentials)| (credentials, meta.endpoint))
+        }).await;
+        if let Some((credentials, endpoint)) = received {
+            socket.send_to(&UDP_PROVISION_MAGIC, endpoint).await.ok();
+            provision(credentials);
+        }
+    }
+}
+
+/// Run the open setup access point `<hostname>-setup-XXYY` until credentials are received,
+/// blinking the status LED meanwhile. The stack must have been created with `ap_config`.
+pub async fn run<D: Driver>(
+    control: &mut cyw43::Control<'_>,
+    stack: &Stack<D>,
+    mac: &[u8; 6],
+    led: &mut StatusLed,
+) -> ! {
+    let mut ssid = String::<32>::new();
+    uwrite!(ssid, "{}-setup-{:02X}{:02X}", config::get().hostname().as_str(), mac[4], mac[5]).ok();
+    info!("starting setup access point {}", ssid.as_str());
+    control.start_ap_open(&ssid, SETUP_AP_CHANNEL).await;
+    status_led::set_status(LinkStatus::Setup);
+    join4(run_dhcp_server(stack), run_http(stack), run_udp(stack), status_led::drive(led, control)).await;
+    unreachable!()
+}
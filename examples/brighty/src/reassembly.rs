@@ -0,0 +1,85 @@
+use defmt::{debug, warn};
+use embassy_net::IpEndpoint;
+use embassy_time::{Duration, Instant};
+use nom::{IResult, bytes::complete::tag, combinator::{map, rest}, number::complete::le_u16, sequence::{preceded, tuple}};
+
+/// Starts a fragment datagram, never a command byte or the header magic.
+const FRAGMENT_MAGIC: [u8; 2] = *b"MF";
+/// Largest payload that can be reassembled from fragments.
+pub const MAX_REASSEMBLED_LEN: usize = 1024;
+/// Transfers that stop receiving fragments for this long are dropped.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Magic, le_u16 transfer id, le_u16 offset of this fragment's data in the payload, le_u16
+/// total payload length, then the data. The reassembled payload is handled like a datagram
+/// received in one piece.
+pub struct Fragment<'a> {
+    transfer_id: u16,
+    offset: u16,
+    total: u16,
+    data: &'a [u8],
+}
+
+pub fn parse_fragment(input: &[u8]) -> IResult<&[u8], Fragment<'_>> {
+    preceded(
+        tag(FRAGMENT_MAGIC),
+        map(tuple((le_u16, le_u16, le_u16, rest)), |(transfer_id, offset, total, data)| {
+            Fragment { transfer_id, offset, total, data }
+        })
+    )(input)
+}
+
+/// Collects one transfer at a time. Fragments have to arrive in order, a gap abandons the
+/// transfer and the sender retries it with a new id; repeated fragments are ignored.
+pub struct Reassembler {
+    source: Option<(IpEndpoint, u16)>,
+    total: usize,
+    received: usize,
+    last_fragment: Instant,
+    buffer: [u8; MAX_REASSEMBLED_LEN],
+}
+
+impl Reassembler {
+    pub const fn new() -> Self {
+        Self { source: None, total: 0, received: 0, last_fragment: Instant::MIN, buffer: [0; MAX_REASSEMBLED_LEN] }
+    }
+
+    /// Add a fragment from `endpoint`, returning the payload once it is complete.
+    pub fn add(&mut self, endpoint: IpEndpoint, fragment: Fragment<'_>) -> Option<&[u8]> {
+        let total = fragment.total as usize;
+        if total > MAX_REASSEMBLED_LEN {
+            warn!("fragmented payload of {} octets is too large", total);
+            return None;
+        }
+        let source = (endpoint, fragment.transfer_id);
+        let expired = Instant::now() > self.last_fragment + REASSEMBLY_TIMEOUT;
+        if self.source != Some(source) || expired {
+            if fragment.offset != 0 {
+                return None;
+            }
+            // A new transfer replaces one still in progress, controllers send one at a time
+            self.source = Some(source);
+            self.total = total;
+            self.received = 0;
+        }
+
+        let offset = fragment.offset as usize;
+        if offset < self.received || total != self.total {
+            return None;
+        }
+        if offset > self.received || offset + fragment.data.len() > total {
+            debug!("dropping transfer {} after a missing fragment", fragment.transfer_id);
+            self.source = None;
+            return None;
+        }
+        self.buffer[offset..offset + fragment.data.len()].copy_from_slice(fragment.data);
+        self.received += fragment.data.len();
+        self.last_fragment = Instant::now();
+
+        if self.received < total {
+            return None;
+        }
+        self.source = None;
+        Some(&self.buffer[..total])
+    }
+}
@@ -0,0 +1,148 @@
+use defmt::{debug, info};
+use embassy_net::udp::UdpSocket;
+use embassy_net::Ipv4Address;
+use embassy_time::{Duration, Instant};
+use nom::{IResult, bytes::complete::{tag, take}, combinator::map, number::complete::{be_u16, u8}, sequence::tuple};
+use crate::{consts, dmx};
+use crate::leds::{self, LedSender, RealtimeFrame};
+
+pub const SACN_PORT: u16 = 5568;
+
+const ACN_PACKET_ID: [u8; 12] = *b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x00000004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x00000002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const START_CODE_DMX: u8 = 0x00;
+const OPTION_PREVIEW_DATA: u8 = 0x80;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+/// A source is considered gone after this long without data (E1.31 section 6.7.1).
+const NETWORK_DATA_LOSS_TIMEOUT: Duration = Duration::from_millis(2500);
+/// Sequence numbers up to this far behind the last one are stale (E1.31 section 6.7.2).
+const SEQUENCE_STALE_WINDOW: i8 = -20;
+
+/// Multicast group carrying `universe`.
+pub fn universe_group(universe: u16) -> Ipv4Address {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Address::new(239, 255, hi, lo)
+}
+
+/// Ethernet address the cyw43 has to accept for `universe_group(universe)`.
+pub fn universe_mac(universe: u16) -> [u8; 6] {
+    let [hi, lo] = universe.to_be_bytes();
+    [0x01, 0x00, 0x5E, 0x7F, hi, lo]
+}
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+struct DataPacket<'a> {
+    cid: &'a [u8],
+    priority: u8,
+    sequence: u8,
+    options: u8,
+    universe: u16,
+    start_code: u8,
+    slots: &'a [u8],
+}
+
+fn parse_root_layer(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    // Preamble size, postamble size, packet identifier, flags and length, vector, CID
+    map(
+        tuple((tag([0x00, 0x10, 0x00, 0x00]), tag(ACN_PACKET_ID), be_u16, tag(VECTOR_ROOT_E131_DATA.to_be_bytes()), take(16_usize))),
+        |(_, _, _, _, cid)| cid,
+    )(input)
+}
+
+fn parse_data_packet(input: &[u8]) -> IResult<&[u8], DataPacket> {
+    let (input, cid) = parse_root_layer(input)?;
+    // Framing layer: flags and length, vector, source name, priority, sync address, sequence, options, universe
+    let (input, (_, _, _, priority, _, sequence, options, universe)) = tuple((
+        be_u16, tag(VECTOR_E131_DATA_PACKET.to_be_bytes()), take(64_usize), u8, be_u16, u8, u8, be_u16,
+    ))(input)?;
+    // DMP layer: flags and length, vector, address type, first address, increment, count, start code
+    let (input, (_, _, property_count, start_code)) = tuple((
+        be_u16,
+        tag([VECTOR_DMP_SET_PROPERTY, 0xA1, 0x00, 0x00, 0x00, 0x01]),
+        be_u16,
+        u8,
+    ))(input)?;
+    let (input, slots) = take((property_count as usize).saturating_sub(1))(input)?;
+    Ok((input, DataPacket { cid, priority, sequence, options, universe, start_code, slots }))
+}
+
+/// Source currently driving the LEDs.
+struct Source {
+    cid: [u8; 16],
+    priority: u8,
+    sequence: u8,
+    last_seen: Instant,
+}
+
+struct Receiver {
+    universe: u16,
+    source: Option<Source>,
+}
+
+impl Receiver {
+    /// Decide whether `packet` should be shown, tracking the highest priority source.
+    fn accept(&mut self, packet: &DataPacket) -> bool {
+        if let Some(source) = &mut self.source {
+            let timed_out = source.last_seen.elapsed() >= NETWORK_DATA_LOSS_TIMEOUT;
+            if source.cid == packet.cid {
+                let delta = packet.sequence.wrapping_sub(source.sequence) as i8;
+                if delta <= 0 && delta > SEQUENCE_STALE_WINDOW {
+                    return false;
+                }
+                source.sequence = packet.sequence;
+                source.priority = packet.priority;
+                source.last_seen = Instant::now();
+                return true;
+            }
+            if !timed_out && packet.priority <= source.priority {
+                return false;
+            }
+        }
+        info!("sACN source changed, priority {}", packet.priority);
+        self.source = Some(Source {
+            cid: packet.cid.try_into().unwrap(),
+            priority: packet.priority,
+            sequence: packet.sequence,
+            last_seen: Instant::now(),
+        });
+        true
+    }
+
+    fn on_packet(&mut self, packet: DataPacket) -> Option<RealtimeFrame> {
+        if packet.universe != self.universe
+            || packet.start_code != START_CODE_DMX
+            || packet.options & OPTION_PREVIEW_DATA != 0
+        {
+            return None;
+        }
+        if packet.options & OPTION_STREAM_TERMINATED != 0 {
+            if self.source.as_ref().is_some_and(|source| source.cid == packet.cid) {
+                debug!("sACN stream terminated");
+                self.source = None;
+                return Some(RealtimeFrame::STOP);
+            }
+            return None;
+        }
+        self.accept(&packet).then(|| dmx::frame_from_dmx(packet.slots, NETWORK_DATA_LOSS_TIMEOUT))
+    }
+}
+
+/// Receive E1.31 data for `consts::DMX_UNIVERSE` on a socket bound to `SACN_PORT` whose stack
+/// has joined `universe_group(consts::DMX_UNIVERSE)`.
+pub async fn run<'a>(socket: &mut UdpSocket<'a>) -> ! {
+    let mut receiver = Receiver { universe: consts::DMX_UNIVERSE, source: None };
+    loop {
+        socket.recv_from_with(|buffer, _| {
+            if let Ok((_, packet)) = parse_data_packet(buffer) {
+                if let Some(frame) = receiver.on_packet(packet) {
+                    get_led_sender().set_realtime(frame);
+                }
+            }
+        }).await;
+    }
+}
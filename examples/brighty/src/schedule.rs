@@ -0,0 +1,64 @@
+use defmt::{debug, warn};
+use heapless::Vec;
+use crate::{clock, udplisten};
+
+/// Scheduled commands held at once.
+pub const MAX_SCHEDULED: usize = 16;
+/// Largest command datagram payload that can be scheduled.
+pub const MAX_SCHEDULED_LEN: usize = 64;
+
+/// Command bytes, in the same format as a command datagram, to apply at a Unix time.
+#[derive(Copy, Clone)]
+pub struct ScheduledCommand {
+    pub at_ms: u64,
+    /// Reapply every this many seconds, 0 runs once. 86400 repeats daily.
+    pub repeat_s: u32,
+    pub len: u8,
+    pub bytes: [u8; MAX_SCHEDULED_LEN],
+}
+
+/// Commands waiting for their time, so a controller can program the day and go offline.
+pub struct Schedule {
+    entries: Vec<ScheduledCommand, MAX_SCHEDULED>,
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn add(&mut self, command: ScheduledCommand) {
+        if self.entries.push(command).is_err() {
+            warn!("schedule full, dropping command");
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Apply every command that is due. Nothing runs until the wall clock has been set.
+    pub fn run_due(&mut self) {
+        let Some(now) = clock::unix_millis() else {
+            return;
+        };
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &mut self.entries[i];
+            if entry.at_ms > now {
+                i += 1;
+                continue;
+            }
+            debug!("running scheduled command due at {}", entry.at_ms);
+            udplisten::apply_commands(&entry.bytes[..entry.len as usize]);
+            if entry.repeat_s == 0 {
+                self.entries.swap_remove(i);
+                continue;
+            }
+            // Skip occurrences missed while the clock was unset or the device was busy
+            let period = entry.repeat_s as u64 * 1000;
+            entry.at_ms += (now - entry.at_ms) / period * period + period;
+            i += 1;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use defmt::{debug, info, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::driver::Driver;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use crate::storage::{self, StorageSender};
+use crate::{clock, config};
+
+pub const NTP_PORT: u16 = 123;
+/// Local port replies come back to.
+const LOCAL_PORT: u16 = 1123;
+const PACKET_LEN: usize = 48;
+/// LI 0, version 4, mode 3 (client).
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+/// Seconds from the NTP epoch (1900) to the Unix epoch.
+const NTP_UNIX_OFFSET_S: u64 = 2_208_988_800;
+/// Often enough to keep scheduled commands within a second or so, with the drift correction.
+const SYNC_INTERVAL: Duration = Duration::from_secs(3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unix time in milliseconds of a 64-bit NTP timestamp.
+fn unix_millis(timestamp: &[u8]) -> u64 {
+    let mut seconds = u32::from_be_bytes(timestamp[0..4].try_into().unwrap()) as u64;
+    let fraction = u32::from_be_bytes(timestamp[4..8].try_into().unwrap()) as u64;
+    // Timestamps with the top bit clear are past 2036, in the next era (RFC 4330 section 3)
+    if seconds & 0x8000_0000 == 0 {
+        seconds += 1 << 32;
+    }
+    (seconds - NTP_UNIX_OFFSET_S) * 1000 + (fraction * 1000 >> 32)
+}
+
+fn get_storage_sender() -> StorageSender {
+    storage::STORAGE_CHANNEL.sender()
+}
+
+/// Query the configured server once and set the clock from its reply.
+async fn sync<D: Driver>(stack: &Stack<D>, socket: &mut UdpSocket<'_>) -> bool {
+    let server = config::get().ntp_server();
+    let address = match stack.dns_query(&server, DnsQueryType::A).await {
+        Ok(addresses) if !addresses.is_empty() => addresses[0],
+        _ => {
+            warn!("failed to resolve time server {}", server.as_str());
+            return false;
+        }
+    };
+    let endpoint = IpEndpoint::new(address, NTP_PORT);
+
+    // The transmit timestamp comes back as the originate timestamp, identifying our reply
+    let sent = Instant::now();
+    let nonce = sent.as_ticks().to_be_bytes();
+    let mut request = [0; PACKET_LEN];
+    request[0] = CLIENT_HEADER;
+    request[40..48].copy_from_slice(&nonce);
+    if socket.send_to(&request, endpoint).await.is_err() {
+        return false;
+    }
+
+    let reply = with_timeout(REPLY_TIMEOUT, async {
+        loop {
+            let mut reply = [0; PACKET_LEN];
+            match socket.recv_from(&mut reply).await {
+                Ok((len, meta)) if len == PACKET_LEN && meta.endpoint == endpoint && reply[24..32] == nonce => {
+                    return reply;
+                }
+                _ => {}
+            }
+        }
+    })
+    .await;
+    let Ok(reply) = reply else {
+        warn!("time server {} didn't answer", endpoint);
+        return false;
+    };
+    // Stratum 0 is a kiss-o'-death, the server wants us to back off
+    if reply[0] & 0x07 != MODE_SERVER || reply[1] == 0 {
+        warn!("time server {} refused the request", endpoint);
+        return false;
+    }
+
+    // Half the round trip, less the time the server held on to the request
+    let round_trip = (Instant::now() - sent).as_millis();
+    let (received, transmitted) = (unix_millis(&reply[32..40]), unix_millis(&reply[40..48]));
+    let delay = round_trip.saturating_sub(transmitted.saturating_sub(received));
+    let error = clock::sync_unix_millis(transmitted + delay / 2);
+    info!("clock synced with {}, off by {} ms, drift {} ppm", endpoint, error, clock::drift_ppm());
+    debug!("time server round trip {} ms", round_trip);
+    if let Some(stored) = clock::stored() {
+        get_storage_sender().save_clock(stored);
+    }
+    true
+}
+
+/// Keep the wall clock set from the configured time server, for scheduled commands.
+pub async fn run<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; PACKET_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(LOCAL_PORT).is_err() {
+        warn!("failed to bind SNTP socket");
+    }
+    loop {
+        let interval = if sync(stack, &mut socket).await { SYNC_INTERVAL } else { RETRY_INTERVAL };
+        Timer::after(interval).await;
+    }
+}
@@ -0,0 +1,611 @@
+use defmt::{info, warn};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use config_store::{ConfigStore, Record};
+use crate::clock::StoredClock;
+use crate::config::DeviceConfig;
+use crate::define_peripheral_set;
+use crate::instrument;
+#[cfg(feature = "ir")]
+use crate::ir::{IrBinding, IrBindings, NUM_IR_BINDINGS};
+#[cfg(feature = "ota")]
+use crate::ota::{self, OtaCommand, OtaState, OtaWriter};
+use crate::palette::{Palette, Palettes, NUM_PALETTES};
+use crate::playlist::Playlist;
+use crate::presets::{Preset, Presets, NUM_PRESETS};
+use crate::leds::LedStatus;
+use crate::provision::WifiCredentials;
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Start of the storage region reserved at the end of flash, see `memory-default.x`.
+const STORAGE_OFFSET: u32 = (FLASH_SIZE - 64 * 1024) as u32;
+/// The whole region holds the settings log.
+const STORE_SECTORS: u32 = (64 * 1024 / ERASE_SIZE) as u32;
+
+/// Only write once the state has been stable this long, sliders and fades produce bursts.
+const LED_STATE_DEBOUNCE: Duration = Duration::from_secs(5);
+/// Give the final OTA progress reply time to go out before resetting into the bootloader.
+#[cfg(feature = "ota")]
+const OTA_REBOOT_DELAY: Duration = Duration::from_secs(1);
+
+pub type StorageFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+#[macro_export]
+macro_rules! storage_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            StoragePeripherals,
+            flash: FLASH,
+        }
+    };
+}
+
+storage_peripherals!(define_peripheral_set);
+
+#[derive(Copy, Clone)]
+pub enum StorageCommand {
+    SavePalette(u8, Palette),
+    SavePlaylist(Playlist),
+    SaveLedState(LedStatus),
+    SaveWifiCredentials(WifiCredentials),
+    SaveConfig(DeviceConfig),
+    /// Store or, with `None`, delete the preset at an index.
+    SavePreset(u8, Option<Preset>),
+    SaveClock(StoredClock),
+    /// Store the floor below which authenticated nonces are refused after a reset.
+    #[cfg(feature = "auth")]
+    SaveNonceFloor(NonceFloor),
+    /// Store a learned remote key in a binding slot.
+    #[cfg(feature = "ir")]
+    SaveIrBinding(u8, IrBinding),
+    #[cfg(feature = "ir")]
+    ForgetIrBindings,
+    /// Reset once every command queued before it has been written.
+    Reboot,
+    /// Erase every stored setting and reset into the defaults.
+    FactoryReset,
+    #[cfg(feature = "ota")]
+    Ota(OtaCommand),
+    /// Keep a freshly swapped in image, see `ota::confirm_boot`.
+    #[cfg(feature = "ota")]
+    ConfirmBoot,
+}
+
+pub type StorageReceiver = Receiver<'static, CriticalSectionRawMutex, StorageCommand, CHANNEL_BUF_LEN>;
+
+pub struct StorageSender(Sender<'static, CriticalSectionRawMutex, StorageCommand, CHANNEL_BUF_LEN>);
+impl StorageSender {
+    pub fn save_palette(&mut self, index: u8, palette: Palette) {
+        self.0.try_send(StorageCommand::SavePalette(index, palette)).ok();
+    }
+
+    pub fn save_playlist(&mut self, playlist: Playlist) {
+        self.0.try_send(StorageCommand::SavePlaylist(playlist)).ok();
+    }
+
+    pub fn save_led_state(&mut self, state: LedStatus) {
+        self.0.try_send(StorageCommand::SaveLedState(state)).ok();
+    }
+
+    pub fn save_wifi_credentials(&mut self, credentials: WifiCredentials) {
+        self.0.try_send(StorageCommand::SaveWifiCredentials(credentials)).ok();
+    }
+
+    pub fn save_config(&mut self, config: DeviceConfig) {
+        self.0.try_send(StorageCommand::SaveConfig(config)).ok();
+    }
+
+    pub fn save_preset(&mut self, index: u8, preset: Option<Preset>) {
+        self.0.try_send(StorageCommand::SavePreset(index, preset)).ok();
+    }
+
+    /// Whether the floor was queued, it must reach flash before the nonces it covers are trusted.
+    #[cfg(feature = "auth")]
+    pub fn save_nonce_floor(&mut self, floor: u64) -> bool {
+        self.0.try_send(StorageCommand::SaveNonceFloor(NonceFloor(floor))).is_ok()
+    }
+
+    pub fn save_clock(&mut self, clock: StoredClock) {
+        self.0.try_send(StorageCommand::SaveClock(clock)).ok();
+    }
+
+    #[cfg(feature = "ir")]
+    pub fn save_ir_binding(&mut self, index: u8, binding: IrBinding) {
+        self.0.try_send(StorageCommand::SaveIrBinding(index, binding)).ok();
+    }
+
+    #[cfg(feature = "ir")]
+    pub fn forget_ir_bindings(&mut self) {
+        self.0.try_send(StorageCommand::ForgetIrBindings).ok();
+    }
+
+    pub fn reboot(&mut self) {
+        self.0.try_send(StorageCommand::Reboot).ok();
+    }
+
+    pub fn factory_reset(&mut self) {
+        self.0.try_send(StorageCommand::FactoryReset).ok();
+    }
+
+    /// Returns false if the queue is full, OTA chunks must not be dropped silently.
+    #[cfg(feature = "ota")]
+    pub fn ota(&mut self, cmd: OtaCommand) -> bool {
+        self.0.try_send(StorageCommand::Ota(cmd)).is_ok()
+    }
+
+    #[cfg(feature = "ota")]
+    pub fn confirm_boot(&mut self) {
+        self.0.try_send(StorageCommand::ConfirmBoot).ok();
+    }
+}
+
+pub struct StorageChannel(Channel<CriticalSectionRawMutex, StorageCommand, CHANNEL_BUF_LEN>);
+
+impl StorageChannel {
+    pub const fn new() -> Self {
+        Self(Channel::new())
+    }
+
+    pub fn sender(&'static self) -> StorageSender {
+        StorageSender(self.0.sender())
+    }
+
+    pub fn receiver(&'static self) -> StorageReceiver {
+        self.0.receiver()
+    }
+}
+
+const CHANNEL_BUF_LEN: usize = 8;
+pub(crate) static STORAGE_CHANNEL: StorageChannel = StorageChannel::new();
+
+/// Fixed length settings read back padded with erased flash, so fields appended to a layout read
+/// as absent in records saved before they existed.
+fn padded<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut buf = [0xFF; N];
+    let len = bytes.len().min(N);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    buf
+}
+
+/// Stored once per palette slot.
+impl Record for Palette {
+    const KEY: u8 = 1;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        Palette::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        Some(Palette::deserialize(&padded::<{ Palette::SERIALIZED_LEN }>(bytes)))
+    }
+}
+
+impl Record for Playlist {
+    const KEY: u8 = 2;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        Playlist::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        Playlist::deserialize(&padded::<{ Playlist::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+impl Record for LedStatus {
+    const KEY: u8 = 3;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        LedStatus::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        LedStatus::deserialize(&padded::<{ LedStatus::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+impl Record for WifiCredentials {
+    const KEY: u8 = 4;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        WifiCredentials::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        WifiCredentials::deserialize(&padded::<{ WifiCredentials::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+impl Record for DeviceConfig {
+    const KEY: u8 = 5;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        DeviceConfig::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        DeviceConfig::deserialize(&padded::<{ DeviceConfig::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+/// Stored once per preset slot, deleting a preset removes its record.
+impl Record for Preset {
+    const KEY: u8 = 6;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        Preset::serialize(&Some(*self), buf);
+        Preset::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        Preset::deserialize(&padded::<{ Preset::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+/// Written once per time server sync, hourly.
+impl Record for StoredClock {
+    const KEY: u8 = 7;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        StoredClock::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        StoredClock::deserialize(&padded::<{ StoredClock::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+/// Highest authenticated nonce accepted plus a margin, see `auth::ReplayGuard`.
+#[cfg(feature = "auth")]
+#[derive(Copy, Clone)]
+pub struct NonceFloor(u64);
+
+/// Written about once a minute while authenticated commands keep arriving.
+#[cfg(feature = "auth")]
+impl Record for NonceFloor {
+    const KEY: u8 = 9;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        buf[..8].copy_from_slice(&self.0.to_le_bytes());
+        8
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        Some(NonceFloor(u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?)))
+    }
+}
+
+/// Stored once per binding slot.
+#[cfg(feature = "ir")]
+impl Record for IrBinding {
+    const KEY: u8 = 8;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        self.serialize(buf);
+        IrBinding::SERIALIZED_LEN
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        IrBinding::deserialize(&padded::<{ IrBinding::SERIALIZED_LEN }>(bytes))
+    }
+}
+
+/// Flash backed settings. Flash can only be written from core0, so all writes funnel
+/// through `storage_task`.
+pub struct Storage {
+    store: ConfigStore<StorageFlash>,
+    led_state: Option<LedStatus>,
+    pending_led_state: Option<(LedStatus, Instant)>,
+    #[cfg(feature = "ota")]
+    ota: OtaWriter,
+    /// Rollback deadline while a new image hasn't confirmed it works.
+    #[cfg(feature = "ota")]
+    trial_deadline: Option<Instant>,
+}
+
+impl Storage {
+    pub fn new(p: StoragePeripherals) -> Self {
+        #[allow(unused_mut)]
+        let mut flash = Flash::new_blocking(p.flash);
+        #[cfg(feature = "ota")]
+        let trial_deadline = if ota::is_trial(&mut flash) {
+            info!("running a new image, rolling back unless it confirms within the boot window");
+            Some(Instant::now() + ota::BOOT_WINDOW)
+        } else {
+            ota::mark_booted(&mut flash);
+            None
+        };
+        let store = ConfigStore::new(flash, STORAGE_OFFSET, STORE_SECTORS);
+        Self {
+            store,
+            led_state: None,
+            pending_led_state: None,
+            #[cfg(feature = "ota")]
+            ota: OtaWriter::new(),
+            #[cfg(feature = "ota")]
+            trial_deadline,
+        }
+    }
+
+    /// Load palettes, falling back to defaults for any never saved.
+    pub fn load_palettes(&mut self) -> Palettes {
+        let mut palettes = [Palette::rainbow(); NUM_PALETTES];
+        for (index, palette) in palettes.iter_mut().enumerate() {
+            if let Some(stored) = self.store.load_at(index as u8) {
+                *palette = stored;
+            }
+        }
+        palettes
+    }
+
+    fn save_palette(&mut self, index: u8, palette: &Palette) {
+        if let Err(e) = self.store.save_at(index, palette) {
+            warn!("failed to write palette to flash: {}", e);
+        }
+    }
+
+    /// Load the effect playlist, empty if none was ever saved.
+    pub fn load_playlist(&mut self) -> Playlist {
+        self.store.load().unwrap_or(Playlist::EMPTY)
+    }
+
+    fn save_playlist(&mut self, playlist: &Playlist) {
+        if let Err(e) = self.store.save(playlist) {
+            warn!("failed to write playlist to flash: {}", e);
+        }
+    }
+
+    /// Load the station credentials, none until the device has been provisioned.
+    pub fn load_wifi_credentials(&mut self) -> Option<WifiCredentials> {
+        let credentials = self.store.load();
+        if credentials.is_none() {
+            info!("no stored wifi credentials");
+        }
+        credentials
+    }
+
+    fn save_wifi_credentials(&mut self, credentials: &WifiCredentials) {
+        if let Err(e) = self.store.save(credentials) {
+            warn!("failed to write wifi credentials to flash: {}", e);
+        }
+    }
+
+    /// Unique ID of the flash chip, which tells boards apart before the cyw43 is up.
+    pub fn load_unique_id(&mut self) -> [u8; 8] {
+        let mut id = [0; 8];
+        if self.store.flash().blocking_unique_id(&mut id).is_err() {
+            warn!("failed to read flash unique ID");
+        }
+        id
+    }
+
+    /// Load the device configuration, defaults if it was never changed.
+    pub fn load_config(&mut self) -> DeviceConfig {
+        self.store.load().unwrap_or(DeviceConfig::DEFAULT)
+    }
+
+    fn save_config(&mut self, config: &DeviceConfig) {
+        if let Err(e) = self.store.save(config) {
+            warn!("failed to write device config to flash: {}", e);
+        }
+    }
+
+    /// Load the presets, all empty if none were ever saved.
+    pub fn load_presets(&mut self) -> Presets {
+        let mut presets = [None; NUM_PRESETS];
+        for (index, preset) in presets.iter_mut().enumerate() {
+            *preset = self.store.load_at(index as u8);
+        }
+        presets
+    }
+
+    fn save_preset(&mut self, index: u8, preset: &Option<Preset>) {
+        let result = match preset {
+            Some(preset) => self.store.save_at(index, preset),
+            None => self.store.remove_at::<Preset>(index),
+        };
+        if let Err(e) = result {
+            warn!("failed to write preset to flash: {}", e);
+        }
+    }
+
+    /// Load the learned remote keys, none if none were ever learned.
+    #[cfg(feature = "ir")]
+    pub fn load_ir_bindings(&mut self) -> IrBindings {
+        let mut bindings = [None; NUM_IR_BINDINGS];
+        for (index, binding) in bindings.iter_mut().enumerate() {
+            *binding = self.store.load_at(index as u8);
+        }
+        bindings
+    }
+
+    #[cfg(feature = "ir")]
+    fn save_ir_binding(&mut self, index: u8, binding: &IrBinding) {
+        if let Err(e) = self.store.save_at(index, binding) {
+            warn!("failed to write remote key to flash: {}", e);
+        }
+    }
+
+    #[cfg(feature = "ir")]
+    fn forget_ir_bindings(&mut self) {
+        for index in 0..NUM_IR_BINDINGS as u8 {
+            if self.store.load_at::<IrBinding>(index).is_none() {
+                continue;
+            }
+            if let Err(e) = self.store.remove_at::<IrBinding>(index) {
+                warn!("failed to remove remote key from flash: {}", e);
+            }
+        }
+    }
+
+    /// Load the most recently saved LED state.
+    pub fn load_led_state(&mut self) -> Option<LedStatus> {
+        self.led_state = self.store.load();
+        if self.led_state.is_none() {
+            info!("no stored LED state");
+        }
+        self.led_state
+    }
+
+    fn save_led_state(&mut self, state: &LedStatus) {
+        if self.led_state == Some(*state) {
+            return;
+        }
+        if let Err(e) = self.store.save(state) {
+            warn!("failed to write LED state to flash: {}", e);
+            return;
+        }
+        self.led_state = Some(*state);
+    }
+
+    /// Load the time of the last server sync, none if the clock was never synced.
+    pub fn load_clock(&mut self) -> Option<StoredClock> {
+        self.store.load()
+    }
+
+    fn save_clock(&mut self, clock: &StoredClock) {
+        if let Err(e) = self.store.save(clock) {
+            warn!("failed to write clock to flash: {}", e);
+        }
+    }
+
+    /// Load the floor of authenticated nonces, 0 if no authenticated command was ever accepted.
+    #[cfg(feature = "auth")]
+    pub fn load_nonce_floor(&mut self) -> u64 {
+        self.store.load::<NonceFloor>().map_or(0, |floor| floor.0)
+    }
+
+    #[cfg(feature = "auth")]
+    fn save_nonce_floor(&mut self, floor: NonceFloor) {
+        if let Err(e) = self.store.save(&floor) {
+            warn!("failed to write nonce floor to flash: {}", e);
+        }
+    }
+
+    pub fn process_command(&mut self, cmd: &StorageCommand) {
+        match cmd {
+            StorageCommand::SavePalette(index, palette) => {
+                if (*index as usize) < NUM_PALETTES {
+                    self.save_palette(*index, palette);
+                }
+            }
+            StorageCommand::SavePlaylist(playlist) => {
+                self.save_playlist(playlist);
+            }
+            StorageCommand::SaveLedState(state) => {
+                self.pending_led_state = Some((*state, Instant::now() + LED_STATE_DEBOUNCE));
+            }
+            StorageCommand::SaveWifiCredentials(credentials) => {
+                self.save_wifi_credentials(credentials);
+            }
+            StorageCommand::SaveConfig(config) => {
+                self.save_config(config);
+            }
+            StorageCommand::SavePreset(index, preset) => {
+                if (*index as usize) < NUM_PRESETS {
+                    self.save_preset(*index, preset);
+                }
+            }
+            StorageCommand::SaveClock(clock) => {
+                self.save_clock(clock);
+            }
+            #[cfg(feature = "auth")]
+            StorageCommand::SaveNonceFloor(floor) => {
+                self.save_nonce_floor(*floor);
+            }
+            #[cfg(feature = "ir")]
+            StorageCommand::SaveIrBinding(index, binding) => {
+                if (*index as usize) < NUM_IR_BINDINGS {
+                    self.save_ir_binding(*index, binding);
+                }
+            }
+            #[cfg(feature = "ir")]
+            StorageCommand::ForgetIrBindings => {
+                self.forget_ir_bindings();
+            }
+            StorageCommand::Reboot => {
+                // Settle the debounced LED state first, it would be lost otherwise
+                if let Some((state, _)) = self.pending_led_state.take() {
+                    self.save_led_state(&state);
+                }
+                info!("rebooting");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            StorageCommand::FactoryReset => {
+                let end = STORAGE_OFFSET + STORE_SECTORS * ERASE_SIZE as u32;
+                if self.store.flash().blocking_erase(STORAGE_OFFSET, end).is_err() {
+                    warn!("failed to erase settings");
+                }
+                info!("settings erased, rebooting");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            #[cfg(feature = "ota")]
+            StorageCommand::Ota(cmd) => {
+                self.ota.process(self.store.flash(), cmd);
+            }
+            #[cfg(feature = "ota")]
+            StorageCommand::ConfirmBoot => {
+                if self.trial_deadline.take().is_some() {
+                    info!("new image confirmed");
+                    ota::mark_booted(self.store.flash());
+                }
+            }
+        }
+    }
+
+    pub async fn run(&mut self, receiver: StorageReceiver) -> ! {
+        loop {
+            let deadline = self.pending_led_state.map_or(Instant::MAX, |(_, deadline)| deadline);
+            #[cfg(feature = "ota")]
+            let deadline = deadline.min(self.trial_deadline.unwrap_or(Instant::MAX));
+            match select(receiver.receive(), Timer::at(deadline)).await {
+                Either::First(command) => {
+                    self.process_command(&command);
+                    #[cfg(feature = "ota")]
+                    if ota::progress().state == OtaState::Rebooting {
+                        Timer::after(OTA_REBOOT_DELAY).await;
+                        cortex_m::peripheral::SCB::sys_reset();
+                    }
+                }
+                Either::Second(_) => {
+                    #[cfg(feature = "ota")]
+                    if self.trial_deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                        warn!("new image not confirmed within the boot window, rolling back");
+                        cortex_m::peripheral::SCB::sys_reset();
+                    }
+                    if let Some((state, deadline)) = self.pending_led_state {
+                        if deadline <= Instant::now() {
+                            self.pending_led_state = None;
+                            self.save_led_state(&state);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn storage_task(receiver: StorageReceiver, mut storage: Storage) -> ! {
+    instrument::measured("storage", storage.run(receiver)).await
+}
@@ -0,0 +1,117 @@
+use core::cell::Cell;
+use defmt::{debug, info, warn, Format};
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::UdpSocket;
+use embassy_net::{IpEndpoint, Ipv4Address};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use nom::{IResult, bytes::complete::{tag, take}, combinator::map_opt, number::complete::{le_i64, le_u32}, sequence::{preceded, tuple}};
+use num_derive::FromPrimitive;
+use crate::leds::{self, LedSender, LedStatus};
+
+pub const SYNC_PORT: u16 = 6723;
+pub const SYNC_GROUP: Ipv4Address = Ipv4Address::new(239, 255, 77, 83);
+/// Ethernet address the cyw43 has to accept for `SYNC_GROUP`.
+pub const SYNC_MAC: [u8; 6] = [0x01, 0x00, 0x5E, 0x7F, 77, 83];
+
+const SYNC_MAGIC: [u8; 4] = *b"MSYN";
+const SYNC_PACKET_LEN: usize = SYNC_MAGIC.len() + 4 + 8 + LedStatus::SERIALIZED_LEN;
+/// How often the leader multicasts its clock.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Copy, Clone, PartialEq, Format, FromPrimitive)]
+pub enum SyncMode {
+    Off = 0,
+    /// Multicast this device's animation clock and effect.
+    Leader = 1,
+    /// Phase-lock to sync packets from a leader device or controller.
+    Follower = 2,
+}
+
+static SYNC_MODE: Mutex<CriticalSectionRawMutex, Cell<SyncMode>> = Mutex::new(Cell::new(SyncMode::Off));
+
+pub fn set_mode(mode: SyncMode) {
+    info!("sync mode {}", mode);
+    SYNC_MODE.lock(|cell| cell.set(mode));
+}
+
+fn mode() -> SyncMode {
+    SYNC_MODE.lock(|cell| cell.get())
+}
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+/// Magic, le_u32 sender id, le_i64 animation clock in milliseconds, then the sender's
+/// `LedStatus`. Controllers can lead a room by sending these themselves with any unused id.
+struct SyncPacket {
+    sender_id: u32,
+    clock_ms: i64,
+    status: LedStatus,
+}
+
+fn parse_sync_packet(input: &[u8]) -> IResult<&[u8], SyncPacket> {
+    preceded(
+        tag(SYNC_MAGIC),
+        map_opt(tuple((le_u32, le_i64, take(LedStatus::SERIALIZED_LEN))), |(sender_id, clock_ms, status)| {
+            Some(SyncPacket { sender_id, clock_ms, status: LedStatus::deserialize(status)? })
+        })
+    )(input)
+}
+
+fn encode_sync_packet(sender_id: u32) -> [u8; SYNC_PACKET_LEN] {
+    let mut packet = [0; SYNC_PACKET_LEN];
+    packet[0..4].copy_from_slice(&SYNC_MAGIC);
+    packet[4..8].copy_from_slice(&sender_id.to_le_bytes());
+    packet[8..16].copy_from_slice(&leds::animation_millis().to_le_bytes());
+    leds::LED_STATUS.lock(|cell| cell.get()).serialize(&mut packet[16..]);
+    packet
+}
+
+fn follow(packet: &SyncPacket) {
+    let mut sender = get_led_sender();
+    sender.sync_clock(packet.clock_ms - Instant::now().as_millis() as i64);
+
+    // Brightness stays local, sconces in one room are often dimmed differently
+    let status = leds::LED_STATUS.lock(|cell| cell.get());
+    let leader = packet.status;
+    if status.effect != leader.effect {
+        debug!("following sync leader effect {}", leader.effect as u8);
+        sender.set_effect(leader.effect);
+    }
+    if status.params != leader.params {
+        sender.set_effect_params(leader.params);
+    }
+    if status.primary_color != leader.primary_color {
+        sender.set_primary_color(leader.primary_color);
+    }
+}
+
+/// Lead or follow animation sync depending on the mode, on a socket bound to `SYNC_PORT` whose
+/// stack has joined `SYNC_GROUP`. `mac` identifies this device so it ignores its own packets.
+pub async fn run<'a>(socket: &mut UdpSocket<'a>, mac: &[u8; 6]) -> ! {
+    let own_id = u32::from_le_bytes([mac[2], mac[3], mac[4], mac[5]]);
+    let group = IpEndpoint::new(SYNC_GROUP.into(), SYNC_PORT);
+    let mut next_send = Instant::now();
+    loop {
+        let deadline = if mode() == SyncMode::Leader { next_send } else { Instant::now() + SYNC_INTERVAL };
+        match select(socket.recv_from_with(|buffer, _| parse_sync_packet(buffer).ok().map(|(_, p)| p)), Timer::at(deadline)).await {
+            Either::First(Some(packet)) => {
+                if mode() == SyncMode::Follower && packet.sender_id != own_id {
+                    follow(&packet);
+                }
+            }
+            Either::First(None) => {}
+            Either::Second(_) => {
+                if mode() == SyncMode::Leader {
+                    if socket.send_to(&encode_sync_packet(own_id), group).await.is_err() {
+                        warn!("failed to send sync packet");
+                    }
+                    next_send = Instant::now() + SYNC_INTERVAL;
+                }
+            }
+        }
+    }
+}
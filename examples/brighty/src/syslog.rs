@@ -0,0 +1,152 @@
+use core::cell::RefCell;
+use defmt::{debug, error, info, warn};
+use embassy_futures::select::select;
+use embassy_net::driver::Driver;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{Deque, String};
+use ufmt::uwrite;
+use crate::config;
+use crate::logbuf;
+
+pub const SYSLOG_PORT: u16 = 514;
+pub const MAX_MESSAGE_LEN: usize = 96;
+/// Messages kept while the collector is unreachable or unset, the oldest are dropped first.
+const QUEUE_LEN: usize = 16;
+/// Queued messages are sent together, so the radio wakes once per batch rather than per message.
+const BATCH_INTERVAL: Duration = Duration::from_secs(10);
+/// Queue length that flushes before `BATCH_INTERVAL` is up.
+const BATCH_THRESHOLD: usize = QUEUE_LEN / 2;
+/// RFC 5424 `local0`.
+const FACILITY: u8 = 16;
+const MAX_PACKET_LEN: usize = 192;
+
+/// RFC 5424 severities that are used here.
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub enum Severity {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+struct Entry {
+    severity: Severity,
+    uptime_ms: u64,
+    message: String<MAX_MESSAGE_LEN>,
+}
+
+struct Queue {
+    entries: Deque<Entry, QUEUE_LEN>,
+    dropped: u32,
+}
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<Queue>> =
+    Mutex::new(RefCell::new(Queue { entries: Deque::new(), dropped: 0 }));
+static FLUSH: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Log through defmt and queue for the remote collector. Arguments are formatted with ufmt, so
+/// they need `uDisplay` rather than `Format`.
+#[macro_export]
+macro_rules! remote_log {
+    ($severity:ident, $($arg:tt)*) => {{
+        let mut message = heapless::String::<{ $crate::syslog::MAX_MESSAGE_LEN }>::new();
+        // A message too long for the buffer is sent truncated
+        ufmt::uwrite!(message, $($arg)*).ok();
+        $crate::syslog::log($crate::syslog::Severity::$severity, &message);
+    }};
+}
+
+pub fn log(severity: Severity, message: &str) {
+    let tag = match severity {
+        Severity::Error => {
+            error!("{=str}", message);
+            'E'
+        }
+        Severity::Warning => {
+            warn!("{=str}", message);
+            'W'
+        }
+        Severity::Info => {
+            info!("{=str}", message);
+            'I'
+        }
+        Severity::Debug => {
+            debug!("{=str}", message);
+            'D'
+        }
+    };
+    // Kept on the device as well, for a collector that wasn't set up or couldn't be reached
+    logbuf::append(tag, message);
+    let mut entry = Entry { severity, uptime_ms: Instant::now().as_millis(), message: String::new() };
+    entry.message.push_str(message).ok();
+    let queued = QUEUE.lock(|queue| {
+        let mut queue = queue.borrow_mut();
+        if queue.entries.is_full() {
+            queue.entries.pop_front();
+            queue.dropped += 1;
+        }
+        queue.entries.push_back(entry).ok();
+        queue.entries.len()
+    });
+    // Problems go out right away, they may be the last thing sent before a reset
+    if severity <= Severity::Warning || queued >= BATCH_THRESHOLD {
+        FLUSH.signal(());
+    }
+}
+
+/// RFC 5424 message with the uptime in hundredths of a second as `meta sysUpTime`.
+fn encode(buf: &mut String<MAX_PACKET_LEN>, hostname: &str, entry: &Entry) {
+    buf.clear();
+    let priority = FACILITY * 8 + entry.severity as u8;
+    uwrite!(
+        buf,
+        "<{}>1 - {} brighty - - [meta sysUpTime=\"{}\"] {}",
+        priority,
+        hostname,
+        entry.uptime_ms / 10,
+        entry.message.as_str()
+    )
+    .ok();
+}
+
+/// Send queued messages to the configured collector in batches, holding on to them while no
+/// collector is set.
+pub async fn run<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; QUEUE_LEN];
+    let mut tx_buffer = [0; 4 * MAX_PACKET_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(0).is_err() {
+        warn!("failed to bind syslog socket");
+    }
+    let mut buf = String::<MAX_PACKET_LEN>::new();
+    loop {
+        select(FLUSH.wait(), Timer::after(BATCH_INTERVAL)).await;
+        let config = config::get();
+        let Some(collector) = config.log_collector else {
+            continue;
+        };
+        let endpoint = IpEndpoint::new(Ipv4Address(collector.address).into(), collector.port);
+        let hostname = config.hostname();
+
+        let dropped = QUEUE.lock(|queue| core::mem::take(&mut queue.borrow_mut().dropped));
+        if dropped > 0 {
+            let mut entry = Entry { severity: Severity::Warning, uptime_ms: Instant::now().as_millis(), message: String::new() };
+            uwrite!(entry.message, "{} log messages dropped", dropped).ok();
+            encode(&mut buf, &hostname, &entry);
+            socket.send_to(buf.as_bytes(), endpoint).await.ok();
+        }
+        while let Some(entry) = QUEUE.lock(|queue| queue.borrow_mut().entries.pop_front()) {
+            encode(&mut buf, &hostname, &entry);
+            if socket.send_to(buf.as_bytes(), endpoint).await.is_err() {
+                debug!("failed to send log message to {}", endpoint);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use defmt::{debug, warn};
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+use crate::{consts, udplisten};
+
+/// Largest command frame accepted, matching what fits a single UDP datagram in practice.
+const MAX_FRAME_LEN: usize = 1024;
+/// Drop connections that stay silent this long so a vanished controller frees the listener.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Accept controllers on TCP `consts::CMD_PORT`, one connection at a time. Each frame is a
+/// little endian u16 length followed by the same bytes as a command datagram, and replies are
+/// framed the same way.
+pub async fn run<D: Driver>(stack: &Stack<D>) -> ! {
+    let mut rx_buffer = [0; MAX_FRAME_LEN];
+    let mut tx_buffer = [0; 256];
+    let mut frame = [0; MAX_FRAME_LEN];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+        if socket.accept(consts::CMD_PORT).await.is_err() {
+            warn!("TCP control accept failed");
+            continue;
+        }
+        let endpoint = socket
+            .remote_endpoint()
+            .unwrap_or(IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::UNSPECIFIED), 0));
+        debug!("TCP control connection from {}", endpoint);
+
+        'connection: loop {
+            let mut len = [0; 2];
+            if socket.read_exact(&mut len).await.is_err() {
+                break;
+            }
+            let len = u16::from_le_bytes(len) as usize;
+            if len > MAX_FRAME_LEN {
+                warn!("TCP control frame of {} octets too long", len);
+                break;
+            }
+            if socket.read_exact(&mut frame[..len]).await.is_err() {
+                break;
+            }
+
+            let Some(replies) = udplisten::dispatch(&frame[..len], endpoint) else {
+                continue;
+            };
+            for reply in replies.iter() {
+                let header = (reply.len() as u16).to_le_bytes();
+                if socket.write_all(&header).await.is_err() || socket.write_all(reply).await.is_err() {
+                    // The controller is gone, stop reading from it too
+                    break 'connection;
+                }
+            }
+        }
+
+        debug!("TCP control connection from {} closed", endpoint);
+        socket.abort();
+        socket.flush().await.ok();
+    }
+}
@@ -0,0 +1,103 @@
+use core::cell::Cell;
+use defmt::{debug, warn};
+use embassy_rp::adc;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+#[cfg(not(feature = "mic"))]
+use embassy_time::Timer;
+use crate::define_peripheral_set;
+#[cfg(not(feature = "mic"))]
+use crate::Irqs;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Die temperature in tenths of a degree where the LEDs start dimming, the strip's supply and
+/// drivers sit right next to the RP2040 in the sconce.
+const DERATE_FROM: i32 = 600;
+/// Die temperature in tenths of a degree where the LEDs reach `DERATED_SCALE`.
+const DERATE_TO: i32 = 750;
+/// Output scale out of 255 at and above `DERATE_TO`.
+const DERATED_SCALE: i32 = 64;
+
+#[macro_export]
+macro_rules! temperature_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            TemperaturePeripherals,
+            adc: ADC,
+            sensor: ADC_TEMP_SENSOR,
+        }
+    };
+}
+
+temperature_peripherals!(define_peripheral_set);
+
+/// Smoothed die temperature in tenths of a degree Celsius, `None` until the first sample.
+static TEMPERATURE: Mutex<CriticalSectionRawMutex, Cell<Option<i16>>> = Mutex::new(Cell::new(None));
+
+pub fn decicelsius() -> Option<i16> {
+    TEMPERATURE.lock(|cell| cell.get())
+}
+
+/// Scale out of 255 the LED output is limited to, so a sconce running hot dims instead of
+/// cooking itself.
+pub fn derating() -> u8 {
+    match decicelsius() {
+        Some(t) if t as i32 >= DERATE_TO => DERATED_SCALE as u8,
+        Some(t) if t as i32 > DERATE_FROM => {
+            (255 - (t as i32 - DERATE_FROM) * (255 - DERATED_SCALE) / (DERATE_TO - DERATE_FROM)) as u8
+        }
+        _ => 255,
+    }
+}
+
+/// Conversion from the RP2040 datasheet, T = 27 - (V - 0.706 V) / 1.721 mV, with a 3.3 V
+/// reference, in tenths of a degree.
+fn to_decicelsius(raw: u16) -> i16 {
+    let microvolts = raw as i32 * 3_300_000 / 4096;
+    (270 - (microvolts - 706_000) * 10 / 1721) as i16
+}
+
+/// The on-chip sensor, sampled every `SAMPLE_INTERVAL` on an ADC it may share.
+pub struct Sensor {
+    channel: adc::Channel<'static>,
+    next_sample: Instant,
+}
+
+impl Sensor {
+    pub fn new(sensor: embassy_rp::peripherals::ADC_TEMP_SENSOR) -> Self {
+        Self { channel: adc::Channel::new_temp_sensor(sensor), next_sample: Instant::now() }
+    }
+
+    /// Take a reading if one is due, between other conversions on the same ADC.
+    pub async fn sample_if_due(&mut self, adc: &mut adc::Adc<'_, adc::Async>) {
+        if Instant::now() < self.next_sample {
+            return;
+        }
+        self.next_sample += SAMPLE_INTERVAL;
+        let Ok(raw) = adc.read(&mut self.channel).await else {
+            warn!("temperature sampling failed");
+            return;
+        };
+        let sample = to_decicelsius(raw);
+        // A single conversion is noisy to a few degrees, average over about a minute
+        let smoothed = TEMPERATURE.lock(|cell| {
+            let smoothed = cell.get().map_or(sample, |t| t + (sample - t) / 8);
+            cell.set(Some(smoothed));
+            smoothed
+        });
+        debug!("die temperature {} dC", smoothed);
+    }
+}
+
+/// Sample the temperature on an ADC of its own, with the microphone the mic task does it.
+#[cfg(not(feature = "mic"))]
+#[embassy_executor::task]
+pub async fn temperature_task(p: TemperaturePeripherals) -> ! {
+    let mut adc = adc::Adc::new(p.adc, Irqs, adc::Config::default());
+    let mut sensor = Sensor::new(p.sensor);
+    loop {
+        sensor.sample_if_due(&mut adc).await;
+        Timer::at(sensor.next_sample).await;
+    }
+}
@@ -1,19 +1,87 @@
 use core::cmp::min;
 use defmt::{debug, warn, error, Format, Formatter, unwrap};
+use embassy_net::driver::Driver;
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
 use embassy_net::udp::{UdpMetadata, UdpSocket};
 use num::FromPrimitive;
-use nom::{Err, IResult, bytes::complete::{tag, take}, branch::alt, sequence::{tuple, preceded}, combinator::{map, map_res, map_opt}, number::complete::{le_u16, u8}, Parser, Needed, Slice};
+use nom::{Err, IResult, bytes::complete::{tag, take}, branch::alt, sequence::{tuple, preceded}, combinator::{map, map_res, map_opt, opt, verify}, number::complete::{le_u16, le_u32, le_u64, u8}, Parser, Needed, Slice};
 use heapless::Vec;
 use nom::error::{error_to_u32, ErrorKind};
+use core::cell::RefCell;
 use embassy_futures::select;
-use embassy_futures::select::Either;
-use ufmt::uwrite;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_futures::select::Either3;
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "auth")]
+use crate::auth;
+use crate::clock;
 use crate::color::Color;
+use crate::config::{self, ColorOrder, DeviceConfig, LogCollector, StaticIp};
 use crate::leds;
-use crate::leds::{Effect, LedSender, NUM_LEDS};
+use crate::link;
+use crate::logbuf;
+#[cfg(feature = "ota")]
+use crate::ota::{self, OtaResult};
+use crate::palette::{Palette, NUM_PALETTES};
+use crate::playlist::{Playlist, PlaylistEntry, MAX_PLAYLIST_ENTRIES};
+use crate::reassembly::{self, Reassembler, MAX_REASSEMBLED_LEN};
+use crate::presets::{self, Preset, MAX_PRESET_NAME_LEN, NUM_PRESETS};
+use crate::storage::{self, StorageSender};
+use crate::schedule::{ScheduledCommand, MAX_SCHEDULED_LEN};
+#[cfg(feature = "sync")]
+use crate::sync::{self, SyncMode};
+use crate::leds::{AudioLevels, Effect, EffectParams, LedSender, RealtimeFrame, LED_MASK_LEN, MAX_AUDIO_BANDS, MAX_FRAME_RATE, NUM_LEDS};
+use crate::parse::{parse_color, parse_color_list, parse_keyframe_sequence};
+
+/// Optional datagram header: magic, le_u16 sequence number, flags, then a group id if
+/// `FLAG_GROUP` is set. Headerless datagrams start directly with a command byte, which never
+/// collides with the magic.
+const HEADER_MAGIC: [u8; 2] = *b"MS";
+/// Sender wants an ACK for this datagram.
+const FLAG_ACK_REQUESTED: u8 = 0x01;
+/// Sender wants an ACK only if the datagram failed to parse.
+const FLAG_NACK_REQUESTED: u8 = 0x02;
+/// Datagram addresses one device group, devices outside it ignore the datagram entirely.
+const FLAG_GROUP: u8 = 0x04;
+/// Datagram ends with a le_u16 CRC-16/CCITT-FALSE of everything before it, from the header
+/// magic on. Only sent to devices reporting `PROTOCOL_VERSION` 2 or later.
+const FLAG_CRC16: u8 = 0x08;
+/// Command protocol features understood, reported in the discover reply:
+/// 1 the original header, 2 adds `FLAG_CRC16`.
+const PROTOCOL_VERSION: u8 = 2;
+/// Set on datagrams sent by the device in reply.
+const FLAG_ACK: u8 = 0x80;
+const ACK_OK: u8 = 0;
+const ACK_PARSE_ERROR: u8 = 1;
+/// Magic, seq, flags, status, then the nom error code and the datagram offset it occurred at.
+const ACK_LEN: usize = 9;
+const STATS_LEN: usize = 25;
+/// Layout version of the `GetStatus` reply, bumped whenever fields are added.
+const STATUS_LAYOUT_VERSION: u8 = 2;
+const STATUS_LEN: usize = 25;
+#[cfg(feature = "ota")]
+const OTA_PROGRESS_LEN: usize = 11;
+const PRESETS_LEN: usize = 1 + NUM_PRESETS * (1 + MAX_PRESET_NAME_LEN);
+/// Log bytes per `GetLog` reply, a whole buffer takes a few round trips.
+const LOG_CHUNK_LEN: usize = 512;
+const LOG_REPLY_LEN: usize = 9 + LOG_CHUNK_LEN;
+/// Controllers receiving status pushes at once.
+const MAX_SUBSCRIBERS: usize = 4;
+/// Peers whose last sequence number is remembered for duplicate suppression.
+const MAX_SEQ_PEERS: usize = 4;
+/// Sequence numbers this far behind the last one are treated as retransmissions,
+/// anything older as a restarted controller.
+const SEQ_DUPLICATE_WINDOW: u16 = 32;
+/// Holdover of stream frames that don't set their own.
+const DEFAULT_STREAM_HOLDOVER: Duration = Duration::from_millis(2500);
 
 fn get_led_sender() -> LedSender {
-    unsafe { leds::LED_CHANNEL.sender() }
+    leds::LED_CHANNEL.sender()
+}
+
+fn get_storage_sender() -> StorageSender {
+    storage::STORAGE_CHANNEL.sender()
 }
 
 enum ListenCmd {
@@ -23,43 +91,134 @@ enum ListenCmd {
     SetEffect = 3,
     SetEffectSpeed = 4,
     SetBrightness = 5,
+    SetKeyframes = 6,
+    ClearKeyframes = 7,
+    AudioLevels = 8,
+    UploadPalette = 9,
+    SelectPalette = 10,
+    SetEffectParams = 11,
+    SetBrightnessFade = 12,
+    StartSunrise = 13,
+    SetFrameRate = 14,
+    SetPlaylist = 15,
+    GetStatus = 16,
+    GetStats = 17,
+    #[cfg(feature = "ota")]
+    OtaBegin = 18,
+    #[cfg(feature = "ota")]
+    OtaChunk = 19,
+    #[cfg(feature = "ota")]
+    OtaFinish = 20,
+    #[cfg(feature = "sync")]
+    SetSyncMode = 21,
+    SetConfig = 22,
+    ScheduleCommand = 23,
+    ClearSchedule = 24,
+    SetTime = 25,
+    StreamFrame = 26,
+    SavePreset = 27,
+    RecallPreset = 28,
+    DeletePreset = 29,
+    GetPresets = 30,
+    SetMaskedColor = 31,
+    Identify = 32,
+    Subscribe = 33,
+    GetLog = 34,
 }
 
-fn parse_color_list(input: &[u8]) -> IResult<&[u8], [Color; NUM_LEDS]> {
-    let (input, color_count) = u8(input)?;
-    let num_color_bytes = color_count as usize * 4;
-    map(take(num_color_bytes), |color_bytes: &[u8]| {
-        let mut colors = [Color::BLACK; NUM_LEDS];
-        for i in 0..min(NUM_LEDS, color_bytes.len() / 4) {
-            colors[i] = Color::from_rgbw(color_bytes[i * 4],
-                                         color_bytes[i * 4 + 1],
-                                         color_bytes[i * 4 + 2],
-                                         color_bytes[i * 4 + 3]);
-        }
-        colors
-    })(input)
+/// Keys of `ListenCmd::SetConfig`.
+enum ConfigKey {
+    Hostname = 0,
+    LedCount = 1,
+    ColorOrder = 2,
+    MaxPower = 3,
+    DefaultEffect = 4,
+    /// le_u32 mask of the groups the device belongs to, bit n for group n.
+    Groups = 5,
+    /// Prefix length, 0 restores DHCP. Otherwise followed by address, gateway and DNS server,
+    /// 0.0.0.0 for no gateway or DNS server.
+    StaticIp = 6,
+    /// Time server name or IPv4 address length and bytes, empty restores `consts::NTP_SERVER`.
+    NtpServer = 7,
+    /// Syslog collector IPv4 address and le_u16 port, port 0 stops remote logging.
+    LogCollector = 8,
 }
 
-fn parse_color(input: &[u8]) -> IResult<&[u8], Color> {
-    map(take(4usize), |color_bytes: &[u8]| {
-        Color::from_rgbw(color_bytes[0],
-                         color_bytes[1],
-                         color_bytes[2],
-                         color_bytes[3])
+fn parse_audio_levels(input: &[u8]) -> IResult<&[u8], AudioLevels> {
+    let (input, band_count) = verify(u8, |count| *count as usize <= MAX_AUDIO_BANDS)(input)?;
+    map(take(band_count as usize), move |band_bytes: &[u8]| {
+        let mut bands = [0; MAX_AUDIO_BANDS];
+        bands[..band_bytes.len()].copy_from_slice(band_bytes);
+        AudioLevels { bands, len: band_count }
     })(input)
 }
 
+fn parse_palette(input: &[u8]) -> IResult<&[u8], Palette> {
+    map(take(Palette::SERIALIZED_LEN), Palette::deserialize)(input)
+}
+
+fn parse_palette_index(input: &[u8]) -> IResult<&[u8], u8> {
+    verify(u8, |index| (*index as usize) < NUM_PALETTES)(input)
+}
+
 fn parse_effect(input: &[u8]) -> IResult<&[u8], Effect> {
     map_opt(u8, Effect::from_u8)(input)
 }
 
+fn parse_playlist_entry(input: &[u8]) -> IResult<&[u8], PlaylistEntry> {
+    map(tuple((parse_effect, parse_effect_params, le_u16)), |(effect, params, duration_s)| PlaylistEntry {
+        effect,
+        params,
+        duration_s,
+    })(input)
+}
+
+fn parse_playlist(input: &[u8]) -> IResult<&[u8], Playlist> {
+    let (mut input, (crossfade_ms, len)) =
+        tuple((le_u16, verify(u8, |len| *len as usize <= MAX_PLAYLIST_ENTRIES)))(input)?;
+    let mut playlist = Playlist { len, crossfade_ms, ..Playlist::EMPTY };
+    for i in 0..len as usize {
+        let (rest, entry) = parse_playlist_entry(input)?;
+        playlist.entries[i] = entry;
+        input = rest;
+    }
+    Ok((input, playlist))
+}
+
 fn parse_set_color_list(input: &[u8]) -> IResult<&[u8], ()> {
     preceded(
         tag([ListenCmd::SetColorList as u8]),
-        map(parse_color_list, |color_list| get_led_sender().set_color_list(color_list))
+        map(parse_color_list::<NUM_LEDS>, |color_list| get_led_sender().set_color_list(color_list))
     )(input)
 }
 
+/// le_u16 holdover in milliseconds (0 for the default), then a color list covering the whole
+/// frame. Effects pause while frames keep arriving and resume once the holdover passes.
+fn parse_stream_frame(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::StreamFrame as u8]),
+        map(tuple((le_u16, parse_color_list::<NUM_LEDS>)), |(holdover_ms, color_list)| {
+            let timeout = match holdover_ms {
+                0 => DEFAULT_STREAM_HOLDOVER,
+                ms => Duration::from_millis(ms as u64),
+            };
+            get_led_sender().set_realtime(RealtimeFrame { colors: color_list.map(Some), timeout });
+        })
+    )(input)
+}
+
+/// Mask length and mask bytes, LED 0 in the lowest bit of the first byte, then the color.
+/// Masks shorter than `LED_MASK_LEN` leave the remaining LEDs unselected.
+fn parse_set_masked_color(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, len) = preceded(tag([ListenCmd::SetMaskedColor as u8]), u8)(input)?;
+    map(tuple((take(len as usize), parse_color)), |(mask_bytes, color): (&[u8], Color)| {
+        let mut mask = [0; LED_MASK_LEN];
+        let len = min(LED_MASK_LEN, mask_bytes.len());
+        mask[..len].copy_from_slice(&mask_bytes[..len]);
+        get_led_sender().set_masked_color(mask, color);
+    })(input)
+}
+
 fn parse_shift_color(input: &[u8]) -> IResult<&[u8], ()> {
     preceded(
         tag([ListenCmd::ShiftColor as u8]),
@@ -88,6 +247,23 @@ fn parse_set_effect_speed(input: &[u8]) -> IResult<&[u8], ()> {
     )(input)
 }
 
+/// Palette index 0xFF selects the raw HSV wheel.
+fn parse_effect_params(input: &[u8]) -> IResult<&[u8], EffectParams> {
+    map(tuple((le_u16, u8, u8, u8)), |(speed, intensity, option, palette)| EffectParams {
+        speed,
+        intensity,
+        option,
+        palette: (palette != 0xFF).then_some(palette),
+    })(input)
+}
+
+fn parse_set_effect_params(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetEffectParams as u8]),
+        map(parse_effect_params, |params| get_led_sender().set_effect_params(params))
+    )(input)
+}
+
 fn parse_set_brightness(input: &[u8]) -> IResult<&[u8], ()> {
     preceded(
         tag([ListenCmd::SetBrightness as u8]),
@@ -95,18 +271,358 @@ fn parse_set_brightness(input: &[u8]) -> IResult<&[u8], ()> {
     )(input)
 }
 
+fn parse_set_keyframes(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetKeyframes as u8]),
+        map(parse_keyframe_sequence, |sequence| get_led_sender().set_keyframes(sequence))
+    )(input)
+}
+
+fn parse_clear_keyframes(input: &[u8]) -> IResult<&[u8], ()> {
+    map(tag([ListenCmd::ClearKeyframes as u8]), |_| get_led_sender().clear_keyframes())(input)
+}
+
+fn parse_audio_levels_cmd(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::AudioLevels as u8]),
+        map(parse_audio_levels, |levels| get_led_sender().set_audio_levels(levels))
+    )(input)
+}
+
+fn parse_upload_palette(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::UploadPalette as u8]),
+        map(tuple((parse_palette_index, parse_palette)), |(index, palette)| {
+            get_led_sender().set_palette(index, palette);
+            get_storage_sender().save_palette(index, palette);
+        })
+    )(input)
+}
+
+/// Palette index 0xFF selects the raw HSV wheel.
+fn parse_select_palette(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SelectPalette as u8]),
+        map(u8, |index| get_led_sender().select_palette((index != 0xFF).then_some(index)))
+    )(input)
+}
+
+/// Fade duration in milliseconds, 0 snaps to the new brightness.
+fn parse_set_brightness_fade(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetBrightnessFade as u8]),
+        map(le_u16, |fade_ms| get_led_sender().set_brightness_fade(Duration::from_millis(fade_ms as u64)))
+    )(input)
+}
+
+/// Direction byte 0 starts a sunrise, 1 a sunset, followed by the duration in minutes.
+fn parse_start_sunrise(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::StartSunrise as u8]),
+        map(tuple((verify(u8, |dir| *dir <= 1), le_u16)), |(dir, duration_min)| {
+            get_led_sender().start_sunrise(dir == 1, Duration::from_secs(duration_min as u64 * 60))
+        })
+    )(input)
+}
+
+fn parse_set_frame_rate(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetFrameRate as u8]),
+        map(verify(u8, |fps| (1..=MAX_FRAME_RATE).contains(fps)), |fps| get_led_sender().set_frame_rate(fps))
+    )(input)
+}
+
+/// An empty playlist stops cycling, entries are stored so cycling resumes after a reboot.
+fn parse_set_playlist(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetPlaylist as u8]),
+        map(parse_playlist, |playlist| {
+            get_led_sender().set_playlist(playlist);
+            get_storage_sender().save_playlist(playlist);
+        })
+    )(input)
+}
+
+/// 0 stops syncing, 1 leads the room, 2 follows a leader.
+#[cfg(feature = "sync")]
+fn parse_set_sync_mode(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetSyncMode as u8]),
+        map(map_opt(u8, SyncMode::from_u8), sync::set_mode)
+    )(input)
+}
+
+/// Hostname length and bytes; LED count; color order; max power in mA (0 unlimited); default
+/// effect (0xFF restores the last one); group mask; static IPv4 config; time server; syslog
+/// collector. Invalid values fail the command.
+fn parse_config_value(input: &[u8]) -> IResult<&[u8], DeviceConfig> {
+    let mut config = config::get();
+    let (input, key) = u8(input)?;
+    let input = match key {
+        k if k == ConfigKey::Hostname as u8 => {
+            let (input, len) = u8(input)?;
+            map_opt(take(len as usize), |hostname| config.set_hostname(hostname).then_some(()))(input)?.0
+        }
+        k if k == ConfigKey::LedCount as u8 => {
+            let (input, count) = verify(le_u16, |count| (1..=NUM_LEDS as u16).contains(count))(input)?;
+            config.led_count = count;
+            input
+        }
+        k if k == ConfigKey::ColorOrder as u8 => {
+            let (input, order) = map_opt(u8, ColorOrder::from_u8)(input)?;
+            config.color_order = order;
+            input
+        }
+        k if k == ConfigKey::MaxPower as u8 => {
+            let (input, max_power_ma) = le_u16(input)?;
+            config.max_power_ma = max_power_ma;
+            input
+        }
+        k if k == ConfigKey::DefaultEffect as u8 => {
+            let (input, effect) = verify(u8, |effect| *effect == 0xFF || Effect::from_u8(*effect).is_some())(input)?;
+            config.default_effect = Effect::from_u8(effect);
+            input
+        }
+        k if k == ConfigKey::Groups as u8 => {
+            let (input, groups) = le_u32(input)?;
+            config.groups = groups;
+            input
+        }
+        k if k == ConfigKey::StaticIp as u8 => {
+            let (input, prefix_len) = verify(u8, |len| *len <= 32)(input)?;
+            if prefix_len == 0 {
+                config.static_ip = None;
+                input
+            } else {
+                let (input, (address, gateway, dns)) = tuple((take(4usize), take(4usize), take(4usize)))(input)?;
+                config.static_ip = Some(StaticIp {
+                    address: address.try_into().unwrap(),
+                    prefix_len,
+                    gateway: gateway.try_into().unwrap(),
+                    dns: dns.try_into().unwrap(),
+                });
+                input
+            }
+        }
+        k if k == ConfigKey::NtpServer as u8 => {
+            let (input, len) = u8(input)?;
+            map_opt(take(len as usize), |server| config.set_ntp_server(server).then_some(()))(input)?.0
+        }
+        k if k == ConfigKey::LogCollector as u8 => {
+            let (input, (address, port)) = tuple((take(4usize), le_u16))(input)?;
+            let collector = LogCollector { address: address.try_into().unwrap(), port };
+            if port == 0 {
+                config.log_collector = None;
+            } else if collector.is_valid() {
+                config.log_collector = Some(collector);
+            } else {
+                return Err(Err::Error(nom::error::Error::new(input, ErrorKind::Verify)));
+            }
+            input
+        }
+        _ => return Err(Err::Error(nom::error::Error::new(input, ErrorKind::Switch))),
+    };
+    Ok((input, config))
+}
+
+/// Change one device setting and store it, the hostname, default effect and static IP apply
+/// after a reboot, the time server at the next sync, the log collector at the next batch.
+fn parse_set_config(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetConfig as u8]),
+        map(parse_config_value, |new_config| {
+            config::set(new_config);
+            get_storage_sender().save_config(new_config);
+            get_led_sender().refresh();
+        })
+    )(input)
+}
+
+fn parse_preset_index(input: &[u8]) -> IResult<&[u8], u8> {
+    verify(u8, |index| (*index as usize) < NUM_PRESETS)(input)
+}
+
+/// Seconds to blink the identify pattern for, 0 stops it.
+fn parse_identify(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::Identify as u8]),
+        map(u8, |seconds| get_led_sender().identify(Duration::from_secs(seconds as u64)))
+    )(input)
+}
+
+/// Index, name length, name. Saves the current effect, color, brightness and effect params,
+/// as last published by the LED task.
+fn parse_save_preset(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, (index, name_len)) = preceded(
+        tag([ListenCmd::SavePreset as u8]),
+        tuple((parse_preset_index, verify(u8, |len| *len as usize <= MAX_PRESET_NAME_LEN)))
+    )(input)?;
+    map(take(name_len as usize), move |name| {
+        let preset = Preset::new(name, leds::LED_STATUS.lock(|cell| cell.get()));
+        presets::set(index as usize, Some(preset));
+        get_storage_sender().save_preset(index, Some(preset));
+    })(input)
+}
+
+fn parse_recall_preset(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::RecallPreset as u8]),
+        map(parse_preset_index, |index| {
+            if !presets::recall(index as usize, &mut get_led_sender()) {
+                warn!("preset {} is empty", index);
+            }
+        })
+    )(input)
+}
+
+fn parse_delete_preset(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::DeletePreset as u8]),
+        map(parse_preset_index, |index| {
+            presets::set(index as usize, None);
+            get_storage_sender().save_preset(index, None);
+        })
+    )(input)
+}
+
+/// le_u64 Unix time in milliseconds to run at, le_u32 repeat interval in seconds (0 runs once),
+/// then the length prefixed commands to run, in the same format as a command datagram.
+fn parse_schedule_command(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, (at_ms, repeat_s, len)) = preceded(
+        tag([ListenCmd::ScheduleCommand as u8]),
+        tuple((le_u64, le_u32, verify(u8, |len| *len as usize <= MAX_SCHEDULED_LEN)))
+    )(input)?;
+    map(take(len as usize), move |commands: &[u8]| {
+        let mut bytes = [0; MAX_SCHEDULED_LEN];
+        bytes[..commands.len()].copy_from_slice(commands);
+        get_led_sender().schedule(ScheduledCommand { at_ms, repeat_s, len, bytes });
+    })(input)
+}
+
+fn parse_clear_schedule(input: &[u8]) -> IResult<&[u8], ()> {
+    map(tag([ListenCmd::ClearSchedule as u8]), |_| get_led_sender().clear_schedule())(input)
+}
+
+/// Unix time in milliseconds, for networks without a reachable time server. The next SNTP
+/// sync overrides it.
+fn parse_set_time(input: &[u8]) -> IResult<&[u8], ()> {
+    preceded(
+        tag([ListenCmd::SetTime as u8]),
+        map(le_u64, clock::set_unix_millis)
+    )(input)
+}
+
 fn parse_cmd(input: &[u8]) -> IResult<&[u8], ()> {
+    // Nested because alt takes at most 21 parsers
+    alt((parse_effect_cmd, parse_device_cmd))(input)
+}
+
+fn parse_device_cmd(input: &[u8]) -> IResult<&[u8], ()> {
+    alt((
+        parse_set_config,
+        parse_schedule_command,
+        parse_clear_schedule,
+        parse_set_time,
+        parse_save_preset,
+        parse_recall_preset,
+        parse_delete_preset,
+        parse_identify,
+    ))(input)
+}
+
+fn parse_effect_cmd(input: &[u8]) -> IResult<&[u8], ()> {
     alt((
         parse_set_color_list,
+        parse_stream_frame,
+        parse_set_masked_color,
         parse_shift_color,
         parse_set_primary_color,
         parse_set_effect,
         parse_set_effect_speed,
         parse_set_brightness,
+        parse_set_keyframes,
+        parse_clear_keyframes,
+        parse_audio_levels_cmd,
+        parse_upload_palette,
+        parse_select_palette,
+        parse_set_effect_params,
+        parse_set_brightness_fade,
+        parse_start_sunrise,
+        parse_set_frame_rate,
+        parse_set_playlist,
     ))(input)
 }
 
-fn fmt_err(err: Err<nom::error::Error<&[u8]>>) {
+struct Header {
+    seq: u16,
+    flags: u8,
+    group: Option<u8>,
+}
+
+fn parse_header(input: &[u8]) -> IResult<&[u8], Header> {
+    let (input, (seq, flags)) = preceded(tag(HEADER_MAGIC), tuple((le_u16, u8)))(input)?;
+    let (input, group) = if flags & FLAG_GROUP != 0 { map(u8, Some)(input)? } else { (input, None) };
+    Ok((input, Header { seq, flags, group }))
+}
+
+/// CRC-16/CCITT-FALSE, polynomial 0x1021 with initial value 0xFFFF.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Check the CRC-16 trailer ending the datagram.
+fn check_crc16(datagram: &[u8]) -> bool {
+    let Some(checked_len) = datagram.len().checked_sub(2) else {
+        return false;
+    };
+    let (checked, trailer) = datagram.split_at(checked_len);
+    crc16(checked) == u16::from_le_bytes([trailer[0], trailer[1]])
+}
+
+/// Last sequence number seen from each recent peer.
+struct SeqTracker {
+    peers: Vec<(IpEndpoint, u16), MAX_SEQ_PEERS>,
+    next_evict: usize,
+}
+
+impl SeqTracker {
+    const fn new() -> Self {
+        Self { peers: Vec::new(), next_evict: 0 }
+    }
+
+    /// Record `seq` from `endpoint`, returning false if it was already seen.
+    fn accept(&mut self, endpoint: IpEndpoint, seq: u16) -> bool {
+        if let Some((_, last)) = self.peers.iter_mut().find(|(peer, _)| *peer == endpoint) {
+            if last.wrapping_sub(seq) < SEQ_DUPLICATE_WINDOW {
+                return false;
+            }
+            *last = seq;
+        } else if self.peers.is_full() {
+            self.peers[self.next_evict] = (endpoint, seq);
+            self.next_evict = (self.next_evict + 1) % MAX_SEQ_PEERS;
+        } else {
+            self.peers.push((endpoint, seq)).ok();
+        }
+        true
+    }
+}
+
+/// nom error code and the offset into `datagram` where parsing stopped.
+fn error_details(err: &Err<nom::error::Error<&[u8]>>, datagram: &[u8]) -> (u8, u16) {
+    match err {
+        Err::Incomplete(_) => (error_to_u32(&ErrorKind::Eof) as u8, datagram.len() as u16),
+        Err::Failure(c) | Err::Error(c) => (error_to_u32(&c.code) as u8, (datagram.len() - c.input.len()) as u16),
+    }
+}
+
+fn fmt_err(err: &Err<nom::error::Error<&[u8]>>) {
     match err {
         Err::Incomplete(Needed::Size(u)) => error!("Parsing requires {} bytes/chars", u),
         Err::Incomplete(Needed::Unknown) => error!("Parsing requires more data"),
@@ -115,24 +631,482 @@ fn fmt_err(err: Err<nom::error::Error<&[u8]>>) {
     }
 }
 
-fn on_cmd_datagram_received(mut buffer: &[u8], endpoint: UdpMetadata) {
-    debug!("Received datagram of {} octets", buffer.len());
-    while buffer.len() > 0 {
-        match parse_cmd(buffer) {
-            Ok((buf, _)) => buffer = buf,
+struct Subscription {
+    /// Unspecified address or port stand for the sender's.
+    endpoint: IpEndpoint,
+    ttl: Duration,
+}
+
+/// IPv4 address and le_u16 port to push to, zero for the sender's own, then le_u16 lifetime
+/// in seconds. Subscribers renew before it runs out, a lifetime of 0 unsubscribes.
+fn parse_subscribe(input: &[u8]) -> IResult<&[u8], Subscription> {
+    preceded(
+        tag([ListenCmd::Subscribe as u8]),
+        map(tuple((take(4usize), le_u16, le_u16)), |(address, port, ttl): (&[u8], u16, u16)| {
+            let address = Ipv4Address::from_bytes(address);
+            Subscription { endpoint: IpEndpoint::new(address.into(), port), ttl: Duration::from_secs(ttl as u64) }
+        })
+    )(input)
+}
+
+/// Controllers that get the status reply pushed to them whenever the LED status changes.
+struct Subscribers {
+    entries: Vec<(IpEndpoint, Instant), MAX_SUBSCRIBERS>,
+}
+
+impl Subscribers {
+    const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    fn subscribe(&mut self, endpoint: IpEndpoint, ttl: Duration) {
+        let now = Instant::now();
+        self.entries.retain(|(subscriber, expires)| *subscriber != endpoint && *expires > now);
+        if ttl == Duration::from_ticks(0) {
+            return;
+        }
+        if self.entries.is_full() {
+            // Drop whichever subscription would lapse first
+            let soonest = self.entries.iter().enumerate().min_by_key(|(_, (_, expires))| *expires).map(|(i, _)| i);
+            if let Some(i) = soonest {
+                self.entries.swap_remove(i);
+            }
+        }
+        self.entries.push((endpoint, now + ttl)).ok();
+    }
+
+    fn live(&self) -> Vec<IpEndpoint, MAX_SUBSCRIBERS> {
+        let now = Instant::now();
+        self.entries.iter().filter(|(_, expires)| *expires > now).map(|(endpoint, _)| *endpoint).collect()
+    }
+}
+
+/// Commands answered by the UDP task itself rather than forwarded to the LED task.
+enum Query {
+    Status,
+    Stats,
+    Presets,
+    /// Kept log from le_u32 stream position on.
+    Log(u32),
+}
+
+fn parse_query(input: &[u8]) -> IResult<&[u8], Query> {
+    alt((
+        map(tag([ListenCmd::GetStatus as u8]), |_| Query::Status),
+        map(tag([ListenCmd::GetStats as u8]), |_| Query::Stats),
+        map(tag([ListenCmd::GetPresets as u8]), |_| Query::Presets),
+        map(preceded(tag([ListenCmd::GetLog as u8]), le_u32), Query::Log),
+    ))(input)
+}
+
+/// Image size and tag.
+#[cfg(feature = "ota")]
+fn parse_ota_begin(input: &[u8]) -> IResult<&[u8], OtaResult> {
+    preceded(
+        tag([ListenCmd::OtaBegin as u8]),
+        map(tuple((le_u32, take(ota::TAG_LEN))), |(size, image_tag): (u32, &[u8])| {
+            ota::begin(size, image_tag.try_into().unwrap())
+        })
+    )(input)
+}
+
+/// Offset into the image, CRC-32 of the data, data length, data.
+#[cfg(feature = "ota")]
+fn parse_ota_chunk(input: &[u8]) -> IResult<&[u8], OtaResult> {
+    let (input, (offset, crc, len)) = preceded(
+        tag([ListenCmd::OtaChunk as u8]),
+        tuple((le_u32, le_u32, verify(le_u16, |len| *len as usize <= ota::MAX_CHUNK_LEN)))
+    )(input)?;
+    map(take(len as usize), move |data| ota::chunk(offset, crc, data))(input)
+}
+
+/// OTA commands are answered with a progress reply: the command, `OtaResult`, `OtaState`, then
+/// the image size and bytes received as little endian u32s.
+#[cfg(feature = "ota")]
+fn parse_ota(input: &[u8]) -> IResult<&[u8], [u8; OTA_PROGRESS_LEN]> {
+    let (rest, result) = alt((
+        parse_ota_begin,
+        parse_ota_chunk,
+        map(tag([ListenCmd::OtaFinish as u8]), |_| ota::finish()),
+    ))(input)?;
+    let progress = ota::progress();
+    let mut reply = [0; OTA_PROGRESS_LEN];
+    reply[0] = input[0];
+    reply[1] = result as u8;
+    reply[2] = progress.state as u8;
+    reply[3..7].copy_from_slice(&progress.size.to_le_bytes());
+    reply[7..11].copy_from_slice(&progress.received.to_le_bytes());
+    Ok((rest, reply))
+}
+
+/// Status reply, also pushed to subscribers on every change. All multi-byte fields little endian:
+///
+/// | offset | field                                      |
+/// |--------|--------------------------------------------|
+/// | 0      | `ListenCmd::GetStatus`                     |
+/// | 1      | layout version                             |
+/// | 2..5   | firmware version major, minor, patch       |
+/// | 5      | effect                                     |
+/// | 6..10  | primary color r, g, b, w                   |
+/// | 10     | brightness                                 |
+/// | 11..13 | effect speed                               |
+/// | 13     | effect intensity                           |
+/// | 14     | effect option                              |
+/// | 15     | palette, 0xFF for the HSV wheel            |
+/// | 16..18 | LED count                                  |
+/// | 18..22 | uptime in seconds                          |
+/// | 22     | RSSI in dBm as i8, 0 while unknown         |
+/// | 23..25 | PHY rate in 500 kbit/s units, 0 if unknown |
+///
+/// Layout version 1 ended at offset 22 with a reserved byte.
+fn encode_status() -> [u8; STATUS_LEN] {
+    let status = leds::LED_STATUS.lock(|cell| cell.get());
+    let version = |s: &str| s.parse::<u8>().unwrap_or(0);
+    let mut reply = [0; STATUS_LEN];
+    reply[0] = ListenCmd::GetStatus as u8;
+    reply[1] = STATUS_LAYOUT_VERSION;
+    reply[2] = version(env!("CARGO_PKG_VERSION_MAJOR"));
+    reply[3] = version(env!("CARGO_PKG_VERSION_MINOR"));
+    reply[4] = version(env!("CARGO_PKG_VERSION_PATCH"));
+    reply[5] = status.effect as u8;
+    let color = status.primary_color;
+    reply[6..10].copy_from_slice(&[color.r, color.g, color.b, color.w]);
+    reply[10] = status.brightness;
+    reply[11..13].copy_from_slice(&status.params.speed.to_le_bytes());
+    reply[13] = status.params.intensity;
+    reply[14] = status.params.option;
+    reply[15] = status.params.palette.unwrap_or(0xFF);
+    reply[16..18].copy_from_slice(&config::get().led_count.to_le_bytes());
+    reply[18..22].copy_from_slice(&(Instant::now().as_secs() as u32).to_le_bytes());
+    let quality = link::quality();
+    reply[22] = quality.rssi as u8;
+    reply[23..25].copy_from_slice(&quality.tx_rate.to_le_bytes());
+    reply
+}
+
+/// Preset names reply: `ListenCmd::GetPresets`, then per preset slot a name length and the
+/// name padded to `MAX_PRESET_NAME_LEN`. Empty slots have a name length of 0xFF.
+fn encode_presets() -> [u8; PRESETS_LEN] {
+    let mut reply = [0; PRESETS_LEN];
+    reply[0] = ListenCmd::GetPresets as u8;
+    for (preset, bytes) in presets::all().iter().zip(reply[1..].chunks_exact_mut(1 + MAX_PRESET_NAME_LEN)) {
+        match preset {
+            Some(preset) => {
+                bytes[0] = preset.name().len() as u8;
+                bytes[1..1 + preset.name().len()].copy_from_slice(preset.name());
+            }
+            None => bytes[0] = 0xFF,
+        }
+    }
+    reply
+}
+
+/// le_u32 position of the first log byte in the reply and le_u32 position the log has reached,
+/// then the bytes. Positions count bytes since boot, so a reader asks for the position after
+/// its last reply and notices from the first one when older lines were overwritten.
+fn encode_log(from: u32) -> Vec<u8, LOG_REPLY_LEN> {
+    let mut chunk = [0; LOG_CHUNK_LEN];
+    let (start, len, written) = logbuf::read(from, &mut chunk);
+    let mut reply = Vec::new();
+    reply.push(ListenCmd::GetLog as u8).ok();
+    reply.extend_from_slice(&start.to_le_bytes()).ok();
+    reply.extend_from_slice(&written.to_le_bytes()).ok();
+    reply.extend_from_slice(&chunk[..len]).ok();
+    reply
+}
+
+/// Datagram counters since boot, for diagnosing controllers without a debug probe.
+struct Stats {
+    received: u32,
+    /// Datagrams whose commands all parsed.
+    parsed: u32,
+    parse_errors: u32,
+    auth_failures: u32,
+    duplicates: u32,
+    crc_failures: u32,
+}
+
+impl Stats {
+    /// Reply layout: `ListenCmd::GetStats` followed by the counters as little endian u32s,
+    /// in field order.
+    fn encode(&self) -> [u8; STATS_LEN] {
+        let mut reply = [0; STATS_LEN];
+        reply[0] = ListenCmd::GetStats as u8;
+        let counters = [self.received, self.parsed, self.parse_errors, self.auth_failures, self.duplicates, self.crc_failures];
+        for (counter, bytes) in counters.iter().zip(reply[1..].chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&counter.to_le_bytes());
+        }
+        reply
+    }
+}
+
+/// State kept across command datagrams.
+struct ListenState {
+    stats: Stats,
+    seqs: SeqTracker,
+    subscribers: Subscribers,
+    reassembler: Reassembler,
+    #[cfg(feature = "auth")]
+    replay_guard: auth::ReplayGuard,
+}
+
+impl ListenState {
+    const fn new() -> Self {
+        Self {
+            stats: Stats { received: 0, parsed: 0, parse_errors: 0, auth_failures: 0, duplicates: 0, crc_failures: 0 },
+            seqs: SeqTracker::new(),
+            subscribers: Subscribers::new(),
+            reassembler: Reassembler::new(),
+            #[cfg(feature = "auth")]
+            replay_guard: auth::ReplayGuard::new(),
+        }
+    }
+}
+
+/// Shared by every transport carrying command datagrams, so stats, duplicate suppression and
+/// replay protection cover all of them. Only tasks on core0's thread-mode executor use it, so a
+/// datagram is parsed without masking interrupts or holding core1's LED task off the spinlock.
+static LISTEN_STATE: Mutex<ThreadModeRawMutex, RefCell<ListenState>> = Mutex::new(RefCell::new(ListenState::new()));
+
+/// Replies owed to the sender of a command datagram, already encoded.
+pub struct Replies {
+    ack: Option<[u8; ACK_LEN]>,
+    status: Option<[u8; STATUS_LEN]>,
+    stats: Option<[u8; STATS_LEN]>,
+    presets: Option<[u8; PRESETS_LEN]>,
+    log: Option<Vec<u8, LOG_REPLY_LEN>>,
+    #[cfg(feature = "ota")]
+    ota: Option<[u8; OTA_PROGRESS_LEN]>,
+}
+
+impl Replies {
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let ack = self.ack.as_ref().map(|reply| &reply[..]);
+        let status = self.status.as_ref().map(|reply| &reply[..]);
+        let stats = self.stats.as_ref().map(|reply| &reply[..]);
+        let presets = self.presets.as_ref().map(|reply| &reply[..]);
+        let log = self.log.as_ref().map(|reply| &reply[..]);
+        let replies = ack.into_iter().chain(status).chain(stats).chain(presets).chain(log);
+        #[cfg(feature = "ota")]
+        let replies = replies.chain(self.ota.as_ref().map(|reply| &reply[..]));
+        replies
+    }
+}
+
+/// Apply headerless commands without replies or stats, for commands scheduled earlier.
+pub fn apply_commands(mut commands: &[u8]) {
+    while !commands.is_empty() {
+        match parse_cmd(commands) {
+            Ok((rest, _)) => commands = rest,
             Err(e) => {
-                fmt_err(e);
-                break
-            },
-        };
+                fmt_err(&e);
+                break;
+            }
+        }
     }
 }
 
-pub async fn run<'a>(cmd_socket: &mut UdpSocket<'a>, discover_socket: &mut UdpSocket<'a>, mac: &[u8; 6]) -> ! {
+/// Carry replay protection over a reset, from the floor `storage` kept.
+#[cfg(feature = "auth")]
+pub fn restore_nonce_floor(floor: u64) {
+    LISTEN_STATE.lock(|state| state.borrow_mut().replay_guard.restore(floor));
+}
+
+/// Apply the commands in a datagram received over any transport, returning the replies to send.
+pub fn dispatch(datagram: &[u8], endpoint: IpEndpoint) -> Option<Replies> {
+    LISTEN_STATE.lock(|state| on_cmd_datagram_received(datagram, endpoint, &mut state.borrow_mut()))
+}
+
+fn on_cmd_datagram_received(datagram: &[u8], endpoint: IpEndpoint, state: &mut ListenState) -> Option<Replies> {
+    debug!("Received datagram of {} octets", datagram.len());
+    state.stats.received = state.stats.received.wrapping_add(1);
+    #[cfg(feature = "auth")]
+    let Some(datagram) = auth::verify(datagram, &mut state.replay_guard) else {
+        warn!("Dropping unauthenticated datagram from {}", endpoint);
+        state.stats.auth_failures = state.stats.auth_failures.wrapping_add(1);
+        return None;
+    };
+    #[cfg(feature = "auth")]
+    if let Some(floor) = state.replay_guard.next_floor() {
+        // Tried again with the next datagram if the storage queue is full
+        if get_storage_sender().save_nonce_floor(floor) {
+            state.replay_guard.floor_stored(floor);
+        }
+    }
+    let mut reassembled = [0; MAX_REASSEMBLED_LEN];
+    let datagram = match reassembly::parse_fragment(datagram) {
+        Ok((_, fragment)) => {
+            let payload = state.reassembler.add(endpoint, fragment)?;
+            reassembled[..payload.len()].copy_from_slice(payload);
+            &reassembled[..payload.len()]
+        }
+        Err(_) => datagram,
+    };
+    let Ok((mut buffer, header)) = opt(parse_header)(datagram) else {
+        return None;
+    };
+    if header.as_ref().is_some_and(|header| header.flags & FLAG_CRC16 != 0) {
+        if !check_crc16(datagram) {
+            warn!("Dropping corrupted datagram from {}", endpoint);
+            state.stats.crc_failures = state.stats.crc_failures.wrapping_add(1);
+            return None;
+        }
+        buffer = &buffer[..buffer.len().saturating_sub(2)];
+    }
+    if let Some(group) = header.as_ref().and_then(|header| header.group) {
+        if !config::get().in_group(group) {
+            debug!("Ignoring datagram for group {}", group);
+            return None;
+        }
+    }
+
+    let mut replies = Replies {
+        ack: None,
+        status: None,
+        stats: None,
+        presets: None,
+        log: None,
+        #[cfg(feature = "ota")]
+        ota: None,
+    };
+    let mut status = ACK_OK;
+    let mut error = (0, 0);
+    if header.as_ref().map_or(true, |header| state.seqs.accept(endpoint, header.seq)) {
+        while buffer.len() > 0 {
+            if let Ok((buf, query)) = parse_query(buffer) {
+                match query {
+                    Query::Status => replies.status = Some(encode_status()),
+                    Query::Stats => replies.stats = Some([0; STATS_LEN]),
+                    Query::Presets => replies.presets = Some(encode_presets()),
+                    Query::Log(from) => replies.log = Some(encode_log(from)),
+                }
+                buffer = buf;
+                continue;
+            }
+            if let Ok((buf, subscription)) = parse_subscribe(buffer) {
+                let mut target = subscription.endpoint;
+                if target.addr.is_unspecified() {
+                    target.addr = endpoint.addr;
+                }
+                if target.port == 0 {
+                    target.port = endpoint.port;
+                }
+                state.subscribers.subscribe(target, subscription.ttl);
+                buffer = buf;
+                continue;
+            }
+            #[cfg(feature = "ota")]
+            if let Ok((buf, progress)) = parse_ota(buffer) {
+                replies.ota = Some(progress);
+                buffer = buf;
+                continue;
+            }
+            #[cfg(feature = "sync")]
+            if let Ok((buf, _)) = parse_set_sync_mode(buffer) {
+                buffer = buf;
+                continue;
+            }
+            match parse_cmd(buffer) {
+                Ok((buf, _)) => buffer = buf,
+                Err(e) => {
+                    fmt_err(&e);
+                    status = ACK_PARSE_ERROR;
+                    error = error_details(&e, datagram);
+                    break
+                },
+            };
+        }
+        if status == ACK_OK {
+            state.stats.parsed = state.stats.parsed.wrapping_add(1);
+        } else {
+            state.stats.parse_errors = state.stats.parse_errors.wrapping_add(1);
+        }
+    } else {
+        // Already applied, the ACK was probably lost so send it again
+        debug!("Dropping duplicate datagram from {}", endpoint);
+        state.stats.duplicates = state.stats.duplicates.wrapping_add(1);
+    }
+
+    let wants_ack = |flags: u8| flags & FLAG_ACK_REQUESTED != 0 || (flags & FLAG_NACK_REQUESTED != 0 && status != ACK_OK);
+    replies.ack = header.filter(|header| wants_ack(header.flags)).map(|header| {
+        let seq = header.seq.to_le_bytes();
+        let offset = error.1.to_le_bytes();
+        [HEADER_MAGIC[0], HEADER_MAGIC[1], seq[0], seq[1], FLAG_ACK, status, error.0, offset[0], offset[1]]
+    });
+    // Encoded last so the counters include this datagram
+    if replies.stats.is_some() {
+        replies.stats = Some(state.stats.encode());
+    }
+    let any = replies.iter().next().is_some();
+    any.then_some(replies)
+}
+
+/// Magic and layout version starting a discover reply, followed by `DiscoverTlv` records.
+const DISCOVER_REPLY_MAGIC: [u8; 3] = *b"MSD";
+const DISCOVER_LAYOUT_VERSION: u8 = 1;
+const DISCOVER_REPLY_LEN: usize = 104;
+/// `DiscoverTlv::StripType` value of SK6812 RGBW strips.
+const STRIP_TYPE_SK6812_RGBW: u8 = 1;
+
+/// Record types of a discover reply. Each record is a type byte, a length byte and the
+/// value, controllers skip types they don't know so records can be added freely.
+enum DiscoverTlv {
+    Mac = 1,
+    Hostname = 2,
+    /// Major, minor, patch.
+    FirmwareVersion = 3,
+    /// le_u16 configured LED count.
+    LedCount = 4,
+    /// le_u32 with bit n set when `Effect` n is supported.
+    Effects = 5,
+    StripType = 6,
+    /// Current IPv4 address, absent until DHCP is bound.
+    Ipv4Address = 7,
+    /// le_u32 group membership mask.
+    Groups = 8,
+    /// `PROTOCOL_VERSION`, controllers only use header flags the device understands.
+    ProtocolVersion = 9,
+    /// Link-local IPv6 address, for controllers on IPv6-only networks.
+    Ipv6Address = 10,
+}
+
+fn encode_discover_reply<D: Driver>(stack: &Stack<D>, mac: &[u8; 6]) -> Vec<u8, DISCOVER_REPLY_LEN> {
+    let version = |s: &str| s.parse::<u8>().unwrap_or(0);
+    let config = config::get();
+    let effects = (0..32).filter(|bit| Effect::from_u8(*bit).is_some()).fold(0_u32, |bits, bit| bits | 1 << bit);
+    let mut reply = Vec::new();
+    reply.extend_from_slice(&DISCOVER_REPLY_MAGIC).ok();
+    reply.push(DISCOVER_LAYOUT_VERSION).ok();
+    let mut record = |tlv: DiscoverTlv, value: &[u8]| {
+        reply.extend_from_slice(&[tlv as u8, value.len() as u8]).ok();
+        reply.extend_from_slice(value).ok();
+    };
+    record(DiscoverTlv::Mac, mac);
+    record(DiscoverTlv::Hostname, config.hostname().as_bytes());
+    record(DiscoverTlv::FirmwareVersion, &[
+        version(env!("CARGO_PKG_VERSION_MAJOR")),
+        version(env!("CARGO_PKG_VERSION_MINOR")),
+        version(env!("CARGO_PKG_VERSION_PATCH")),
+    ]);
+    record(DiscoverTlv::LedCount, &config.led_count.to_le_bytes());
+    record(DiscoverTlv::Effects, &effects.to_le_bytes());
+    record(DiscoverTlv::StripType, &[STRIP_TYPE_SK6812_RGBW]);
+    record(DiscoverTlv::Groups, &config.groups.to_le_bytes());
+    record(DiscoverTlv::ProtocolVersion, &[PROTOCOL_VERSION]);
+    if let Some(ip) = stack.config_v4() {
+        record(DiscoverTlv::Ipv4Address, &ip.address.address().0);
+    }
+    if let Some(ip) = stack.config_v6() {
+        record(DiscoverTlv::Ipv6Address, &ip.address.address().0);
+    }
+    reply
+}
+
+pub async fn run<'a, D: Driver>(cmd_socket: &mut UdpSocket<'a>, discover_socket: &mut UdpSocket<'a>, stack: &Stack<D>, mac: &[u8; 6]) -> ! {
     loop {
-        match select::select(
-            cmd_socket.recv_from_with(|buffer, endpoint| {
-                on_cmd_datagram_received(buffer, endpoint);
+        match select::select3(
+            cmd_socket.recv_from_with(|buffer, meta: UdpMetadata| {
+                dispatch(buffer, meta.endpoint).map(|replies| (replies, meta))
             }),
             discover_socket.recv_from_with(|buffer, endpoint| {
                 if buffer == "mow sconce discover".as_bytes() {
@@ -143,13 +1117,25 @@ pub async fn run<'a>(cmd_socket: &mut UdpSocket<'a>, discover_socket: &mut UdpSo
                     None
                 }
             }),
+            leds::STATUS_CHANGED.wait(),
         ).await {
-            Either::Second(Some(endpoint)) => {
+            Either3::Third(_) => {
+                let subscribers = LISTEN_STATE.lock(|state| state.borrow().subscribers.live());
+                if !subscribers.is_empty() {
+                    let status = encode_status();
+                    for subscriber in subscribers {
+                        cmd_socket.send_to(&status, subscriber).await.ok();
+                    }
+                }
+            }
+            Either3::Second(Some(endpoint)) => {
                 debug!("Sending discover reply to {}", endpoint);
-                let mut reply = heapless::String::<36>::new();
-                uwrite!(reply, "mow sconce reply: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                    mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]).unwrap();
-                discover_socket.send_to(reply.as_bytes(), endpoint).await.ok();
+                discover_socket.send_to(&encode_discover_reply(stack, mac), endpoint).await.ok();
+            }
+            Either3::First(Some((replies, endpoint))) => {
+                for reply in replies.iter() {
+                    cmd_socket.send_to(reply, endpoint).await.ok();
+                }
             }
             _ => {}
         }
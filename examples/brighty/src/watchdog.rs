@@ -0,0 +1,63 @@
+use defmt::Format;
+use embassy_time::Duration;
+use crate::{define_peripheral_set, remote_log};
+
+pub use board_support::watchdog::check_in;
+
+#[macro_export]
+macro_rules! watchdog_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            WatchdogPeripherals,
+            watchdog: WATCHDOG,
+        }
+    };
+}
+
+watchdog_peripherals!(define_peripheral_set);
+
+/// Parts of the firmware that have to check in within their window for the watchdog to be fed.
+/// The UDP services only wake on traffic, the gateway probe stands in for the stack polling them.
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum Subsystem {
+    /// cyw43 and the net stack, on join attempts, answered gateway probes and quality samples.
+    Net = 0,
+    /// The LED task on core1, on every pass of its loop.
+    Leds = 1,
+}
+
+impl Subsystem {
+    fn name(self) -> &'static str {
+        match self {
+            Subsystem::Net => "net",
+            Subsystem::Leds => "leds",
+        }
+    }
+}
+
+impl board_support::watchdog::Subsystem for Subsystem {
+    const ALL: &'static [Self] = &[Subsystem::Net, Subsystem::Leds];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn window(self) -> Duration {
+        match self {
+            // A rejoin and a fresh DHCP lease can take a while, a DHCP server that stays silent
+            // longer gets a reboot as its retry
+            Subsystem::Net => Duration::from_secs(300),
+            Subsystem::Leds => Duration::from_secs(5),
+        }
+    }
+}
+
+/// Feed the hardware watchdog while every subsystem keeps checking in, so a lockup anywhere
+/// reboots the device.
+#[embassy_executor::task]
+pub async fn watchdog_task(p: WatchdogPeripherals) -> ! {
+    board_support::watchdog::run(p.watchdog, |subsystem: Option<Subsystem>| {
+        remote_log!(Warning, "rebooted by the watchdog, {} had stalled", subsystem.map_or("?", Subsystem::name));
+    })
+    .await
+}
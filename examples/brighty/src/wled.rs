@@ -0,0 +1,197 @@
+use defmt::debug;
+use embassy_net::udp::UdpSocket;
+use embassy_time::Duration;
+use heapless::String;
+use nom::{IResult, branch::alt, bytes::complete::tag, combinator::{map, rest}, number::complete::{be_u16, u8}, sequence::{preceded, tuple}};
+use ufmt::uwrite;
+use crate::color::Color;
+use crate::json::{json_u8, json_value};
+use crate::leds::{self, Effect, LedSender, RealtimeFrame, NUM_LEDS};
+
+pub const REALTIME_PORT: u16 = 21324;
+
+/// Realtime timeout byte meaning "never time out".
+const TIMEOUT_FOREVER: u8 = 255;
+/// Brightness restored by `"on":true` when the LEDs were switched off before any `bri` was sent.
+const DEFAULT_ON_BRIGHTNESS: u8 = 128;
+/// Version reported in `/json/info`, the API subset matches WLED 0.14.
+const WLED_VERSION: &str = "0.14.0";
+
+fn get_led_sender() -> LedSender {
+    leds::LED_CHANNEL.sender()
+}
+
+#[repr(u8)]
+enum RealtimeProtocol {
+    Warls = 1,
+    Drgb = 2,
+    Drgbw = 3,
+    Dnrgb = 4,
+}
+
+fn set_led(frame: &mut RealtimeFrame, index: usize, color: Color) {
+    if let Some(led) = frame.colors.get_mut(index) {
+        *led = Some(color);
+    }
+}
+
+fn parse_warls(input: &[u8]) -> IResult<&[u8], RealtimeFrame> {
+    preceded(tag([RealtimeProtocol::Warls as u8]), map(parse_timeout_and_rest, |(timeout, data)| {
+        let mut frame = RealtimeFrame { colors: [None; NUM_LEDS], timeout };
+        for led in data.chunks_exact(4) {
+            set_led(&mut frame, led[0] as usize, Color::from_rgbw(led[1], led[2], led[3], 0));
+        }
+        frame
+    }))(input)
+}
+
+fn parse_drgb(input: &[u8]) -> IResult<&[u8], RealtimeFrame> {
+    preceded(tag([RealtimeProtocol::Drgb as u8]), map(parse_timeout_and_rest, |(timeout, data)| {
+        let mut frame = RealtimeFrame { colors: [None; NUM_LEDS], timeout };
+        for (i, led) in data.chunks_exact(3).enumerate() {
+            set_led(&mut frame, i, Color::from_rgbw(led[0], led[1], led[2], 0));
+        }
+        frame
+    }))(input)
+}
+
+fn parse_drgbw(input: &[u8]) -> IResult<&[u8], RealtimeFrame> {
+    preceded(tag([RealtimeProtocol::Drgbw as u8]), map(parse_timeout_and_rest, |(timeout, data)| {
+        let mut frame = RealtimeFrame { colors: [None; NUM_LEDS], timeout };
+        for (i, led) in data.chunks_exact(4).enumerate() {
+            set_led(&mut frame, i, Color::from_rgbw(led[0], led[1], led[2], led[3]));
+        }
+        frame
+    }))(input)
+}
+
+fn parse_dnrgb(input: &[u8]) -> IResult<&[u8], RealtimeFrame> {
+    preceded(tag([RealtimeProtocol::Dnrgb as u8]), map(tuple((parse_timeout, be_u16, rest)), |(timeout, start, data)| {
+        let mut frame = RealtimeFrame { colors: [None; NUM_LEDS], timeout };
+        for (i, led) in data.chunks_exact(3).enumerate() {
+            set_led(&mut frame, start as usize + i, Color::from_rgbw(led[0], led[1], led[2], 0));
+        }
+        frame
+    }))(input)
+}
+
+fn parse_timeout(input: &[u8]) -> IResult<&[u8], Duration> {
+    map(u8, |secs| match secs {
+        TIMEOUT_FOREVER => Duration::MAX,
+        secs => Duration::from_secs(secs.max(1) as u64),
+    })(input)
+}
+
+fn parse_timeout_and_rest(input: &[u8]) -> IResult<&[u8], (Duration, &[u8])> {
+    tuple((parse_timeout, rest))(input)
+}
+
+fn parse_realtime(input: &[u8]) -> IResult<&[u8], RealtimeFrame> {
+    alt((parse_warls, parse_drgb, parse_drgbw, parse_dnrgb))(input)
+}
+
+/// Receive WLED realtime frames (WARLS, DRGB, DRGBW, DNRGB) on `REALTIME_PORT`.
+pub async fn run_realtime<'a>(socket: &mut UdpSocket<'a>) -> ! {
+    loop {
+        socket.recv_from_with(|buffer, endpoint| match parse_realtime(buffer) {
+            Ok((_, frame)) => get_led_sender().set_realtime(frame),
+            Err(_) => debug!("Discarding invalid WLED realtime packet from {}", endpoint),
+        }).await;
+    }
+}
+
+fn json_bool(value: &[u8]) -> Option<bool> {
+    if value.starts_with(b"true") {
+        Some(true)
+    } else if value.starts_with(b"false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// First color of `"col":[[r,g,b(,w)],...]`.
+fn json_first_color(value: &[u8]) -> Option<Color> {
+    let mut value = value.strip_prefix(b"[")?.trim_ascii_start().strip_prefix(b"[")?;
+    let mut channels = [0; 4];
+    for (i, channel) in channels.iter_mut().enumerate() {
+        value = value.trim_ascii_start();
+        *channel = json_u8(value)?;
+        value = value[value.iter().take_while(|c| c.is_ascii_digit()).count()..].trim_ascii_start();
+        match value.first() {
+            Some(b',') => value = &value[1..],
+            Some(b']') if i >= 2 => break,
+            _ => return None,
+        }
+    }
+    Some(Color::from_rgbw(channels[0], channels[1], channels[2], channels[3]))
+}
+
+/// Subset of the WLED JSON API: `GET /json`, `/json/state`, `/json/info` and `POST /json/state`
+/// with `on`, `bri` and the first color of the first segment.
+pub struct JsonApi {
+    /// Brightness to restore on `"on":true`.
+    on_brightness: u8,
+    mac: [u8; 6],
+}
+
+impl JsonApi {
+    pub fn new(mac: [u8; 6]) -> Self {
+        Self { on_brightness: DEFAULT_ON_BRIGHTNESS, mac }
+    }
+
+    fn apply_state(&mut self, body: &[u8]) {
+        let mut led_sender = get_led_sender();
+        let current = leds::LED_STATUS.lock(|cell| cell.get()).brightness;
+        if let Some(bri) = json_value(body, "bri").and_then(json_u8) {
+            self.on_brightness = bri.max(1);
+            led_sender.set_brightness(bri);
+        }
+        if let Some(on) = json_value(body, "on").and_then(json_bool) {
+            if !on && current > 0 {
+                self.on_brightness = current;
+            }
+            led_sender.set_brightness(if on { self.on_brightness } else { 0 });
+        }
+        if let Some(color) = json_value(body, "col").and_then(json_first_color) {
+            led_sender.set_primary_color(color);
+            led_sender.set_effect(Effect::Static);
+        }
+    }
+
+    fn write_state<const N: usize>(&self, out: &mut String<N>) {
+        let status = leds::LED_STATUS.lock(|cell| cell.get());
+        let c = status.primary_color;
+        uwrite!(out, "{{\"on\":{},\"bri\":{},\"seg\":[{{\"id\":0,\"start\":0,\"stop\":{},\"fx\":{},\"sx\":{},\"col\":[[{},{},{},{}]]}}]}}",
+            if status.brightness > 0 { "true" } else { "false" }, status.brightness, crate::config::get().led_count, status.effect as u8,
+            (status.params.speed >> 8) as u8, c.r, c.g, c.b, c.w).ok();
+    }
+
+    fn write_info<const N: usize>(&self, out: &mut String<N>) {
+        let m = &self.mac;
+        let config = crate::config::get();
+        uwrite!(out, "{{\"ver\":\"{}\",\"name\":\"{}\",\"brand\":\"WLED\",\"product\":\"brighty\",\"leds\":{{\"count\":{},\"rgbw\":true}},\"mac\":\"{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}\"}}",
+            WLED_VERSION, config.hostname().as_str(), config.led_count, m[0], m[1], m[2], m[3], m[4], m[5]).ok();
+    }
+
+    /// Handle one request, writing the response body to `out`. Returns false for unknown paths.
+    pub fn handle<const N: usize>(&mut self, method: &[u8], path: &[u8], body: &[u8], out: &mut String<N>) -> bool {
+        match (method, path) {
+            (b"GET", b"/json") | (b"GET", b"/json/") => {
+                out.push_str("{\"state\":").ok();
+                self.write_state(out);
+                out.push_str(",\"info\":").ok();
+                self.write_info(out);
+                out.push('}').ok();
+            }
+            (b"GET", b"/json/state") => self.write_state(out),
+            (b"GET", b"/json/info") => self.write_info(out),
+            (b"POST", b"/json/state") | (b"POST", b"/json") => {
+                self.apply_state(body);
+                out.push_str("{\"success\":true}").ok();
+            }
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,658 @@
+//! Typed settings records in a reserved flash region, shared by the examples.
+//!
+//! Records are appended to a log in the active sector and the newest copy of each wins on load.
+//! Once the active sector is full the live records are compacted into the next one, so erases
+//! rotate through the whole region. Every record carries a CRC, a record torn by a reset is
+//! skipped, and the layout version it was written with, so [`Record::load`] can migrate older
+//! layouts.
+#![no_std]
+
+use embedded_storage::nor_flash::NorFlash;
+
+/// "CFS1", at the start of every sector that holds a complete log.
+const SECTOR_MAGIC: u32 = 0x3153_4643;
+const SECTOR_HEADER_LEN: u32 = 8;
+const RECORD_HEADER_LEN: usize = 12;
+/// Records and headers start on this alignment, enough for flashes that write whole words.
+const ALIGN: usize = 4;
+/// Largest record payload.
+pub const MAX_RECORD_LEN: usize = 1024;
+/// Key of erased flash, the end of a log.
+const ERASED_KEY: u8 = 0xFF;
+const FLAG_REMOVED: u8 = 0x01;
+/// Chunk size for reading and copying payloads without a second record buffer.
+const CHUNK_LEN: usize = 64;
+
+/// A settings type kept in a [`ConfigStore`].
+pub trait Record: Sized {
+    /// Tells record types apart in flash. Unique within a store and never reused for a different
+    /// type, 0xFF is reserved.
+    const KEY: u8;
+    /// Layout version written with new records, bump it whenever `save` changes the layout.
+    const VERSION: u8;
+
+    /// Write the current layout to `buf`, returning its length.
+    fn save(&self, buf: &mut [u8]) -> usize;
+
+    /// Read a record written with layout `version`, migrating older layouts. `None` discards it.
+    fn load(version: u8, bytes: &[u8]) -> Option<Self>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The flash reported an error reading, writing or erasing.
+    Flash,
+    /// The live records don't fit into one sector even after compaction.
+    Full,
+    /// The record is longer than `MAX_RECORD_LEN`.
+    TooLong,
+}
+
+#[derive(Copy, Clone)]
+struct Header {
+    key: u8,
+    index: u8,
+    version: u8,
+    flags: u8,
+    len: usize,
+    crc: u32,
+}
+
+impl Header {
+    fn parse(bytes: &[u8; RECORD_HEADER_LEN]) -> Self {
+        Self {
+            key: bytes[0],
+            index: bytes[1],
+            version: bytes[2],
+            flags: bytes[3],
+            len: u16::from_le_bytes([bytes[4], bytes[5]]) as usize,
+            crc: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        }
+    }
+
+    fn encode(&self) -> [u8; RECORD_HEADER_LEN] {
+        let mut bytes = [0xFF; RECORD_HEADER_LEN];
+        bytes[..4].copy_from_slice(&[self.key, self.index, self.version, self.flags]);
+        bytes[4..6].copy_from_slice(&(self.len as u16).to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// CRC over the header fields before the CRC itself, continued over the payload.
+    fn crc_start(&self) -> u32 {
+        crc32_update(!0, &self.encode()[..8])
+    }
+
+    fn same_record(&self, other: &Header) -> bool {
+        self.key == other.key && self.index == other.index
+    }
+
+    /// Space the record takes in the log.
+    fn stored_len(&self) -> u32 {
+        (RECORD_HEADER_LEN + aligned(self.len)) as u32
+    }
+}
+
+const fn aligned(len: usize) -> usize {
+    len.div_ceil(ALIGN) * ALIGN
+}
+
+/// CRC-32 (IEEE 802.3) as computed by zlib and most scripting languages.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Continue a CRC-32 over `data`, from `!0` for the first slice, inverting the end result.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Log of [`Record`]s on `sectors` erase sectors of `F`, starting at the sector aligned `start`.
+pub struct ConfigStore<F: NorFlash> {
+    flash: F,
+    start: u32,
+    sectors: u32,
+    /// Sector records are appended to and its sequence number, `None` for a blank region.
+    active: Option<(u32, u32)>,
+    /// Offset of the first free byte in the active sector.
+    cursor: u32,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    /// Find the newest complete log in the region and the end of it.
+    pub fn new(flash: F, start: u32, sectors: u32) -> Self {
+        // Compaction erases the next sector before the live records are safe in it
+        assert!(sectors >= 2);
+        assert!(F::WRITE_SIZE <= ALIGN && F::READ_SIZE <= ALIGN);
+        let mut store = Self {
+            flash,
+            start,
+            sectors,
+            active: None,
+            cursor: 0,
+        };
+        for sector in 0..sectors {
+            let mut header = [0; SECTOR_HEADER_LEN as usize];
+            if store.flash.read(store.sector_start(sector), &mut header).is_err() {
+                continue;
+            }
+            let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let sequence = u32::from_le_bytes(header[4..].try_into().unwrap());
+            if magic == SECTOR_MAGIC && store.active.map_or(true, |(_, newest)| sequence > newest) {
+                store.active = Some((sector, sequence));
+            }
+        }
+        if let Some((sector, _)) = store.active {
+            store.cursor = store.scan(sector, |_, _| {});
+        }
+        store
+    }
+
+    /// The flash itself, for other users of the chip such as firmware updates.
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Hand the flash back once the settings have been read.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Whether nothing was ever saved.
+    pub fn is_empty(&self) -> bool {
+        self.active.is_none()
+    }
+
+    pub fn load<R: Record>(&mut self) -> Option<R> {
+        self.load_at(0)
+    }
+
+    /// Newest copy of record `index` of type `R`, for types stored more than once.
+    pub fn load_at<R: Record>(&mut self, index: u8) -> Option<R> {
+        let (sector, _) = self.active?;
+        let wanted = Header {
+            key: R::KEY,
+            index,
+            version: 0,
+            flags: 0,
+            len: 0,
+            crc: 0,
+        };
+        let mut newest = None;
+        self.scan(sector, |offset, header| {
+            if header.same_record(&wanted) {
+                newest = Some((offset, *header));
+            }
+        });
+        let (offset, header) = newest?;
+        if header.flags & FLAG_REMOVED != 0 {
+            return None;
+        }
+        let mut buf = [0; MAX_RECORD_LEN];
+        self.flash
+            .read(offset + RECORD_HEADER_LEN as u32, &mut buf[..aligned(header.len)])
+            .ok()?;
+        R::load(header.version, &buf[..header.len])
+    }
+
+    pub fn save<R: Record>(&mut self, record: &R) -> Result<(), Error> {
+        self.save_at(0, record)
+    }
+
+    /// Store `record` as record `index` of its type.
+    pub fn save_at<R: Record>(&mut self, index: u8, record: &R) -> Result<(), Error> {
+        let mut buf = [0xFF; MAX_RECORD_LEN];
+        let len = record.save(&mut buf);
+        if len > MAX_RECORD_LEN {
+            return Err(Error::TooLong);
+        }
+        let mut header = Header {
+            key: R::KEY,
+            index,
+            version: R::VERSION,
+            flags: 0,
+            len,
+            crc: 0,
+        };
+        header.crc = !crc32_update(header.crc_start(), &buf[..len]);
+        self.append(&header, &buf[..aligned(len)])
+    }
+
+    /// Drop record `index` of type `R`, later loads return `None`.
+    pub fn remove_at<R: Record>(&mut self, index: u8) -> Result<(), Error> {
+        let mut header = Header {
+            key: R::KEY,
+            index,
+            version: R::VERSION,
+            flags: FLAG_REMOVED,
+            len: 0,
+            crc: 0,
+        };
+        header.crc = !header.crc_start();
+        self.append(&header, &[])
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+        self.start + sector * F::ERASE_SIZE as u32
+    }
+
+    fn sector_end(&self, sector: u32) -> u32 {
+        self.sector_start(sector + 1)
+    }
+
+    /// Call `f` with every intact record of `sector` in the order they were written, returning
+    /// the end of the log.
+    fn scan(&mut self, sector: u32, mut f: impl FnMut(u32, &Header)) -> u32 {
+        let end = self.sector_end(sector);
+        let mut offset = self.sector_start(sector) + SECTOR_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN as u32 <= end {
+            let mut bytes = [0; RECORD_HEADER_LEN];
+            if self.flash.read(offset, &mut bytes).is_err() {
+                return end;
+            }
+            let header = Header::parse(&bytes);
+            if header.key == ERASED_KEY {
+                return offset;
+            }
+            // A length running past the sector is a torn header, nothing after it can be found
+            if header.len > MAX_RECORD_LEN || offset + header.stored_len() > end {
+                return end;
+            }
+            if self.payload_crc(offset, &header) == Some(header.crc) {
+                f(offset, &header);
+            }
+            offset += header.stored_len();
+        }
+        end
+    }
+
+    fn payload_crc(&mut self, offset: u32, header: &Header) -> Option<u32> {
+        let mut crc = header.crc_start();
+        let mut chunk = [0; CHUNK_LEN];
+        let mut pos = 0;
+        while pos < header.len {
+            let len = (header.len - pos).min(CHUNK_LEN);
+            let start = offset + (RECORD_HEADER_LEN + pos) as u32;
+            self.flash.read(start, &mut chunk[..aligned(len)]).ok()?;
+            crc = crc32_update(crc, &chunk[..len]);
+            pos += len;
+        }
+        Some(!crc)
+    }
+
+    fn append(&mut self, header: &Header, payload: &[u8]) -> Result<(), Error> {
+        let stored_len = header.stored_len();
+        let fits = |store: &Self| {
+            store
+                .active
+                .is_some_and(|(sector, _)| store.cursor + stored_len <= store.sector_end(sector))
+        };
+        if !fits(self) {
+            self.compact()?;
+            if !fits(self) {
+                return Err(Error::Full);
+            }
+        }
+        self.flash
+            .write(self.cursor, &header.encode())
+            .map_err(|_| Error::Flash)?;
+        // Consume the space even if the payload fails, the header is already there
+        let offset = self.cursor + RECORD_HEADER_LEN as u32;
+        self.cursor += stored_len;
+        if !payload.is_empty() {
+            self.flash.write(offset, payload).map_err(|_| Error::Flash)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the newest intact copy of every record into the next sector and make it the active
+    /// one. Its header is written last, a reset halfway leaves the old sector in charge.
+    fn compact(&mut self) -> Result<(), Error> {
+        let (next, sequence) = match self.active {
+            Some((sector, sequence)) => ((sector + 1) % self.sectors, sequence + 1),
+            None => (0, 0),
+        };
+        let next_start = self.sector_start(next);
+        self.flash
+            .erase(next_start, self.sector_end(next))
+            .map_err(|_| Error::Flash)?;
+
+        let mut cursor = next_start + SECTOR_HEADER_LEN;
+        if let Some((sector, _)) = self.active {
+            let end = self.cursor;
+            let mut offset = self.sector_start(sector) + SECTOR_HEADER_LEN;
+            while offset < end {
+                let mut bytes = [0; RECORD_HEADER_LEN];
+                self.flash.read(offset, &mut bytes).map_err(|_| Error::Flash)?;
+                let header = Header::parse(&bytes);
+                if header.key == ERASED_KEY || header.len > MAX_RECORD_LEN || offset + header.stored_len() > end {
+                    break;
+                }
+                let live = header.flags & FLAG_REMOVED == 0
+                    && self.payload_crc(offset, &header) == Some(header.crc)
+                    && !self.superseded(offset, &header, end);
+                if live {
+                    self.copy(offset, cursor, header.stored_len())?;
+                    cursor += header.stored_len();
+                }
+                offset += header.stored_len();
+            }
+        }
+
+        // The magic goes last, a torn header must not leave an erased sequence number behind it
+        self.flash
+            .write(next_start + 4, &sequence.to_le_bytes())
+            .map_err(|_| Error::Flash)?;
+        self.flash
+            .write(next_start, &SECTOR_MAGIC.to_le_bytes())
+            .map_err(|_| Error::Flash)?;
+        self.active = Some((next, sequence));
+        self.cursor = cursor;
+        Ok(())
+    }
+
+    /// Whether an intact later copy of the record at `offset` exists before `end`.
+    fn superseded(&mut self, offset: u32, header: &Header, end: u32) -> bool {
+        let mut later = offset + header.stored_len();
+        while later + RECORD_HEADER_LEN as u32 <= end {
+            let mut bytes = [0; RECORD_HEADER_LEN];
+            if self.flash.read(later, &mut bytes).is_err() {
+                return false;
+            }
+            let other = Header::parse(&bytes);
+            if other.key == ERASED_KEY || other.len > MAX_RECORD_LEN || later + other.stored_len() > end {
+                return false;
+            }
+            if other.same_record(header) && self.payload_crc(later, &other) == Some(other.crc) {
+                return true;
+            }
+            later += other.stored_len();
+        }
+        false
+    }
+
+    fn copy(&mut self, from: u32, to: u32, len: u32) -> Result<(), Error> {
+        let mut chunk = [0; CHUNK_LEN];
+        let mut pos = 0;
+        while pos < len {
+            let n = (len - pos).min(CHUNK_LEN as u32);
+            let part = &mut chunk[..n as usize];
+            self.flash.read(from + pos, part).map_err(|_| Error::Flash)?;
+            self.flash.write(to + pos, part).map_err(|_| Error::Flash)?;
+            pos += n;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR_LEN: usize = 256;
+    const SECTORS: u32 = 3;
+
+    /// NOR flash in RAM: writes can only clear bits, and a power cut can be set up that tears a
+    /// write or erase and fails everything after it.
+    struct RamFlash {
+        mem: [u8; SECTOR_LEN * SECTORS as usize],
+        /// Writes and erases that still complete before the power cut.
+        ops_left: Option<usize>,
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self { mem: [0xFF; SECTOR_LEN * SECTORS as usize], ops_left: None }
+        }
+
+        /// Whether the next write or erase completes, counting it.
+        fn powered(&mut self) -> bool {
+            match &mut self.ops_left {
+                Some(0) => false,
+                Some(n) => {
+                    *n -= 1;
+                    true
+                }
+                None => true,
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            let stored = self.mem.get(offset..offset + bytes.len()).ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(stored);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.mem.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR_LEN;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if from % SECTOR_LEN != 0 || to % SECTOR_LEN != 0 {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            if to > self.mem.len() {
+                return Err(NorFlashErrorKind::OutOfBounds);
+            }
+            if !self.powered() {
+                // Torn halfway through the first sector
+                self.mem[from..from + SECTOR_LEN / 2].fill(0xFF);
+                return Err(NorFlashErrorKind::Other);
+            }
+            self.mem[from..to].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if offset % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            if offset + bytes.len() > self.mem.len() {
+                return Err(NorFlashErrorKind::OutOfBounds);
+            }
+            // A torn write gets the first half of its words out
+            let (len, result) = match self.powered() {
+                true => (bytes.len(), Ok(())),
+                false => (bytes.len() / 2 / Self::WRITE_SIZE * Self::WRITE_SIZE, Err(NorFlashErrorKind::Other)),
+            };
+            for (stored, byte) in self.mem[offset..offset + len].iter_mut().zip(bytes) {
+                *stored &= byte;
+            }
+            result
+        }
+    }
+
+    /// A setting of 10 bytes all set to its value.
+    #[derive(Debug, PartialEq)]
+    struct Setting(u8);
+
+    impl Record for Setting {
+        const KEY: u8 = 1;
+        const VERSION: u8 = 1;
+
+        fn save(&self, buf: &mut [u8]) -> usize {
+            buf[..10].fill(self.0);
+            10
+        }
+
+        fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+            (bytes.len() == 10 && bytes.iter().all(|b| *b == bytes[0])).then(|| Setting(bytes[0]))
+        }
+    }
+
+    fn open(flash: RamFlash) -> ConfigStore<RamFlash> {
+        ConfigStore::new(flash, 0, SECTORS)
+    }
+
+    /// Run `f` with the power cut after `ops` more writes or erases, then open the store again
+    /// with the power back as after a reset.
+    fn power_cut(mut store: ConfigStore<RamFlash>, ops: usize, f: impl FnOnce(&mut ConfigStore<RamFlash>)) -> ConfigStore<RamFlash> {
+        store.flash().ops_left = Some(ops);
+        f(&mut store);
+        let mut flash = store.release();
+        assert_eq!(flash.ops_left, Some(0), "the power cut was never reached");
+        flash.ops_left = None;
+        open(flash)
+    }
+
+    #[test]
+    fn newest_copy_wins() {
+        let mut store = open(RamFlash::new());
+        assert!(store.is_empty());
+        assert_eq!(store.load::<Setting>(), None);
+        store.save(&Setting(1)).unwrap();
+        store.save(&Setting(2)).unwrap();
+        store.save_at(3, &Setting(30)).unwrap();
+        assert_eq!(store.load(), Some(Setting(2)));
+        assert_eq!(store.load_at(3), Some(Setting(30)));
+        assert_eq!(store.load_at::<Setting>(4), None);
+
+        let mut store = open(store.release());
+        assert!(!store.is_empty());
+        assert_eq!(store.load(), Some(Setting(2)));
+        assert_eq!(store.load_at(3), Some(Setting(30)));
+    }
+
+    #[test]
+    fn torn_payload() {
+        let mut store = open(RamFlash::new());
+        store.save(&Setting(1)).unwrap();
+        // The header goes out, the payload only half
+        let mut store = power_cut(store, 1, |store| assert_eq!(store.save(&Setting(2)), Err(Error::Flash)));
+        assert_eq!(store.load(), Some(Setting(1)));
+        // The torn record fails its CRC and is stepped over
+        store.save(&Setting(3)).unwrap();
+        let mut store = open(store.release());
+        assert_eq!(store.load(), Some(Setting(3)));
+    }
+
+    #[test]
+    fn torn_header() {
+        let mut store = open(RamFlash::new());
+        store.save(&Setting(1)).unwrap();
+        store.save_at(1, &Setting(10)).unwrap();
+        // Only the key, index, version and flags make it, the length reads as erased and runs
+        // past the sector
+        let mut store = power_cut(store, 0, |store| assert_eq!(store.save(&Setting(2)), Err(Error::Flash)));
+        assert_eq!(store.load(), Some(Setting(1)));
+        assert_eq!(store.load_at(1), Some(Setting(10)));
+        // Nothing can follow the torn header, so the next save compacts into a fresh sector
+        let (sector, _) = store.active.unwrap();
+        store.save(&Setting(3)).unwrap();
+        assert_ne!(store.active.unwrap().0, sector);
+        let mut store = open(store.release());
+        assert_eq!(store.load(), Some(Setting(3)));
+        assert_eq!(store.load_at(1), Some(Setting(10)));
+    }
+
+    #[test]
+    fn compaction_rotates_through_every_sector() {
+        let mut store = open(RamFlash::new());
+        store.save_at(1, &Setting(100)).unwrap();
+        let mut sectors_used = [false; SECTORS as usize];
+        // Enough saves to go round the region a few times
+        for value in 0..100 {
+            store.save(&Setting(value)).unwrap();
+            sectors_used[store.active.unwrap().0 as usize] = true;
+            assert_eq!(store.load(), Some(Setting(value)));
+            assert_eq!(store.load_at(1), Some(Setting(100)));
+        }
+        assert_eq!(sectors_used, [true; SECTORS as usize]);
+
+        // The sector with the highest sequence number is found again
+        let active = store.active;
+        let mut store = open(store.release());
+        assert_eq!(store.active, active);
+        assert_eq!(store.load(), Some(Setting(99)));
+        assert_eq!(store.load_at(1), Some(Setting(100)));
+    }
+
+    #[test]
+    fn remove_and_reinsert() {
+        let mut store = open(RamFlash::new());
+        for index in 0..3 {
+            store.save_at(index, &Setting(index)).unwrap();
+        }
+        store.remove_at::<Setting>(1).unwrap();
+        assert_eq!(store.load_at::<Setting>(1), None);
+        assert_eq!(store.load_at(0), Some(Setting(0)));
+        assert_eq!(store.load_at(2), Some(Setting(2)));
+
+        // Compaction drops the removal without bringing back the copy before it
+        let (sector, _) = store.active.unwrap();
+        while store.active.unwrap().0 == sector {
+            store.save_at(0, &Setting(0)).unwrap();
+        }
+        let mut store = open(store.release());
+        assert_eq!(store.load_at::<Setting>(1), None);
+        assert_eq!(store.load_at(2), Some(Setting(2)));
+
+        store.save_at(1, &Setting(11)).unwrap();
+        let mut store = open(store.release());
+        assert_eq!(store.load_at(1), Some(Setting(11)));
+    }
+
+    #[test]
+    fn power_cut_mid_compaction() {
+        let mut store = open(RamFlash::new());
+        store.save_at(1, &Setting(100)).unwrap();
+        // Fill the first sector up to the save that has to compact
+        let (first, _) = store.active.unwrap();
+        let stored_len = (RECORD_HEADER_LEN + aligned(10)) as u32;
+        let mut value = 0;
+        while store.cursor + stored_len <= store.sector_end(first) {
+            store.save(&Setting(value)).unwrap();
+            value += 1;
+        }
+        let newest = value - 1;
+
+        // Cut during the erase, while copying either live record, and between the new sector's
+        // sequence number and its magic: the old sector stays in charge each time
+        for ops in 0..5 {
+            store = power_cut(store, ops, |store| assert_eq!(store.save(&Setting(value)), Err(Error::Flash)));
+            assert_eq!(store.active.unwrap().0, first);
+            assert_eq!(store.load(), Some(Setting(newest)));
+            assert_eq!(store.load_at(1), Some(Setting(100)));
+        }
+
+        // With the power back the compaction starts over from a fresh erase
+        store.save(&Setting(value)).unwrap();
+        assert_ne!(store.active.unwrap().0, first);
+        let mut store = open(store.release());
+        assert_eq!(store.load(), Some(Setting(value)));
+        assert_eq!(store.load_at(1), Some(Setting(100)));
+    }
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(!crc32_update(crc32_update(!0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,97 @@
+//! Just enough HTTP/1.1 for small status and control pages: one request per connection, read
+//! whole, answered with a complete response and closed.
+use embassy_net::tcp::TcpSocket;
+use embedded_io_async::Write;
+use heapless::String;
+use ufmt::uwrite;
+
+pub const HTTP_PORT: u16 = 80;
+pub const CONTENT_HTML: &str = "text/html";
+pub const CONTENT_JSON: &str = "application/json";
+pub const CONTENT_TEXT: &str = "text/plain";
+
+fn parse_u16(value: &[u8]) -> Option<u16> {
+    core::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Split a complete request into method, path and body once all of it has arrived.
+pub fn split_request(request: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let header_end = request.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let mut parts = request[..header_end].split(|c| *c == b' ');
+    let (method, path) = (parts.next()?, parts.next()?);
+    let content_length = request[..header_end]
+        .split(|c| *c == b'\n')
+        .find_map(|line| {
+            let line = line.trim_ascii();
+            let (name, value) = line.split_at(line.iter().position(|c| *c == b':')?);
+            name.eq_ignore_ascii_case(b"content-length")
+                .then(|| parse_u16(value[1..].trim_ascii()))
+        })
+        .flatten()
+        .unwrap_or(0) as usize;
+    let body = request.get(header_end..header_end + content_length)?;
+    Some((method, path, body))
+}
+
+/// Read one request into `buf`, `None` if the connection closed before all of it arrived or
+/// it doesn't fit.
+pub async fn read_request<'b>(
+    socket: &mut TcpSocket<'_>,
+    buf: &'b mut [u8],
+) -> Option<(&'b [u8], &'b [u8], &'b [u8])> {
+    let mut len = 0;
+    loop {
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => len += n,
+        }
+        if split_request(&buf[..len]).is_some() {
+            break;
+        }
+        if len == buf.len() {
+            return None;
+        }
+    }
+    split_request(&buf[..len])
+}
+
+/// Percent-decode form field `name` of an `application/x-www-form-urlencoded` body into `out`.
+pub fn form_value<'a>(body: &[u8], name: &[u8], out: &'a mut [u8]) -> Option<&'a [u8]> {
+    let encoded = body.split(|c| *c == b'&').find_map(|field| field.strip_prefix(name)?.strip_prefix(b"="))?;
+    let hex = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    let mut len = 0;
+    let mut i = 0;
+    while i < encoded.len() {
+        let byte = match encoded[i] {
+            b'+' => b' ',
+            b'%' => {
+                let byte = hex(*encoded.get(i + 1)?)? << 4 | hex(*encoded.get(i + 2)?)?;
+                i += 2;
+                byte
+            }
+            c => c,
+        };
+        *out.get_mut(len)? = byte;
+        len += 1;
+        i += 1;
+    }
+    Some(&out[..len])
+}
+
+/// Send a complete response and close the connection.
+pub async fn respond(socket: &mut TcpSocket<'_>, status: &str, content_type: &str, body: &[u8]) {
+    let mut header = String::<160>::new();
+    uwrite!(
+        header,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .ok();
+    if socket.write_all(header.as_bytes()).await.is_ok() {
+        socket.write_all(body).await.ok();
+    }
+    socket.flush().await.ok();
+    socket.close();
+}
@@ -0,0 +1,186 @@
+//! Wi-Fi and network stack bring-up on a Pico W, shared by the examples.
+//!
+//! [`init_cyw43`] powers the cyw43 up over its PIO SPI bus and loads its firmware, for examples
+//! that build their own stack. [`bring_up`] goes on to start an embassy-net stack on it. Both
+//! spawn the tasks driving what they create, so the examples' task instrumentation doesn't see
+//! those. [`status_led`] blinks the link status on an LED, [`ping`] probes hosts with ICMP
+//! echoes and [`link`] probes the gateway with them. [`http`] serves small pages and [`mdns`]
+//! answers for the host name.
+#![no_std]
+
+pub mod http;
+pub mod link;
+pub mod mdns;
+pub mod ping;
+pub mod status_led;
+
+use cyw43_pio::PioSpi;
+use defmt::{debug, info, unwrap};
+use embassy_executor::Spawner;
+use embassy_net::{Config, Stack, StackResources};
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::interrupt::typelevel::{Binding, PIO0_IRQ_0};
+use embassy_rp::peripherals::{DMA_CH0, PIN_23, PIN_24, PIN_25, PIN_29, PIO0};
+use embassy_rp::pio::{InterruptHandler, Pio};
+use static_cell::StaticCell;
+
+/// The cyw43's pins on a Pico W.
+pub struct WifiPeripherals {
+    pub pwr: PIN_23,
+    pub cs: PIN_25,
+    pub pio: PIO0,
+    pub dio: PIN_24,
+    pub clk: PIN_29,
+    pub dma0: DMA_CH0,
+}
+
+/// Firmware blobs loaded into the cyw43.
+#[derive(Copy, Clone)]
+pub struct Firmware {
+    pub wifi: &'static [u8],
+    pub clm: &'static [u8],
+    #[cfg(feature = "bluetooth")]
+    pub bluetooth: &'static [u8],
+}
+
+#[cfg(feature = "firmware")]
+impl Firmware {
+    /// The blobs in `cyw43-firmware`, built into the image.
+    pub const BUNDLED: Self = Self {
+        wifi: include_bytes!("../../../cyw43-firmware/43439A0.bin"),
+        clm: include_bytes!("../../../cyw43-firmware/43439A0_clm.bin"),
+        #[cfg(feature = "bluetooth")]
+        bluetooth: include_bytes!("../../../cyw43-firmware/43439A0_btfw.bin"),
+    };
+}
+
+/// How [`bring_up`] sets up the cyw43 and the stack.
+pub struct NetConfig<const SOCKETS: usize> {
+    pub firmware: Firmware,
+    /// Mode the cyw43 starts in, the application can change it once the link is up.
+    pub power_management: cyw43::PowerManagementMode,
+    /// Addressing the stack starts out with.
+    pub ip: Config,
+    pub resources: &'static mut StackResources<SOCKETS>,
+}
+
+pub type NetDevice = cyw43::NetDriver<'static>;
+type WifiRunner = cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>;
+
+static STATE: StaticCell<cyw43::State> = StaticCell::new();
+static STACK: StaticCell<Stack<NetDevice>> = StaticCell::new();
+
+#[embassy_executor::task]
+async fn wifi_task(runner: WifiRunner) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<NetDevice>) -> ! {
+    stack.run().await
+}
+
+fn new_spi(
+    p: WifiPeripherals,
+    irqs: impl Binding<PIO0_IRQ_0, InterruptHandler<PIO0>>,
+) -> (Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>) {
+    info!("set up wifi peripherals");
+    let pwr = Output::new(p.pwr, Level::Low);
+    let cs = Output::new(p.cs, Level::High);
+    let mut pio = Pio::new(p.pio, irqs);
+    let spi = PioSpi::new(&mut pio.common, pio.sm0, pio.irq0, cs, p.dio, p.clk, p.dma0);
+    (pwr, spi)
+}
+
+async fn start_cyw43(
+    spawner: Spawner,
+    control: &mut cyw43::Control<'static>,
+    runner: WifiRunner,
+    firmware: &Firmware,
+    power_management: cyw43::PowerManagementMode,
+) {
+    unwrap!(spawner.spawn(wifi_task(runner)));
+
+    info!("init cyw43");
+    control.init(firmware.clm).await;
+    control.set_power_management(power_management).await;
+}
+
+/// Power up the cyw43 and load its firmware, returning its network device and control.
+pub async fn init_cyw43(
+    spawner: Spawner,
+    p: WifiPeripherals,
+    irqs: impl Binding<PIO0_IRQ_0, InterruptHandler<PIO0>>,
+    firmware: Firmware,
+    power_management: cyw43::PowerManagementMode,
+) -> (NetDevice, cyw43::Control<'static>) {
+    let (pwr, spi) = new_spi(p, irqs);
+
+    info!("set up cyw43");
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, firmware.wifi).await;
+    start_cyw43(spawner, &mut control, runner, &firmware, power_management).await;
+    (net_device, control)
+}
+
+/// `init_cyw43` that also brings up the Bluetooth controller and returns its HCI driver.
+#[cfg(feature = "bluetooth")]
+pub async fn init_cyw43_with_bluetooth(
+    spawner: Spawner,
+    p: WifiPeripherals,
+    irqs: impl Binding<PIO0_IRQ_0, InterruptHandler<PIO0>>,
+    firmware: Firmware,
+    power_management: cyw43::PowerManagementMode,
+) -> (NetDevice, cyw43::BtDriver<'static>, cyw43::Control<'static>) {
+    let (pwr, spi) = new_spi(p, irqs);
+
+    info!("set up cyw43 with bluetooth");
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, bt_device, mut control, runner) =
+        cyw43::new_with_bluetooth(state, pwr, spi, firmware.wifi, firmware.bluetooth).await;
+    start_cyw43(spawner, &mut control, runner, &firmware, power_management).await;
+    (net_device, bt_device, control)
+}
+
+fn start_stack<const SOCKETS: usize>(
+    spawner: Spawner,
+    net_device: NetDevice,
+    ip: Config,
+    resources: &'static mut StackResources<SOCKETS>,
+) -> &'static Stack<NetDevice> {
+    let seed = {
+        use rand_core::RngCore;
+        embassy_rp::clocks::RoscRng.next_u64()
+    };
+    debug!("rand seed {}", seed);
+
+    let stack = &*STACK.init(Stack::new(net_device, ip, resources, seed));
+    info!("set up net");
+    unwrap!(spawner.spawn(net_task(stack)));
+    stack
+}
+
+/// Power up the cyw43 and start a network stack on it. Joining a network is left to the caller,
+/// through the returned control.
+pub async fn bring_up<const SOCKETS: usize>(
+    spawner: Spawner,
+    p: WifiPeripherals,
+    irqs: impl Binding<PIO0_IRQ_0, InterruptHandler<PIO0>>,
+    config: NetConfig<SOCKETS>,
+) -> (&'static Stack<NetDevice>, cyw43::Control<'static>) {
+    let (net_device, control) = init_cyw43(spawner, p, irqs, config.firmware, config.power_management).await;
+    (start_stack(spawner, net_device, config.ip, config.resources), control)
+}
+
+/// `bring_up` that also brings up the Bluetooth controller and returns its HCI driver.
+#[cfg(feature = "bluetooth")]
+pub async fn bring_up_with_bluetooth<const SOCKETS: usize>(
+    spawner: Spawner,
+    p: WifiPeripherals,
+    irqs: impl Binding<PIO0_IRQ_0, InterruptHandler<PIO0>>,
+    config: NetConfig<SOCKETS>,
+) -> (&'static Stack<NetDevice>, cyw43::BtDriver<'static>, cyw43::Control<'static>) {
+    let (net_device, bt_device, control) =
+        init_cyw43_with_bluetooth(spawner, p, irqs, config.firmware, config.power_management).await;
+    (start_stack(spawner, net_device, config.ip, config.resources), bt_device, control)
+}
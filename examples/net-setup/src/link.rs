@@ -0,0 +1,83 @@
+//! Link supervision for losses the network interface doesn't report itself: the DHCP lease
+//! running out, or the gateway going quiet as when the access point reboots.
+use core::future::Future;
+
+use defmt::debug;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::ping::{self, PingId};
+
+/// How often the link is checked while it is up.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Consecutive unanswered gateway probes before the link counts as lost.
+pub const MAX_PROBE_FAILURES: u8 = 3;
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Any port does, a refused connection proves the gateway is there as much as an accepted one.
+const PROBE_PORT: u16 = 53;
+
+/// What one check of the link found.
+#[derive(Copy, Clone)]
+pub enum Probe {
+    /// The gateway answered.
+    Reachable,
+    /// The gateway didn't answer, `failures` checks in a row.
+    Unanswered { gateway: Ipv4Address, failures: u8 },
+    /// The DHCP lease ran out.
+    LeaseLost,
+}
+
+/// Whether the gateway answers a TCP SYN, with a SYN-ACK or a RST.
+async fn answers_syn<D: Driver>(stack: &Stack<D>, gateway: Ipv4Address) -> bool {
+    let mut rx_buffer = [0; 16];
+    let mut tx_buffer = [0; 16];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    // A RST and smoltcp's own timeout both look like `ConnectionReset`, so time out here instead
+    let reachable = with_timeout(PROBE_TIMEOUT, socket.connect(IpEndpoint::new(gateway.into(), PROBE_PORT)))
+        .await
+        .is_ok();
+    socket.abort();
+    reachable
+}
+
+/// Whether the gateway answers a ping, or failing that a TCP SYN for gateways that drop pings.
+async fn gateway_reachable<D: Driver>(stack: &Stack<D>, ping_id: &PingId, gateway: Ipv4Address) -> bool {
+    if let Some(rtt) = ping::ping(stack, ping_id, gateway, PROBE_TIMEOUT).await {
+        debug!("gateway {} answered ping in {} ms", gateway, rtt.as_millis());
+        return true;
+    }
+    answers_syn(stack, gateway).await
+}
+
+/// Check the link every `CHECK_INTERVAL`, passing what each check found to `checked`, and
+/// resolve once the DHCP lease ran out or the gateway missed `MAX_PROBE_FAILURES` checks in a
+/// row.
+pub async fn probe_until_lost<D: Driver, F: Future<Output = ()>>(
+    stack: &Stack<D>,
+    ping_id: &PingId,
+    mut checked: impl FnMut(Probe) -> F,
+) {
+    let mut failures = 0;
+    loop {
+        Timer::after(CHECK_INTERVAL).await;
+        let Some(config) = stack.config_v4() else {
+            checked(Probe::LeaseLost).await;
+            return;
+        };
+        let Some(gateway) = config.gateway else {
+            continue;
+        };
+        if gateway_reachable(stack, ping_id, gateway).await {
+            failures = 0;
+            checked(Probe::Reachable).await;
+        } else {
+            failures += 1;
+            checked(Probe::Unanswered { gateway, failures }).await;
+            if failures >= MAX_PROBE_FAILURES {
+                return;
+            }
+        }
+    }
+}
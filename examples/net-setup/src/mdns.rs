@@ -0,0 +1,351 @@
+//! mDNS responder answering for `<hostname>.local`, and advertising a DNS-SD service where the
+//! application has one.
+use defmt::{debug, warn};
+use embassy_net::driver::Driver;
+use embassy_net::udp::{UdpMetadata, UdpSocket};
+#[cfg(feature = "ipv6")]
+use embassy_net::Ipv6Address;
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::Timer;
+use heapless::Vec;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+/// Ethernet address the network interface has to accept for `MDNS_GROUP`.
+pub const MDNS_MAC: [u8; 6] = [0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB];
+#[cfg(feature = "ipv6")]
+pub const MDNS_GROUP_V6: Ipv6Address = Ipv6Address::new(0xFF02, 0, 0, 0, 0, 0, 0, 0xFB);
+
+const SERVICES_META: [&str; 4] = ["_services", "_dns-sd", "_udp", "local"];
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records only this device answers for.
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+/// Set on questions that want a unicast response.
+const CLASS_UNICAST_RESPONSE: u16 = 0x8000;
+const FLAGS_RESPONSE: u16 = 0x8400;
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+const MAX_NAME_LABELS: usize = 8;
+const MAX_POINTER_HOPS: usize = 8;
+const MAX_PACKET_LEN: usize = 512;
+
+/// Record sets, combined as bit flags.
+const REC_A: u8 = 0x01;
+const REC_PTR: u8 = 0x02;
+const REC_SRV: u8 = 0x04;
+const REC_TXT: u8 = 0x08;
+const REC_META: u8 = 0x10;
+const REC_AAAA: u8 = 0x20;
+const REC_ALL: u8 = REC_A | REC_AAAA | REC_PTR | REC_SRV | REC_TXT;
+/// Host records a resolver looks up after finding the service.
+const REC_HOST: u8 = REC_A | REC_AAAA;
+
+/// A DNS-SD service instance named after the host.
+pub struct Service {
+    /// Service type and domain, such as `["_http", "_tcp", "local"]`.
+    pub name: [&'static str; 3],
+    pub port: u16,
+    /// A single TXT string, such as `b"proto=1"`.
+    pub txt: &'static [u8],
+}
+
+/// Addresses `<hostname>.local` resolves to.
+#[derive(Copy, Clone)]
+struct HostAddresses {
+    v4: Ipv4Address,
+    v6: Option<[u8; 16]>,
+}
+
+impl HostAddresses {
+    fn of<D: Driver>(stack: &Stack<D>) -> Self {
+        #[cfg(feature = "ipv6")]
+        let v6 = stack.config_v6().map(|config| config.address.address().0);
+        #[cfg(not(feature = "ipv6"))]
+        let v6 = None;
+        Self {
+            v4: stack.config_v4().map_or(Ipv4Address::UNSPECIFIED, |config| config.address.address()),
+            v6,
+        }
+    }
+}
+
+/// Labels of the name at `offset` and the offset just past it, following compression pointers.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(Vec<&[u8], MAX_NAME_LABELS>, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..MAX_NAME_LABELS + MAX_POINTER_HOPS {
+        let len = *packet.get(offset)? as usize;
+        if len & 0xC0 == 0xC0 {
+            end.get_or_insert(offset + 2);
+            offset = ((len & 0x3F) << 8) | *packet.get(offset + 1)? as usize;
+        } else if len == 0 {
+            return Some((labels, end.unwrap_or(offset + 1)));
+        } else {
+            labels.push(packet.get(offset + 1..offset + 1 + len)?).ok()?;
+            offset += 1 + len;
+        }
+    }
+    None
+}
+
+fn name_eq(labels: &[&[u8]], expected: &[&str]) -> bool {
+    labels.len() == expected.len()
+        && labels
+            .iter()
+            .zip(expected)
+            .all(|(label, expected)| label.eq_ignore_ascii_case(expected.as_bytes()))
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn put(&mut self, bytes: &[u8]) {
+        // Records that don't fit are truncated, the response is still valid up to `len`
+        if let Some(dst) = self.buf.get_mut(self.len..self.len + bytes.len()) {
+            dst.copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.put(&value.to_be_bytes());
+    }
+
+    fn put_name(&mut self, labels: &[&str]) {
+        for label in labels {
+            self.put(&[label.len() as u8]);
+            self.put(label.as_bytes());
+        }
+        self.put(&[0]);
+    }
+
+    fn put_record(&mut self, name: &[&str], rtype: u16, class: u16, ttl: u32, rdata: impl FnOnce(&mut Self)) {
+        self.put_name(name);
+        self.put_u16(rtype);
+        self.put_u16(class);
+        self.put(&ttl.to_be_bytes());
+        let len_offset = self.len;
+        self.put_u16(0);
+        rdata(self);
+        let rdata_len = (self.len - len_offset).saturating_sub(2) as u16;
+        if let Some(dst) = self.buf.get_mut(len_offset..len_offset + 2) {
+            dst.copy_from_slice(&rdata_len.to_be_bytes());
+        }
+    }
+}
+
+struct Responder<'a> {
+    hostname: &'a str,
+    service: Option<&'a Service>,
+}
+
+impl<'a> Responder<'a> {
+    fn host(&self) -> [&str; 2] {
+        [self.hostname, "local"]
+    }
+
+    fn instance(&self, service: &'a Service) -> [&str; 4] {
+        [self.hostname, service.name[0], service.name[1], service.name[2]]
+    }
+
+    /// Records answering one question, `REC_*` flags.
+    fn records_for(&self, name: &[&[u8]], qtype: u16) -> u8 {
+        let wants = |rtype: u16| qtype == rtype || qtype == TYPE_ANY;
+        let mut records = 0;
+        if name_eq(name, &self.host()) {
+            if wants(TYPE_A) {
+                records |= REC_A;
+            }
+            if wants(TYPE_AAAA) {
+                records |= REC_AAAA;
+            }
+        }
+        if let Some(service) = self.service {
+            if name_eq(name, &service.name) && wants(TYPE_PTR) {
+                records |= REC_PTR;
+            }
+            if name_eq(name, &self.instance(service)) {
+                if wants(TYPE_SRV) {
+                    records |= REC_SRV;
+                }
+                if wants(TYPE_TXT) {
+                    records |= REC_TXT;
+                }
+            }
+            if name_eq(name, &SERVICES_META) && wants(TYPE_PTR) {
+                records |= REC_META;
+            }
+        }
+        records
+    }
+
+    fn put_records(&self, w: &mut Writer, records: u8, ip: HostAddresses) -> u16 {
+        let mut count = 0;
+        if let Some(service) = self.service {
+            let instance = self.instance(service);
+            if records & REC_PTR != 0 {
+                w.put_record(&service.name, TYPE_PTR, CLASS_IN, SERVICE_TTL, |w| w.put_name(&instance));
+                count += 1;
+            }
+            if records & REC_SRV != 0 {
+                w.put_record(&instance, TYPE_SRV, CLASS_IN | CLASS_CACHE_FLUSH, HOST_TTL, |w| {
+                    // Priority, weight, port, target
+                    w.put_u16(0);
+                    w.put_u16(0);
+                    w.put_u16(service.port);
+                    w.put_name(&self.host());
+                });
+                count += 1;
+            }
+            if records & REC_TXT != 0 {
+                w.put_record(&instance, TYPE_TXT, CLASS_IN | CLASS_CACHE_FLUSH, SERVICE_TTL, |w| {
+                    w.put(&[service.txt.len() as u8]);
+                    w.put(service.txt);
+                });
+                count += 1;
+            }
+        }
+        if records & REC_A != 0 {
+            w.put_record(&self.host(), TYPE_A, CLASS_IN | CLASS_CACHE_FLUSH, HOST_TTL, |w| {
+                w.put(ip.v4.as_bytes())
+            });
+            count += 1;
+        }
+        if let Some(v6) = ip.v6.filter(|_| records & REC_AAAA != 0) {
+            w.put_record(&self.host(), TYPE_AAAA, CLASS_IN | CLASS_CACHE_FLUSH, HOST_TTL, |w| w.put(&v6));
+            count += 1;
+        }
+        if let Some(service) = self.service.filter(|_| records & REC_META != 0) {
+            w.put_record(&SERVICES_META, TYPE_PTR, CLASS_IN, SERVICE_TTL, |w| w.put_name(&service.name));
+            count += 1;
+        }
+        count
+    }
+
+    /// Write a response carrying `answers`, with the records a resolver will look up next as
+    /// additionals. Returns the response length.
+    fn respond(&self, buf: &mut [u8], id: u16, answers: u8, ip: HostAddresses) -> usize {
+        let mut additionals = 0;
+        if answers & REC_PTR != 0 {
+            additionals |= REC_SRV | REC_TXT | REC_HOST;
+        }
+        if answers & REC_SRV != 0 {
+            additionals |= REC_HOST;
+        }
+        additionals &= !answers;
+
+        let mut w = Writer { buf, len: 12 };
+        let ancount = self.put_records(&mut w, answers, ip);
+        let arcount = self.put_records(&mut w, additionals, ip);
+        let len = w.len;
+        let header = [id, FLAGS_RESPONSE, 0, ancount, 0, arcount];
+        for (value, dst) in header.iter().zip(buf.chunks_exact_mut(2)) {
+            dst.copy_from_slice(&value.to_be_bytes());
+        }
+        len
+    }
+
+    /// Build the response to a query, returning its length and destination.
+    fn on_query(
+        &self,
+        packet: &[u8],
+        meta: UdpMetadata,
+        buf: &mut [u8],
+        ip: HostAddresses,
+    ) -> Option<(usize, IpEndpoint)> {
+        let id = read_u16(packet, 0)?;
+        let flags = read_u16(packet, 2)?;
+        let qdcount = read_u16(packet, 4)?;
+        if flags & 0x8000 != 0 {
+            // Someone else's response
+            return None;
+        }
+
+        let mut answers = 0;
+        let mut unicast = false;
+        let mut offset = 12;
+        for _ in 0..qdcount {
+            let (name, end) = read_name(packet, offset)?;
+            let qtype = read_u16(packet, end)?;
+            let qclass = read_u16(packet, end + 2)?;
+            let records = self.records_for(&name, qtype);
+            if records != 0 {
+                unicast |= qclass & CLASS_UNICAST_RESPONSE != 0;
+            }
+            answers |= records;
+            offset = end + 4;
+        }
+        if answers == 0 {
+            return None;
+        }
+
+        // One-shot resolvers query from an ephemeral port and expect the id echoed back
+        let legacy = meta.endpoint.port != MDNS_PORT;
+        let len = self.respond(buf, if legacy { id } else { 0 }, answers, ip);
+        let dest = if legacy || unicast {
+            meta.endpoint
+        } else {
+            // Multicast responses go back to the group of the query's address family
+            match meta.endpoint.addr {
+                IpAddress::Ipv4(_) => IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT),
+                #[cfg(feature = "ipv6")]
+                IpAddress::Ipv6(_) => IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT),
+            }
+        };
+        debug!("Answering mDNS query from {} with records {:02X}", meta.endpoint, answers);
+        Some((len, dest))
+    }
+}
+
+/// Answer A and AAAA queries for `<hostname>.local` and advertise `service` on a socket bound
+/// to `MDNS_PORT` whose stack has joined `MDNS_GROUP`, and `MDNS_GROUP_V6` with `ipv6`.
+pub async fn run<D: Driver>(
+    socket: &mut UdpSocket<'_>,
+    stack: &Stack<D>,
+    hostname: &str,
+    service: Option<&Service>,
+) -> ! {
+    let responder = Responder { hostname, service };
+    let mut buf = [0; MAX_PACKET_LEN];
+    let groups = [
+        IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT),
+        #[cfg(feature = "ipv6")]
+        IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT),
+    ];
+
+    // Announce twice, a second apart (RFC 6762 section 8.3)
+    for _ in 0..2 {
+        let len = responder.respond(&mut buf, 0, REC_ALL, HostAddresses::of(stack));
+        for group in groups {
+            if socket.send_to(&buf[..len], group).await.is_err() {
+                warn!("failed to send mDNS announcement to {}", group);
+            }
+        }
+        Timer::after_secs(1).await;
+    }
+
+    loop {
+        let ip = HostAddresses::of(stack);
+        let response = socket
+            .recv_from_with(|packet, meta| responder.on_query(packet, meta, &mut buf, ip))
+            .await;
+        if let Some((len, dest)) = response {
+            socket.send_to(&buf[..len], dest).await.ok();
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! ICMP echo requests over a raw socket, to tell whether a host is there.
+use core::cell::Cell;
+
+use embassy_net::driver::Driver;
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Instant};
+
+const IPV4_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+const PAYLOAD_LEN: usize = 8;
+const REQUEST_LEN: usize = IPV4_HEADER_LEN + ICMP_HEADER_LEN + PAYLOAD_LEN;
+/// Room for a reply with IP options, anything longer isn't ours.
+const MAX_REPLY_LEN: usize = REQUEST_LEN + 40;
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const TTL: u8 = 64;
+
+/// What an application's echo requests carry.
+#[derive(Copy, Clone)]
+pub struct PingId {
+    /// Tells replies to these pings apart from others the raw socket sees.
+    pub identifier: u16,
+    /// Such as the application's name, for packet captures.
+    pub payload: [u8; PAYLOAD_LEN],
+}
+
+static SEQUENCE: Mutex<CriticalSectionRawMutex, Cell<u16>> = Mutex::new(Cell::new(0));
+
+/// RFC 1071 internet checksum.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Raw sockets send whole IP packets, smoltcp only fills in the header checksum.
+fn echo_request(id: &PingId, source: Ipv4Address, target: Ipv4Address, sequence: u16) -> [u8; REQUEST_LEN] {
+    let mut packet = [0; REQUEST_LEN];
+    let (header, icmp) = packet.split_at_mut(IPV4_HEADER_LEN);
+    header[0] = 0x45;
+    header[2..4].copy_from_slice(&(REQUEST_LEN as u16).to_be_bytes());
+    header[8] = TTL;
+    header[9] = IpProtocol::Icmp.into();
+    header[12..16].copy_from_slice(&source.0);
+    header[16..20].copy_from_slice(&target.0);
+    icmp[0] = ECHO_REQUEST;
+    icmp[4..6].copy_from_slice(&id.identifier.to_be_bytes());
+    icmp[6..8].copy_from_slice(&sequence.to_be_bytes());
+    icmp[ICMP_HEADER_LEN..].copy_from_slice(&id.payload);
+    let sum = checksum(icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+fn is_echo_reply(id: &PingId, packet: &[u8], target: Ipv4Address, sequence: u16) -> bool {
+    let header_len = (packet.first().copied().unwrap_or(0) & 0x0F) as usize * 4;
+    let Some(icmp) = packet.get(header_len..header_len + ICMP_HEADER_LEN) else {
+        return false;
+    };
+    packet[12..16] == target.0
+        && icmp[0] == ECHO_REPLY
+        && icmp[4..6] == id.identifier.to_be_bytes()
+        && icmp[6..8] == sequence.to_be_bytes()
+}
+
+/// Send one ICMP echo request to `target` and return the round trip time, `None` without an
+/// address of our own or a reply within `timeout`.
+pub async fn ping<D: Driver>(
+    stack: &Stack<D>,
+    id: &PingId,
+    target: Ipv4Address,
+    timeout: Duration,
+) -> Option<Duration> {
+    let source = stack.config_v4()?.address.address();
+    let sequence = SEQUENCE.lock(|cell| {
+        let sequence = cell.get().wrapping_add(1);
+        cell.set(sequence);
+        sequence
+    });
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * MAX_REPLY_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; REQUEST_LEN];
+    let socket = RawSocket::new(
+        stack,
+        IpVersion::Ipv4,
+        IpProtocol::Icmp,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    let sent = Instant::now();
+    socket.send(&echo_request(id, source, target, sequence)).await;
+    with_timeout(timeout, async {
+        let mut reply = [0; MAX_REPLY_LEN];
+        loop {
+            // Longer packets are truncated and can't be our reply
+            if let Ok(len) = socket.recv(&mut reply).await {
+                if is_echo_reply(id, &reply[..len], target, sequence) {
+                    return sent.elapsed();
+                }
+            }
+        }
+    })
+    .await
+    .ok()
+}
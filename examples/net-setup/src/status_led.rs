@@ -0,0 +1,129 @@
+//! Link status on an LED, in blink patterns stepped through by [`status_led_task`]. Whoever owns
+//! the cyw43 sets the LED, as the Pico W's onboard one is on the cyw43's GPIO 0.
+use core::cell::Cell;
+
+use embassy_rp::gpio::{Level, Output};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+/// Where the status LED is wired.
+pub enum StatusLed {
+    /// The Pico W's onboard LED, on the cyw43's GPIO 0.
+    Cyw43,
+    /// An LED on one of the RP2040's own pins, active high.
+    Pin(Output<'static>),
+}
+
+impl StatusLed {
+    /// Light the LED or turn it off.
+    pub async fn set(&mut self, control: &mut cyw43::Control<'_>, on: bool) {
+        match self {
+            StatusLed::Cyw43 => control.gpio_set(0, on).await,
+            StatusLed::Pin(pin) => pin.set_level(Level::from(on)),
+        }
+    }
+}
+
+/// How far bringing the link up got.
+#[derive(Copy, Clone, PartialEq)]
+pub enum LinkStatus {
+    /// Serving a setup access point, waiting on a person.
+    Setup,
+    Joining,
+    WaitingDhcp,
+    /// Addressed, [`set_fault`] tells whether it works.
+    Up,
+}
+
+/// What the status LED shows.
+#[derive(Copy, Clone, PartialEq)]
+enum Shown {
+    /// Fast blink.
+    Setup,
+    /// Lit, the cyw43 can't switch the Pico W's LED in the middle of a join.
+    Joining,
+    /// Slow blink.
+    WaitingDhcp,
+    /// Short flash every 3 s.
+    Connected,
+    /// Double blink, while the link is up but not working.
+    Error,
+}
+
+impl Shown {
+    /// Lit and dark times in ms, starting lit.
+    fn pattern(&self) -> &'static [u64] {
+        match self {
+            Shown::Setup => &[100, 100],
+            Shown::Joining => &[1000],
+            Shown::WaitingDhcp => &[500, 500],
+            Shown::Connected => &[50, 2950],
+            Shown::Error => &[100, 100, 100, 700],
+        }
+    }
+}
+
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<(LinkStatus, bool)>> =
+    Mutex::new(Cell::new((LinkStatus::Joining, false)));
+static LEVEL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+pub fn set_status(status: LinkStatus) {
+    STATUS.lock(|cell| cell.set((status, cell.get().1)));
+}
+
+/// Whether the link is up but not working, as during an outage the application's own checks
+/// found.
+pub fn set_fault(fault: bool) {
+    STATUS.lock(|cell| cell.set((cell.get().0, fault)));
+}
+
+fn shown() -> Shown {
+    match STATUS.lock(|cell| cell.get()) {
+        (LinkStatus::Setup, _) => Shown::Setup,
+        (LinkStatus::Joining, _) => Shown::Joining,
+        (LinkStatus::WaitingDhcp, _) => Shown::WaitingDhcp,
+        (LinkStatus::Up, false) => Shown::Connected,
+        (LinkStatus::Up, true) => Shown::Error,
+    }
+}
+
+/// Resolve with the LED level to set next. Only the cyw43's owner can set the Pico W's LED, so
+/// it waits on this alongside whatever else it does with the cyw43.
+pub async fn changed() -> bool {
+    LEVEL.wait().await
+}
+
+/// Set the LED levels as they change, for while the cyw43 has nothing else to do.
+pub async fn drive(led: &mut StatusLed, control: &mut cyw43::Control<'_>) -> ! {
+    loop {
+        led.set(control, changed().await).await;
+    }
+}
+
+/// Light the LED for the join, the cyw43 is busy until it's done.
+pub async fn show_joining(led: &mut StatusLed, control: &mut cyw43::Control<'_>) {
+    set_status(LinkStatus::Joining);
+    led.set(control, true).await;
+}
+
+/// Step through the pattern of the link status, starting over when the status changes.
+#[embassy_executor::task]
+pub async fn status_led_task() -> ! {
+    let mut lit = None;
+    loop {
+        let status = shown();
+        for (i, millis) in status.pattern().iter().enumerate() {
+            if shown() != status {
+                break;
+            }
+            let on = i % 2 == 0;
+            if lit != Some(on) {
+                LEVEL.signal(on);
+                lit = Some(on);
+            }
+            Timer::after_millis(*millis).await;
+        }
+    }
+}
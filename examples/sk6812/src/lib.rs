@@ -0,0 +1,231 @@
+//! SK6812 RGBW strips driven by a PIO state machine, shared by the examples.
+//!
+//! A [`PioSK6812Program`] is loaded once per PIO block and runs a [`PioSK6812`] per state
+//! machine, which streams frames out over DMA. [`PioSK6812Multi`] groups up to four of them into
+//! one [`LedDriver`].
+#![no_std]
+
+use animation::{KeyframeColor, LedDriver};
+use core::cell::RefCell;
+use embassy_futures::join::join4;
+use embassy_rp::dma::{AnyChannel, Channel};
+use embassy_rp::gpio::{Drive, Level, SlewRate};
+use embassy_rp::{Peripheral, PeripheralRef};
+use embassy_rp::pio::{Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftDirection, StateMachine};
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_time::{with_timeout, Duration};
+use pio_proc::pio_asm;
+use fixed::{FixedU32, FixedU64};
+use fixed::types::extra::U8;
+use fixed::traits::FromFixed;
+
+/// Words encoded per DMA transfer when streaming a frame with `write_with`.
+const CHUNK_WORDS: usize = 32;
+/// A chunk takes about 1.3 ms on the wire.
+const CHUNK_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// The word the strip expects for a color, green, red, blue then white from the top byte down.
+pub fn encode([r, g, b, w]: [u8; 4]) -> u32 {
+    u32::from_be_bytes([g, r, b, w])
+}
+
+/// A chunk of the frame didn't go out within `CHUNK_TIMEOUT`, the rest of the frame was dropped.
+#[derive(Copy, Clone, Debug)]
+pub struct WriteTimeout;
+
+/// SK6812 bit timing program, loaded once and shared by every state machine of a PIO block.
+pub struct PioSK6812Program<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioSK6812Program<'d, PIO> {
+    /// Load the SK6812 program into the PIO instruction memory.
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        let program = pio_asm!(
+            ".side_set 1"
+            ".wrap_target"
+            "bitloop:"
+            "  out x, 1        side 0 [2]"
+            "  jmp !x do_zero  side 1 [1]"
+            "  jmp bitloop     side 1 [4]"
+            "do_zero:"
+            "  nop             side 0 [4]"
+            ".wrap"
+        );
+
+        Self {
+            prg: common.load_program(&program.program),
+        }
+    }
+}
+
+pub struct PioSK6812<'d, PIO: Instance, const SM: usize, DMA: Channel> {
+    sm: StateMachine<'d, PIO, SM>,
+    dma: PeripheralRef<'d, DMA>,
+    wrap_target: u8,
+}
+
+impl<'d, PIO: Instance, const SM: usize, DMA: Channel> PioSK6812<'d, PIO, SM, DMA>
+{
+    /// Create a new instance of PioSK6812 running a previously loaded program.
+    pub fn new<DIO>(
+        common: &mut Common<'d, PIO>,
+        program: &PioSK6812Program<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        dio: DIO,
+        dma: impl Peripheral<P = DMA> + 'd,
+    ) -> Self
+        where
+            DIO: PioPin,
+    {
+        let mut pin_io = common.make_pio_pin(dio);
+        pin_io.set_drive_strength(Drive::_12mA);
+        pin_io.set_slew_rate(SlewRate::Fast);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[&pin_io]);
+        cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = 32;
+        // The 8 word FIFO keeps the line busy for ~320 us while the next chunk is queued
+        cfg.fifo_join = FifoJoin::TxOnly;
+
+        type Fix = FixedU32<U8>;
+        type Fix64 = FixedU64<U8>;
+        cfg.clock_divider = Fix::from_fixed(Fix64::from_num(clk_sys_freq()) / Fix64::const_from_int(8000000));
+
+        sm.set_config(&cfg);
+
+        sm.set_pin_dirs(Direction::Out, &[&pin_io]);
+        sm.set_pins(Level::Low, &[&pin_io]);
+
+        sm.set_enable(true);
+
+        Self {
+            sm,
+            dma: dma.into_ref(),
+            wrap_target: program.prg.wrap.target,
+        }
+    }
+
+    /// Get a wedged state machine going again: stop it, drop whatever is queued and restart the
+    /// program from the top with the line low.
+    pub fn reinit(&mut self) {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+        // `jmp wrap_target side 0`, the side-set bit is the top of the delay field
+        unsafe { self.sm.exec_instr(self.wrap_target as u16) };
+        self.sm.set_enable(true);
+    }
+
+    fn fill_chunk(chunk: &mut [u32; CHUNK_WORDS], start: usize, len: usize, encode: &mut impl FnMut(usize) -> u32) -> usize {
+        let chunk_len = CHUNK_WORDS.min(len - start);
+        for (i, word) in chunk[..chunk_len].iter_mut().enumerate() {
+            *word = encode(start + i);
+        }
+        chunk_len
+    }
+
+    /// Stream `len` words produced by `encode` without a full frame buffer. Two small chunk
+    /// buffers alternate, the next chunk is encoded while DMA pushes the current one, and
+    /// DMA itself waits on the TX FIFO so the strip sets the pace. A transfer timing out is
+    /// aborted.
+    pub async fn write_with(&mut self, len: usize, mut encode: impl FnMut(usize) -> u32) -> Result<(), WriteTimeout> {
+        let [mut chunk_a, mut chunk_b] = [[0_u32; CHUNK_WORDS]; 2];
+        let (mut current, mut next) = (&mut chunk_a, &mut chunk_b);
+        let mut start = 0;
+        let mut current_len = Self::fill_chunk(current, start, len, &mut encode);
+        while current_len > 0 {
+            // The transfer is started right away, only completion is awaited
+            let transfer = self.sm.tx().dma_push(self.dma.reborrow(), &current[..current_len]);
+            start += current_len;
+            let next_len = Self::fill_chunk(next, start, len, &mut encode);
+            // Dropping the transfer on a timeout aborts its DMA channel
+            with_timeout(CHUNK_TIMEOUT, transfer).await.map_err(|_| WriteTimeout)?;
+            core::mem::swap(&mut current, &mut next);
+            current_len = next_len;
+        }
+        Ok(())
+    }
+}
+
+/// Up to four strips driven in parallel, one per state machine of the same PIO block.
+pub struct PioSK6812Multi<'d, PIO: Instance> {
+    strip0: Option<PioSK6812<'d, PIO, 0, AnyChannel>>,
+    strip1: Option<PioSK6812<'d, PIO, 1, AnyChannel>>,
+    strip2: Option<PioSK6812<'d, PIO, 2, AnyChannel>>,
+    strip3: Option<PioSK6812<'d, PIO, 3, AnyChannel>>,
+    strip_len: usize,
+}
+
+impl<'d, PIO: Instance> PioSK6812Multi<'d, PIO> {
+    /// Group strips of `strip_len` LEDs each. Frames passed to `write_with` are split across
+    /// the present strips in state machine order.
+    pub fn new(
+        strip0: Option<PioSK6812<'d, PIO, 0, AnyChannel>>,
+        strip1: Option<PioSK6812<'d, PIO, 1, AnyChannel>>,
+        strip2: Option<PioSK6812<'d, PIO, 2, AnyChannel>>,
+        strip3: Option<PioSK6812<'d, PIO, 3, AnyChannel>>,
+        strip_len: usize,
+    ) -> Self {
+        Self {
+            strip0,
+            strip1,
+            strip2,
+            strip3,
+            strip_len,
+        }
+    }
+
+    /// Stream strip `index`'s part of a `len` LED frame, if it is fitted and the frame reaches it.
+    async fn write_strip<const SM: usize, C: KeyframeColor>(
+        strip: &mut Option<PioSK6812<'d, PIO, SM, AnyChannel>>,
+        index: usize,
+        strip_len: usize,
+        len: usize,
+        pixel: &RefCell<impl FnMut(usize) -> C>,
+    ) -> Result<(), WriteTimeout> {
+        let first = index * strip_len;
+        match strip {
+            Some(strip) if first < len => {
+                // Chunks are encoded between awaits, so the strips never borrow `pixel` at once
+                strip.write_with(strip_len.min(len - first), |i| encode(pixel.borrow_mut()(first + i).to_rgbw())).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'d, PIO: Instance> LedDriver for PioSK6812Multi<'d, PIO> {
+    type Error = WriteTimeout;
+
+    /// Fails if any strip timed out, the others still got their part of the frame.
+    async fn write_with<C: KeyframeColor>(&mut self, len: usize, pixel: impl FnMut(usize) -> C) -> Result<(), WriteTimeout> {
+        let pixel = RefCell::new(pixel);
+        let strip_len = self.strip_len;
+        let (r0, r1, r2, r3) = join4(
+            Self::write_strip(&mut self.strip0, 0, strip_len, len, &pixel),
+            Self::write_strip(&mut self.strip1, 1, strip_len, len, &pixel),
+            Self::write_strip(&mut self.strip2, 2, strip_len, len, &pixel),
+            Self::write_strip(&mut self.strip3, 3, strip_len, len, &pixel),
+        ).await;
+        r0.and(r1).and(r2).and(r3)
+    }
+
+    /// Restart every strip's state machine, see `PioSK6812::reinit`.
+    fn reinit(&mut self) {
+        if let Some(strip) = &mut self.strip0 {
+            strip.reinit();
+        }
+        if let Some(strip) = &mut self.strip1 {
+            strip.reinit();
+        }
+        if let Some(strip) = &mut self.strip2 {
+            strip.reinit();
+        }
+        if let Some(strip) = &mut self.strip3 {
+            strip.reinit();
+        }
+    }
+}
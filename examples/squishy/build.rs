@@ -1,5 +1,5 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! This build script copies the memory layout from the crate root into
+//! `memory.x` in a directory where the linker can always find it at build time.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -7,19 +7,39 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also turns the pad's settings in `device.toml`, or the file `SQUISHY_DEVICE` points at,
+//! into the constants included by `consts.rs` and `command.rs`.
 
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
+use std::fmt::Write as _;
+use std::fs::{self, File};
 use std::io::Write;
+use std::net::Ipv4Addr;
 use std::path::PathBuf;
 
+use serde::Deserialize;
+
+/// Longest `notify` service and escaped message together, so the service call fits the 256 bytes
+/// the websocket formats it into.
+const MAX_NOTIFY_LEN: usize = 150;
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // on the linker search path. The `ota` feature links the firmware
+    // behind the bootloader instead. Neither layout may be named
+    // `memory.x` in the crate root, the linker searches its working
+    // directory first.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let memory_x: &[u8] = if env::var_os("CARGO_FEATURE_OTA").is_some() {
+        include_bytes!("memory-ota.x")
+    } else {
+        include_bytes!("memory-default.x")
+    };
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
@@ -27,7 +47,8 @@ fn main() {
     // any file in the project changes. By specifying `memory.x`
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory-default.x");
+    println!("cargo:rerun-if-changed=memory-ota.x");
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
@@ -36,4 +57,616 @@ fn main() {
 
     println!("cargo:rerun-if-env-changed=DEFMT_LOG");
     println!("cargo:rerun-if-env-changed=EMBASSY_EXECUTOR_TASK_ARENA_SIZE");
+
+    let device_path = env::var("SQUISHY_DEVICE").unwrap_or_else(|_| "device.toml".into());
+    println!("cargo:rerun-if-env-changed=SQUISHY_DEVICE");
+    println!("cargo:rerun-if-changed={}", device_path);
+    let device = fs::read_to_string(&device_path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| toml::from_str::<Device>(&text).map_err(|err| err.to_string()))
+        .and_then(|device| Ok((device.consts()?, device.buttons()?)));
+    let (consts, buttons) = match device {
+        Ok(generated) => generated,
+        Err(err) => panic!("{}: {}", device_path, err),
+    };
+    fs::write(out.join("device_consts.rs"), consts).unwrap();
+    fs::write(out.join("device_buttons.rs"), buttons).unwrap();
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Device {
+    device: Identity,
+    #[serde(default)]
+    wifi: Wifi,
+    endpoints: BTreeMap<String, Endpoint>,
+    static_ip: Option<StaticIp>,
+    entities: BTreeMap<String, String>,
+    leds: Leds,
+    /// Keyframe animations shared by name between buttons.
+    #[serde(default)]
+    animations: BTreeMap<String, Animation>,
+    buttons: Vec<Button>,
+    encoder: Option<Encoder>,
+    pir: Option<Pir>,
+    accent: Option<Accent>,
+    ambient: Option<Ambient>,
+    #[serde(default)]
+    timers: Timers,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Identity {
+    hostname_prefix: String,
+    hostname: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Wifi {
+    ssid: String,
+    psk: Option<String>,
+    #[serde(default)]
+    passphrase: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Endpoint {
+    domain: String,
+    port: u16,
+    token: String,
+    /// Seconds a write to the websocket may take.
+    #[serde(default = "default_write_timeout")]
+    write_timeout: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StaticIp {
+    address: Ipv4Addr,
+    prefix_len: u8,
+    gateway: Ipv4Addr,
+    dns: Ipv4Addr,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Leds {
+    width: usize,
+    height: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Button {
+    action: String,
+    /// Every action but `notify` has one.
+    entity: Option<String>,
+    effect: Option<String>,
+    /// One of `animations`, or the keyframes and interpolation are given on the button.
+    animation: Option<String>,
+    keyframes: Option<Vec<Keyframe>>,
+    easing: Option<Easing>,
+    blend: Option<Blend>,
+    /// Send the command only once the pad is held down.
+    #[serde(default)]
+    hold: bool,
+    /// Degrees added to the setpoint by `climate_step`.
+    step: Option<f32>,
+    /// Service of the `notify` domain and the message `notify` sends with it.
+    service: Option<String>,
+    message: Option<String>,
+    /// Seconds from a press to the command with the `timers` feature, pressing again cancels it.
+    countdown: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Encoder {
+    action: String,
+    entity: String,
+    /// Brightness percent per detent.
+    step: Option<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pir {
+    #[serde(default = "yes")]
+    wake: bool,
+    #[serde(default = "yes")]
+    event: bool,
+    /// Seconds between `squishy_motion` events.
+    #[serde(default = "default_holdoff")]
+    holdoff: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Accent {
+    leds: usize,
+    /// Out of 255, for the whole strip.
+    #[serde(default = "default_accent_brightness")]
+    brightness: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Ambient {
+    entity: String,
+    /// Out of 255, how much of the tint the idle pads take on.
+    #[serde(default = "default_ambient_strength")]
+    strength: u8,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Timers {
+    ntp_server: Option<String>,
+    /// Minutes local time is ahead of UTC.
+    #[serde(default)]
+    utc_offset: i32,
+    #[serde(default)]
+    at: Vec<ScheduledPress>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduledPress {
+    /// Local time as `HH:MM`.
+    time: String,
+    button: usize,
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_holdoff() -> u32 {
+    60
+}
+
+fn default_write_timeout() -> u32 {
+    10
+}
+
+fn default_accent_brightness() -> u8 {
+    128
+}
+
+fn default_ambient_strength() -> u8 {
+    96
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Animation {
+    keyframes: Vec<Keyframe>,
+    #[serde(default)]
+    easing: Easing,
+    #[serde(default)]
+    blend: Blend,
+}
+
+/// Mirrors `animation::Easing`.
+#[derive(Deserialize, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Easing {
+    #[default]
+    Linear,
+    Smooth,
+    Step,
+}
+
+/// Mirrors `animation::Blend`.
+#[derive(Deserialize, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Blend {
+    #[default]
+    Rgb,
+    Hsv,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct Keyframe {
+    frame: u32,
+    color: [u8; 3],
+}
+
+fn feature(name: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", name)).is_some()
+}
+
+fn ipv4(address: Ipv4Addr) -> String {
+    let [a, b, c, d] = address.octets();
+    format!("embassy_net::Ipv4Address::new({}, {}, {}, {})", a, b, c, d)
+}
+
+fn entity_const(key: &str) -> String {
+    format!("{}_ENTITY", key.to_uppercase())
+}
+
+/// `text` as the inside of a JSON string, `None` if it has control characters.
+fn json_escape(text: &str) -> Option<String> {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+/// Minutes past midnight of `HH:MM`.
+fn minute_of_day(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+    (hours < 24 && minutes < 60 && time.len() == 5).then_some(hours * 60 + minutes)
+}
+
+fn animation_const(name: &str) -> String {
+    format!("{}_KEYFRAMES", name.to_uppercase())
+}
+
+impl Animation {
+    /// Keyframes as `KeyframeReader` expects them, `what` names them in the error.
+    fn check(&self, what: &str) -> Result<(), String> {
+        let frames = self.keyframes.iter().map(|keyframe| keyframe.frame);
+        if self.keyframes.first().map(|keyframe| keyframe.frame) != Some(0)
+            || frames.clone().zip(frames.skip(1)).any(|(a, b)| b <= a)
+        {
+            return Err(format!("{} keyframes must start at frame 0 and increase", what));
+        }
+        Ok(())
+    }
+
+    fn keyframes_literal(&self) -> String {
+        let mut out = String::from("&[\n");
+        for Keyframe { frame, color: [r, g, b] } in &self.keyframes {
+            writeln!(out, "    Keyframe {{ frame: {}, color: Color {{ r: {}, g: {}, b: {} }} }},", frame, r, g, b).unwrap();
+        }
+        out.push(']');
+        out
+    }
+
+    fn interpolation_literal(&self) -> String {
+        format!("Interpolation {{ easing: Easing::{:?}, blend: Blend::{:?} }}", self.easing, self.blend)
+    }
+}
+
+impl Device {
+    /// Everything but the button map, for `consts.rs`.
+    fn consts(&self) -> Result<String, String> {
+        let mut out = String::new();
+        writeln!(out, "pub const HOSTNAME_PREFIX: &str = {:?};", self.device.hostname_prefix).unwrap();
+        writeln!(out, "pub const HOSTNAME: Option<&str> = {:?};", self.device.hostname).unwrap();
+
+        let wifi = &self.wifi;
+        if wifi.ssid.len() > 32 {
+            return Err("wifi.ssid is longer than 32 bytes".into());
+        }
+        if !wifi.passphrase.is_empty() && !(8..=63).contains(&wifi.passphrase.len()) {
+            return Err("wifi.passphrase must have 8 to 63 characters".into());
+        }
+        if feature("WPA3") && wifi.passphrase.is_empty() {
+            return Err("the wpa3 feature needs wifi.passphrase".into());
+        }
+        let psk = match &wifi.psk {
+            Some(hex) => {
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .filter(|bytes| bytes.len() == 32)
+                    .ok_or("wifi.psk must be 64 hex digits")?;
+                format!("Some(&{:?})", bytes)
+            }
+            None => "None".into(),
+        };
+        writeln!(out, "pub const WIFI_SSID: &str = {:?};", wifi.ssid).unwrap();
+        writeln!(out, "pub const WIFI_PSK: Option<&[u8; 32]> = {};", psk).unwrap();
+        writeln!(out, "pub const WIFI_PASSPHRASE: &str = {:?};", wifi.passphrase).unwrap();
+
+        let endpoint_name = if feature("MBP") { "mbp" } else { "default" };
+        let endpoint = self
+            .endpoints
+            .get(endpoint_name)
+            .ok_or_else(|| format!("no endpoints.{}", endpoint_name))?;
+        // Same check as the settings apply to tokens stored from the console
+        let token = &endpoint.token;
+        if token.is_empty() || !token.bytes().all(|c| c.is_ascii_graphic() && c != b'"' && c != b'\\') {
+            return Err(format!("endpoints.{}.token is not a Home Assistant token", endpoint_name));
+        }
+        if !(1..=300).contains(&endpoint.write_timeout) {
+            return Err(format!("endpoints.{}.write_timeout must be 1 to 300 seconds", endpoint_name));
+        }
+        let auth = format!(r#"{{"type":"auth","access_token":"{}"}}"#, token);
+        writeln!(
+            out,
+            "pub const HA_CONSTS: HaEndpointConsts = HaEndpointConsts {{ domain: {:?}, port: {}, auth: {:?}, write_timeout_secs: {} }};",
+            endpoint.domain, endpoint.port, auth, endpoint.write_timeout
+        )
+        .unwrap();
+
+        if feature("STATIC_IP") {
+            let ip = self.static_ip.as_ref().ok_or("the static-ip feature needs static_ip")?;
+            if ip.prefix_len > 32 {
+                return Err("static_ip.prefix_len must be at most 32".into());
+            }
+            writeln!(
+                out,
+                "pub const STATIC_IP: StaticIpConsts = StaticIpConsts {{ address: {}, prefix_len: {}, gateway: {}, dns: {} }};",
+                ipv4(ip.address),
+                ip.prefix_len,
+                ipv4(ip.gateway),
+                ipv4(ip.dns)
+            )
+            .unwrap();
+        }
+
+        if feature("PIR") {
+            let pir = self.pir.as_ref().ok_or("the pir feature needs pir")?;
+            if !pir.wake && !pir.event {
+                return Err("pir must wake the pads, fire events or both".into());
+            }
+            writeln!(
+                out,
+                "pub const PIR: PirConsts = PirConsts {{ wake: {}, event: {}, holdoff_secs: {} }};",
+                pir.wake, pir.event, pir.holdoff
+            )
+            .unwrap();
+        }
+
+        if feature("ACCENT") {
+            let accent = self.accent.as_ref().ok_or("the accent feature needs accent")?;
+            if accent.leds == 0 || accent.leds > 300 {
+                return Err("accent must have 1 to 300 LEDs".into());
+            }
+            writeln!(
+                out,
+                "pub const ACCENT: AccentConsts = AccentConsts {{ leds: {}, brightness: {} }};",
+                accent.leds, accent.brightness
+            )
+            .unwrap();
+        }
+
+        let timers = &self.timers;
+        if feature("TIMERS") {
+            if timers.utc_offset.abs() > 14 * 60 {
+                return Err("timers.utc_offset must be within 14 hours of UTC, in minutes".into());
+            }
+            let mut at = String::new();
+            for press in &timers.at {
+                let minute = minute_of_day(&press.time)
+                    .ok_or_else(|| format!("timers.at time {} is not HH:MM", press.time))?;
+                if press.button >= self.buttons.len() {
+                    return Err(format!("timers.at presses button {}, there are {}", press.button, self.buttons.len()));
+                }
+                write!(at, "ScheduledPress {{ minute_of_day: {}, button: {} }}, ", minute, press.button).unwrap();
+            }
+            writeln!(
+                out,
+                "pub const TIMERS: TimersConsts = TimersConsts {{ ntp_server: {:?}, utc_offset_mins: {}, at: &[{}] }};",
+                timers.ntp_server.as_deref().unwrap_or("pool.ntp.org"),
+                timers.utc_offset,
+                at
+            )
+            .unwrap();
+        } else if !timers.at.is_empty() {
+            return Err("timers.at needs the timers feature".into());
+        }
+
+        for (key, entity) in &self.entities {
+            writeln!(out, "pub const {}: &str = {:?};", entity_const(key), entity).unwrap();
+        }
+
+        let pads = self.leds.width * self.leds.height;
+        if pads == 0 || pads > 16 {
+            return Err("leds must have 1 to 16 pads, one per expander input".into());
+        }
+        writeln!(out, "pub const PAD_COLUMNS: usize = {};", self.leds.width).unwrap();
+        writeln!(out, "pub const PAD_ROWS: usize = {};", self.leds.height).unwrap();
+        Ok(out)
+    }
+
+    /// `BUTTON_COMMANDS` with the shared animations it uses, `AMBIENT` and `ENCODER_ACTION`, for
+    /// `command.rs`.
+    fn buttons(&self) -> Result<String, String> {
+        if self.buttons.len() > self.leds.width * self.leds.height {
+            return Err("more buttons than pads".into());
+        }
+        let mut out = String::new();
+        for (name, animation) in &self.animations {
+            if name.is_empty()
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("animation name {} must be letters, digits and underscores", name));
+            }
+            animation.check(&format!("animation {}", name))?;
+            writeln!(out, "const {}: &[Keyframe] = {};", animation_const(name), animation.keyframes_literal()).unwrap();
+        }
+        writeln!(out, "pub const BUTTON_COMMANDS: [HaButtonCommand; {}] = [", self.buttons.len()).unwrap();
+        // The desk strip for its effect, and climate entities for the setpoint their steps add to
+        let mut subscribed = vec![format!("consts::{}", entity_const("desk_strip"))];
+        for (i, button) in self.buttons.iter().enumerate() {
+            let notify = button.action == "notify";
+            let entity = match &button.entity {
+                Some(_) if notify => return Err(format!("button {} notifies, it has no entity", i)),
+                None if notify => String::new(),
+                Some(entity) if self.entities.contains_key(entity) => format!("consts::{}", entity_const(entity)),
+                Some(entity) => return Err(format!("button {} uses entity {}, not in entities", i, entity)),
+                None => return Err(format!("button {} needs an entity", i)),
+            };
+            let countdown = button.countdown.unwrap_or(0);
+            if button.countdown.is_some() && !feature("TIMERS") {
+                return Err(format!("button {} countdown needs the timers feature", i));
+            }
+            if button.countdown.is_some() && !(1..=24 * 3600).contains(&countdown) {
+                return Err(format!("button {} countdown must be 1 second to a day", i));
+            }
+            if button.step.is_some() && button.action != "climate_step" {
+                return Err(format!("button {} has a step it doesn't use", i));
+            }
+            if (button.service.is_some() || button.message.is_some()) && !notify {
+                return Err(format!("button {} has a service or message it doesn't use", i));
+            }
+            let command = match (button.action.as_str(), &button.effect) {
+                ("set_effect", Some(effect)) => format!(
+                    "HaCommand::SetEffect(HaCommandSetEffect {{ entity_name: {}, effect_name: {:?} }})",
+                    entity, effect
+                ),
+                ("turn_off", None) => format!("HaCommand::TurnOff(HaCommandTurnOff {{ entity_name: {} }})", entity),
+                ("play_pause", None) => {
+                    format!("HaCommand::PlayPause(HaCommandPlayPause {{ entity_name: {} }})", entity)
+                }
+                ("lock", None) => format!(
+                    "HaCommand::Lock(HaCommandLock {{ entity_name: {}, action: LockAction::Lock }})",
+                    entity
+                ),
+                ("unlock", None) => format!(
+                    "HaCommand::Lock(HaCommandLock {{ entity_name: {}, action: LockAction::Unlock }})",
+                    entity
+                ),
+                ("vacuum_start", None) => format!(
+                    "HaCommand::Vacuum(HaCommandVacuum {{ entity_name: {}, action: VacuumAction::Start }})",
+                    entity
+                ),
+                ("vacuum_return", None) => format!(
+                    "HaCommand::Vacuum(HaCommandVacuum {{ entity_name: {}, action: VacuumAction::ReturnToBase }})",
+                    entity
+                ),
+                ("vacuum_stop", None) => format!(
+                    "HaCommand::Vacuum(HaCommandVacuum {{ entity_name: {}, action: VacuumAction::Stop }})",
+                    entity
+                ),
+                ("climate_step", None) => {
+                    let tenths = (button.step.ok_or_else(|| format!("button {} needs a step", i))? * 10.0).round();
+                    if tenths == 0.0 || tenths.abs() > i8::MAX as f32 {
+                        return Err(format!("button {} step must be 0.1 to 12.7 degrees either way", i));
+                    }
+                    if !subscribed.contains(&entity) {
+                        subscribed.push(entity.clone());
+                    }
+                    format!(
+                        "HaCommand::ClimateTempStep(HaCommandClimateTempStep {{ entity_name: {}, tenths: {} }})",
+                        entity, tenths as i8
+                    )
+                }
+                ("notify", None) => {
+                    let (Some(service), Some(message)) = (&button.service, &button.message) else {
+                        return Err(format!("button {} needs a service and a message to notify", i));
+                    };
+                    if service.is_empty() || !service.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        return Err(format!("button {} service must be letters, digits and underscores", i));
+                    }
+                    let message =
+                        json_escape(message).ok_or_else(|| format!("button {} message has control characters", i))?;
+                    if service.len() + message.len() > MAX_NOTIFY_LEN {
+                        return Err(format!(
+                            "button {} service and message are longer than {} bytes",
+                            i, MAX_NOTIFY_LEN
+                        ));
+                    }
+                    format!("HaCommand::Notify(HaCommandNotify {{ service: {:?}, message: {:?} }})", service, message)
+                }
+                ("set_effect", None) => return Err(format!("button {} sets an effect but has none", i)),
+                (
+                    "turn_off" | "play_pause" | "lock" | "unlock" | "vacuum_start" | "vacuum_return" | "vacuum_stop"
+                    | "climate_step" | "notify",
+                    Some(_),
+                ) => {
+                    return Err(format!("button {} has an effect it doesn't set", i))
+                }
+                (action, _) => return Err(format!("button {} has unknown action {}", i, action)),
+            };
+            let (keyframes, interpolation) = match (&button.animation, &button.keyframes) {
+                (Some(name), None) if button.easing.is_none() && button.blend.is_none() => {
+                    let animation = self
+                        .animations
+                        .get(name)
+                        .ok_or_else(|| format!("button {} uses animation {}, not in animations", i, name))?;
+                    (animation_const(name), animation.interpolation_literal())
+                }
+                (Some(_), _) => {
+                    return Err(format!("button {} takes its keyframes and interpolation from its animation", i))
+                }
+                (None, Some(keyframes)) => {
+                    let animation = Animation {
+                        keyframes: keyframes.clone(),
+                        easing: button.easing.unwrap_or_default(),
+                        blend: button.blend.unwrap_or_default(),
+                    };
+                    animation.check(&format!("button {}", i))?;
+                    (animation.keyframes_literal(), animation.interpolation_literal())
+                }
+                (None, None) => return Err(format!("button {} needs keyframes or an animation", i)),
+            };
+            writeln!(
+                out,
+                "    HaButtonCommand {{\n        keyframes: {},\n        interpolation: {},\n        command: {},\n        hold: {},\n        countdown_secs: {},\n    }},",
+                keyframes, interpolation, command, button.hold, countdown
+            )
+            .unwrap();
+        }
+        writeln!(out, "];").unwrap();
+
+        // Tints the idle pads with the weather or the sun it reports
+        let ambient = match &self.ambient {
+            Some(ambient) => {
+                let entity_id = self
+                    .entities
+                    .get(&ambient.entity)
+                    .ok_or_else(|| format!("ambient uses entity {}, not in entities", ambient.entity))?;
+                if !entity_id.starts_with("weather.") && !entity_id.starts_with("sun.") {
+                    return Err("ambient.entity must be a weather or sun entity".into());
+                }
+                let entity = format!("consts::{}", entity_const(&ambient.entity));
+                subscribed.push(entity.clone());
+                format!("Some(Ambient {{ entity_name: {}, strength: {} }})", entity, ambient.strength)
+            }
+            None => "None".into(),
+        };
+        writeln!(out, "pub const AMBIENT: Option<Ambient> = {};", ambient).unwrap();
+        writeln!(
+            out,
+            "pub const ENTITIES_TO_SUBSCRIBE: [&str; {}] = [{}];",
+            subscribed.len(),
+            subscribed.join(", ")
+        )
+        .unwrap();
+
+        let encoder_action = match &self.encoder {
+            Some(encoder) => {
+                if !self.entities.contains_key(&encoder.entity) {
+                    return Err(format!("encoder uses entity {}, not in entities", encoder.entity));
+                }
+                let entity = format!("consts::{}", entity_const(&encoder.entity));
+                match (encoder.action.as_str(), encoder.step) {
+                    ("brightness", step) => {
+                        let step = step.unwrap_or(10);
+                        if !(1..=100).contains(&step) {
+                            return Err("encoder.step must be 1 to 100 percent".into());
+                        }
+                        format!("Some(EncoderAction::Brightness {{ entity_name: {}, step_pct: {} }})", entity, step)
+                    }
+                    ("volume", None) => format!("Some(EncoderAction::Volume {{ entity_name: {} }})", entity),
+                    ("volume", Some(_)) => return Err("encoder.step only applies to brightness".into()),
+                    (action, _) => return Err(format!("encoder has unknown action {}", action)),
+                }
+            }
+            None if feature("ENCODER") => return Err("the encoder feature needs encoder".into()),
+            None => "None".into(),
+        };
+        writeln!(out, "pub const ENCODER_ACTION: Option<EncoderAction> = {};", encoder_action).unwrap();
+        Ok(out)
+    }
 }
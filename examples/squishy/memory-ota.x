@@ -0,0 +1,34 @@
+/* Layout for the `ota` feature, the firmware runs behind embassy-boot-rp. The bootloader must be
+   built with the same BOOTLOADER_STATE, ACTIVE (FLASH here) and DFU regions, i.e. FLASH at
+   0x10007000 of 960K and DFU at 0x100F7000 of 964K, and its RAM must end below CRASH for
+   crash records to survive it. */
+MEMORY {
+    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+    /* Bootloader occupies 0x10000100 .. 0x10006000 */
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10007000, LENGTH = 960K
+    /* Update partition, one erase sector larger than FLASH for the swap progress */
+    DFU : ORIGIN = 0x100F7000, LENGTH = 964K
+    /* Settings storage, see settings.rs */
+    STORAGE : ORIGIN = 0x101F8000, LENGTH = 32K
+
+    RAM   : ORIGIN = 0x20000000, LENGTH = 264K - 2K
+    /* Crash record, see board-support/src/crash.rs. Below the stacks and clear of the boot ROM,
+       which uses the top of RAM for the second stage bootloader */
+    CRASH : ORIGIN = 0x20041800, LENGTH = 512
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
+
+/* Crash record kept across resets, see board-support/src/crash.rs. Nothing initializes it at
+   startup. */
+SECTIONS {
+    .crash (NOLOAD) : ALIGN(4)
+    {
+        KEEP(*(.crash .crash.*));
+    } > CRASH
+} INSERT AFTER .uninit;
@@ -0,0 +1,101 @@
+use defmt::{info, warn};
+use embassy_rp::peripherals::{DMA_CH4, PIN_22, PIO1};
+use embassy_rp::pio::Pio;
+use embassy_time::{Duration, Instant};
+use sk6812::{PioSK6812, PioSK6812Program};
+
+use crate::command::{HaCommand, BUTTON_COMMANDS};
+use crate::keyframe::{Color, KeyframeReader};
+use crate::{consts, Irqs};
+
+const NUM_LEDS: usize = consts::ACCENT.leds;
+/// Keyframe frames between neighbouring LEDs, so the palette of the active effect runs along the
+/// strip rather than the whole strip changing at once.
+const SPREAD_FRAMES: u64 = 40;
+const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+
+/// SK6812 underglow strip, sent a frame by the LED task after each one of the pads. It plays the
+/// keyframes of the pad of the active effect, or a notification color from Home Assistant while
+/// one is up.
+pub struct Accent {
+    strip: PioSK6812<'static, PIO1, 0, DMA_CH4>,
+    reader: KeyframeReader,
+    /// Pad whose keyframes the strip plays, none while no effect is on.
+    palette: Option<usize>,
+    /// Color shown and when it goes away.
+    notification: Option<(Color, Instant)>,
+}
+
+impl Accent {
+    pub fn new(pio1: PIO1, dio: PIN_22, dma4: DMA_CH4) -> Self {
+        info!("set up accent strip");
+        let Pio { mut common, sm0, .. } = Pio::new(pio1, Irqs);
+        let program = PioSK6812Program::new(&mut common);
+        let strip = PioSK6812::new(&mut common, &program, sm0, dio, dma4);
+        Self {
+            strip,
+            reader: KeyframeReader::default(),
+            palette: None,
+            notification: None,
+        }
+    }
+
+    /// Show `color` for `duration` instead of the palette, black takes a notification down early.
+    pub fn notify(&mut self, color: Color, duration: Duration) {
+        let black = color.r == 0 && color.g == 0 && color.b == 0;
+        self.notification = (!black).then(|| (color, Instant::now() + duration));
+    }
+
+    /// Whether a notification is up, the LED task keeps sending frames for it while the pads are
+    /// asleep.
+    pub fn notifying(&mut self) -> bool {
+        if self.notification.is_some_and(|(_, until)| Instant::now() >= until) {
+            self.notification = None;
+        }
+        self.notification.is_some()
+    }
+
+    /// Play the keyframes of the first effect pad checked in `checked_mask`.
+    fn follow(&mut self, checked_mask: u16) {
+        let palette = (0..BUTTON_COMMANDS.len()).find(|&i| {
+            checked_mask & (1 << i) != 0 && matches!(BUTTON_COMMANDS[i].command, HaCommand::SetEffect(_))
+        });
+        if palette != self.palette {
+            if let Some(i) = palette {
+                let button_cmd = &BUTTON_COMMANDS[i];
+                self.reader.set_keyframes(button_cmd.keyframes, 0, button_cmd.keyframes.len());
+                self.reader.set_interpolation(button_cmd.interpolation);
+            }
+            self.palette = palette;
+        }
+    }
+
+    /// Send a frame at `level` out of 255 of the configured brightness, following the pads as they
+    /// fade. `frame` is where the pads are in their keyframes.
+    pub async fn send(&mut self, checked_mask: u16, level: u32, frame: u64) {
+        self.follow(checked_mask);
+        let notification = if self.notifying() { self.notification.map(|(color, _)| color) } else { None };
+        // Notifications are up at full brightness even while the pads fade out
+        let level = if notification.is_some() { 255 } else { level } * consts::ACCENT.brightness as u32 / 255;
+        let keyframes = self.palette.map(|i| BUTTON_COMMANDS[i].keyframes);
+        let reader = &mut self.reader;
+        let result = self
+            .strip
+            .write_with(NUM_LEDS, |i| {
+                let color = match (notification, keyframes) {
+                    (Some(color), _) => color,
+                    (None, Some(keyframes)) => {
+                        reader.evaluate_color_at_frame(keyframes, frame + (NUM_LEDS - i) as u64 * SPREAD_FRAMES)
+                    }
+                    (None, None) => BLACK,
+                };
+                let scale = |c: u8| (c as u32 * level / 255) as u8;
+                sk6812::encode([scale(color.r), scale(color.g), scale(color.b), 0])
+            })
+            .await;
+        if result.is_err() {
+            warn!("accent strip frame timed out");
+            self.strip.reinit();
+        }
+    }
+}
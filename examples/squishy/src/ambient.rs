@@ -0,0 +1,85 @@
+//! Background tints for the idle pads from the weather or the sun outside.
+
+use crate::keyframe::Color;
+
+/// Sky colors by elevation of the sun in tenths of a degree, from night through the twilights
+/// and golden hour to day. Lower and higher elevations take the first and last.
+const SKY: [(i32, Color); 5] = [
+    (-120, Color { r: 10, g: 20, b: 90 }),
+    (-60, Color { r: 90, g: 40, b: 150 }),
+    (0, Color { r: 255, g: 70, b: 20 }),
+    (100, Color { r: 255, g: 170, b: 60 }),
+    (300, Color { r: 200, g: 220, b: 255 }),
+];
+
+/// Tint for the state of a Home Assistant weather entity, `None` for conditions it doesn't know
+/// such as `unavailable`.
+pub fn condition_tint(condition: &str) -> Option<Color> {
+    let (r, g, b) = match condition {
+        "sunny" => (255, 180, 40),
+        "clear-night" => (20, 30, 120),
+        "partlycloudy" => (200, 170, 110),
+        "cloudy" | "fog" => (120, 120, 130),
+        "rainy" | "pouring" => (30, 70, 200),
+        "snowy" | "snowy-rainy" | "hail" => (200, 220, 255),
+        "lightning" | "lightning-rainy" | "exceptional" => (150, 40, 220),
+        "windy" | "windy-variant" => (60, 200, 160),
+        _ => return None,
+    };
+    Some(Color { r, g, b })
+}
+
+/// Tint for the elevation of the sun in tenths of a degree, blended between the `SKY` colors
+/// around it.
+pub fn elevation_tint(tenths: i32) -> Color {
+    let Some(above) = SKY.iter().position(|(elevation, _)| tenths < *elevation) else {
+        return SKY[SKY.len() - 1].1;
+    };
+    if above == 0 {
+        return SKY[0].1;
+    }
+    let ((low, from), (high, to)) = (SKY[above - 1], SKY[above]);
+    let mix = |a: u8, b: u8| (a as i32 + (b as i32 - a as i32) * (tenths - low) / (high - low)) as u8;
+    Color { r: mix(from.r, to.r), g: mix(from.g, to.g), b: mix(from.b, to.b) }
+}
+
+/// `color` with `strength` out of 255 of `tint` mixed in.
+pub fn blend(color: Color, tint: Color, strength: u8) -> Color {
+    let mix = |a: u8, b: u8| ((a as u32 * (255 - strength as u32) + b as u32 * strength as u32) / 255) as u8;
+    Color { r: mix(color.r, tint.r), g: mix(color.g, tint.g), b: mix(color.b, tint.b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(color: Color) -> [u8; 3] {
+        [color.r, color.g, color.b]
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(condition_tint("rainy").map(rgb), Some([30, 70, 200]));
+        assert_eq!(condition_tint("pouring").map(rgb), condition_tint("rainy").map(rgb));
+        assert!(condition_tint("unavailable").is_none());
+    }
+
+    #[test]
+    fn elevations() {
+        // Past either end of the sky colors
+        assert_eq!(rgb(elevation_tint(-900)), [10, 20, 90]);
+        assert_eq!(rgb(elevation_tint(900)), [200, 220, 255]);
+        // On a stop and halfway between the horizon and 10 degrees
+        assert_eq!(rgb(elevation_tint(0)), [255, 70, 20]);
+        assert_eq!(rgb(elevation_tint(50)), [255, 120, 40]);
+    }
+
+    #[test]
+    fn blends() {
+        let white = Color { r: 255, g: 255, b: 255 };
+        let blue = Color { r: 0, g: 0, b: 255 };
+        assert_eq!(rgb(blend(white, blue, 0)), [255, 255, 255]);
+        assert_eq!(rgb(blend(white, blue, 255)), [0, 0, 255]);
+        assert_eq!(rgb(blend(white, blue, 128)), [127, 127, 255]);
+    }
+}
@@ -0,0 +1,64 @@
+use animation::{KeyframeColor, LedDriver};
+use embassy_rp::{gpio, spi};
+use embassy_time::{with_timeout, Duration};
+
+/// A frame takes well under a millisecond at the SPI clock.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(20);
+const START_FRAME: [u8; 4] = [0; 4];
+/// Enough clock edges to push the last LED's data through a chain of up to 64.
+const END_FRAME: [u8; 4] = [0; 4];
+const GLOBAL_BRIGHTNESS_MAX: u32 = 31;
+
+/// A frame didn't go out within `WRITE_TIMEOUT`.
+#[derive(Copy, Clone, Debug)]
+pub struct WriteTimeout;
+
+/// A chain of up to `LEDS` APA102s on a TX only SPI, with a chip select gating the bus.
+pub struct Apa102<'d, T: spi::Instance, const LEDS: usize> {
+    spi: spi::Spi<'d, T, spi::Async>,
+    cs: gpio::Output<'d>,
+}
+
+impl<'d, T: spi::Instance, const LEDS: usize> Apa102<'d, T, LEDS> {
+    pub fn new(spi: spi::Spi<'d, T, spi::Async>, cs: gpio::Output<'d>) -> Self {
+        Self { spi, cs }
+    }
+
+    /// The LED word for a color: the lowest 5-bit global brightness that still reaches its
+    /// brightest channel, with the channels scaled up to match, so dim colors keep more of
+    /// their 8-bit resolution. White is dropped.
+    fn encode([r, g, b, _]: [u8; 4]) -> [u8; 4] {
+        let max = r.max(g).max(b) as u32;
+        let brightness = ((max * GLOBAL_BRIGHTNESS_MAX + 254) / 255).max(1);
+        let scale = |c: u8| (c as u32 * GLOBAL_BRIGHTNESS_MAX / brightness).min(255) as u8;
+        [0b1110_0000 | brightness as u8, scale(b), scale(g), scale(r)]
+    }
+
+    async fn write_words(&mut self, words: &[u8]) -> Result<(), spi::Error> {
+        self.spi.write(&START_FRAME).await?;
+        self.spi.write(words).await?;
+        self.spi.write(&END_FRAME).await
+    }
+}
+
+impl<'d, T: spi::Instance, const LEDS: usize> LedDriver for Apa102<'d, T, LEDS> {
+    type Error = WriteTimeout;
+
+    /// LEDs past `LEDS` are left out.
+    async fn write_with<C: KeyframeColor>(&mut self, len: usize, mut pixel: impl FnMut(usize) -> C) -> Result<(), WriteTimeout> {
+        let len = len.min(LEDS);
+        let mut words = [[0_u8; 4]; LEDS];
+        for (i, word) in words[..len].iter_mut().enumerate() {
+            *word = Self::encode(pixel(i).to_rgbw());
+        }
+
+        self.cs.set_low();
+        // Dropping a timed out transfer aborts its DMA channel
+        let result = with_timeout(WRITE_TIMEOUT, self.write_words(words[..len].as_flattened())).await;
+        self.cs.set_high();
+        match result {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(WriteTimeout),
+        }
+    }
+}
@@ -1,96 +1,206 @@
-use defmt::{info, unwrap};
-use embassy_rp::{gpio, i2c};
+use defmt::{debug, info};
+use embassy_futures::select::{select4, Either4};
+use embassy_time::{Duration, Instant, Timer};
 
-use crate::command::CommandSender;
+#[cfg(feature = "buzzer")]
+use crate::buzzer::{self, Tone};
+use crate::command::{CommandSender, BUTTON_COMMANDS};
+use crate::error::Error;
 use crate::leds::LedSender;
-use crate::{define_peripheral_set, tca9555, Irqs};
-
-#[macro_export]
-macro_rules! button_peripherals {
-    ($macro_name:ident $(,$arg:tt)*) => {
-        $macro_name!{$($arg,)*
-            ButtonPeripherals,
-            sda: PIN_4,
-            scl: PIN_5,
-            i2c0: I2C0,
-            button_int: PIN_3,
-        }
-    };
-}
+use crate::selftest::{self, Outcome};
+#[cfg(feature = "timers")]
+use crate::timers;
+#[cfg(feature = "touch")]
+pub use crate::touch::{source, ButtonPeripherals};
+#[cfg(not(feature = "touch"))]
+pub use crate::tca9555::{source, ButtonPeripherals};
+use crate::{dormant, instrument, kept_log};
+
+/// Reads retried over glitches on the bus before the source counts as not answering.
+const READ_RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+/// How often the source is tried again while it isn't answering.
+const DEGRADED_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long pads with `hold` in `device.toml` have to be held down for their command.
+pub const HOLD_TIME: Duration = Duration::from_millis(800);
 
-button_peripherals!(define_peripheral_set);
+/// Where the pad's button states come from: the TCA9555 expander by default, or capacitive
+/// touch pads on GPIOs with the `touch` feature. Each provides `source` and
+/// `ButtonPeripherals` for the button task to build it.
+pub trait ButtonSource {
+    /// One bit per pad, first pad in bit 0, set while it is released.
+    async fn read(&mut self) -> Result<u16, Error>;
 
-struct Buttons<'d, T: i2c::Instance> {
-    i2c: i2c::I2c<'d, T, i2c::Async>,
-    button_int: gpio::Input<'d>,
+    /// Ready when the states may have changed.
+    async fn wait_for_change(&mut self);
+
+    /// Check the hardware answers the way it should.
+    async fn self_test(&mut self) -> Outcome;
+
+    /// Go dormant until a pad is pressed, see `dormant::enter`.
+    fn enter_dormant(&mut self);
+}
+
+struct Buttons<S: ButtonSource> {
+    source: S,
     sender: CommandSender,
     led_sender: LedSender,
+    /// When each pad with `hold` that is still down was pressed.
+    held_since: [Option<Instant>; 16],
 }
 
-impl<'d, T: i2c::Instance> Buttons<'d, T> {
-    pub fn new(
-        i2c: i2c::I2c<'d, T, i2c::Async>,
-        button_int: gpio::Input<'d>,
-        sender: CommandSender,
-        led_sender: LedSender,
-    ) -> Self {
+impl<S: ButtonSource> Buttons<S> {
+    pub fn new(source: S, sender: CommandSender, led_sender: LedSender) -> Self {
         Self {
-            i2c,
-            button_int,
+            source,
             sender,
             led_sender,
+            held_since: [None; 16],
         }
     }
 
-    pub async fn read_buttons(&mut self) -> u16 {
-        use embedded_hal_async::i2c::I2c;
-        let mut port0 = [0; 2];
-        unwrap!(
-            self.i2c
-                .write_read(tca9555::ADDR, &[tca9555::INPORT0], &mut port0)
-                .await
-        );
-        u16::from_le_bytes(port0)
+    async fn read_buttons_retrying(&mut self) -> Result<u16, Error> {
+        let mut retries = 0;
+        loop {
+            match self.source.read().await {
+                Err(err) if retries < READ_RETRIES => {
+                    debug!("button read failed: {}", err);
+                    retries += 1;
+                    Timer::after(RETRY_DELAY).await;
+                }
+                result => return result,
+            }
+        }
     }
 
     fn on_button_pressed(&mut self, i: usize) {
         info!("button {} pressed", i);
-        self.sender.on_button_pressed(i);
-        self.led_sender.on_button_pressed(i);
+        if BUTTON_COMMANDS.get(i).is_some_and(|button_cmd| button_cmd.hold) {
+            // Only lights the pads up until it was held for long enough
+            self.held_since[i] = Some(Instant::now());
+            self.led_sender.wake();
+        } else {
+            self.activate(i);
+        }
     }
 
     fn on_button_released(&mut self, i: usize) {
         info!("button {} released", i);
+        if self.held_since[i].take().is_some() {
+            debug!("button {} let go before the hold time", i);
+        }
+    }
+
+    fn activate(&mut self, i: usize) {
+        #[cfg(feature = "buzzer")]
+        buzzer::play(Tone::Click);
+        #[cfg(feature = "timers")]
+        if BUTTON_COMMANDS.get(i).is_some_and(|button_cmd| button_cmd.countdown_secs != 0) {
+            timers::toggle_countdown(i);
+            return;
+        }
+        self.sender.on_button_pressed(i);
+        self.led_sender.on_button_pressed(i);
+    }
+
+    /// When the first of the pads being held reaches `HOLD_TIME`.
+    fn next_hold(&self) -> Instant {
+        self.held_since.iter().flatten().min().map_or(Instant::MAX, |since| *since + HOLD_TIME)
+    }
+
+    fn on_hold_time(&mut self) {
+        let now = Instant::now();
+        for i in 0..self.held_since.len() {
+            if self.held_since[i].is_some_and(|since| now - since >= HOLD_TIME) {
+                info!("button {} held", i);
+                self.held_since[i] = None;
+                self.activate(i);
+            }
+        }
     }
 
+    fn on_states_changed(&mut self, states: u16, new_states: u16) {
+        let flips = states ^ new_states;
+        for i in 0..16 {
+            if (flips >> i) & 0x1 != 0 {
+                if (new_states >> i) & 0x1 != 0 {
+                    self.on_button_released(i);
+                } else {
+                    self.on_button_pressed(i);
+                }
+            }
+        }
+    }
+
+    /// Without its buttons the pad still shows its entities, so a missing expander only turns
+    /// them off. One that stops answering later more likely hangs the bus, which the reset of a
+    /// reboot clears.
     pub async fn run(&mut self) -> ! {
-        let mut states = self.read_buttons().await;
+        // `None` while the source isn't answering
+        let mut states = match self.read_buttons_retrying().await {
+            Ok(states) => Some(states),
+            Err(err) => {
+                kept_log!(Warn, "button expander: {}, buttons off", err.as_str());
+                None
+            }
+        };
         loop {
-            self.button_int.wait_for_low().await;
-            let new_states = self.read_buttons().await;
-            let flips = states ^ new_states;
-
-            if flips != 0 {
-                for i in 0..16 {
-                    if (flips >> i) & 0x1 != 0 {
-                        if (new_states >> i) & 0x1 != 0 {
-                            self.on_button_released(i);
-                        } else {
-                            self.on_button_pressed(i);
-                        }
-                    }
+            let next_hold = self.next_hold();
+            // The buttons are the only wake source while dormant, so this task goes dormant
+            match select4(
+                wait_for_change(&mut self.source, states.is_none()),
+                dormant::requested(),
+                selftest::BUTTONS.requested(),
+                Timer::at(next_hold),
+            )
+            .await
+            {
+                Either4::First(_) => {}
+                Either4::Second(_) => {
+                    self.source.enter_dormant();
+                    continue;
+                }
+                Either4::Third(_) => {
+                    let outcome = self.source.self_test().await;
+                    selftest::BUTTONS.report(outcome);
+                    continue;
+                }
+                Either4::Fourth(_) => {
+                    self.on_hold_time();
+                    continue;
+                }
+            }
+            match (self.read_buttons_retrying().await, states) {
+                (Ok(new_states), Some(old_states)) => {
+                    self.on_states_changed(old_states, new_states);
+                    states = Some(new_states);
+                }
+                (Ok(new_states), None) => {
+                    kept_log!(Info, "button expander answering again");
+                    states = Some(new_states);
+                }
+                (Err(_), None) => {}
+                (Err(err), Some(_)) => {
+                    kept_log!(Error, "button expander: {}, rebooting", err.as_str());
+                    cortex_m::peripheral::SCB::sys_reset();
                 }
             }
-
-            states = new_states;
         }
     }
 }
 
+/// Ready when the inputs may have changed, or when it's time to try a source that isn't
+/// answering again.
+async fn wait_for_change(source: &mut impl ButtonSource, degraded: bool) {
+    if degraded {
+        Timer::after(DEGRADED_POLL_INTERVAL).await;
+    } else {
+        source.wait_for_change().await;
+    }
+}
+
 #[embassy_executor::task]
 pub async fn button_task(sender: CommandSender, led_sender: LedSender, p: ButtonPeripherals) -> ! {
-    info!("set up i2c");
-    let i2c = i2c::I2c::new_async(p.i2c0, p.scl, p.sda, Irqs, i2c::Config::with_frequency(400_000));
-    let button_int = gpio::Input::new(p.button_int, gpio::Pull::None);
-    Buttons::new(i2c, button_int, sender, led_sender).run().await
+    let mut buttons = Buttons::new(source(p), sender, led_sender);
+    instrument::measured("buttons", buttons.run()).await
 }
@@ -0,0 +1,92 @@
+use defmt::info;
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::pwm::{self, Pwm};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+
+use crate::{define_peripheral_set, instrument, settings};
+
+/// The PWM counts at the system clock over this, fine enough for tones of 500 Hz up at any
+/// system clock the pad runs.
+const DIVIDER: u8 = 64;
+
+/// Passive piezo buzzer between the pin and ground.
+#[macro_export]
+macro_rules! buzzer_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            BuzzerPeripherals,
+            pwm: PWM_SLICE4,
+            pin: PIN_9,
+        }
+    };
+}
+
+buzzer_peripherals!(define_peripheral_set);
+
+/// Feedback sounds, short enough not to get in the way of pressing on.
+#[derive(Copy, Clone)]
+pub enum Tone {
+    /// A pad was pressed.
+    Click,
+    /// Home Assistant carried out the command.
+    Confirm,
+    /// Home Assistant rejected the command, or it couldn't be sent.
+    Error,
+}
+
+impl Tone {
+    /// Frequency in Hz and length in ms of each note, 0 Hz for a rest.
+    fn notes(&self) -> &'static [(u32, u64)] {
+        match self {
+            Tone::Click => &[(4000, 8)],
+            Tone::Confirm => &[(2000, 60), (3000, 80)],
+            Tone::Error => &[(400, 150), (0, 50), (400, 150)],
+        }
+    }
+}
+
+/// Tones waiting to be played, more than this while one plays are dropped.
+static TONES: Channel<CriticalSectionRawMutex, Tone, 4> = Channel::new();
+
+/// Play `tone` after the ones already waiting, unless the buzzer is muted.
+pub fn play(tone: Tone) {
+    if !settings::buzzer().muted {
+        TONES.try_send(tone).ok();
+    }
+}
+
+fn config(frequency: u32, volume: u8) -> pwm::Config {
+    let mut config = pwm::Config::default();
+    config.divider = DIVIDER.into();
+    if frequency == 0 {
+        return config;
+    }
+    let top = (clk_sys_freq() / DIVIDER as u32 / frequency).clamp(2, u16::MAX as u32 + 1) - 1;
+    config.top = top as u16;
+    // A piezo is loudest at half duty
+    config.compare_b = ((top + 1) * volume as u32 / 200) as u16;
+    config
+}
+
+async fn run(pwm: &mut Pwm<'_>) -> ! {
+    loop {
+        let tone = TONES.receive().await;
+        // Read per tone, so a volume change from the console is heard on the next one
+        let volume = settings::buzzer().volume;
+        for &(frequency, millis) in tone.notes() {
+            pwm.set_config(&config(frequency, volume));
+            Timer::after_millis(millis).await;
+        }
+        pwm.set_config(&config(0, 0));
+    }
+}
+
+/// Play the tones asked for with `play` on a piezo buzzer.
+#[embassy_executor::task]
+pub async fn buzzer_task(p: BuzzerPeripherals) -> ! {
+    info!("set up buzzer");
+    let mut pwm = Pwm::new_output_b(p.pwm, p.pin, config(0, 0));
+    instrument::measured("buzzer", run(&mut pwm)).await
+}
@@ -0,0 +1,103 @@
+/// Rise over the baseline, in percent, that counts as a touch, and the rise below which it is
+/// let go again. The gap keeps a finger resting on the edge of a pad from chattering.
+const PRESS_PERCENT: u32 = 20;
+const RELEASE_PERCENT: u32 = 10;
+/// Smallest rise that counts as a touch, for pads with very short charge times.
+const MIN_RISE: u32 = 4;
+/// The baseline follows untouched readings by 1/2^`BASELINE_SHIFT` of the difference each
+/// update, slow enough that a finger approaching doesn't drag it along.
+const BASELINE_SHIFT: u32 = 4;
+
+/// Decides whether a capacitive pad is touched from its charge times. A finger adds capacitance,
+/// so the time goes up, while humidity and temperature move the untouched time slowly, which the
+/// baseline tracks.
+pub struct Channel {
+    /// Untouched charge time, scaled by 2^`BASELINE_SHIFT`, `None` before the first reading.
+    baseline: Option<u32>,
+    touched: bool,
+}
+
+impl Channel {
+    pub const fn new() -> Self {
+        Self { baseline: None, touched: false }
+    }
+
+    /// Untouched charge time, 0 before the first reading.
+    pub fn baseline(&self) -> u32 {
+        self.baseline.unwrap_or(0) >> BASELINE_SHIFT
+    }
+
+    /// Feed the latest charge time, returns whether the pad is touched. The first reading is
+    /// taken as untouched.
+    pub fn update(&mut self, count: u32) -> bool {
+        let Some(scaled) = self.baseline else {
+            self.baseline = Some(count << BASELINE_SHIFT);
+            return false;
+        };
+        let baseline = scaled >> BASELINE_SHIFT;
+        let rise = count.saturating_sub(baseline);
+        let threshold = |percent: u32| (baseline * percent / 100).max(MIN_RISE);
+        let percent = if self.touched { RELEASE_PERCENT } else { PRESS_PERCENT };
+        self.touched = rise >= threshold(percent);
+        // A touch held down doesn't become the new normal
+        if !self.touched {
+            self.baseline = Some(scaled - (scaled >> BASELINE_SHIFT) + count);
+        }
+        self.touched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibrated(count: u32) -> Channel {
+        let mut channel = Channel::new();
+        for _ in 0..32 {
+            assert!(!channel.update(count));
+        }
+        channel
+    }
+
+    #[test]
+    fn touch_and_release() {
+        let mut channel = calibrated(200);
+        assert!(channel.update(260));
+        assert!(!channel.update(200));
+    }
+
+    #[test]
+    fn small_rise_is_not_a_touch() {
+        let mut channel = calibrated(200);
+        assert!(!channel.update(230));
+    }
+
+    #[test]
+    fn release_has_hysteresis() {
+        let mut channel = calibrated(200);
+        assert!(channel.update(250));
+        // Below the press threshold, above the release one
+        assert!(channel.update(225));
+        assert!(!channel.update(215));
+    }
+
+    #[test]
+    fn baseline_follows_slow_drift() {
+        let mut channel = calibrated(200);
+        for i in 0..400 {
+            assert!(!channel.update(200 + i / 4));
+        }
+        assert!(channel.baseline() > 280);
+        assert!(channel.update(400));
+    }
+
+    #[test]
+    fn held_touch_keeps_baseline() {
+        let mut channel = calibrated(200);
+        for _ in 0..1000 {
+            assert!(channel.update(300));
+        }
+        assert_eq!(channel.baseline(), 200);
+        assert!(!channel.update(200));
+    }
+}
@@ -0,0 +1,119 @@
+use core::cell::Cell;
+use defmt::{info, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use crate::transport::NetDevice;
+use crate::{consts, instrument};
+
+const NTP_PORT: u16 = 123;
+/// Local port replies come back to.
+const LOCAL_PORT: u16 = 1123;
+const PACKET_LEN: usize = 48;
+/// LI 0, version 4, mode 3 (client).
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+/// Seconds from the NTP epoch (1900) to the Unix epoch.
+const NTP_UNIX_OFFSET_S: u64 = 2_208_988_800;
+/// Scheduled presses only need the minute, the crystal doesn't drift that far in a day.
+const SYNC_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `Instant` and Unix time in milliseconds of the last sync, unset from boot until the time server
+/// first answers.
+static REFERENCE: Mutex<CriticalSectionRawMutex, Cell<Option<(Instant, u64)>>> = Mutex::new(Cell::new(None));
+
+/// Current Unix time in seconds, `None` until the clock has been set.
+pub fn unix_seconds() -> Option<u64> {
+    let reference = REFERENCE.lock(|cell| cell.get());
+    reference.map(|(instant, unix_ms)| (unix_ms + (Instant::now() - instant).as_millis()) / 1000)
+}
+
+/// Unix time in milliseconds of a 64-bit NTP timestamp.
+fn unix_millis(timestamp: &[u8]) -> u64 {
+    let mut seconds = u32::from_be_bytes(timestamp[0..4].try_into().unwrap()) as u64;
+    let fraction = u32::from_be_bytes(timestamp[4..8].try_into().unwrap()) as u64;
+    // Timestamps with the top bit clear are past 2036, in the next era (RFC 4330 section 3)
+    if seconds & 0x8000_0000 == 0 {
+        seconds += 1 << 32;
+    }
+    (seconds - NTP_UNIX_OFFSET_S) * 1000 + (fraction * 1000 >> 32)
+}
+
+/// Query `consts::TIMERS.ntp_server` once and set the clock from its reply.
+async fn sync(stack: &Stack<NetDevice>, socket: &mut UdpSocket<'_>) -> bool {
+    let server = consts::TIMERS.ntp_server;
+    let address = match stack.dns_query(server, DnsQueryType::A).await {
+        Ok(addresses) if !addresses.is_empty() => addresses[0],
+        _ => {
+            warn!("failed to resolve time server {}", server);
+            return false;
+        }
+    };
+    let endpoint = IpEndpoint::new(address, NTP_PORT);
+
+    // The transmit timestamp comes back as the originate timestamp, identifying our reply
+    let sent = Instant::now();
+    let nonce = sent.as_ticks().to_be_bytes();
+    let mut request = [0; PACKET_LEN];
+    request[0] = CLIENT_HEADER;
+    request[40..48].copy_from_slice(&nonce);
+    if socket.send_to(&request, endpoint).await.is_err() {
+        return false;
+    }
+
+    let reply = with_timeout(REPLY_TIMEOUT, async {
+        loop {
+            let mut reply = [0; PACKET_LEN];
+            match socket.recv_from(&mut reply).await {
+                Ok((len, meta)) if len == PACKET_LEN && meta.endpoint == endpoint && reply[24..32] == nonce => {
+                    return reply;
+                }
+                _ => {}
+            }
+        }
+    })
+    .await;
+    let Ok(reply) = reply else {
+        warn!("time server {} didn't answer", endpoint);
+        return false;
+    };
+    // Stratum 0 is a kiss-o'-death, the server wants us to back off
+    if reply[0] & 0x07 != MODE_SERVER || reply[1] == 0 {
+        warn!("time server {} refused the request", endpoint);
+        return false;
+    }
+
+    // Half the round trip is close enough for whole minutes
+    let now = Instant::now();
+    let unix_ms = unix_millis(&reply[40..48]) + (now - sent).as_millis() / 2;
+    REFERENCE.lock(|cell| cell.set(Some((now, unix_ms))));
+    info!("clock synced with {}", endpoint);
+    true
+}
+
+async fn run(stack: &Stack<NetDevice>, socket: &mut UdpSocket<'_>) -> ! {
+    loop {
+        let interval = if sync(stack, socket).await { SYNC_INTERVAL } else { RETRY_INTERVAL };
+        Timer::after(interval).await;
+    }
+}
+
+/// Keep the wall clock set from the configured time server, for the scheduled presses.
+#[embassy_executor::task]
+pub async fn sntp_task(stack: &'static Stack<NetDevice>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; PACKET_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(LOCAL_PORT).is_err() {
+        warn!("failed to bind SNTP socket");
+    }
+    instrument::measured("sntp", run(stack, &mut socket)).await
+}
@@ -0,0 +1,254 @@
+use board_support::bootsel;
+use board_support::console::{Args, Console, RESET_DELAY};
+use core::cell::Cell;
+use defmt::warn;
+use embassy_futures::join::join3;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Instant, Timer};
+use embassy_usb::Handler;
+use heapless::{String, Vec};
+use static_cell::StaticCell;
+use ufmt::uwrite;
+
+use crate::{safemode, selftest};
+use crate::leds;
+use crate::link::{self, NetHealth};
+#[cfg(feature = "buzzer")]
+use crate::buzzer::{self, Tone};
+#[cfg(feature = "buzzer")]
+use crate::settings::BuzzerSettings;
+use crate::settings::{self, SettingsError};
+use crate::websocket::{self, HaConnection};
+use crate::{define_peripheral_set, identity, instrument, Irqs};
+
+/// Long enough for a Home Assistant token.
+const MAX_LINE_LEN: usize = 320;
+const MAX_REPLY_LEN: usize = 512;
+
+#[cfg(not(feature = "ethernet"))]
+const WIFI_HELP: &str = "wifi <ssid> [passphrase]    store network credentials, used after a reboot\r\n";
+#[cfg(feature = "ethernet")]
+const WIFI_HELP: &str = "";
+#[cfg(feature = "buzzer")]
+const BUZZER_HELP: &str = "buzzer                      show the buzzer volume\r\n\
+                           buzzer volume <0-100>       set and store the buzzer volume\r\n\
+                           buzzer mute|unmute          silence the buzzer or bring it back\r\n";
+#[cfg(not(feature = "buzzer"))]
+const BUZZER_HELP: &str = "";
+const HELP: &str = "status                      show the device state\r\n\
+                    tasks                       show core loads and task poll stats\r\n\
+                    selftest                    check the expander, LEDs, network and settings flash\r\n\
+                    token <token>               store the Home Assistant token, used after a reboot\r\n\
+                    reboot                      restart the device\r\n\
+                    bootsel                     restart into the USB bootloader for flashing\r\n\
+                    factory-reset               erase stored settings and restart\r\n\
+                    Quote arguments containing spaces, \"like this\".\r\n";
+
+#[macro_export]
+macro_rules! console_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            ConsolePeripherals,
+            usb: USB,
+        }
+    };
+}
+
+console_peripherals!(define_peripheral_set);
+
+static HOST_ATTACHED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Whether a USB host configured the device and hasn't suspended it. Chargers and batteries
+/// never do, a pulled cable looks like a suspend.
+pub fn host_attached() -> bool {
+    HOST_ATTACHED.lock(|cell| cell.get())
+}
+
+#[derive(Default)]
+struct UsbState {
+    configured: bool,
+    suspended: bool,
+}
+
+impl UsbState {
+    fn update(&self) {
+        HOST_ATTACHED.lock(|cell| cell.set(self.configured && !self.suspended));
+    }
+}
+
+impl Handler for UsbState {
+    fn reset(&mut self) {
+        self.configured = false;
+        self.update();
+    }
+
+    fn configured(&mut self, configured: bool) {
+        self.configured = configured;
+        self.update();
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+        self.update();
+    }
+}
+
+/// What to do once the reply went out.
+enum Then {
+    Continue,
+    /// Run the self-test, appending its report to the reply.
+    SelfTest,
+    Reboot,
+    Bootsel,
+}
+
+fn saved(result: Result<(), SettingsError>, what: &'static str) -> &'static str {
+    match result {
+        Ok(()) => "stored, reboot to apply\r\n",
+        Err(SettingsError::Invalid) => what,
+        Err(SettingsError::Flash) => "failed to write the settings\r\n",
+    }
+}
+
+/// Store the buzzer settings and play a tone at the new volume.
+#[cfg(feature = "buzzer")]
+fn save_buzzer(buzzer: BuzzerSettings) -> &'static str {
+    match settings::save_buzzer(buzzer) {
+        Ok(()) => {
+            buzzer::play(Tone::Confirm);
+            "stored\r\n"
+        }
+        Err(SettingsError::Invalid) => "volume of 0 to 100 expected\r\n",
+        Err(SettingsError::Flash) => "failed to write the settings\r\n",
+    }
+}
+
+fn write_status(out: &mut String<MAX_REPLY_LEN>) {
+    let net = match link::net_health() {
+        NetHealth::Up => "up",
+        NetHealth::Outage => "outage",
+        NetHealth::HaUnreachable => "ha unreachable",
+    };
+    let ha = match websocket::connection() {
+        HaConnection::Disconnected => "disconnected",
+        HaConnection::Connected => "connected",
+        HaConnection::Authenticated => "authenticated",
+    };
+    uwrite!(
+        out,
+        "name {}\r\nfirmware {}\r\nuptime {} s\r\nnet {}\r\nhome assistant {}\r\n",
+        identity::hostname(),
+        env!("CARGO_PKG_VERSION"),
+        Instant::now().as_secs(),
+        net,
+        ha
+    )
+    .ok();
+    let led_errors = leds::output_errors();
+    if led_errors.failed_sends != 0 {
+        uwrite!(out, "led frames failed {}, spi re-inits {}\r\n", led_errors.failed_sends, led_errors.reinits).ok();
+    }
+    if safemode::active() {
+        out.push_str("safe mode after repeated crashes, reboot to try a normal boot\r\n").ok();
+    }
+}
+
+fn execute(line: &str, out: &mut String<MAX_REPLY_LEN>) -> Then {
+    // One more than any command takes, to reject extra arguments
+    let args: Vec<&str, 4> = Args(line).take(4).collect();
+    let reply = match args.as_slice() {
+        [] => "",
+        ["help"] => {
+            out.push_str(WIFI_HELP).ok();
+            out.push_str(BUZZER_HELP).ok();
+            HELP
+        }
+        ["status"] => {
+            write_status(out);
+            ""
+        }
+        ["tasks"] => {
+            instrument::write_summary(out);
+            ""
+        }
+        #[cfg(not(feature = "ethernet"))]
+        ["wifi", ssid, passphrase @ ..] if passphrase.len() <= 1 => saved(
+            settings::save_wifi_credentials(ssid, passphrase.first().copied().unwrap_or("")),
+            "SSID of 1 to 32 bytes and passphrase of 8 to 63 characters expected\r\n",
+        ),
+        ["selftest"] => {
+            out.push_str("running, this takes up to half a minute\r\n").ok();
+            return Then::SelfTest;
+        }
+        #[cfg(feature = "buzzer")]
+        ["buzzer"] => {
+            let buzzer = settings::buzzer();
+            uwrite!(out, "volume {}{}\r\n", buzzer.volume, if buzzer.muted { ", muted" } else { "" }).ok();
+            ""
+        }
+        #[cfg(feature = "buzzer")]
+        ["buzzer", "volume", volume] => match volume.parse() {
+            Ok(volume) => save_buzzer(BuzzerSettings { volume, ..settings::buzzer() }),
+            Err(_) => "volume of 0 to 100 expected\r\n",
+        },
+        #[cfg(feature = "buzzer")]
+        ["buzzer", "mute"] => save_buzzer(BuzzerSettings { muted: true, ..settings::buzzer() }),
+        #[cfg(feature = "buzzer")]
+        ["buzzer", "unmute"] => save_buzzer(BuzzerSettings { muted: false, ..settings::buzzer() }),
+        ["token", token] => saved(settings::save_ha_token(token), "not a Home Assistant token\r\n"),
+        ["reboot"] => {
+            out.push_str("rebooting\r\n").ok();
+            return Then::Reboot;
+        }
+        ["bootsel"] => {
+            out.push_str("rebooting into BOOTSEL\r\n").ok();
+            return Then::Bootsel;
+        }
+        ["factory-reset"] => {
+            if settings::erase().is_err() {
+                warn!("failed to erase settings");
+            }
+            out.push_str("settings erased, rebooting\r\n").ok();
+            return Then::Reboot;
+        }
+        _ => "unknown command or wrong arguments, try help\r\n",
+    };
+    out.push_str(reply).ok();
+    Then::Continue
+}
+
+/// Serial console over the USB port, for configuring a pad from a laptop without rebuilding
+/// the firmware, next to a DFU runtime interface for getting into BOOTSEL.
+#[embassy_executor::task]
+pub async fn console_task(p: ConsolePeripherals) -> ! {
+    static USB_STATE: StaticCell<UsbState> = StaticCell::new();
+    let usb_state: &'static mut dyn Handler = USB_STATE.init(UsbState::default());
+    let (mut usb, mut console) =
+        Console::<MAX_LINE_LEN>::new(p.usb, Irqs, "squishy console", identity::hostname(), Some(usb_state));
+
+    let session = async {
+        loop {
+            let mut reply = String::<MAX_REPLY_LEN>::new();
+            let then = execute(console.read_line().await, &mut reply);
+            if let Then::SelfTest = then {
+                console.write(reply.as_bytes()).await;
+                reply.clear();
+                selftest::run(&mut reply).await;
+            }
+            console.reply(reply.as_bytes()).await;
+            match then {
+                Then::Continue | Then::SelfTest => {}
+                Then::Reboot => {
+                    Timer::after(RESET_DELAY).await;
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+                Then::Bootsel => {
+                    Timer::after(RESET_DELAY).await;
+                    bootsel::reboot();
+                }
+            }
+        }
+    };
+    instrument::measured("console", join3(usb.run(), session, bootsel::run())).await.0
+}
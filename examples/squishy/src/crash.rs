@@ -0,0 +1,37 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+pub use board_support::crash::{Record, MAX_MESSAGE_LEN, STACK_WORDS};
+
+use crate::kept_log;
+
+/// Crash recorded before the last reset, until it reached Home Assistant.
+static PENDING: Mutex<CriticalSectionRawMutex, Cell<Option<Record>>> = Mutex::new(Cell::new(None));
+
+/// Move a crash recorded before the last reset aside for `pending`, at boot. Clears the record
+/// so a crash of this boot gets recorded even if the previous one was never reported.
+pub fn init() {
+    let Some(record) = board_support::crash::take() else {
+        return;
+    };
+    kept_log!(
+        Error,
+        "crashed before the last reset on core {}, pc {:08x} lr {:08x}: {}",
+        record.core,
+        record.pc,
+        record.lr,
+        record.message()
+    );
+    PENDING.lock(|cell| cell.set(Some(record)));
+}
+
+/// Crash waiting to be reported.
+pub fn pending() -> Option<Record> {
+    PENDING.lock(|cell| cell.get())
+}
+
+/// The pending crash was reported, forget it.
+pub fn reported() {
+    PENDING.lock(|cell| cell.set(None));
+}
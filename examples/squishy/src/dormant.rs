@@ -0,0 +1,55 @@
+use defmt::info;
+use embassy_rp::clocks;
+use embassy_rp::gpio::{DormantWakeConfig, Input};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+use crate::{console, leds, power};
+
+/// How often the pad checks whether it can go dormant.
+const IDLE_POLL: Duration = Duration::from_secs(1);
+
+static SLEEP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static WOKEN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn can_sleep() -> bool {
+    leds::asleep() && power::idle() && !console::host_attached()
+}
+
+/// Resolve once the pad can go dormant: its LEDs are asleep, Home Assistant has been quiet
+/// for a while and no USB host is attached. Never resolves without the `dormant` feature.
+pub async fn idle() {
+    if !cfg!(feature = "dormant") {
+        return core::future::pending().await;
+    }
+    while !can_sleep() {
+        Timer::after(IDLE_POLL).await;
+    }
+}
+
+/// Stop every clock until a button is pressed. The network has to be let go of first, nothing
+/// answers it while dormant and timers stand still.
+pub async fn sleep() {
+    info!("going dormant");
+    WOKEN.reset();
+    SLEEP.signal(());
+    WOKEN.wait().await;
+    info!("woken by a button");
+}
+
+/// Resolve once `sleep` asks the button task to go dormant.
+pub async fn requested() {
+    SLEEP.wait().await
+}
+
+/// Go dormant with the TCA9555 interrupt line as the wake source, from the task owning it.
+/// A press already pending wakes right away and is read as usual.
+#[cfg_attr(feature = "touch", allow(dead_code))]
+pub fn enter(button_int: &mut Input<'_>) {
+    {
+        let _wake = button_int.dormant_wake(DormantWakeConfig { level_low: true, ..Default::default() });
+        clocks::dormant_sleep();
+    }
+    WOKEN.signal(());
+}
@@ -0,0 +1,55 @@
+use defmt::{debug, info};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use squishy::quadrature::Decoder;
+
+use crate::command::CommandSender;
+use crate::define_peripheral_set;
+
+/// Detents turned within this long of the first go out as one command, so a quick spin doesn't
+/// flood the connection.
+const COALESCE_WINDOW: Duration = Duration::from_millis(150);
+
+/// Mechanical rotary encoder with its common pin to ground.
+#[macro_export]
+macro_rules! encoder_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            EncoderPeripherals,
+            a: PIN_6 => Input(Up),
+            b: PIN_7 => Input(Up),
+        }
+    };
+}
+
+encoder_peripherals!(define_peripheral_set);
+
+/// Decode the encoder on edges of either phase and send the detents turned as the
+/// `encoder` action in `device.toml`.
+#[embassy_executor::task]
+pub async fn encoder_task(mut sender: CommandSender, mut p: EncoderPeripherals) -> ! {
+    info!("set up encoder");
+    let mut decoder = Decoder::new(p.a.is_high(), p.b.is_high());
+    let mut detents = 0;
+    let mut send_at = Instant::MAX;
+    loop {
+        match select(select(p.a.wait_for_any_edge(), p.b.wait_for_any_edge()), Timer::at(send_at)).await {
+            Either::First(_) => {
+                detents += decoder.update(p.a.is_high(), p.b.is_high());
+                if detents != 0 && send_at == Instant::MAX {
+                    send_at = Instant::now() + COALESCE_WINDOW;
+                }
+            }
+            Either::Second(_) => {
+                debug!("encoder turned {} detents", detents);
+                // A full channel keeps the detents for the next try
+                if sender.on_encoder_turned(detents) {
+                    detents = 0;
+                    send_at = Instant::MAX;
+                } else {
+                    send_at = Instant::now() + COALESCE_WINDOW;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use defmt::Format;
+use embassy_net::tcp;
+use embassy_rp::i2c;
+
+/// Failures the tasks get past at run time, by retrying, carrying on without the part that
+/// failed, or rebooting, as each task loop sees fit.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Error {
+    /// An I2C transfer wasn't acknowledged or didn't finish, a glitch on the bus or a device that
+    /// isn't answering.
+    I2c(i2c::Error),
+    /// A connection couldn't be set up.
+    Connect(tcp::ConnectError),
+    /// An established connection failed while reading or writing.
+    Tcp(tcp::Error),
+    /// A message didn't fit its buffer.
+    Format,
+    /// The peer sent something that isn't valid HTTP or websocket framing, or text that isn't
+    /// UTF-8.
+    Protocol,
+    /// A write didn't finish in time, the peer stopped draining its window.
+    Timeout,
+}
+
+impl Error {
+    /// Short description for `kept_log!`, which can't take `Format` arguments.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::I2c(_) => "I2C transfer failed",
+            Error::Connect(_) => "connect failed",
+            Error::Tcp(_) => "connection failed",
+            Error::Format => "message too long",
+            Error::Protocol => "protocol error",
+            Error::Timeout => "write timed out",
+        }
+    }
+}
+
+impl From<i2c::Error> for Error {
+    fn from(err: i2c::Error) -> Self {
+        Error::I2c(err)
+    }
+}
+
+impl From<tcp::ConnectError> for Error {
+    fn from(err: tcp::ConnectError) -> Self {
+        Error::Connect(err)
+    }
+}
+
+impl From<tcp::Error> for Error {
+    fn from(err: tcp::Error) -> Self {
+        Error::Tcp(err)
+    }
+}
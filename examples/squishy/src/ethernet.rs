@@ -0,0 +1,145 @@
+use defmt::{info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::select;
+use embassy_net::driver::Driver;
+use embassy_net::Stack;
+use embassy_rp::gpio::Output;
+use embassy_rp::peripherals::SPI1;
+use embassy_rp::spi::{self, Spi};
+use embassy_time::{Delay, Duration, Timer};
+use embedded_hal_bus::spi::ExclusiveDevice;
+use crate::command::CommandSender;
+use crate::link::{self, NetHealth};
+use crate::transport::Transport;
+use crate::watchdog::{self, Subsystem};
+use crate::{define_peripheral_set, identity, instrument};
+#[cfg(feature = "ethernet-w5500")]
+use {
+    embassy_net_wiznet::chip::W5500,
+    embassy_rp::gpio::Input,
+    static_cell::StaticCell,
+};
+#[cfg(feature = "ethernet-enc28j60")]
+use embassy_net_enc28j60::{Enc28j60, Packet};
+
+#[cfg(all(feature = "ethernet-w5500", feature = "ethernet-enc28j60"))]
+compile_error!("enable only one of the ethernet-w5500 and ethernet-enc28j60 features");
+
+/// The module reports cable state through the driver, it is polled at this rate.
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "ethernet-w5500")]
+const SPI_FREQUENCY: u32 = 50_000_000;
+/// The ENC28J60 tops out at 20 MHz.
+#[cfg(feature = "ethernet-enc28j60")]
+const SPI_FREQUENCY: u32 = 20_000_000;
+
+/// SPI1, clear of the LED strip on SPI0 and the cyw43 pins.
+#[macro_export]
+macro_rules! net_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            NetPeripherals,
+            spi1: SPI1,
+            clk: PIN_10,
+            mosi: PIN_11,
+            miso: PIN_12,
+            cs: PIN_13 => Output(High),
+            #[cfg(feature = "ethernet-w5500")]
+            int: PIN_14 => Input(Up),
+            rst: PIN_15 => Output(High),
+            dma_tx: DMA_CH2,
+            dma_rx: DMA_CH3,
+        }
+    };
+}
+
+net_peripherals!(define_peripheral_set);
+
+#[cfg(feature = "ethernet-w5500")]
+type EthernetSpi = ExclusiveDevice<Spi<'static, SPI1, spi::Async>, Output<'static>, Delay>;
+#[cfg(feature = "ethernet-enc28j60")]
+type EthernetSpi = ExclusiveDevice<Spi<'static, SPI1, spi::Blocking>, Output<'static>, Delay>;
+
+#[cfg(feature = "ethernet-w5500")]
+pub type NetDevice = embassy_net_wiznet::Device<'static>;
+#[cfg(feature = "ethernet-enc28j60")]
+pub type NetDevice = Enc28j60<EthernetSpi, Output<'static>>;
+
+#[cfg(feature = "ethernet-w5500")]
+#[embassy_executor::task]
+async fn ethernet_task(runner: embassy_net_wiznet::Runner<'static, W5500, EthernetSpi, Input<'static>, Output<'static>>) -> ! {
+    instrument::measured("ethernet", runner.run()).await
+}
+
+/// Resolve once the cable is unplugged or the gateway stopped answering probes.
+async fn wait_lost<D: Driver>(stack: &Stack<D>) {
+    let link_down = async {
+        while stack.is_link_up() {
+            Timer::after(LINK_POLL_INTERVAL).await;
+        }
+        warn!("ethernet link down");
+        link::set_net_health(NetHealth::Outage);
+    };
+    select(link_down, link::probe_until_lost(stack)).await;
+}
+
+pub struct EthernetTransport;
+
+impl Transport for EthernetTransport {
+    async fn connect<D: Driver>(&mut self, stack: &Stack<D>) {
+        info!("waiting for ethernet link...");
+        while !stack.is_link_up() {
+            // A module that reports link state is answering on the bus
+            watchdog::check_in(Subsystem::Net);
+            Timer::after(LINK_POLL_INTERVAL).await;
+        }
+        watchdog::check_in(Subsystem::Net);
+    }
+
+    async fn add_multicast_address(&mut self, _mac: [u8; 6]) -> bool {
+        // The W5500 drops multicast while filtering on its own address in MACRAW mode, the
+        // ENC28J60 is set up to accept all of it
+        cfg!(feature = "ethernet-enc28j60")
+    }
+
+    async fn run_until_lost<D: Driver>(&mut self, stack: &Stack<D>, _sender: &mut CommandSender) {
+        wait_lost(stack).await
+    }
+
+    async fn reconnect<D: Driver>(&mut self, stack: &Stack<D>) {
+        self.connect(stack).await;
+        link::renew_lease(stack).await;
+    }
+
+    /// Nothing to let go of, the module keeps its link on its own clock.
+    async fn suspend(&mut self) {}
+}
+
+/// Bring up the Ethernet module and hand its network device to the stack.
+pub async fn init(#[allow(unused_variables)] spawner: Spawner, p: NetPeripherals) -> (NetDevice, EthernetTransport) {
+    let mac = identity::mac_address();
+    let mut spi_config = spi::Config::default();
+    spi_config.frequency = SPI_FREQUENCY;
+
+    #[cfg(feature = "ethernet-w5500")]
+    let device = {
+        let spi = Spi::new(p.spi1, p.clk, p.mosi, p.miso, p.dma_tx, p.dma_rx, spi_config);
+        static STATE: StaticCell<embassy_net_wiznet::State<8, 8>> = StaticCell::new();
+        let state = STATE.init(embassy_net_wiznet::State::new());
+        let spi = unwrap!(ExclusiveDevice::new(spi, p.cs, Delay));
+        let (device, runner) = unwrap!(embassy_net_wiznet::new(mac, state, spi, p.int, p.rst).await);
+        unwrap!(spawner.spawn(ethernet_task(runner)));
+        device
+    };
+    #[cfg(feature = "ethernet-enc28j60")]
+    let device = {
+        // Polled by the stack, no interrupt line or runner needed
+        let spi = Spi::new_blocking(p.spi1, p.clk, p.mosi, p.miso, spi_config);
+        let mut device = Enc28j60::new(unwrap!(ExclusiveDevice::new(spi, p.cs, Delay)), Some(p.rst), mac);
+        // The receive filter only passes unicast and broadcast by default, mDNS needs multicast
+        device.accept(&[Packet::Multicast]);
+        device
+    };
+
+    (device, EthernetTransport)
+}
@@ -0,0 +1,70 @@
+use edge_ws::FrameHeader;
+use heapless::Vec;
+
+/// Whether a frame's payload fits a buffer of `capacity` bytes, larger ones are discarded.
+pub fn payload_fits(header: &FrameHeader, capacity: usize) -> bool {
+    header.payload_len <= capacity as u64
+}
+
+/// Append the part of a frame's payload at the start of `bytes` to `payload`, unmasked,
+/// returning how many bytes were taken. `payload` holds the payload read so far.
+pub fn append_payload<const N: usize>(header: &FrameHeader, payload: &mut Vec<u8, N>, bytes: &[u8]) -> usize {
+    let read_size = usize::min(bytes.len(), header.payload_len as usize - payload.len());
+    let payload_buf_start = payload.len();
+    payload.extend_from_slice(&bytes[0..read_size]).unwrap();
+    header.mask(&mut payload[payload_buf_start..], payload_buf_start);
+    read_size
+}
+
+#[cfg(test)]
+mod tests {
+    use edge_ws::FrameType;
+    use super::*;
+
+    #[test]
+    fn server_text_frame_header() {
+        // FIN and text, then a 16-bit extended length of 300
+        let (header, len) = FrameHeader::deserialize(&[0x81, 126, 0x01, 0x2C]).unwrap();
+        assert_eq!(len, 4);
+        assert!(matches!(header.frame_type, FrameType::Text(false)));
+        assert_eq!(header.payload_len, 300);
+        assert!(header.mask_key.is_none());
+        assert!(payload_fits(&header, 300));
+        assert!(!payload_fits(&header, 299));
+    }
+
+    #[test]
+    fn truncated_frame_header_is_rejected() {
+        assert!(FrameHeader::deserialize(&[0x81]).is_err());
+        assert!(FrameHeader::deserialize(&[0x81, 126, 0x01]).is_err());
+    }
+
+    #[test]
+    fn payload_split_across_reads() {
+        let header = FrameHeader { frame_type: FrameType::Text(false), payload_len: 11, mask_key: None };
+        let mut payload = Vec::<u8, 16>::new();
+        assert_eq!(append_payload(&header, &mut payload, b"hello"), 5);
+        // The next frame's bytes stay in the socket
+        assert_eq!(append_payload(&header, &mut payload, b" world\x89\x00"), 6);
+        assert_eq!(payload.as_slice(), b"hello world");
+    }
+
+    #[test]
+    fn masked_payload_split_across_reads() {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let header = FrameHeader {
+            frame_type: FrameType::Text(false),
+            payload_len: 5,
+            mask_key: Some(u32::from_be_bytes(mask)),
+        };
+        let mut masked = *b"Hello";
+        for (i, byte) in masked.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        let mut payload = Vec::<u8, 16>::new();
+        for chunk in masked.chunks(2) {
+            assert_eq!(append_payload(&header, &mut payload, chunk), chunk.len());
+        }
+        assert_eq!(payload.as_slice(), b"Hello");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::keyframe::Color;
+
+/// How long a `squishy_accent` event without `seconds` shows its color.
+pub const DEFAULT_ACCENT_SECS: u32 = 30;
+
+/// Messages from the Home Assistant websocket API the pad acts on, found by substring
+/// matching rather than a JSON parser.
+pub enum Message<'a> {
+    AuthRequired,
+    AuthOk,
+    /// Effect of a light that changed, `None` when it was turned off.
+    StateChange { entity: &'a str, effect: Option<&'a str> },
+    /// Target temperature of a climate entity that changed, in tenths of a degree, `None` while
+    /// it has none such as when it's off.
+    Setpoint { entity: &'a str, tenths: Option<i32> },
+    /// New state of a weather or sun entity, for the ambient tint.
+    Outside { entity: &'a str, outside: Outside<'a> },
+    /// Outcome of the request sent with `id`.
+    Result { id: i32, success: bool },
+    /// A `squishy_accent` event, asking for `color` on the accent strip for `seconds`.
+    Accent { color: Color, seconds: u32 },
+    Other,
+}
+
+pub enum Outside<'a> {
+    /// State of a `weather.*` entity, such as `rainy`.
+    Condition(&'a str),
+    /// Elevation of a `sun.*` entity, in tenths of a degree.
+    Elevation(i32),
+}
+
+pub fn parse_message(message: &str) -> Message<'_> {
+    if message.starts_with(r#"{"type":"auth_required","#) {
+        Message::AuthRequired
+    } else if message.starts_with(r#"{"type":"auth_ok","#) {
+        Message::AuthOk
+    } else if let Some((color, seconds)) = parse_accent(message) {
+        Message::Accent { color, seconds }
+    } else if let Some((entity, tenths)) = parse_setpoint(message) {
+        Message::Setpoint { entity, tenths }
+    } else if let Some((entity, outside)) = parse_outside(message) {
+        Message::Outside { entity, outside }
+    } else if let Some((entity, effect)) = parse_state_change(message) {
+        Message::StateChange { entity, effect }
+    } else if let Some((id, success)) = parse_result(message) {
+        Message::Result { id, success }
+    } else {
+        Message::Other
+    }
+}
+
+/// Entity from an entity subscription's additions or a `state_changed` event's new state, and
+/// the rest of the message after it.
+fn changed_entity(message: &str) -> Option<(&str, &str)> {
+    let name_start = if let Some(start) = message.find(r#""a":{""#) {
+        start + 6
+    } else {
+        message.find(r#""new_state":{"entity_id":""#)? + 26
+    };
+    let name_end = name_start + message[name_start..].find('"')?;
+    Some((&message[name_start..name_end], &message[name_end..]))
+}
+
+/// Changed entity with the effect following it.
+fn parse_state_change(message: &str) -> Option<(&str, Option<&str>)> {
+    let (entity_name, rest) = changed_entity(message)?;
+    if let Some(effect_key_start) = rest.find(r#""effect":""#) {
+        let effect_start = effect_key_start + 10;
+        let effect_end = effect_start + rest[effect_start..].find('"')?;
+        Some((entity_name, Some(&rest[effect_start..effect_end])))
+    } else if rest.contains(r#""state":"off""#) {
+        Some((entity_name, None))
+    } else {
+        None
+    }
+}
+
+/// Changed climate entity with its `temperature` attribute, which isn't `current_temperature`
+/// thanks to the leading quote.
+fn parse_setpoint(message: &str) -> Option<(&str, Option<i32>)> {
+    let (entity_name, rest) = changed_entity(message)?;
+    if !entity_name.starts_with("climate.") {
+        return None;
+    }
+    let value_start = rest.find(r#""temperature":"#)? + 14;
+    let value = &rest[value_start..];
+    let value = &value[..value.find([',', '}'])?];
+    if value == "null" {
+        Some((entity_name, None))
+    } else {
+        Some((entity_name, Some(parse_tenths(value)?)))
+    }
+}
+
+/// Changed weather entity with its state, or sun entity with its `elevation` attribute. Entity
+/// subscriptions shorten the state to `s`.
+fn parse_outside(message: &str) -> Option<(&str, Outside<'_>)> {
+    let (entity_name, rest) = changed_entity(message)?;
+    if entity_name.starts_with("weather.") {
+        let state_start = match rest.find(r#""s":""#) {
+            Some(key_start) => key_start + 5,
+            None => rest.find(r#""state":""#)? + 9,
+        };
+        let state_end = state_start + rest[state_start..].find('"')?;
+        Some((entity_name, Outside::Condition(&rest[state_start..state_end])))
+    } else if entity_name.starts_with("sun.") {
+        let value_start = rest.find(r#""elevation":"#)? + 12;
+        let value = &rest[value_start..];
+        let value = &value[..value.find([',', '}'])?];
+        Some((entity_name, Outside::Elevation(parse_tenths(value)?)))
+    } else {
+        None
+    }
+}
+
+/// A decimal such as `21.5` in tenths, digits past the first decimal are dropped.
+pub fn parse_tenths(value: &str) -> Option<i32> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    if !digits(whole) || !digits(fraction) {
+        return None;
+    }
+    let tenths = whole.parse::<i32>().ok()?.checked_mul(10)? + (fraction.as_bytes()[0] - b'0') as i32;
+    Some(if negative { -tenths } else { tenths })
+}
+
+fn parse_result(message: &str) -> Option<(i32, bool)> {
+    let rest = message.strip_prefix(r#"{"id":"#)?;
+    let id_end = rest.find(',')?;
+    let id = rest[..id_end].parse().ok()?;
+    let success = rest[id_end..].strip_prefix(r#","type":"result","success":"#)?;
+    if success.starts_with("true") {
+        Some((id, true))
+    } else if success.starts_with("false") {
+        Some((id, false))
+    } else {
+        None
+    }
+}
+
+/// Color and duration of a `squishy_accent` event, its data being
+/// `{"color":[r,g,b],"seconds":n}` with `seconds` optional.
+fn parse_accent(message: &str) -> Option<(Color, u32)> {
+    let rest = &message[message.find(r#""event_type":"squishy_accent""#)?..];
+    let color_start = rest.find(r#""color":["#)? + 9;
+    let color_end = color_start + rest[color_start..].find(']')?;
+    let mut channels = rest[color_start..color_end].split(',').map(|channel| channel.trim().parse::<u8>().ok());
+    let (Some(Some(r)), Some(Some(g)), Some(Some(b)), None) =
+        (channels.next(), channels.next(), channels.next(), channels.next())
+    else {
+        return None;
+    };
+    let seconds = match rest[color_end..].find(r#""seconds":"#) {
+        Some(key_start) => {
+            let digits = &rest[color_end + key_start + 10..];
+            digits[..digits.find(|c: char| !c.is_ascii_digit())?].parse().ok()?
+        }
+        None => DEFAULT_ACCENT_SECS,
+    };
+    Some((Color { r, g, b }, seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_change(message: &str) -> Option<(&str, Option<&str>)> {
+        match parse_message(message) {
+            Message::StateChange { entity, effect } => Some((entity, effect)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn auth() {
+        assert!(matches!(
+            parse_message(r#"{"type":"auth_required","ha_version":"2024.8.0"}"#),
+            Message::AuthRequired
+        ));
+        assert!(matches!(parse_message(r#"{"type":"auth_ok","ha_version":"2024.8.0"}"#), Message::AuthOk));
+        assert!(matches!(
+            parse_message(r#"{"type":"auth_invalid","message":"Invalid access token"}"#),
+            Message::Other
+        ));
+    }
+
+    #[test]
+    fn subscribed_entity_state() {
+        let message = r#"{"id":2,"type":"event","event":{"a":{"light.desk":{"s":"on","a":{"effect":"Rainbow","brightness":255}}}}}"#;
+        assert_eq!(state_change(message), Some(("light.desk", Some("Rainbow"))));
+    }
+
+    #[test]
+    fn state_changed_event() {
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"light.desk","old_state":{"entity_id":"light.desk","state":"on","attributes":{"effect":"Fire"}},"new_state":{"entity_id":"light.desk","state":"on","attributes":{"effect":"Sunrise"}}}}}"#;
+        assert_eq!(state_change(message), Some(("light.desk", Some("Sunrise"))));
+    }
+
+    #[test]
+    fn turned_off() {
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"light.desk","new_state":{"entity_id":"light.desk","state":"off","attributes":{"effect":null}}}}}"#;
+        assert_eq!(state_change(message), Some(("light.desk", None)));
+    }
+
+    fn setpoint(message: &str) -> Option<(&str, Option<i32>)> {
+        match parse_message(message) {
+            Message::Setpoint { entity, tenths } => Some((entity, tenths)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn climate_setpoint() {
+        let message = r#"{"id":3,"type":"event","event":{"a":{"climate.hall":{"s":"heat","a":{"hvac_modes":["off","heat"],"current_temperature":19.4,"temperature":21.5}}}}}"#;
+        assert_eq!(setpoint(message), Some(("climate.hall", Some(215))));
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"climate.hall","old_state":{"entity_id":"climate.hall","state":"heat","attributes":{"temperature":21.5}},"new_state":{"entity_id":"climate.hall","state":"heat","attributes":{"current_temperature":19.5,"temperature":22}}}}}"#;
+        assert_eq!(setpoint(message), Some(("climate.hall", Some(220))));
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"climate.hall","new_state":{"entity_id":"climate.hall","state":"off","attributes":{"current_temperature":19.5,"temperature":null}}}}}"#;
+        assert_eq!(setpoint(message), Some(("climate.hall", None)));
+    }
+
+    fn outside(message: &str) -> Option<(&str, Result<&str, i32>)> {
+        match parse_message(message) {
+            Message::Outside { entity, outside: Outside::Condition(condition) } => Some((entity, Ok(condition))),
+            Message::Outside { entity, outside: Outside::Elevation(tenths) } => Some((entity, Err(tenths))),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn weather_and_sun() {
+        let message = r#"{"id":4,"type":"event","event":{"a":{"weather.home":{"s":"rainy","a":{"temperature":12.5,"humidity":88}}}}}"#;
+        assert_eq!(outside(message), Some(("weather.home", Ok("rainy"))));
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"weather.home","old_state":{"entity_id":"weather.home","state":"rainy"},"new_state":{"entity_id":"weather.home","state":"partlycloudy","attributes":{"temperature":14}}}}}"#;
+        assert_eq!(outside(message), Some(("weather.home", Ok("partlycloudy"))));
+        let message = r#"{"id":1,"type":"event","event":{"event_type":"state_changed","data":{"entity_id":"sun.sun","new_state":{"entity_id":"sun.sun","state":"below_horizon","attributes":{"next_dawn":"2024-08-11T03:58:40+00:00","elevation":-4.21,"azimuth":301.5}}}}}"#;
+        assert_eq!(outside(message), Some(("sun.sun", Err(-42))));
+    }
+
+    #[test]
+    fn tenths() {
+        assert_eq!(parse_tenths("21"), Some(210));
+        assert_eq!(parse_tenths("21.5"), Some(215));
+        assert_eq!(parse_tenths("21.25"), Some(212));
+        assert_eq!(parse_tenths("-3.5"), Some(-35));
+        assert_eq!(parse_tenths("off"), None);
+        assert_eq!(parse_tenths("21."), None);
+        assert_eq!(parse_tenths("--2"), None);
+        assert_eq!(parse_tenths(""), None);
+    }
+
+    #[test]
+    fn results() {
+        assert!(matches!(
+            parse_message(r#"{"id":3,"type":"result","success":true,"result":null}"#),
+            Message::Result { id: 3, success: true }
+        ));
+        assert!(matches!(
+            parse_message(
+                r#"{"id":12,"type":"result","success":false,"error":{"code":"not_found","message":"Service not found."}}"#
+            ),
+            Message::Result { id: 12, success: false }
+        ));
+    }
+
+    fn accent(message: &str) -> Option<([u8; 3], u32)> {
+        match parse_message(message) {
+            Message::Accent { color, seconds } => Some(([color.r, color.g, color.b], seconds)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn accent_event() {
+        let message = r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[255, 64, 0],"seconds":10},"origin":"REMOTE","time_fired":"2024-08-10T18:02:11.000000+00:00","context":{"id":"01J4","parent_id":null,"user_id":null}}}"#;
+        assert_eq!(accent(message), Some(([255, 64, 0], 10)));
+        let message = r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[0,0,255]},"origin":"LOCAL"}}"#;
+        assert_eq!(accent(message), Some(([0, 0, 255], DEFAULT_ACCENT_SECS)));
+    }
+
+    #[test]
+    fn malformed_accent_is_ignored() {
+        for message in [
+            r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[256,0,0]}}}"#,
+            r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[255,0]}}}"#,
+            r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[1,2,3,4]}}}"#,
+            r#"{"id":2,"type":"event","event":{"event_type":"squishy_accent","data":{"color":[1,2,3],"seconds":-5}}}"#,
+        ] {
+            assert!(accent(message).is_none());
+        }
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        assert!(matches!(parse_message(r#"{"id":3,"type":"pong"}"#), Message::Other));
+        assert!(matches!(parse_message(r#"{"id":x,"type":"result","success":true}"#), Message::Other));
+        assert!(matches!(parse_message(""), Message::Other));
+    }
+
+    #[test]
+    fn truncated_messages_are_ignored() {
+        assert!(matches!(parse_message(r#"{"id":2,"type":"event","event":{"a":{"light.de"#), Message::Other));
+        assert!(matches!(
+            parse_message(r#"{"id":2,"type":"event","event":{"a":{"light.desk":{"a":{"effect":"Rain"#),
+            Message::Other
+        ));
+        assert!(matches!(
+            parse_message(r#"{"data":{"new_state":{"entity_id":"light.desk","state":"on"}}}"#),
+            Message::Other
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Commands for Home Assistant, sent by the websocket task as service calls and events.
+
+#[derive(Copy, Clone)]
+pub struct HaCommandSetEffect {
+    pub entity_name: &'static str,
+    pub effect_name: &'static str,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandTurnOff {
+    pub entity_name: &'static str,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandPlayPause {
+    pub entity_name: &'static str,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandSetValue {
+    pub entity_name: &'static str,
+    pub value: i32,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandStepBrightness {
+    pub entity_name: &'static str,
+    pub percent: i32,
+}
+
+/// Most volume steps folded into one command for turns of the encoder that came in together, each
+/// step is a service call of its own. Turns past it wait in a command of their own.
+pub const MAX_VOLUME_STEPS: i32 = 10;
+
+#[derive(Copy, Clone)]
+pub struct HaCommandStepVolume {
+    pub entity_name: &'static str,
+    /// Volume steps of the media player, negative to turn it down.
+    pub steps: i32,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandClimateTempStep {
+    pub entity_name: &'static str,
+    /// Added to the last setpoint reported for the entity, in tenths of a degree.
+    pub tenths: i8,
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandNotify {
+    /// Of the `notify` domain, such as `mobile_app_<device>`.
+    pub service: &'static str,
+    /// Escaped for JSON already.
+    pub message: &'static str,
+}
+
+#[derive(Copy, Clone)]
+pub enum LockAction {
+    Lock,
+    Unlock,
+}
+
+impl LockAction {
+    /// Service of the `lock` domain.
+    pub fn service(&self) -> &'static str {
+        match self {
+            LockAction::Lock => "lock",
+            LockAction::Unlock => "unlock",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandLock {
+    pub entity_name: &'static str,
+    pub action: LockAction,
+}
+
+#[derive(Copy, Clone)]
+pub enum VacuumAction {
+    Start,
+    ReturnToBase,
+    Stop,
+}
+
+impl VacuumAction {
+    /// Service of the `vacuum` domain.
+    pub fn service(&self) -> &'static str {
+        match self {
+            VacuumAction::Start => "start",
+            VacuumAction::ReturnToBase => "return_to_base",
+            VacuumAction::Stop => "stop",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct HaCommandVacuum {
+    pub entity_name: &'static str,
+    pub action: VacuumAction,
+}
+
+#[derive(Copy, Clone)]
+pub enum HaCommand {
+    SetEffect(HaCommandSetEffect),
+    TurnOff(HaCommandTurnOff),
+    PlayPause(HaCommandPlayPause),
+    SetValue(HaCommandSetValue),
+    StepBrightness(HaCommandStepBrightness),
+    StepVolume(HaCommandStepVolume),
+    Lock(HaCommandLock),
+    Vacuum(HaCommandVacuum),
+    ClimateTempStep(HaCommandClimateTempStep),
+    Notify(HaCommandNotify),
+    /// The `squishy_motion` event, for the PIR sensor.
+    Motion,
+}
+
+impl HaCommand {
+    pub fn led_latch(&self) -> bool {
+        match self {
+            HaCommand::SetEffect(_) | HaCommand::TurnOff(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether someone at the pad asked for it, rather than a report going out in the background.
+    pub fn from_input(&self) -> bool {
+        !matches!(self, HaCommand::SetValue(_) | HaCommand::Motion)
+    }
+
+    /// Entity the command acts on, `None` for notifications and events.
+    pub fn entity_name(&self) -> Option<&'static str> {
+        match self {
+            HaCommand::SetEffect(cmd) => Some(cmd.entity_name),
+            HaCommand::TurnOff(cmd) => Some(cmd.entity_name),
+            HaCommand::PlayPause(cmd) => Some(cmd.entity_name),
+            HaCommand::SetValue(cmd) => Some(cmd.entity_name),
+            HaCommand::StepBrightness(cmd) => Some(cmd.entity_name),
+            HaCommand::StepVolume(cmd) => Some(cmd.entity_name),
+            HaCommand::Lock(cmd) => Some(cmd.entity_name),
+            HaCommand::Vacuum(cmd) => Some(cmd.entity_name),
+            HaCommand::ClimateTempStep(cmd) => Some(cmd.entity_name),
+            HaCommand::Notify(_) | HaCommand::Motion => None,
+        }
+    }
+
+    /// Fold `later` into this command where sending the result does the same as sending both,
+    /// returns whether it did. Only the last command still waiting for the same entity is
+    /// folded into, so commands for it keep their order.
+    pub fn coalesce(&mut self, later: &HaCommand) -> bool {
+        if self.led_latch() && later.led_latch() && self.entity_name() == later.entity_name() {
+            // Only the last effect or off counts
+            *self = *later;
+            return true;
+        }
+        match (self, later) {
+            (HaCommand::SetValue(this), HaCommand::SetValue(later)) if this.entity_name == later.entity_name => {
+                this.value = later.value;
+            }
+            (HaCommand::StepBrightness(this), HaCommand::StepBrightness(later))
+                if this.entity_name == later.entity_name =>
+            {
+                this.percent = (this.percent + later.percent).clamp(-100, 100);
+            }
+            (HaCommand::StepVolume(this), HaCommand::StepVolume(later))
+                if this.entity_name == later.entity_name
+                    && (this.steps + later.steps).abs() <= MAX_VOLUME_STEPS =>
+            {
+                this.steps += later.steps;
+            }
+            (HaCommand::ClimateTempStep(this), HaCommand::ClimateTempStep(later))
+                if this.entity_name == later.entity_name =>
+            {
+                this.tenths = this.tenths.saturating_add(later.tenths);
+            }
+            // One event stands for any number of motions
+            (HaCommand::Motion, HaCommand::Motion) => {}
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,114 @@
+//! Lines of the HTTP response header to the websocket upgrade, split as the bytes arrive in
+//! whatever chunks the socket hands out.
+
+use heapless::Vec;
+
+/// Longest header line kept, longer ones such as big `Set-Cookie`s are skipped over.
+pub const MAX_LINE_LEN: usize = 512;
+
+/// A line ended in a bare LF, had a CR in it or isn't UTF-8.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MalformedHeader;
+
+pub struct HeaderLines {
+    line: Vec<u8, MAX_LINE_LEN>,
+    /// The line so far didn't fit `line`, the rest of it is dropped.
+    overlong: bool,
+    /// The last byte was the CR of a line end.
+    cr: bool,
+}
+
+impl HeaderLines {
+    pub const fn new() -> Self {
+        Self { line: Vec::new(), overlong: false, cr: false }
+    }
+
+    /// Split `bytes` into lines, calling `f` with each one as it ends, `None` for lines longer
+    /// than `MAX_LINE_LEN`. Returns how many bytes were taken and whether the blank line ending
+    /// the header was among them, the bytes after it are left for the websocket.
+    pub fn feed(&mut self, bytes: &[u8], mut f: impl FnMut(Option<&str>)) -> Result<(usize, bool), MalformedHeader> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            match (byte, self.cr) {
+                (b'\n', true) => {
+                    self.cr = false;
+                    if self.overlong {
+                        f(None);
+                    } else if self.line.is_empty() {
+                        return Ok((i + 1, true));
+                    } else {
+                        f(Some(core::str::from_utf8(&self.line).map_err(|_| MalformedHeader)?));
+                    }
+                    self.line.clear();
+                    self.overlong = false;
+                }
+                (b'\r', false) => self.cr = true,
+                (b'\n', false) | (_, true) => return Err(MalformedHeader),
+                (_, false) => {
+                    if self.line.push(byte).is_err() {
+                        self.overlong = true;
+                    }
+                }
+            }
+        }
+        Ok((bytes.len(), false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `chunks` in turn until the header ends, returning the lines, `"<skipped>"` for the
+    /// overlong ones, and the bytes left over from the last chunk.
+    fn split<'a>(chunks: &[&'a [u8]]) -> Result<(std::vec::Vec<String>, &'a [u8]), MalformedHeader> {
+        let mut lines = HeaderLines::new();
+        let mut seen = std::vec::Vec::new();
+        for chunk in chunks {
+            let (taken, done) = lines.feed(chunk, |line| seen.push(line.unwrap_or("<skipped>").to_string()))?;
+            if done {
+                return Ok((seen, &chunk[taken..]));
+            }
+        }
+        Ok((seen, &[]))
+    }
+
+    #[test]
+    fn upgrade_response() {
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n\x81\x05";
+        let (lines, rest) = split(&[response]).unwrap();
+        assert_eq!(lines, ["HTTP/1.1 101 Switching Protocols", "Upgrade: websocket", "Connection: Upgrade"]);
+        // The first websocket frame stays in the socket
+        assert_eq!(rest, b"\x81\x05");
+    }
+
+    #[test]
+    fn lines_split_across_reads() {
+        // Including between the CR and the LF of a line end
+        let (lines, rest) = split(&[b"HTTP/1.1 101 Swi", b"tching Protocols\r", b"\nUpgrade: websocket\r\n\r", b"\n"]).unwrap();
+        assert_eq!(lines, ["HTTP/1.1 101 Switching Protocols", "Upgrade: websocket"]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn overlong_lines_are_skipped() {
+        let cookie = format!("Set-Cookie: session={}\r\n", "a".repeat(3000));
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\n{}{}Upgrade: websocket\r\n\r\n", cookie, cookie);
+        let (lines, _) = split(&[response.as_bytes()]).unwrap();
+        assert_eq!(lines, ["HTTP/1.1 101 Switching Protocols", "<skipped>", "<skipped>", "Upgrade: websocket"]);
+        // One byte at a time, and a line exactly as long as the buffer is still kept
+        let exact = format!("X: {}\r\n", "b".repeat(MAX_LINE_LEN - 3));
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\n{}{}\r\n", cookie, exact);
+        let chunks: std::vec::Vec<&[u8]> = response.as_bytes().chunks(1).collect();
+        let (lines, _) = split(&chunks).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "<skipped>");
+        assert_eq!(lines[2].len(), MAX_LINE_LEN);
+    }
+
+    #[test]
+    fn malformed_line_ends_are_rejected() {
+        assert_eq!(split(&[b"HTTP/1.1 101 Switching Protocols\nUpgrade: websocket\r\n\r\n"]), Err(MalformedHeader));
+        assert_eq!(split(&[b"HTTP/1.1 101\rSwitching Protocols\r\n\r\n"]), Err(MalformedHeader));
+        assert_eq!(split(&[b"HTTP/1.1 101 \xff\xfe\r\n\r\n"]), Err(MalformedHeader));
+    }
+}
@@ -0,0 +1,99 @@
+use defmt::warn;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use net_setup::http::{read_request, respond, CONTENT_HTML, CONTENT_JSON, CONTENT_TEXT, HTTP_PORT};
+use ufmt::uwrite;
+
+use crate::command::ENTITIES_TO_SUBSCRIBE;
+use crate::link::{self, NetHealth};
+use crate::logbuf::{self, LOG_BUFFER_LEN};
+use crate::websocket::{self, HaConnection};
+
+const MAX_REQUEST_LEN: usize = 512;
+const MAX_STATUS_LEN: usize = 256;
+
+const STATUS_PAGE: &str = "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+<title>squishy</title></head><body><h1>squishy</h1><pre id=\"s\"></pre><script>function u(){fetch('/status')\
+.then(r=>r.json()).then(s=>{document.getElementById('s').textContent=JSON.stringify(s,null,1)})}\
+u();setInterval(u,5000)</script></body></html>";
+
+/// Socket and request buffers, kept out of the connection future.
+pub struct HttpBuffers {
+    rx: [u8; MAX_REQUEST_LEN],
+    tx: [u8; MAX_REQUEST_LEN],
+    request: [u8; MAX_REQUEST_LEN],
+    log: [u8; LOG_BUFFER_LEN],
+}
+
+impl HttpBuffers {
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; MAX_REQUEST_LEN],
+            tx: [0; MAX_REQUEST_LEN],
+            request: [0; MAX_REQUEST_LEN],
+            log: [0; LOG_BUFFER_LEN],
+        }
+    }
+}
+
+/// Append `s` as a JSON string, dropping characters that would need escaping.
+fn push_json_str<const N: usize>(out: &mut String<N>, s: &str) {
+    out.push('"').ok();
+    for c in s.chars().filter(|c| *c != '"' && *c != '\\' && !c.is_control()) {
+        out.push(c).ok();
+    }
+    out.push('"').ok();
+}
+
+fn write_status(out: &mut String<MAX_STATUS_LEN>) {
+    let net = match link::net_health() {
+        NetHealth::Up => "up",
+        NetHealth::Outage => "outage",
+        NetHealth::HaUnreachable => "ha unreachable",
+    };
+    let ha = match websocket::connection() {
+        HaConnection::Disconnected => "disconnected",
+        HaConnection::Connected => "connected",
+        HaConnection::Authenticated => "authenticated",
+    };
+    uwrite!(out, "{{\"uptime\":{},\"net\":\"{}\",\"ha\":\"{}\",\"entities\":{{", Instant::now().as_secs(), net, ha).ok();
+    for (index, entity) in ENTITIES_TO_SUBSCRIBE.iter().enumerate() {
+        if index > 0 {
+            out.push(',').ok();
+        }
+        push_json_str(out, entity);
+        out.push(':').ok();
+        push_json_str(out, &websocket::entity_state(index));
+    }
+    out.push_str("}}").ok();
+}
+
+/// Serve the status page at `/`, its JSON at `/status` and the kept log at `/log` on
+/// `HTTP_PORT`, one connection at a time.
+pub async fn run<D: Driver>(stack: &Stack<D>, buffers: &mut HttpBuffers) -> ! {
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut buffers.rx, &mut buffers.tx);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if socket.accept(HTTP_PORT).await.is_err() {
+            warn!("HTTP accept failed");
+            continue;
+        }
+
+        let mut status = String::<MAX_STATUS_LEN>::new();
+        match read_request(&mut socket, &mut buffers.request).await {
+            Some((b"GET", b"/", _)) => respond(&mut socket, "200 OK", CONTENT_HTML, STATUS_PAGE.as_bytes()).await,
+            Some((b"GET", b"/status", _)) => {
+                write_status(&mut status);
+                respond(&mut socket, "200 OK", CONTENT_JSON, status.as_bytes()).await
+            }
+            Some((b"GET", b"/log", _)) => {
+                let (_, len, _) = logbuf::read(0, &mut buffers.log);
+                respond(&mut socket, "200 OK", CONTENT_TEXT, &buffers.log[..len]).await
+            }
+            _ => respond(&mut socket, "404 Not Found", CONTENT_TEXT, &[]).await,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use defmt::info;
+use embassy_sync::once_lock::OnceLock;
+use heapless::String;
+use ufmt::uwrite;
+use crate::consts;
+
+pub const MAX_HOSTNAME_LEN: usize = 32;
+
+#[cfg(feature = "ethernet")]
+static UNIQUE_ID: OnceLock<[u8; 8]> = OnceLock::new();
+static HOSTNAME: OnceLock<String<MAX_HOSTNAME_LEN>> = OnceLock::new();
+
+/// Settle the hostname at boot from the flash unique ID: `consts::HOSTNAME` if set, otherwise
+/// `<consts::HOSTNAME_PREFIX>-xxxx` with the end of the unique ID, so several pads on one network
+/// don't all share a name.
+pub fn init(id: [u8; 8]) {
+    #[cfg(feature = "ethernet")]
+    UNIQUE_ID.init(id).ok();
+
+    let mut hostname = String::new();
+    match consts::HOSTNAME {
+        Some(name) => {
+            hostname.push_str(name).ok();
+        }
+        None => {
+            uwrite!(hostname, "{}-{:02x}{:02x}", consts::HOSTNAME_PREFIX, id[6], id[7]).ok();
+        }
+    }
+    info!("hostname {}", hostname.as_str());
+    HOSTNAME.init(hostname).ok();
+}
+
+/// Sent in DHCP requests and answered for as `<hostname>.local`.
+pub fn hostname() -> &'static str {
+    HOSTNAME.try_get().map_or(consts::HOSTNAME_PREFIX, |hostname| hostname.as_str())
+}
+
+/// Locally administered MAC address from the unique ID, for interfaces without one of their own.
+#[cfg(feature = "ethernet")]
+pub fn mac_address() -> [u8; 6] {
+    let id = UNIQUE_ID.try_get().copied().unwrap_or_default();
+    [0x02, id[3], id[4], id[5], id[6], id[7]]
+}
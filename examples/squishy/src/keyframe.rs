@@ -0,0 +1,24 @@
+use animation::KeyframeColor;
+
+pub use animation::{Blend, Easing, Interpolation, KeyframeReader};
+
+#[derive(Copy, Clone)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl KeyframeColor for Color {
+    const BLACK: Self = Color { r: 0, g: 0, b: 0 };
+
+    fn to_rgbw(self) -> [u8; 4] {
+        [self.r, self.g, self.b, 0]
+    }
+
+    fn from_rgbw([r, g, b, _]: [u8; 4]) -> Self {
+        Color { r, g, b }
+    }
+}
+
+pub type Keyframe = animation::Keyframe<Color>;
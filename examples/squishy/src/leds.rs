@@ -1,15 +1,42 @@
-use defmt::{assert, info};
+use core::cell::Cell;
+use animation::LedDriver;
+use defmt::{assert, info, warn};
 use embassy_futures::select;
-use embassy_rp::{gpio, spi};
+use embassy_rp::{gpio, pac, spi};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::channel::{Channel, Receiver, Sender};
 use embassy_time::{Duration, Instant, Timer};
 
-use crate::command::{HaCommand, BUTTON_COMMANDS};
-use crate::{consts, define_peripheral_set};
+#[cfg(feature = "accent")]
+use crate::accent::Accent;
+use crate::apa102::Apa102;
+use crate::ambient;
+use crate::command::{HaCommand, AMBIENT, BUTTON_COMMANDS};
+use crate::instrument;
+use crate::keyframe::{Color, KeyframeReader};
+use crate::link::{self, NetHealth};
+use crate::safemode;
+use crate::selftest::{self, Outcome};
+use crate::supply;
+use crate::watchdog::{self, Subsystem};
+use crate::{consts, define_peripheral_set, kept_log};
 
 const LED_PERIOD: Duration = Duration::from_millis(20); // 50 Hz
 const SLEEP_TIMEOUT_PERIOD: Duration = Duration::from_secs(30);
+/// Half period of the red corner blink shown while the network is out.
+const NET_OUTAGE_BLINK: Duration = Duration::from_millis(500);
+/// Half period of the amber corner blink shown while the supply runs low.
+const LOW_SUPPLY_BLINK: Duration = Duration::from_millis(1000);
+/// Half period of the magenta checkerboard shown in safe mode.
+const SAFE_MODE_BLINK: Duration = Duration::from_millis(250);
+/// How often sleeping pads check for a network outage to show.
+const NET_HEALTH_POLL: Duration = Duration::from_millis(500);
+/// Frames timed out in a row before the SPI is re-initialized.
+const MAX_FAILED_SENDS: u32 = 5;
+/// How long each color of the self-test pattern stays up, well within the watchdog window.
+const SELF_TEST_STEP: Duration = Duration::from_millis(400);
+const SELF_TEST_COLORS: [(u8, u8, u8); 4] = [(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 255, 255)];
 
 #[macro_export]
 macro_rules! led_peripherals {
@@ -21,6 +48,12 @@ macro_rules! led_peripherals {
             cs: PIN_17,
             dma1: DMA_CH1,
             spi0: SPI0,
+            #[cfg(feature = "accent")]
+            pio1: PIO1,
+            #[cfg(feature = "accent")]
+            accent_dio: PIN_22,
+            #[cfg(feature = "accent")]
+            dma4: DMA_CH4,
         }
     };
 }
@@ -31,10 +64,16 @@ led_peripherals!(define_peripheral_set);
 pub enum LedCommand {
     SetButtonCheckedMask(u16),
     OrButtonCheckedMask(u16),
+    Wake,
+    /// Tint of the idle pads, `None` to show them as they are.
+    Ambient(Option<Color>),
+    #[cfg(feature = "accent")]
+    Accent(Color, Duration),
+    /// Pad and when its countdown started and runs out, `None` when it stopped.
+    #[cfg(feature = "timers")]
+    Countdown(usize, Option<(Instant, Instant)>),
 }
 
-unsafe impl Send for LedCommand {}
-
 pub type LedReceiver = Receiver<'static, CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>;
 
 pub struct LedSender(Sender<'static, CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>);
@@ -79,6 +118,28 @@ impl LedSender {
     pub fn on_button_pressed(&mut self, i: usize) {
         self.or_button_checked_mask(1 << i);
     }
+
+    /// Light the pads up again as if a button was pressed, without checking one.
+    pub fn wake(&mut self) {
+        self.0.try_send(LedCommand::Wake).ok();
+    }
+
+    /// Mix `tint` into the idle pads, from the weather or the sun outside.
+    pub fn set_ambient(&mut self, tint: Option<Color>) {
+        self.0.try_send(LedCommand::Ambient(tint)).ok();
+    }
+
+    /// Show `color` on the accent strip for `seconds`, waking the pads.
+    #[cfg(feature = "accent")]
+    pub fn show_accent(&mut self, color: Color, seconds: u32) {
+        self.0.try_send(LedCommand::Accent(color, Duration::from_secs(seconds as u64))).ok();
+    }
+
+    /// Dim pad `i` as its countdown runs out, `None` once it stopped.
+    #[cfg(feature = "timers")]
+    pub fn show_countdown(&mut self, i: usize, countdown: Option<(Instant, Instant)>) {
+        self.0.try_send(LedCommand::Countdown(i, countdown)).ok();
+    }
 }
 
 pub struct LedChannel(Channel<CriticalSectionRawMutex, LedCommand, CHANNEL_BUF_LEN>);
@@ -88,137 +149,55 @@ impl LedChannel {
         Self(Channel::new())
     }
 
-    pub fn sender(&'static mut self) -> LedSender {
+    pub fn sender(&'static self) -> LedSender {
         LedSender(self.0.sender())
     }
 
-    pub fn receiver(&'static mut self) -> LedReceiver {
+    pub fn receiver(&'static self) -> LedReceiver {
         self.0.receiver()
     }
 }
 
 const CHANNEL_BUF_LEN: usize = 64;
-pub(crate) static mut LED_CHANNEL: LedChannel = LedChannel::new();
+pub(crate) static LED_CHANNEL: LedChannel = LedChannel::new();
 
-struct SpiTx<'d, T: spi::Instance> {
-    spi: spi::Spi<'d, T, spi::Async>,
-    cs: gpio::Output<'d>,
-}
-
-impl<'d, T: spi::Instance> SpiTx<'d, T> {
-    pub fn new(spi: spi::Spi<'d, T, spi::Async>, cs: gpio::Output<'d>) -> Self {
-        Self { spi, cs }
-    }
+static ASLEEP: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
 
-    pub async fn send(&mut self, buffer: &[u8]) {
-        self.cs.set_low();
-        self.spi.write(&buffer).await.unwrap();
-        self.cs.set_high();
-    }
+/// Whether the pads went dark after `SLEEP_TIMEOUT_PERIOD` without a press.
+pub fn asleep() -> bool {
+    ASLEEP.lock(|cell| cell.get())
 }
 
-const WIDTH: usize = 4;
-const HEIGHT: usize = 4;
-const NUM_PADS: usize = WIDTH * HEIGHT;
-const NUM_BUF_BYTES: usize = (NUM_PADS * 4) + 8;
-
-#[derive(Copy, Clone)]
-pub struct Color {
-    pub(crate) r: u8,
-    pub(crate) g: u8,
-    pub(crate) b: u8,
+/// Failed LED frames and SPI re-inits since boot.
+#[derive(Copy, Clone, Default)]
+pub struct OutputErrors {
+    pub failed_sends: u32,
+    pub reinits: u32,
 }
 
-#[derive(Copy, Clone)]
-pub struct Keyframe {
-    pub(crate) frame: u32,
-    pub(crate) color: Color,
-}
+static OUTPUT_ERRORS: Mutex<CriticalSectionRawMutex, Cell<OutputErrors>> =
+    Mutex::new(Cell::new(OutputErrors { failed_sends: 0, reinits: 0 }));
 
-#[derive(Copy, Clone)]
-struct KeyframeReader {
-    keyframes: &'static [Keyframe],
-    last_frame: u32,
-    frame_a: u32,
-    frame_b: u32,
-    ib: usize,
+pub fn output_errors() -> OutputErrors {
+    OUTPUT_ERRORS.lock(|cell| cell.get())
 }
 
-impl Default for KeyframeReader {
-    fn default() -> Self {
-        static DEFAULT_KEYFRAMES: [Keyframe; 0] = [];
-        Self {
-            keyframes: &DEFAULT_KEYFRAMES,
-            last_frame: 0,
-            frame_a: 0,
-            frame_b: 0,
-            ib: 1,
-        }
-    }
+fn count_output_error(f: impl FnOnce(&mut OutputErrors)) {
+    OUTPUT_ERRORS.lock(|cell| {
+        let mut errors = cell.get();
+        f(&mut errors);
+        cell.set(errors);
+    });
 }
 
-impl KeyframeReader {
-    pub fn set_keyframes(&mut self, keyframes: &'static [Keyframe]) {
-        self.keyframes = keyframes;
-
-        self.last_frame = if let Some(kf) = keyframes.last() { kf.frame } else { 0 };
-
-        self.frame_a = if let Some(kf) = keyframes.get(0) { kf.frame } else { 0 };
-
-        self.frame_b = if let Some(kf) = keyframes.get(1) {
-            kf.frame
-        } else {
-            self.frame_a
-        };
-
-        self.ib = 1;
-    }
-
-    pub fn evaluate_color_at_frame(&mut self, frame: u64) -> Color {
-        if self.keyframes.is_empty() {
-            return Color { r: 0, g: 0, b: 0 };
-        } else if self.keyframes.len() == 1 {
-            return unsafe { self.keyframes.get_unchecked(0).color };
-        }
-
-        let mod_frame = (frame % self.last_frame as u64) as u32;
-        if mod_frame < self.frame_a {
-            self.ib = 1;
-            self.frame_a = self.keyframes[self.ib - 1].frame;
-            self.frame_b = self.keyframes[self.ib].frame;
-        }
-        if mod_frame >= self.frame_b {
-            self.ib += 1;
-            while self.keyframes[self.ib].frame < mod_frame {
-                self.ib += 1;
-            }
-            self.frame_a = self.keyframes[self.ib - 1].frame;
-            self.frame_b = self.keyframes[self.ib].frame;
-        }
-
-        let a = &self.keyframes[self.ib - 1];
-        let b = &self.keyframes[self.ib];
-        let seg_duration = b.frame - a.frame;
-        assert!(seg_duration > 0);
-        let seg_instant = mod_frame - a.frame;
-
-        let r = (b.color.r as u32 * seg_instant + a.color.r as u32 * (seg_duration - seg_instant)) / seg_duration;
-        let g = (b.color.g as u32 * seg_instant + a.color.g as u32 * (seg_duration - seg_instant)) / seg_duration;
-        let b = (b.color.b as u32 * seg_instant + a.color.b as u32 * (seg_duration - seg_instant)) / seg_duration;
-        //debug!("{} [{},{}]: ({} {} {})", mod_frame, self.ib - 1, self.ib, r, g, b);
-
-        Color {
-            r: r as u8,
-            g: g as u8,
-            b: b as u8,
-        }
-    }
-}
+const WIDTH: usize = consts::PAD_COLUMNS;
+const HEIGHT: usize = consts::PAD_ROWS;
+const NUM_PADS: usize = WIDTH * HEIGHT;
+const CORNER_PADS: [usize; 4] = [0, WIDTH - 1, NUM_PADS - WIDTH, NUM_PADS - 1];
 
-struct Leds<'d, T: spi::Instance> {
-    spi: SpiTx<'d, T>,
+struct Leds {
     keyframe_readers: [KeyframeReader; NUM_PADS],
-    buffer: [u8; NUM_BUF_BYTES],
+    frame: [Color; NUM_PADS],
     checked_mask: u16,
     latch_mask: u16,
     brightness_buffer: [u32; NUM_PADS],
@@ -226,27 +205,36 @@ struct Leds<'d, T: spi::Instance> {
     next_sleep_tick: Instant,
     sleep_pending: bool,
     sleeping: bool,
+    /// Sends failed in a row, the SPI is re-initialized after `MAX_FAILED_SENDS`.
+    failed_sends: u32,
+    /// Tint of `AMBIENT` mixed into the pads that aren't checked.
+    ambient: Option<Color>,
+    #[cfg(feature = "accent")]
+    accent: Accent,
+    /// When each pad's countdown started and runs out, it stays lit until then.
+    #[cfg(feature = "timers")]
+    countdowns: [Option<(Instant, Instant)>; NUM_PADS],
 }
 
 const BRIGHTNESS_INTERP_MUL: u32 = 1;
 const BRIGHTNESS_MAX: u32 = 31;
 const BRIGHTNESS_MIN: u32 = 1;
 
-impl<'d, T: spi::Instance> Leds<'d, T> {
-    pub fn new(spi: SpiTx<'d, T>) -> Self {
+impl Leds {
+    pub fn new(#[cfg(feature = "accent")] accent: Accent) -> Self {
         let mut keyframe_readers: [KeyframeReader; NUM_PADS] = [Default::default(); NUM_PADS];
         let mut latch_mask = 0;
         for i in 0..NUM_PADS {
             if let Some(button_cmd) = BUTTON_COMMANDS.get(i) {
-                keyframe_readers[i].set_keyframes(button_cmd.keyframes);
+                keyframe_readers[i].set_keyframes(button_cmd.keyframes, 0, button_cmd.keyframes.len());
+                keyframe_readers[i].set_interpolation(button_cmd.interpolation);
                 latch_mask |= if button_cmd.command.led_latch() { 1 << i } else { 0 };
             }
         }
 
         Self {
-            spi,
             keyframe_readers,
-            buffer: [0_u8; NUM_BUF_BYTES],
+            frame: [Color { r: 0, g: 0, b: 0 }; NUM_PADS],
             checked_mask: 0,
             latch_mask,
             brightness_buffer: [BRIGHTNESS_MAX * BRIGHTNESS_INTERP_MUL; NUM_PADS],
@@ -254,6 +242,12 @@ impl<'d, T: spi::Instance> Leds<'d, T> {
             next_sleep_tick: Instant::MAX,
             sleep_pending: false,
             sleeping: false,
+            failed_sends: 0,
+            ambient: None,
+            #[cfg(feature = "accent")]
+            accent,
+            #[cfg(feature = "timers")]
+            countdowns: [None; NUM_PADS],
         }
     }
 
@@ -266,15 +260,34 @@ impl<'d, T: spi::Instance> Leds<'d, T> {
                 self.checked_mask |= *mask;
                 self.touch_sleep_timer();
             }
+            LedCommand::Wake => {
+                self.touch_sleep_timer();
+            }
+            // Changes outside don't wake the pads
+            LedCommand::Ambient(tint) => {
+                self.ambient = *tint;
+            }
+            #[cfg(feature = "accent")]
+            LedCommand::Accent(color, duration) => {
+                self.accent.notify(*color, *duration);
+                self.touch_sleep_timer();
+            }
+            #[cfg(feature = "timers")]
+            LedCommand::Countdown(i, countdown) => {
+                if let Some(slot) = self.countdowns.get_mut(*i) {
+                    *slot = *countdown;
+                }
+                self.touch_sleep_timer();
+            }
         }
     }
 
+    /// Brightness is out of `BRIGHTNESS_MAX`, the driver turns it back into the LEDs' global
+    /// brightness where it can.
     pub fn set_led_value(&mut self, i: usize, brightness: u8, r: u8, g: u8, b: u8) {
-        assert!(brightness <= 31);
-        self.buffer[i * 4 + 4] = 0b11100000_u8 | brightness;
-        self.buffer[i * 4 + 5] = b;
-        self.buffer[i * 4 + 6] = g;
-        self.buffer[i * 4 + 7] = r;
+        assert!(brightness as u32 <= BRIGHTNESS_MAX);
+        let scale = |c: u8| (c as u32 * brightness as u32 / BRIGHTNESS_MAX) as u8;
+        self.frame[i] = Color { r: scale(r), g: scale(g), b: scale(b) };
     }
 
     pub fn touch_sleep_timer(&mut self) {
@@ -283,7 +296,18 @@ impl<'d, T: spi::Instance> Leds<'d, T> {
         self.sleeping = false;
     }
 
-    pub async fn tick(&mut self) -> bool {
+    async fn send(&mut self, driver: &mut impl LedDriver) {
+        match driver.write(&self.frame).await {
+            Ok(()) => self.failed_sends = 0,
+            Err(_) => {
+                warn!("LED frame timed out");
+                self.failed_sends += 1;
+                count_output_error(|errors| errors.failed_sends += 1);
+            }
+        }
+    }
+
+    pub async fn tick(&mut self, driver: &mut impl LedDriver) -> bool {
         let cur_period = Instant::now().as_ticks() / LED_PERIOD.as_ticks();
         let delta = if self.last_period != 0 {
             cur_period - self.last_period
@@ -303,9 +327,21 @@ impl<'d, T: spi::Instance> Leds<'d, T> {
                     .saturating_sub(delta)
                     .max(brightness_min * BRIGHTNESS_INTERP_MUL);
             }
+            #[cfg(feature = "timers")]
+            if let Some((start, end)) = self.countdowns[i] {
+                // From full down to the minimum as it runs out, also while the other pads sleep
+                let total = (end - start).as_millis().max(1);
+                let remaining = end.saturating_duration_since(Instant::now()).as_millis().min(total);
+                let brightness = BRIGHTNESS_MIN + ((BRIGHTNESS_MAX - BRIGHTNESS_MIN) as u64 * remaining / total) as u32;
+                self.brightness_buffer[i] = brightness * BRIGHTNESS_INTERP_MUL;
+            }
             all_brightness_bits |= self.brightness_buffer[i];
 
-            let color = self.keyframe_readers[i].evaluate_color_at_frame(cur_period * 10);
+            let keyframes = BUTTON_COMMANDS.get(i).map_or(&[][..], |button_cmd| button_cmd.keyframes);
+            let mut color = self.keyframe_readers[i].evaluate_color_at_frame(keyframes, cur_period * 10);
+            if let (false, Some(tint), Some(ambient)) = (checked, self.ambient, AMBIENT) {
+                color = ambient::blend(color, tint, ambient.strength);
+            }
             self.set_led_value(
                 i,
                 (self.brightness_buffer[i] / BRIGHTNESS_INTERP_MUL) as u8,
@@ -315,59 +351,163 @@ impl<'d, T: spi::Instance> Leds<'d, T> {
             );
         }
 
+        if supply::low() && !self.sleep_pending {
+            // Only while awake, waking the pads for it would drain the battery faster
+            let on = (Instant::now().as_millis() / LOW_SUPPLY_BLINK.as_millis()) % 2 == 0;
+            for i in CORNER_PADS {
+                self.set_led_value(i, if on { BRIGHTNESS_MIN as u8 } else { 0 }, 255, 96, 0);
+            }
+        }
+
+        if link::net_health() == NetHealth::Outage {
+            // Drawn over the button colors, and keeps the pads awake until the network is back
+            let on = (Instant::now().as_millis() / NET_OUTAGE_BLINK.as_millis()) % 2 == 0;
+            for i in CORNER_PADS {
+                self.set_led_value(i, if on { BRIGHTNESS_MAX as u8 } else { 0 }, 255, 0, 0);
+            }
+            all_brightness_bits |= 1;
+        }
+
+        if safemode::active() {
+            // Checkerboard in magenta swapping squares, for as long as safe mode lasts
+            let phase = (Instant::now().as_millis() / SAFE_MODE_BLINK.as_millis()) % 2;
+            for i in 0..NUM_PADS {
+                let on = (i % WIDTH + i / WIDTH) as u64 % 2 == phase;
+                self.set_led_value(i, if on { BRIGHTNESS_MAX as u8 } else { 0 }, 255, 0, 255);
+            }
+            all_brightness_bits |= 1;
+        }
+
         // Auto-clear according to latch mask after one update.
         self.checked_mask &= self.latch_mask;
 
-        self.spi.send(&self.buffer).await;
+        self.send(driver).await;
+
+        #[cfg(feature = "accent")]
+        {
+            // The strip fades out with the brightest pad, the checked effect pad when there is one
+            let level = self.brightness_buffer.iter().max().copied().unwrap_or(0) * 255
+                / (BRIGHTNESS_MAX * BRIGHTNESS_INTERP_MUL);
+            self.accent.send(self.checked_mask, level, cur_period * 10).await;
+            all_brightness_bits |= self.accent.notifying() as u32;
+        }
+
         all_brightness_bits != 0
     }
 
-    pub async fn run(&mut self, receiver: LedReceiver) -> ! {
+    /// Every pad in red, green, blue then white at full brightness, for checking by eye that each
+    /// LED and color channel works.
+    async fn self_test(&mut self, driver: &mut impl LedDriver) -> Outcome {
+        let mut sent = true;
+        for (r, g, b) in SELF_TEST_COLORS {
+            for i in 0..NUM_PADS {
+                self.set_led_value(i, BRIGHTNESS_MAX as u8, r, g, b);
+            }
+            self.send(driver).await;
+            sent &= self.failed_sends == 0;
+            Timer::after(SELF_TEST_STEP).await;
+        }
+        // Back to the current animation, at full brightness
         self.touch_sleep_timer();
-        loop {
+        self.brightness_buffer = [BRIGHTNESS_MAX * BRIGHTNESS_INTERP_MUL; NUM_PADS];
+        if sent {
+            Ok(())
+        } else {
+            Err("SPI transfers timed out")
+        }
+    }
+
+    /// Drive the pads through `driver` until it fails `MAX_FAILED_SENDS` times in a row.
+    pub async fn run(&mut self, driver: &mut impl LedDriver, receiver: LedReceiver) {
+        self.touch_sleep_timer();
+        self.failed_sends = 0;
+        while self.failed_sends < MAX_FAILED_SENDS {
+            watchdog::check_in(Subsystem::Leds);
+            ASLEEP.lock(|cell| cell.set(self.sleeping));
             if !self.sleeping {
                 let next_tick = (Instant::now().as_ticks() + LED_PERIOD.as_ticks() - 1) / LED_PERIOD.as_ticks()
                     * LED_PERIOD.as_ticks();
-                match select::select3(
+                match select::select4(
                     Timer::at(Instant::from_ticks(next_tick)),
                     Timer::at(self.next_sleep_tick),
                     receiver.receive(),
+                    selftest::LEDS.requested(),
                 )
                 .await
                 {
-                    select::Either3::First(_) => {
+                    select::Either4::First(_) => {
                         // Update timer has expired
-                        if !self.tick().await && self.sleep_pending {
+                        if !self.tick(driver).await && self.sleep_pending {
                             self.sleeping = true;
                         }
                     }
-                    select::Either3::Second(_) => {
+                    select::Either4::Second(_) => {
                         // Sleep timer has expired
                         self.sleep_pending = true;
                     }
-                    select::Either3::Third(command) => {
+                    select::Either4::Third(command) => {
                         // Led command
                         self.process_command(&command).await;
                     }
+                    select::Either4::Fourth(_) => {
+                        let outcome = self.self_test(driver).await;
+                        selftest::LEDS.report(outcome);
+                    }
                 }
             } else {
-                // Led command during sleep
-                let command = receiver.receive().await;
-                self.process_command(&command).await;
+                // Led command, network outage or self-test during sleep
+                match select::select3(
+                    receiver.receive(),
+                    Timer::after(NET_HEALTH_POLL),
+                    selftest::LEDS.requested(),
+                )
+                .await
+                {
+                    select::Either3::First(command) => {
+                        self.process_command(&command).await;
+                    }
+                    select::Either3::Second(_) => {
+                        if link::net_health() == NetHealth::Outage {
+                            self.sleeping = false;
+                        }
+                    }
+                    select::Either3::Third(_) => {
+                        let outcome = self.self_test(driver).await;
+                        selftest::LEDS.report(outcome);
+                    }
+                }
             }
         }
     }
 }
 
+/// Put the SPI block back to its power-on state, for a fresh driver to set up.
+fn reset_spi0() {
+    pac::RESETS.reset().modify(|w| w.set_spi0(true));
+    pac::RESETS.reset().modify(|w| w.set_spi0(false));
+    while !pac::RESETS.reset_done().read().spi0() {}
+}
+
 #[embassy_executor::task]
-pub async fn led_task(receiver: LedReceiver, p: LedPeripherals) -> ! {
+pub async fn led_task(receiver: LedReceiver, mut p: LedPeripherals) -> ! {
     info!("set up leds");
-    let spi_config = spi::Config::new(
-        4 * 1024 * 1024,
-        spi::Phase::CaptureOnFirstTransition,
-        spi::Polarity::IdleLow,
-    );
-    let spi = spi::Spi::new_txonly(p.spi0, p.clk, p.mosi, p.dma1, spi_config);
-    let cs = gpio::Output::new(p.cs, gpio::Level::High);
-    Leds::new(SpiTx::new(spi, cs)).run(receiver).await
+    #[cfg(not(feature = "accent"))]
+    let mut leds = Leds::new();
+    #[cfg(feature = "accent")]
+    let mut leds = Leds::new(Accent::new(p.pio1, p.accent_dio, p.dma4));
+    loop {
+        let spi_config = spi::Config::new(
+            4 * 1024 * 1024,
+            spi::Phase::CaptureOnFirstTransition,
+            spi::Polarity::IdleLow,
+        );
+        let spi = spi::Spi::new_txonly(&mut p.spi0, &mut p.clk, &mut p.mosi, &mut p.dma1, spi_config);
+        let cs = gpio::Output::new(&mut p.cs, gpio::Level::High);
+        let mut driver: Apa102<_, NUM_PADS> = Apa102::new(spi, cs);
+        instrument::measured("leds", leds.run(&mut driver, receiver)).await;
+
+        kept_log!(Warn, "LED frames keep timing out, re-initializing the SPI");
+        count_output_error(|errors| errors.reinits += 1);
+        reset_spi0();
+    }
 }
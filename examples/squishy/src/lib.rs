@@ -0,0 +1,16 @@
+//! Parts of squishy that don't touch the hardware, in a library of their own so they also
+//! build for the host. With the `std` feature
+//! `cargo test --lib --features std --target <host triple>` runs their tests, the target in
+//! `.cargo/config.toml` otherwise applies.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod ambient;
+pub mod capsense;
+pub mod frame;
+pub mod ha;
+pub mod ha_command;
+pub mod header;
+pub mod keyframe;
+pub mod outbox;
+pub mod quadrature;
+pub mod schedule;
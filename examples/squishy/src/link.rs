@@ -0,0 +1,126 @@
+use core::cell::Cell;
+use defmt::{info, unwrap, warn, Format};
+use embassy_net::driver::Driver;
+#[cfg(not(feature = "static-ip"))]
+use embassy_net::DhcpConfig;
+#[cfg(feature = "static-ip")]
+use embassy_net::{Ipv4Cidr, StaticConfigV4};
+use embassy_net::{ConfigV4, IpAddress, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use net_setup::link::{self, Probe, MAX_PROBE_FAILURES, PROBE_TIMEOUT};
+use net_setup::ping::{self, PingId};
+#[cfg(feature = "static-ip")]
+use crate::consts;
+#[cfg(not(feature = "static-ip"))]
+use crate::identity;
+use crate::kept_log;
+use crate::watchdog::{self, Subsystem};
+use crate::websocket::{self, HaConnection};
+
+const PING_ID: PingId = PingId { identifier: 0x5351, payload: *b"squishy\0" };
+
+/// Network state as seen by the link supervisor, shown on the corner pads during outages.
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum NetHealth {
+    Up,
+    /// The DHCP lease ran out or the gateway stopped answering, until the link is back.
+    Outage,
+    /// The gateway answers but the Home Assistant host doesn't answer pings while the websocket
+    /// is down, so the problem is on that host rather than the network.
+    HaUnreachable,
+}
+
+static NET_HEALTH: Mutex<CriticalSectionRawMutex, Cell<NetHealth>> = Mutex::new(Cell::new(NetHealth::Up));
+static HA_HOST: Mutex<CriticalSectionRawMutex, Cell<Option<Ipv4Address>>> = Mutex::new(Cell::new(None));
+
+pub fn net_health() -> NetHealth {
+    NET_HEALTH.lock(|cell| cell.get())
+}
+
+pub fn set_net_health(health: NetHealth) {
+    net_setup::status_led::set_fault(health != NetHealth::Up);
+    if NET_HEALTH.lock(|cell| cell.replace(health)) != health {
+        let health = match health {
+            NetHealth::Up => "up",
+            NetHealth::Outage => "outage",
+            NetHealth::HaUnreachable => "ha unreachable",
+        };
+        kept_log!(Info, "network {}", health);
+    }
+}
+
+/// Remember the resolved Home Assistant address for the link probes to ping.
+pub fn set_ha_host(address: IpAddress) {
+    // Only IPv4 is enabled
+    let IpAddress::Ipv4(address) = address;
+    HA_HOST.lock(|cell| cell.set(Some(address)));
+}
+
+#[cfg(not(feature = "static-ip"))]
+pub fn ipv4_config() -> ConfigV4 {
+    let mut dhcp_config: DhcpConfig = Default::default();
+    dhcp_config.hostname = Some(unwrap!(identity::hostname().try_into()));
+    ConfigV4::Dhcp(dhcp_config)
+}
+
+#[cfg(feature = "static-ip")]
+pub fn ipv4_config() -> ConfigV4 {
+    let ip = consts::STATIC_IP;
+    ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(ip.address, ip.prefix_len),
+        gateway: Some(ip.gateway),
+        dns_servers: unwrap!(heapless::Vec::from_slice(&[ip.dns])),
+    })
+}
+
+/// `HaUnreachable` if the websocket is down and the Home Assistant host doesn't answer a ping
+/// either, `Up` otherwise.
+async fn ha_health<D: Driver>(stack: &Stack<D>) -> NetHealth {
+    let Some(host) = HA_HOST.lock(|cell| cell.get()) else {
+        return NetHealth::Up;
+    };
+    // An open websocket shows the host is up without another packet on the air
+    if websocket::connection() != HaConnection::Disconnected {
+        return NetHealth::Up;
+    }
+    match ping::ping(stack, &PING_ID, host, PROBE_TIMEOUT).await {
+        Some(_) => NetHealth::Up,
+        None => {
+            warn!("Home Assistant host {} not answering", host);
+            NetHealth::HaUnreachable
+        }
+    }
+}
+
+/// Resolve once the DHCP lease ran out or the gateway stopped answering probes, for losses the
+/// network interface doesn't report itself.
+pub async fn probe_until_lost<D: Driver>(stack: &Stack<D>) {
+    link::probe_until_lost(stack, &PING_ID, |probe| async move {
+        match probe {
+            Probe::Reachable => {
+                watchdog::check_in(Subsystem::Net);
+                set_net_health(ha_health(stack).await);
+            }
+            Probe::Unanswered { gateway, failures } => {
+                set_net_health(NetHealth::Outage);
+                warn!("gateway {} not answering ({}/{})", gateway, failures, MAX_PROBE_FAILURES);
+            }
+            Probe::LeaseLost => {
+                kept_log!(Warn, "DHCP lease lost");
+                set_net_health(NetHealth::Outage);
+            }
+        }
+    })
+    .await
+}
+
+/// Restart DHCP once the link is back and wait for the lease, the old one may not have survived
+/// the outage.
+pub async fn renew_lease<D: Driver>(stack: &Stack<D>) {
+    stack.set_config_v4(ipv4_config());
+    info!("waiting for network config...");
+    stack.wait_config_up().await;
+    info!("network config is up again");
+    set_net_health(NetHealth::Up);
+}
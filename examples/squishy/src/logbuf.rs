@@ -0,0 +1,42 @@
+use defmt::{error, info, warn};
+
+pub use board_support::logbuf::{append, read, LOG_BUFFER_LEN};
+
+pub const MAX_MESSAGE_LEN: usize = 96;
+
+/// Log through defmt and keep the line for `/log`. Arguments are formatted with ufmt, so they
+/// need `uDisplay` rather than `Format`.
+#[macro_export]
+macro_rules! kept_log {
+    ($level:ident, $($arg:tt)*) => {{
+        let mut message = heapless::String::<{ $crate::logbuf::MAX_MESSAGE_LEN }>::new();
+        // A message too long for the buffer is kept truncated
+        ufmt::uwrite!(message, $($arg)*).ok();
+        $crate::logbuf::log($crate::logbuf::Level::$level, &message);
+    }};
+}
+
+#[derive(Copy, Clone)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+pub fn log(level: Level, message: &str) {
+    let tag = match level {
+        Level::Error => {
+            error!("{=str}", message);
+            'E'
+        }
+        Level::Warn => {
+            warn!("{=str}", message);
+            'W'
+        }
+        Level::Info => {
+            info!("{=str}", message);
+            'I'
+        }
+    };
+    append(tag, message);
+}
@@ -1,106 +1,104 @@
 #![no_std]
 #![no_main]
 
+#[cfg(feature = "accent")]
+mod accent;
+mod apa102;
 mod buttons;
+#[cfg(feature = "buzzer")]
+mod buzzer;
+#[cfg(feature = "timers")]
+mod clock;
 mod command;
+mod console;
 mod consts;
+mod crash;
+mod dormant;
+#[cfg(feature = "encoder")]
+mod encoder;
+mod error;
+#[cfg(feature = "ethernet")]
+mod ethernet;
+mod http;
+mod identity;
 mod leds;
+mod link;
+mod logbuf;
+#[cfg(feature = "ota")]
+mod ota;
 mod peripheral_macros;
+#[cfg(feature = "pir")]
+mod pir;
+mod power;
+mod resolver;
+mod safemode;
+mod selftest;
+mod settings;
+#[cfg(not(feature = "ethernet"))]
+mod supply;
+#[cfg(not(feature = "touch"))]
 mod tca9555;
+mod telemetry;
+#[cfg(feature = "timers")]
+mod timers;
+#[cfg(feature = "touch")]
+mod touch;
+mod transport;
+mod watchdog;
 mod websocket;
+#[cfg(not(feature = "ethernet"))]
+mod wifi;
 
 use crate::leds::LedSender;
+use board_support::instrument;
 use buttons::{button_task, ButtonPeripherals};
 use consts::HA_CONSTS;
-use cyw43_pio::PioSpi;
-use defmt::{debug, info, unwrap};
+use defmt::{debug, info, unwrap, warn};
 use embassy_executor::{Executor, Spawner};
-use embassy_net::dns::DnsQueryType;
+use embassy_futures::select::{select3, select4, Either3};
 use embassy_net::tcp::TcpSocket;
-use embassy_net::{Config, DhcpConfig, IpEndpoint, Stack, StackResources};
-use embassy_rp::gpio::{Level, Output};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Config, Stack, StackResources};
 use embassy_rp::multicore;
 use embassy_rp::multicore::spawn_core1;
-use embassy_rp::peripherals::{DMA_CH0, I2C0, PIO0};
-use embassy_rp::{bind_interrupts, i2c, pio};
+use embassy_rp::peripherals::{I2C0, PIO0, PIO1, USB};
+use embassy_rp::{adc, bind_interrupts, i2c, pio, usb};
 use embassy_time::Timer;
+use net_setup::mdns;
 use leds::{led_task, LedPeripherals};
+use squishy::{ambient, frame, ha, header, keyframe, outbox};
 use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
-
-const WIFI_SSID: &str = include_str!("../wifi_ssid.txt");
-const WIFI_PSK: &[u8; 32] = include_bytes!("../wifi_psk.bin");
+use telemetry::{telemetry_task, TelemetryPeripherals};
+use transport::{NetDevice, NetPeripherals, Transport};
+use watchdog::{watchdog_task, Subsystem, WatchdogPeripherals};
+use defmt_rtt as _;
 
 bind_interrupts!(struct Irqs {
+    ADC_IRQ_FIFO => adc::InterruptHandler;
     I2C0_IRQ => i2c::InterruptHandler<I2C0>;
     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+    // Only used by the accent strip, bound either way as bind_interrupts! takes no cfg
+    PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
 });
 
 #[embassy_executor::task]
-async fn wifi_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
-    runner.run().await
+async fn net_task(stack: &'static Stack<NetDevice>) -> ! {
+    instrument::measured("net", stack.run()).await
 }
 
-#[embassy_executor::task]
-async fn net_task(stack: &'static Stack<cyw43::NetDriver<'static>>) -> ! {
-    stack.run().await
-}
-
-macro_rules! wifi_peripherals {
-    ($macro_name:ident $(,$arg:tt)*) => {
-        $macro_name!{$($arg,)*
-            WifiPeripherals,
-            pwr: PIN_23,
-            cs: PIN_25,
-            pio: PIO0,
-            dio: PIN_24,
-            clk: PIN_29,
-            dma0: DMA_CH0,
-        }
-    };
-}
-
-wifi_peripherals!(define_peripheral_set);
-
 #[embassy_executor::task]
 async fn core0_task(
     spawner: Spawner,
-    wifi_peripherals: WifiPeripherals,
+    net_peripherals: NetPeripherals,
     button_peripherals: ButtonPeripherals,
+    watchdog_peripherals: WatchdogPeripherals,
+    telemetry_peripherals: TelemetryPeripherals,
     mut led_sender: LedSender,
 ) {
-    let fw = include_bytes!("../../../cyw43-firmware/43439A0.bin");
-    let clm = include_bytes!("../../../cyw43-firmware/43439A0_clm.bin");
-
-    info!("set up wifi peripherals");
-    let pwr = Output::new(wifi_peripherals.pwr, Level::Low);
-    let cs = Output::new(wifi_peripherals.cs, Level::High);
-    let mut pio = pio::Pio::new(wifi_peripherals.pio, Irqs);
-    let spi = PioSpi::new(
-        &mut pio.common,
-        pio.sm0,
-        pio.irq0,
-        cs,
-        wifi_peripherals.dio,
-        wifi_peripherals.clk,
-        wifi_peripherals.dma0,
-    );
-
-    info!("set up cyw43");
-    static STATE: StaticCell<cyw43::State> = StaticCell::new();
-    let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-    unwrap!(spawner.spawn(wifi_task(runner)));
-
-    info!("init cyw43");
-    control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
-
-    let mut dhcp_config: DhcpConfig = Default::default();
-    dhcp_config.hostname = Some(unwrap!("squishy".try_into()));
-    let config = Config::dhcpv4(dhcp_config);
+    let (net_device, mut transport) = transport::init(spawner, net_peripherals).await;
+
+    let config = Config { ipv4: link::ipv4_config(), ..Default::default() };
 
     // Generate random seed
     let seed = {
@@ -110,37 +108,39 @@ async fn core0_task(
     debug!("rand seed {}", seed);
 
     // Init network stack
-    static STACK: StaticCell<Stack<cyw43::NetDriver<'static>>> = StaticCell::new();
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    static STACK: StaticCell<Stack<NetDevice>> = StaticCell::new();
+    // One more for the SNTP socket of the timers
+    const SOCKETS: usize = if cfg!(feature = "timers") { 7 } else { 6 };
+    static RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
     let stack = &*STACK.init(Stack::new(
         net_device,
         config,
-        RESOURCES.init(StackResources::<3>::new()),
+        RESOURCES.init(StackResources::<SOCKETS>::new()),
         seed,
     ));
 
     info!("set up net");
     unwrap!(spawner.spawn(net_task(stack)));
+    unwrap!(spawner.spawn(watchdog_task(watchdog_peripherals)));
 
-    loop {
-        //control.join_open(WIFI_NETWORK).await;
-        match control.join_wpa2_psk(WIFI_SSID, WIFI_PSK).await {
-            Ok(_) => break,
-            Err(err) => {
-                info!("join failed with status={}", err.status);
-            }
-        }
-    }
+    transport.connect(stack).await;
 
-    // Wait for DHCP, not necessary when using static IP
-    info!("waiting for DHCP...");
+    // Returns right away with a static address
+    info!("waiting for network config...");
     stack.wait_config_up().await;
-    info!("DHCP is now up!");
+    info!("network config is now up!");
 
-    let command_sender = unsafe { command::COMMAND_CHANNEL.sender() };
-    let mut command_receiver = unsafe { command::COMMAND_CHANNEL.receiver() };
+    let mut command_sender = command::COMMAND_CHANNEL.sender();
+    let mut quality_sender = command_sender.clone();
+    let telemetry_sender = command_sender.clone();
+    let mut command_receiver = command::COMMAND_CHANNEL.receiver();
 
     unwrap!(spawner.spawn(button_task(command_sender, led_sender.clone(), button_peripherals)));
+    unwrap!(spawner.spawn(telemetry_task(telemetry_sender, telemetry_peripherals)));
+    #[cfg(feature = "timers")]
+    if !consts::TIMERS.at.is_empty() {
+        unwrap!(spawner.spawn(clock::sntp_task(stack)));
+    }
 
     static RX_BUFFER: StaticCell<[u8; 4096]> = StaticCell::new();
     let rx_buffer = RX_BUFFER.init([0; 4096]);
@@ -148,43 +148,157 @@ async fn core0_task(
     let tx_buffer = TX_BUFFER.init([0; 4096]);
     static PAYLOAD_BUFFER: StaticCell<heapless::Vec<u8, 4096>> = StaticCell::new();
     let payload_buffer = PAYLOAD_BUFFER.init(heapless::Vec::new());
+    static HTTP_BUFFERS: StaticCell<http::HttpBuffers> = StaticCell::new();
+    let http_buffers = HTTP_BUFFERS.init(http::HttpBuffers::new());
 
+    let mut mdns_socket = {
+        static RX_META: StaticCell<[PacketMetadata; 4]> = StaticCell::new();
+        let rx_meta = RX_META.init([PacketMetadata::EMPTY; 4]);
+        static RX_BUFFER: StaticCell<[u8; 512]> = StaticCell::new();
+        let rx_buffer = RX_BUFFER.init([0; 512]);
+        static TX_META: StaticCell<[PacketMetadata; 2]> = StaticCell::new();
+        let tx_meta = TX_META.init([PacketMetadata::EMPTY; 2]);
+        static TX_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+        let tx_buffer = TX_BUFFER.init([0; 256]);
+
+        UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+    };
+    unwrap!(mdns_socket.bind(mdns::MDNS_PORT));
+    if !transport.add_multicast_address(mdns::MDNS_MAC).await
+        || stack.join_multicast_group(mdns::MDNS_GROUP).await.is_err()
+    {
+        warn!("failed to join mDNS group");
+    }
+
+    let mut ha_resolver = resolver::Resolver::new(HA_CONSTS.domain, HA_CONSTS.port);
+    // Outlives the connections, so what is still waiting goes out on the next one
+    let mut outbox = outbox::Outbox::new();
     loop {
-        if let Ok(dns_result) = stack.dns_query(HA_CONSTS.domain, DnsQueryType::A).await {
-            if !dns_result.is_empty() {
-                let socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
-                let mut websocket =
-                    websocket::Websocket::new(socket, payload_buffer, &mut command_receiver, &mut led_sender);
-                let endpoint = IpEndpoint::new(dns_result[0], HA_CONSTS.port);
-                websocket.run(endpoint, HA_CONSTS.domain).await;
+        watchdog::check_in(Subsystem::Websocket);
+        let connection = async {
+            if let Some(endpoint) = ha_resolver.endpoint(stack).await {
+                link::set_ha_host(endpoint.addr);
+                let socket = TcpSocket::new(stack, &mut *rx_buffer, &mut *tx_buffer);
+                let mut websocket = websocket::Websocket::new(
+                    socket,
+                    &mut *payload_buffer,
+                    &mut outbox,
+                    &mut command_receiver,
+                    &mut led_sender,
+                );
+                if websocket.run(endpoint, HA_CONSTS.domain).await {
+                    ha_resolver.on_connected();
+                } else {
+                    debug!("failed to connect to {}", endpoint);
+                    ha_resolver.on_connect_failed();
+                }
             }
-        }
+        };
 
-        const WAIT_SECS: u64 = 5;
-        debug!("connection dropped, waiting {} seconds", WAIT_SECS);
-        Timer::after_secs(WAIT_SECS).await;
+        // Losing the link drops the websocket mid-connection, it reconnects once the link is back
+        // The responder announces again after every reconnect
+        let services = select4(
+            connection,
+            mdns::run(&mut mdns_socket, stack, identity::hostname(), None),
+            http::run(stack, &mut *http_buffers),
+            selftest::serve_network(stack),
+        );
+        let services = select3(services, transport.run_until_lost(stack, &mut quality_sender), dormant::idle());
+        match instrument::measured("services", services).await {
+            Either3::First(_) => {
+                const WAIT_SECS: u64 = 5;
+                debug!("connection dropped, waiting {} seconds", WAIT_SECS);
+                Timer::after_secs(WAIT_SECS).await;
+            }
+            Either3::Second(_) => transport.reconnect(stack).await,
+            Either3::Third(_) => {
+                transport.suspend().await;
+                dormant::sleep().await;
+                transport.reconnect(stack).await;
+            }
+        }
     }
 }
 
+// Fails the build naming any peripheral two of the sets taken in main both claim
+const _: () = {
+    led_peripherals!(claim_peripheral_set);
+    button_peripherals!(claim_peripheral_set);
+    net_peripherals!(claim_peripheral_set);
+    watchdog_peripherals!(claim_peripheral_set);
+    console_peripherals!(claim_peripheral_set);
+    telemetry_peripherals!(claim_peripheral_set);
+    settings_peripherals!(claim_peripheral_set);
+    #[cfg(feature = "encoder")]
+    encoder_peripherals!(claim_peripheral_set);
+    #[cfg(feature = "pir")]
+    pir_peripherals!(claim_peripheral_set);
+    #[cfg(feature = "buzzer")]
+    buzzer_peripherals!(claim_peripheral_set);
+};
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let p = embassy_rp::init(Default::default());
+    crash::init();
+    safemode::init();
 
     let led_peripherals = led_peripherals!(take_peripheral_set, p);
     let button_peripherals = button_peripherals!(take_peripheral_set, p);
-    let wifi_peripherals = wifi_peripherals!(take_peripheral_set, p);
+    let net_peripherals = net_peripherals!(take_peripheral_set, p);
+    let watchdog_peripherals = watchdog_peripherals!(take_peripheral_set, p);
+    let console_peripherals = console_peripherals!(take_peripheral_set, p);
+    let telemetry_peripherals = telemetry_peripherals!(take_peripheral_set, p);
+    settings::init(settings_peripherals!(take_peripheral_set, p));
+    #[cfg(feature = "encoder")]
+    let encoder_peripherals = encoder_peripherals!(take_peripheral_set, p);
+    #[cfg(feature = "pir")]
+    let pir_peripherals = pir_peripherals!(take_peripheral_set, p);
+    #[cfg(feature = "buzzer")]
+    let buzzer_peripherals = buzzer_peripherals!(take_peripheral_set, p);
 
     static mut CORE1_STACK: multicore::Stack<4096> = multicore::Stack::new();
     spawn_core1(p.CORE1, unsafe { &mut CORE1_STACK }, move || {
         static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
         let executor1 = EXECUTOR1.init(Executor::new());
-        let led_receiver = unsafe { leds::LED_CHANNEL.receiver() };
+        let led_receiver = leds::LED_CHANNEL.receiver();
         executor1.run(|spawner| unwrap!(spawner.spawn(led_task(led_receiver, led_peripherals))));
     });
 
     static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
     let executor0 = EXECUTOR0.init(Executor::new());
-    let led_sender = unsafe { leds::LED_CHANNEL.sender() };
-    executor0
-        .run(|spawner| unwrap!(spawner.spawn(core0_task(spawner, wifi_peripherals, button_peripherals, led_sender))));
+    let led_sender = leds::LED_CHANNEL.sender();
+    executor0.run(|spawner| {
+        #[cfg(feature = "ota")]
+        unwrap!(spawner.spawn(ota::ota_task()));
+        unwrap!(spawner.spawn(console::console_task(console_peripherals)));
+        unwrap!(spawner.spawn(instrument::instrument_task()));
+        // Only the console and the rollback of a freshly updated image, which is never confirmed
+        if safemode::active() {
+            return;
+        }
+        unwrap!(spawner.spawn(safemode::stable_task()));
+        #[cfg(not(feature = "ethernet"))]
+        unwrap!(spawner.spawn(net_setup::status_led::status_led_task()));
+        #[cfg(feature = "encoder")]
+        unwrap!(spawner.spawn(encoder::encoder_task(command::COMMAND_CHANNEL.sender(), encoder_peripherals)));
+        #[cfg(feature = "pir")]
+        unwrap!(spawner.spawn(pir::pir_task(
+            command::COMMAND_CHANNEL.sender(),
+            leds::LED_CHANNEL.sender(),
+            pir_peripherals,
+        )));
+        #[cfg(feature = "buzzer")]
+        unwrap!(spawner.spawn(buzzer::buzzer_task(buzzer_peripherals)));
+        #[cfg(feature = "timers")]
+        unwrap!(spawner.spawn(timers::timer_task(command::COMMAND_CHANNEL.sender(), leds::LED_CHANNEL.sender())));
+        unwrap!(spawner.spawn(core0_task(
+            spawner,
+            net_peripherals,
+            button_peripherals,
+            watchdog_peripherals,
+            telemetry_peripherals,
+            led_sender,
+        )))
+    });
 }
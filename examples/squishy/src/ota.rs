@@ -0,0 +1,68 @@
+use core::cell::RefCell;
+use defmt::{info, warn};
+use embassy_boot_rp::{AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_rp::flash::WRITE_SIZE;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use crate::kept_log;
+use crate::settings::{self, SettingsFlash};
+use crate::websocket::{self, HaConnection};
+
+/// A freshly swapped in image has this long to authenticate with Home Assistant, otherwise the
+/// device resets and the bootloader swaps the previous image back.
+const BOOT_WINDOW: Duration = Duration::from_secs(180);
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Partition<'a, 'f> = BlockingPartition<'a, NoopRawMutex, &'f mut SettingsFlash>;
+
+/// Run `f` on the bootloader state, as laid out in `memory-ota.x`.
+fn with_state<R>(f: impl FnOnce(&mut BlockingFirmwareState<'_, Partition<'_, '_>>) -> R) -> R {
+    settings::with_flash(|flash| {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(flash));
+        let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+        let mut state = BlockingFirmwareState::new(config.state, &mut aligned.0);
+        f(&mut state)
+    })
+}
+
+/// Whether the bootloader just swapped this image in, it rolls back on the next reset unless
+/// marked booted first.
+fn is_trial() -> bool {
+    with_state(|state| state.get_state().is_ok_and(|state| state == State::Swap))
+}
+
+/// Tell the bootloader the running image is good, so it doesn't roll back on the next reset.
+fn mark_booted() {
+    with_state(|state| {
+        if state.get_state().is_ok_and(|state| state != State::Boot) && state.mark_booted().is_err() {
+            warn!("failed to mark firmware as booted");
+        }
+    })
+}
+
+/// Keep a freshly swapped in image once it authenticated with Home Assistant. Without that
+/// within `BOOT_WINDOW` the device resets and the bootloader rolls back, as it does if the image
+/// hangs and the watchdog resets it first. Squishy has no transport for images, they are
+/// written to the DFU partition with a debug probe.
+#[embassy_executor::task]
+pub async fn ota_task() {
+    if !is_trial() {
+        mark_booted();
+        return;
+    }
+
+    info!("running a new image, rolling back unless it reaches Home Assistant within the boot window");
+    let deadline = Instant::now() + BOOT_WINDOW;
+    while websocket::connection() != HaConnection::Authenticated {
+        if Instant::now() >= deadline {
+            kept_log!(Warn, "new image not confirmed within the boot window, rolling back");
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        Timer::after(CONFIRM_POLL_INTERVAL).await;
+    }
+    info!("new image confirmed");
+    mark_booted();
+}
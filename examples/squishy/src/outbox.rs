@@ -0,0 +1,153 @@
+use heapless::Vec;
+
+use crate::ha_command::HaCommand;
+
+/// Commands held while the link to Home Assistant is slow, more are folded together or dropped.
+const OUTBOX_LEN: usize = 16;
+
+/// Commands taken off the command channel that wait for room in the websocket's send buffer, so
+/// a slow link holds them up rather than the frames and pings coming in.
+pub struct Outbox {
+    queue: Vec<HaCommand, OUTBOX_LEN>,
+}
+
+impl Outbox {
+    pub const fn new() -> Self {
+        Self { queue: Vec::new() }
+    }
+
+    /// Queue `command`, folded into the last one waiting for its entity where that does the same.
+    /// A full outbox drops its oldest background report to make room, or else `command`, which
+    /// comes back as the error.
+    pub fn push(&mut self, command: HaCommand) -> Result<(), HaCommand> {
+        let last_for_entity = self.queue.iter_mut().rev().find(|queued| queued.entity_name() == command.entity_name());
+        if last_for_entity.is_some_and(|queued| queued.coalesce(&command)) {
+            return Ok(());
+        }
+        if self.queue.is_full() {
+            let background = self.queue.iter().position(|queued| !queued.from_input()).ok_or(command)?;
+            self.queue.remove(background);
+        }
+        self.queue.push(command)
+    }
+
+    pub fn front(&self) -> Option<&HaCommand> {
+        self.queue.first()
+    }
+
+    pub fn pop(&mut self) -> Option<HaCommand> {
+        (!self.queue.is_empty()).then(|| self.queue.remove(0))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ha_command::{HaCommandSetEffect, HaCommandSetValue, HaCommandStepVolume, MAX_VOLUME_STEPS};
+
+    fn set_effect(entity_name: &'static str, effect_name: &'static str) -> HaCommand {
+        HaCommand::SetEffect(HaCommandSetEffect { entity_name, effect_name })
+    }
+
+    fn set_value(entity_name: &'static str, value: i32) -> HaCommand {
+        HaCommand::SetValue(HaCommandSetValue { entity_name, value })
+    }
+
+    fn step_volume(entity_name: &'static str, steps: i32) -> HaCommand {
+        HaCommand::StepVolume(HaCommandStepVolume { entity_name, steps })
+    }
+
+    fn queue(outbox: &mut Outbox, command: HaCommand) {
+        assert!(outbox.push(command).is_ok(), "dropped a command");
+    }
+
+    /// Entity and the value, effect index or steps of each queued command, front first.
+    fn drain(outbox: &mut Outbox) -> std::vec::Vec<(&'static str, i32)> {
+        core::iter::from_fn(|| outbox.pop())
+            .map(|command| match command {
+                HaCommand::SetEffect(cmd) => (cmd.entity_name, cmd.effect_name.len() as i32),
+                HaCommand::SetValue(cmd) => (cmd.entity_name, cmd.value),
+                HaCommand::StepVolume(cmd) => (cmd.entity_name, cmd.steps),
+                HaCommand::Motion => ("motion", 0),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_into_last_for_entity() {
+        let mut outbox = Outbox::new();
+        queue(&mut outbox, set_effect("light.a", "x"));
+        queue(&mut outbox, set_value("input_number.b", 1));
+        queue(&mut outbox, set_effect("light.a", "xxx"));
+        queue(&mut outbox, set_value("input_number.b", 2));
+        queue(&mut outbox, HaCommand::Motion);
+        queue(&mut outbox, HaCommand::Motion);
+        assert_eq!(outbox.len(), 3);
+        assert_eq!(drain(&mut outbox), [("light.a", 3), ("input_number.b", 2), ("motion", 0)]);
+    }
+
+    #[test]
+    fn volume_steps_past_the_cap_are_kept() {
+        let mut outbox = Outbox::new();
+        for _ in 0..MAX_VOLUME_STEPS + 3 {
+            queue(&mut outbox, step_volume("media_player.c", 1));
+        }
+        queue(&mut outbox, step_volume("media_player.c", -1));
+        assert_eq!(drain(&mut outbox), [("media_player.c", MAX_VOLUME_STEPS), ("media_player.c", 2)]);
+
+        queue(&mut outbox, step_volume("media_player.c", -MAX_VOLUME_STEPS));
+        queue(&mut outbox, step_volume("media_player.c", -1));
+        queue(&mut outbox, step_volume("media_player.c", 4));
+        assert_eq!(drain(&mut outbox), [("media_player.c", -MAX_VOLUME_STEPS), ("media_player.c", 3)]);
+    }
+
+    #[test]
+    fn keeps_order_across_entities() {
+        let mut outbox = Outbox::new();
+        queue(&mut outbox, set_value("input_number.b", 1));
+        queue(&mut outbox, step_volume("media_player.c", 1));
+        queue(&mut outbox, set_effect("light.a", "x"));
+        // Folded into the first, which keeps its place in the queue
+        queue(&mut outbox, set_value("input_number.b", 5));
+        assert_eq!(outbox.front().and_then(HaCommand::entity_name), Some("input_number.b"));
+        assert_eq!(drain(&mut outbox), [("input_number.b", 5), ("media_player.c", 1), ("light.a", 1)]);
+        assert!(outbox.is_empty());
+        assert!(outbox.pop().is_none());
+    }
+
+    #[test]
+    fn full_outbox_drops_background_reports_first() {
+        let mut outbox = Outbox::new();
+        queue(&mut outbox, set_value("input_number.b", 1));
+        // Each as many steps as fold into one command
+        for _ in 1..OUTBOX_LEN {
+            queue(&mut outbox, step_volume("media_player.c", -MAX_VOLUME_STEPS));
+        }
+        assert_eq!(outbox.len(), OUTBOX_LEN);
+
+        // The report makes room for a press
+        queue(&mut outbox, step_volume("media_player.c", -MAX_VOLUME_STEPS));
+        assert_eq!(outbox.len(), OUTBOX_LEN);
+        assert_eq!(outbox.front().and_then(HaCommand::entity_name), Some("media_player.c"));
+
+        // With only presses left the new one comes back
+        let Err(HaCommand::SetEffect(dropped)) = outbox.push(set_effect("light.a", "x")) else {
+            panic!("a press was dropped to make room");
+        };
+        assert_eq!(dropped.entity_name, "light.a");
+        assert_eq!(outbox.len(), OUTBOX_LEN);
+
+        // A command that folds in still fits
+        queue(&mut outbox, step_volume("media_player.c", 1));
+        assert_eq!(outbox.len(), OUTBOX_LEN);
+    }
+}
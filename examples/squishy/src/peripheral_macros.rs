@@ -1,17 +1,121 @@
+//! A module declares the peripherals it needs once, in a macro passed to these, and gets both its
+//! struct (`define_peripheral_set`) and the code taking them out of `Peripherals` in main
+//! (`take_peripheral_set`). Members are written as:
+//!
+//! - `name: PIN_2,` the peripheral itself.
+//! - `name: PIN_2 => Output(High),` a GPIO wrapper built when the set is taken, one of `Input`
+//!   with a `Pull`, or `Output`/`OutputOpenDrain` with a starting `Level`.
+//! - `name: Option<PIN_2>,` or `name: Option<PIN_2> if feature = "x",` a peripheral that is only
+//!   taken while the condition holds, `None` otherwise.
+//!
+//! Attributes in front of a member, such as `#[cfg(..)]`, go on both its field and its
+//! initializer.
+//!
+//! Passing every set to `claim_peripheral_set` in one block fails the build with "the name
+//! `PIN_2` is defined multiple times" when two sets claim the same peripheral, rather than with a
+//! use of moved value in main.
+
 #[macro_export]
 macro_rules! define_peripheral_set {
-    ($set_name:ident, $($name:ident: $type:ident,)*) => {
+    (@fields $set_name:ident [$($fields:tt)*]) => {
         pub struct $set_name {
-            $(pub $name: embassy_rp::peripherals::$type,)*
+            $($fields)*
         }
     };
+    (@fields $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: Option<$type:ident> $(if $cond:meta)?, $($rest:tt)*) => {
+        $crate::define_peripheral_set!(@fields $set_name [
+            $($fields)* $(#[$attr])* pub $name: Option<embassy_rp::peripherals::$type>,
+        ] $($rest)*);
+    };
+    (@fields $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: $type:ident => $wrapper:ident($init:ident), $($rest:tt)*) => {
+        $crate::define_peripheral_set!(@fields $set_name [
+            $($fields)* $(#[$attr])* pub $name: embassy_rp::gpio::$wrapper<'static>,
+        ] $($rest)*);
+    };
+    (@fields $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: $type:ident, $($rest:tt)*) => {
+        $crate::define_peripheral_set!(@fields $set_name [
+            $($fields)* $(#[$attr])* pub $name: embassy_rp::peripherals::$type,
+        ] $($rest)*);
+    };
+    ($set_name:ident, $($rest:tt)*) => {
+        $crate::define_peripheral_set!(@fields $set_name [] $($rest)*);
+    };
 }
 
 #[macro_export]
 macro_rules! take_peripheral_set {
-    ($p:ident, $set_name:ident, $($name:ident: $type:ident,)*) => {
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]) => {
         $set_name {
-            $($name: $p.$type,)*
+            $($fields)*
         }
     };
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: Option<$type:ident> if $cond:meta, $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [
+            $($fields)* $(#[$attr])* $name: {
+                #[cfg($cond)]
+                let member = Some($p.$type);
+                #[cfg(not($cond))]
+                let member = None;
+                member
+            },
+        ] $($rest)*)
+    };
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: Option<$type:ident>, $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [
+            $($fields)* $(#[$attr])* $name: Some($p.$type),
+        ] $($rest)*)
+    };
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: $type:ident => Input($pull:ident), $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [
+            $($fields)* $(#[$attr])* $name: embassy_rp::gpio::Input::new($p.$type, embassy_rp::gpio::Pull::$pull),
+        ] $($rest)*)
+    };
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: $type:ident => $wrapper:ident($level:ident), $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [
+            $($fields)* $(#[$attr])* $name: embassy_rp::gpio::$wrapper::new($p.$type, embassy_rp::gpio::Level::$level),
+        ] $($rest)*)
+    };
+    (@fields $p:ident $set_name:ident [$($fields:tt)*]
+        $(#[$attr:meta])* $name:ident: $type:ident, $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [
+            $($fields)* $(#[$attr])* $name: $p.$type,
+        ] $($rest)*)
+    };
+    ($p:ident, $set_name:ident, $($rest:tt)*) => {
+        $crate::take_peripheral_set!(@fields $p $set_name [] $($rest)*)
+    };
+}
+
+#[macro_export]
+macro_rules! claim_peripheral_set {
+    (@members) => {};
+    (@members $(#[$attr:meta])* $name:ident: Option<$type:ident> if $cond:meta, $($rest:tt)*) => {
+        #[cfg($cond)]
+        $(#[$attr])*
+        #[allow(dead_code)]
+        const $type: () = ();
+        $crate::claim_peripheral_set!(@members $($rest)*);
+    };
+    (@members $(#[$attr:meta])* $name:ident: Option<$type:ident>, $($rest:tt)*) => {
+        $(#[$attr])*
+        #[allow(dead_code)]
+        const $type: () = ();
+        $crate::claim_peripheral_set!(@members $($rest)*);
+    };
+    (@members $(#[$attr:meta])* $name:ident: $type:ident $(=> $wrapper:ident($init:ident))?, $($rest:tt)*) => {
+        $(#[$attr])*
+        #[allow(dead_code)]
+        const $type: () = ();
+        $crate::claim_peripheral_set!(@members $($rest)*);
+    };
+    ($set_name:ident, $($rest:tt)*) => {
+        $crate::claim_peripheral_set!(@members $($rest)*);
+    };
 }
@@ -0,0 +1,41 @@
+use defmt::{debug, info};
+use embassy_time::{Duration, Instant};
+
+use crate::command::CommandSender;
+use crate::consts;
+use crate::define_peripheral_set;
+use crate::leds::LedSender;
+
+/// PIR motion sensor module such as an HC-SR501, its output goes high on motion. The pull down
+/// keeps a missing sensor quiet.
+#[macro_export]
+macro_rules! pir_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            PirPeripherals,
+            motion: PIN_8 => Input(Down),
+        }
+    };
+}
+
+pir_peripherals!(define_peripheral_set);
+
+/// Wake the pads and fire `squishy_motion` on motion, as `pir` in `device.toml` says. The
+/// sensor holds its output high while the motion goes on, so only rising edges count.
+#[embassy_executor::task]
+pub async fn pir_task(mut sender: CommandSender, mut led_sender: LedSender, mut p: PirPeripherals) -> ! {
+    info!("set up motion sensor");
+    let holdoff = Duration::from_secs(consts::PIR.holdoff_secs as u64);
+    let mut next_event = Instant::MIN;
+    loop {
+        p.motion.wait_for_rising_edge().await;
+        debug!("motion");
+        if consts::PIR.wake {
+            led_sender.wake();
+        }
+        if consts::PIR.event && Instant::now() >= next_event {
+            sender.on_motion();
+            next_event = Instant::now() + holdoff;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+/// Levels of the A and B phases, A in bit 1, in the order they go through while turning
+/// clockwise. Both are high at the detents, pulled up with the contacts open.
+const CLOCKWISE: [u8; 4] = [0b11, 0b01, 0b00, 0b10];
+const REST: u8 = 0b11;
+
+/// Quarter steps from the last detent that count as having moved to the next one at rest, less
+/// than a full 4 so a missed edge while turning fast doesn't lose the detent.
+const DETENT_QUARTERS: i8 = 2;
+
+/// Turns the phase levels of a mechanical rotary encoder into detents. Bouncing contacts go
+/// back and forth between neighboring states, which cancels out, and a change of both phases
+/// at once can't be placed so it is ignored.
+pub struct Decoder {
+    state: u8,
+    /// Quarter steps since the last detent, positive clockwise.
+    quarters: i8,
+}
+
+fn position(state: u8) -> i8 {
+    CLOCKWISE.iter().position(|s| *s == state).unwrap_or(0) as i8
+}
+
+impl Decoder {
+    pub fn new(a: bool, b: bool) -> Self {
+        Self { state: ((a as u8) << 1) | b as u8, quarters: 0 }
+    }
+
+    /// Feed the levels after an edge on either phase, returns the detents completed, 1
+    /// clockwise, -1 counterclockwise or 0.
+    pub fn update(&mut self, a: bool, b: bool) -> i32 {
+        let state = ((a as u8) << 1) | b as u8;
+        match (position(state) - position(self.state)).rem_euclid(4) {
+            1 => self.quarters += 1,
+            3 => self.quarters -= 1,
+            _ => {}
+        }
+        self.state = state;
+        if state != REST {
+            return 0;
+        }
+        let detents = if self.quarters >= DETENT_QUARTERS {
+            1
+        } else if self.quarters <= -DETENT_QUARTERS {
+            -1
+        } else {
+            0
+        };
+        self.quarters = 0;
+        detents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(decoder: &mut Decoder, states: &[u8]) -> i32 {
+        states.iter().map(|state| decoder.update(state & 0b10 != 0, state & 0b01 != 0)).sum()
+    }
+
+    #[test]
+    fn one_detent_each_way() {
+        let mut decoder = Decoder::new(true, true);
+        assert_eq!(feed(&mut decoder, &[0b01, 0b00, 0b10, 0b11]), 1);
+        assert_eq!(feed(&mut decoder, &[0b10, 0b00, 0b01, 0b11]), -1);
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        let mut decoder = Decoder::new(true, true);
+        assert_eq!(feed(&mut decoder, &[0b01, 0b11, 0b01, 0b11]), 0);
+        // Bouncing on the way still ends up one detent further
+        assert_eq!(feed(&mut decoder, &[0b01, 0b00, 0b01, 0b00, 0b10, 0b11]), 1);
+    }
+
+    #[test]
+    fn missed_edge_keeps_the_detent() {
+        let mut decoder = Decoder::new(true, true);
+        // 0b00 to 0b11 changes both phases and is ignored
+        assert_eq!(feed(&mut decoder, &[0b01, 0b00, 0b11]), 1);
+    }
+}
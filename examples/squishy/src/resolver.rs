@@ -0,0 +1,71 @@
+use defmt::{debug, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::driver::Driver;
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+/// embassy-net doesn't report record TTLs, so resolved addresses are kept for a fixed time.
+const CACHE_LIFETIME: Duration = Duration::from_secs(600);
+/// Matches `SMOLTCP_DNS_MAX_RESULT_COUNT` in .cargo/config.toml.
+const MAX_ADDRESSES: usize = 4;
+
+/// Addresses of one endpoint, reused across reconnects and rotated through on connect failures.
+pub struct Resolver {
+    domain: &'static str,
+    port: u16,
+    addresses: Vec<IpAddress, MAX_ADDRESSES>,
+    next: usize,
+    /// Consecutive addresses that failed to connect, all of them failing resolves again.
+    failures: usize,
+    expires: Instant,
+}
+
+impl Resolver {
+    pub const fn new(domain: &'static str, port: u16) -> Self {
+        Self {
+            domain,
+            port,
+            addresses: Vec::new(),
+            next: 0,
+            failures: 0,
+            expires: Instant::MIN,
+        }
+    }
+
+    /// Endpoint to connect to next, resolving again once the cache expired or every address
+    /// failed. `None` if the domain doesn't resolve.
+    pub async fn endpoint<D: Driver>(&mut self, stack: &Stack<D>) -> Option<IpEndpoint> {
+        if self.addresses.is_empty() || self.failures >= self.addresses.len() || Instant::now() >= self.expires {
+            self.resolve(stack).await;
+        }
+        let address = *self.addresses.get(self.next)?;
+        Some(IpEndpoint::new(address, self.port))
+    }
+
+    async fn resolve<D: Driver>(&mut self, stack: &Stack<D>) {
+        self.addresses.clear();
+        self.next = 0;
+        self.failures = 0;
+        match stack.dns_query(self.domain, DnsQueryType::A).await {
+            Ok(addresses) => {
+                debug!("{} resolved to {}", self.domain, addresses.as_slice());
+                self.addresses = addresses.iter().copied().take(MAX_ADDRESSES).collect();
+                self.expires = Instant::now() + CACHE_LIFETIME;
+            }
+            Err(err) => warn!("failed to resolve {}: {}", self.domain, err),
+        }
+    }
+
+    pub fn on_connected(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Move on to the next address.
+    pub fn on_connect_failed(&mut self) {
+        self.failures += 1;
+        if !self.addresses.is_empty() {
+            self.next = (self.next + 1) % self.addresses.len();
+        }
+    }
+}
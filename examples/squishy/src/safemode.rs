@@ -0,0 +1,59 @@
+use core::cell::Cell;
+use defmt::info;
+use embassy_rp::pac;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Timer};
+
+use crate::kept_log;
+
+/// Consecutive resets within `STABLE_UPTIME` of boot that put the next boot into safe mode.
+const MAX_EARLY_RESETS: u32 = 3;
+/// Uptime after which a boot no longer counts as part of a crash loop.
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+/// High half of the watchdog scratch register counting early resets, the count is in the low
+/// half. Survives every reset but a power cycle, see
+/// `board_support::watchdog::STALL_SCRATCH` for scratch 0.
+const BOOT_MARK: u32 = 0x424f_0000; // "BO"
+
+static ACTIVE: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+fn read_boots() -> u32 {
+    let scratch = pac::WATCHDOG.scratch1().read();
+    if scratch & 0xFFFF_0000 == BOOT_MARK {
+        scratch & 0xFFFF
+    } else {
+        0
+    }
+}
+
+fn write_boots(boots: u32) {
+    pac::WATCHDOG.scratch1().write(|w| *w = BOOT_MARK | boots.min(0xFFFF));
+}
+
+/// Count this boot towards a crash loop and decide whether it runs in safe mode, at boot. Safe
+/// mode clears the count, so rebooting from the console tries a normal boot again.
+pub fn init() {
+    let early_resets = read_boots();
+    if early_resets >= MAX_EARLY_RESETS {
+        kept_log!(Error, "{} resets shortly after boot in a row, starting in safe mode", early_resets);
+        ACTIVE.lock(|cell| cell.set(true));
+        write_boots(0);
+    } else {
+        write_boots(early_resets + 1);
+    }
+}
+
+/// Whether this boot skips the network and Home Assistant, leaving the USB console and the
+/// firmware rollback for recovery.
+pub fn active() -> bool {
+    ACTIVE.lock(|cell| cell.get())
+}
+
+/// Forget the resets counted so far once this boot has been up for a while.
+#[embassy_executor::task]
+pub async fn stable_task() {
+    Timer::after(STABLE_UPTIME).await;
+    info!("up for {} s, clearing the early reset count", STABLE_UPTIME.as_secs());
+    write_boots(0);
+}
@@ -0,0 +1,43 @@
+//! Local times of day for the scheduled presses of the `timers` feature. The pad only knows a
+//! fixed offset from UTC, daylight saving moves them by an hour until the offset is changed.
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// First Unix second after `after` that is `minute_of_day` minutes past local midnight, with
+/// local time `utc_offset_mins` ahead of UTC.
+pub fn next_occurrence(after: u64, utc_offset_mins: i32, minute_of_day: u16) -> u64 {
+    let offset = utc_offset_mins as i64 * 60;
+    let local = after as i64 + offset;
+    let mut at = local - local.rem_euclid(SECONDS_PER_DAY) + minute_of_day as i64 * 60;
+    if at <= local {
+        at += SECONDS_PER_DAY;
+    }
+    (at - offset) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-01 00:00:00 UTC.
+    const MIDNIGHT: u64 = 1_709_251_200;
+
+    #[test]
+    fn later_today() {
+        assert_eq!(next_occurrence(MIDNIGHT + 3600, 0, 22 * 60 + 30), MIDNIGHT + 22 * 3600 + 30 * 60);
+    }
+
+    #[test]
+    fn passed_and_due_now_are_tomorrow() {
+        assert_eq!(next_occurrence(MIDNIGHT + 23 * 3600, 0, 7 * 60), MIDNIGHT + 86400 + 7 * 3600);
+        assert_eq!(next_occurrence(MIDNIGHT + 7 * 3600, 0, 7 * 60), MIDNIGHT + 86400 + 7 * 3600);
+    }
+
+    #[test]
+    fn offset_crosses_midnight() {
+        // 23:00 UTC is already 00:00 the next day an hour east
+        assert_eq!(next_occurrence(MIDNIGHT + 23 * 3600, 60, 30), MIDNIGHT + 23 * 3600 + 30 * 60);
+        // 03:00 UTC is 22:00 the day before five hours west, so 23:00 there is 04:00 UTC
+        assert_eq!(next_occurrence(MIDNIGHT + 3 * 3600, -300, 23 * 60), MIDNIGHT + 4 * 3600);
+    }
+}
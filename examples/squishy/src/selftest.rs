@@ -0,0 +1,145 @@
+use defmt::info;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+use heapless::String;
+use ufmt::uwrite;
+
+use crate::consts::HA_CONSTS;
+use crate::{kept_log, settings};
+
+const BUTTONS_TIMEOUT: Duration = Duration::from_secs(1);
+/// The pattern itself takes about two seconds.
+const LEDS_TIMEOUT: Duration = Duration::from_secs(5);
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Passed, or why not.
+pub type Outcome = Result<(), &'static str>;
+
+#[derive(Copy, Clone)]
+pub struct NetworkOutcome {
+    dns: Outcome,
+    tcp: Outcome,
+}
+
+/// A check run by the task owning what it exercises, when the self-test asks for it.
+pub struct Check<T: Send> {
+    request: Signal<CriticalSectionRawMutex, ()>,
+    result: Signal<CriticalSectionRawMutex, T>,
+}
+
+impl<T: Send> Check<T> {
+    const fn new() -> Self {
+        Self { request: Signal::new(), result: Signal::new() }
+    }
+
+    /// Wait for the self-test to ask for the check.
+    pub async fn requested(&self) {
+        self.request.wait().await
+    }
+
+    pub fn report(&self, result: T) {
+        self.result.signal(result);
+    }
+
+    /// `None` if the owning task didn't answer within `timeout`.
+    async fn run(&self, timeout: Duration) -> Option<T> {
+        self.result.reset();
+        self.request.signal(());
+        with_timeout(timeout, self.result.wait()).await.ok()
+    }
+}
+
+/// Read back the TCA9555 configuration or the touch pads, in the button task.
+pub static BUTTONS: Check<Outcome> = Check::new();
+/// Cycle every pad through red, green, blue and white, in the LED task.
+pub static LEDS: Check<Outcome> = Check::new();
+/// Resolve and connect to Home Assistant, next to the other network services.
+pub static NETWORK: Check<NetworkOutcome> = Check::new();
+
+async fn check_network<D: Driver>(stack: &Stack<D>) -> NetworkOutcome {
+    let address = match with_timeout(DNS_TIMEOUT, stack.dns_query(HA_CONSTS.domain, DnsQueryType::A)).await {
+        Ok(Ok(addresses)) => addresses.first().copied(),
+        Ok(Err(_)) => None,
+        Err(_) => {
+            return NetworkOutcome {
+                dns: Err("timed out"),
+                tcp: Err("skipped"),
+            }
+        }
+    };
+    let Some(address) = address else {
+        return NetworkOutcome {
+            dns: Err("no address for the Home Assistant domain"),
+            tcp: Err("skipped"),
+        };
+    };
+
+    let mut rx_buffer = [0; 16];
+    let mut tx_buffer = [0; 16];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    let tcp = match with_timeout(TCP_TIMEOUT, socket.connect(IpEndpoint::new(address, HA_CONSTS.port))).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(_)) => Err("Home Assistant refused the connection"),
+        Err(_) => Err("timed out"),
+    };
+    socket.abort();
+    NetworkOutcome { dns: Ok(()), tcp }
+}
+
+/// Answer the self-test's network checks, alongside the other services.
+pub async fn serve_network<D: Driver>(stack: &Stack<D>) -> ! {
+    loop {
+        NETWORK.requested().await;
+        NETWORK.report(check_network(stack).await);
+    }
+}
+
+fn write_item<const N: usize>(out: &mut String<N>, name: &str, outcome: Outcome, failures: &mut usize) {
+    match outcome {
+        Ok(()) => uwrite!(out, "{}: pass\r\n", name),
+        Err(reason) => {
+            *failures += 1;
+            uwrite!(out, "{}: FAIL, {}\r\n", name, reason)
+        }
+    }
+    .ok();
+}
+
+/// Exercise each subsystem in turn and describe the outcome in `out`, for checking a pad after
+/// assembly or a firmware update.
+pub async fn run<const N: usize>(out: &mut String<N>) {
+    info!("running self-test");
+    let mut failures = 0;
+
+    let buttons = BUTTONS.run(BUTTONS_TIMEOUT).await.unwrap_or(Err("button task not answering"));
+    write_item(out, if cfg!(feature = "touch") { "touch pads" } else { "i2c expander" }, buttons, &mut failures);
+
+    let leds = LEDS.run(LEDS_TIMEOUT).await.unwrap_or(Err("LED task not answering"));
+    write_item(out, "leds", leds, &mut failures);
+    if leds.is_ok() {
+        out.push_str("  every pad should have lit red, green, blue and white\r\n").ok();
+    }
+
+    let network = NETWORK.run(NETWORK_TIMEOUT).await.unwrap_or(NetworkOutcome {
+        dns: Err("network not up"),
+        tcp: Err("network not up"),
+    });
+    write_item(out, "dns", network.dns, &mut failures);
+    write_item(out, "tcp", network.tcp, &mut failures);
+
+    let flash = settings::self_test().map_err(|_| "writing or reading back a record failed");
+    write_item(out, "settings flash", flash, &mut failures);
+
+    if failures == 0 {
+        kept_log!(Info, "self-test passed");
+    } else {
+        kept_log!(Warn, "self-test failed {} items", failures);
+    }
+}
@@ -0,0 +1,300 @@
+#[cfg(feature = "buzzer")]
+use core::cell::Cell;
+use core::cell::RefCell;
+use config_store::{ConfigStore, Record};
+use defmt::{info, unwrap, warn, Format};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::once_lock::OnceLock;
+use embassy_time::Instant;
+use heapless::String;
+use crate::consts::HA_CONSTS;
+#[cfg(not(feature = "ethernet"))]
+use crate::consts::{WIFI_PASSPHRASE, WIFI_PSK, WIFI_SSID};
+use crate::{define_peripheral_set, identity};
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Settings region at the end of flash, see `memory-default.x`.
+const STORE_SECTORS: u32 = 8;
+const STORE_OFFSET: u32 = FLASH_SIZE as u32 - STORE_SECTORS * ERASE_SIZE as u32;
+
+const MAX_TOKEN_LEN: usize = 256;
+const AUTH_PREFIX: &str = r#"{"type":"auth","access_token":""#;
+const AUTH_SUFFIX: &str = r#""}"#;
+const MAX_AUTH_LEN: usize = AUTH_PREFIX.len() + MAX_TOKEN_LEN + AUTH_SUFFIX.len();
+
+#[macro_export]
+macro_rules! settings_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            SettingsPeripherals,
+            flash: FLASH,
+        }
+    };
+}
+
+settings_peripherals!(define_peripheral_set);
+
+pub type SettingsFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+/// Home Assistant long-lived access token, used instead of the one in `HA_CONSTS.auth`.
+struct HaToken(String<MAX_TOKEN_LEN>);
+
+impl Record for HaToken {
+    const KEY: u8 = 1;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
+        self.0.len()
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        // Tokens are JWTs, anything needing escapes in the auth message isn't one
+        if bytes.is_empty() || !bytes.iter().all(|c| c.is_ascii_graphic() && *c != b'"' && *c != b'\\') {
+            return None;
+        }
+        Some(Self(String::try_from(core::str::from_utf8(bytes).ok()?).ok()?))
+    }
+}
+
+/// Network to join instead of the one in `device.toml`.
+#[cfg(not(feature = "ethernet"))]
+struct WifiCredentials {
+    ssid: String<32>,
+    /// Precomputed WPA2 PSK, credentials set from the console only have the passphrase.
+    psk: Option<[u8; 32]>,
+    passphrase: String<63>,
+}
+
+#[cfg(not(feature = "ethernet"))]
+impl Record for WifiCredentials {
+    const KEY: u8 = 2;
+    const VERSION: u8 = 2;
+
+    /// SSID length, SSID, precomputed WPA2 PSK, WPA3 passphrase length, passphrase and whether
+    /// the PSK is set. Version 1 always had the PSK.
+    fn save(&self, buf: &mut [u8]) -> usize {
+        let (ssid, passphrase) = (self.ssid.as_bytes(), self.passphrase.as_bytes());
+        buf[..66 + 63 + 1].fill(0);
+        buf[0] = ssid.len() as u8;
+        buf[1..1 + ssid.len()].copy_from_slice(ssid);
+        buf[33..65].copy_from_slice(&self.psk.unwrap_or_default());
+        buf[65] = passphrase.len() as u8;
+        buf[66..66 + passphrase.len()].copy_from_slice(passphrase);
+        buf[66 + 63] = self.psk.is_some() as u8;
+        66 + 63 + 1
+    }
+
+    fn load(version: u8, bytes: &[u8]) -> Option<Self> {
+        let has_psk = version == 1 || bytes.get(66 + 63) == Some(&1);
+        let bytes = bytes.get(..66 + 63)?;
+        let ssid = bytes.get(1..1 + bytes[0] as usize).filter(|ssid| !ssid.is_empty() && ssid.len() <= 32)?;
+        let passphrase = bytes.get(66..66 + bytes[65] as usize)?;
+        Some(Self {
+            ssid: String::try_from(core::str::from_utf8(ssid).ok()?).ok()?,
+            psk: has_psk.then(|| bytes[33..65].try_into().unwrap()),
+            passphrase: String::try_from(core::str::from_utf8(passphrase).ok()?).ok()?,
+        })
+    }
+}
+
+/// Scratch record written and removed again by the self-test.
+#[derive(PartialEq)]
+struct SelfTest(u32);
+
+impl Record for SelfTest {
+    const KEY: u8 = 3;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        buf[..4].copy_from_slice(&self.0.to_le_bytes());
+        4
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        Some(Self(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?)))
+    }
+}
+
+/// Loudness of the buzzer's feedback tones.
+#[cfg(feature = "buzzer")]
+#[derive(Copy, Clone, PartialEq)]
+pub struct BuzzerSettings {
+    /// Percent, 0 to 100.
+    pub volume: u8,
+    pub muted: bool,
+}
+
+#[cfg(feature = "buzzer")]
+impl BuzzerSettings {
+    const DEFAULT: Self = Self { volume: 50, muted: false };
+}
+
+#[cfg(feature = "buzzer")]
+impl Record for BuzzerSettings {
+    const KEY: u8 = 4;
+    const VERSION: u8 = 1;
+
+    fn save(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.volume;
+        buf[1] = self.muted as u8;
+        2
+    }
+
+    fn load(_version: u8, bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [volume @ 0..=100, muted @ (0 | 1), ..] => Some(Self { volume: *volume, muted: *muted == 1 }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum SettingsError {
+    /// Not a valid value for the setting.
+    Invalid,
+    /// Writing the settings region failed.
+    Flash,
+}
+
+impl From<config_store::Error> for SettingsError {
+    fn from(_: config_store::Error) -> Self {
+        SettingsError::Flash
+    }
+}
+
+/// `None` until `init`.
+static STORE: Mutex<CriticalSectionRawMutex, RefCell<Option<ConfigStore<SettingsFlash>>>> =
+    Mutex::new(RefCell::new(None));
+static AUTH: OnceLock<String<MAX_AUTH_LEN>> = OnceLock::new();
+#[cfg(not(feature = "ethernet"))]
+static WIFI_CREDENTIALS: OnceLock<WifiCredentials> = OnceLock::new();
+/// Unlike the other settings, changes apply right away.
+#[cfg(feature = "buzzer")]
+static BUZZER: Mutex<CriticalSectionRawMutex, Cell<BuzzerSettings>> = Mutex::new(Cell::new(BuzzerSettings::DEFAULT));
+
+/// Read the flash unique ID for `identity` and the settings stored in flash at boot. Stored
+/// settings take precedence over the ones compiled in.
+pub fn init(p: SettingsPeripherals) {
+    let mut flash = Flash::new_blocking(p.flash);
+    let mut id = [0; 8];
+    if flash.blocking_unique_id(&mut id).is_err() {
+        warn!("failed to read flash unique ID");
+    }
+    identity::init(id);
+
+    let mut store = ConfigStore::new(flash, STORE_OFFSET, STORE_SECTORS);
+    if let Some(HaToken(token)) = store.load() {
+        info!("using stored Home Assistant token");
+        let mut auth = String::new();
+        // Can't overflow, the buffer is sized for the longest token
+        auth.push_str(AUTH_PREFIX).ok();
+        auth.push_str(&token).ok();
+        auth.push_str(AUTH_SUFFIX).ok();
+        AUTH.init(auth).ok();
+    }
+    #[cfg(not(feature = "ethernet"))]
+    if let Some(credentials) = store.load::<WifiCredentials>() {
+        info!("using stored wifi credentials for {}", credentials.ssid.as_str());
+        WIFI_CREDENTIALS.init(credentials).ok();
+    }
+    #[cfg(feature = "buzzer")]
+    if let Some(buzzer) = store.load::<BuzzerSettings>() {
+        BUZZER.lock(|cell| cell.set(buzzer));
+    }
+    STORE.lock(|cell| cell.replace(Some(store)));
+}
+
+fn with_store<R>(f: impl FnOnce(&mut ConfigStore<SettingsFlash>) -> R) -> R {
+    STORE.lock(|cell| f(unwrap!(cell.borrow_mut().as_mut())))
+}
+
+/// Run `f` on the flash the settings live in, for firmware updates.
+#[cfg(feature = "ota")]
+pub fn with_flash<R>(f: impl FnOnce(&mut SettingsFlash) -> R) -> R {
+    with_store(|store| f(store.flash()))
+}
+
+/// Store a Home Assistant long-lived access token, used from the next boot.
+pub fn save_ha_token(token: &str) -> Result<(), SettingsError> {
+    let token = HaToken::load(HaToken::VERSION, token.as_bytes()).ok_or(SettingsError::Invalid)?;
+    with_store(|store| store.save(&token))?;
+    Ok(())
+}
+
+/// Store a network to join from the next boot, an empty passphrase joins an open network.
+#[cfg(not(feature = "ethernet"))]
+pub fn save_wifi_credentials(ssid: &str, passphrase: &str) -> Result<(), SettingsError> {
+    if ssid.is_empty() || !(passphrase.is_empty() || (8..=63).contains(&passphrase.len())) {
+        return Err(SettingsError::Invalid);
+    }
+    let credentials = WifiCredentials {
+        ssid: String::try_from(ssid).map_err(|_| SettingsError::Invalid)?,
+        psk: None,
+        passphrase: String::try_from(passphrase).map_err(|_| SettingsError::Invalid)?,
+    };
+    with_store(|store| store.save(&credentials))?;
+    Ok(())
+}
+
+/// Store the buzzer's volume and mute, they apply right away.
+#[cfg(feature = "buzzer")]
+pub fn save_buzzer(buzzer: BuzzerSettings) -> Result<(), SettingsError> {
+    if buzzer.volume > 100 {
+        return Err(SettingsError::Invalid);
+    }
+    with_store(|store| store.save(&buzzer))?;
+    BUZZER.lock(|cell| cell.set(buzzer));
+    Ok(())
+}
+
+/// Erase every stored setting, the compiled in ones apply from the next boot. Reset right after,
+/// nothing can be stored until then.
+pub fn erase() -> Result<(), SettingsError> {
+    with_store(|store| store.flash().blocking_erase(STORE_OFFSET, FLASH_SIZE as u32)).map_err(|_| SettingsError::Flash)
+}
+
+/// Write a scratch record, read it back and remove it, leaving the stored settings alone.
+pub fn self_test() -> Result<(), SettingsError> {
+    let record = SelfTest(Instant::now().as_ticks() as u32);
+    with_store(|store| {
+        store.save(&record)?;
+        let read_back = store.load::<SelfTest>();
+        store.remove_at::<SelfTest>(0)?;
+        if read_back == Some(record) {
+            Ok(())
+        } else {
+            Err(SettingsError::Flash)
+        }
+    })
+}
+
+/// Websocket auth message.
+pub fn ha_auth() -> &'static str {
+    AUTH.try_get().map_or(HA_CONSTS.auth, |auth| auth.as_str())
+}
+
+#[cfg(not(feature = "ethernet"))]
+pub fn wifi_ssid() -> &'static str {
+    WIFI_CREDENTIALS.try_get().map_or(WIFI_SSID, |credentials| credentials.ssid.as_str())
+}
+
+/// Precomputed WPA2 PSK, `None` for credentials that only have the passphrase.
+#[cfg(not(feature = "ethernet"))]
+pub fn wifi_psk() -> Option<&'static [u8; 32]> {
+    WIFI_CREDENTIALS.try_get().map_or(WIFI_PSK, |credentials| credentials.psk.as_ref())
+}
+
+/// Passphrase for WPA3 and for credentials without a PSK.
+#[cfg(not(feature = "ethernet"))]
+pub fn wifi_passphrase() -> &'static str {
+    WIFI_CREDENTIALS.try_get().map_or(WIFI_PASSPHRASE, |credentials| credentials.passphrase.as_str())
+}
+
+#[cfg(feature = "buzzer")]
+pub fn buzzer() -> BuzzerSettings {
+    BUZZER.lock(|cell| cell.get())
+}
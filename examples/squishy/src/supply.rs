@@ -0,0 +1,87 @@
+use core::cell::Cell;
+use defmt::{debug, warn};
+use embassy_rp::adc;
+use embassy_rp::gpio::Pull;
+use embassy_rp::pac;
+use embassy_rp::peripherals::PIN_29;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::command::CommandSender;
+use crate::{consts, kept_log};
+
+/// VSYS reaches ADC3 through a 3:1 divider on the Pico boards.
+const VSYS_PIN: usize = 29;
+const DIVIDER: u32 = 3;
+/// cyw43 chip select, high while the bus is idle. It also switches the divider onto GPIO29,
+/// which otherwise carries the cyw43 SPI clock.
+#[cfg(not(feature = "ethernet"))]
+const WL_CS_PIN: usize = 25;
+/// Below this a single LiPo cell has little charge left, USB keeps VSYS near 5 V.
+const LOW_MILLIVOLTS: u32 = 3500;
+/// Recovering takes this much, so a cell sagging under the LEDs doesn't flap the warning.
+const RECOVERED_MILLIVOLTS: u32 = 3600;
+
+static LOW: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Whether the supply is running low, for the LED warning.
+pub fn low() -> bool {
+    LOW.lock(|cell| cell.get())
+}
+
+/// One conversion of VSYS in millivolts, `None` if the cyw43 bus is busy or the ADC failed.
+fn read_millivolts(adc: &mut adc::Adc<'_, adc::Async>) -> Option<u32> {
+    // The cyw43 runner shares this executor, so no transfer starts until this returns
+    #[cfg(not(feature = "ethernet"))]
+    if pac::SIO.gpio_out(0).value().read() & (1 << WL_CS_PIN) == 0 {
+        return None;
+    }
+    let pad = pac::PADS_BANK0.gpio(VSYS_PIN).read();
+    // Safety: the pin is only borrowed for a blocking conversion, its pad is restored right after
+    let mut channel = adc::Channel::new_pin(unsafe { PIN_29::steal() }, Pull::None);
+    let raw = adc.blocking_read(&mut channel);
+    drop(channel);
+    pac::PADS_BANK0.gpio(VSYS_PIN).write_value(pad);
+    Some(raw.ok()? as u32 * DIVIDER * 3300 / 4096)
+}
+
+/// Averages VSYS between reports and tracks the low supply warning.
+#[derive(Default)]
+pub struct Monitor {
+    sum: u32,
+    samples: u32,
+}
+
+impl Monitor {
+    pub fn sample(&mut self, adc: &mut adc::Adc<'_, adc::Async>) {
+        if let Some(millivolts) = read_millivolts(adc) {
+            self.sum += millivolts;
+            self.samples += 1;
+        }
+    }
+
+    /// Update the warning from the average since the last report and write it to
+    /// `consts::VSYS_ENTITY`.
+    pub fn report(&mut self, sender: &mut CommandSender) {
+        if self.samples == 0 {
+            warn!("no VSYS samples");
+            return;
+        }
+        let millivolts = self.sum / self.samples;
+        self.sum = 0;
+        self.samples = 0;
+        debug!("vsys {} mV", millivolts);
+
+        let was_low = low();
+        let is_low = if was_low { millivolts < RECOVERED_MILLIVOLTS } else { millivolts < LOW_MILLIVOLTS };
+        if is_low != was_low {
+            if is_low {
+                kept_log!(Warn, "supply low at {} mV", millivolts);
+            } else {
+                kept_log!(Info, "supply recovered at {} mV", millivolts);
+            }
+            LOW.lock(|cell| cell.set(is_low));
+        }
+        sender.set_value(consts::VSYS_ENTITY, millivolts as i32);
+    }
+}
@@ -1,5 +1,14 @@
 #![allow(dead_code)]
 
+use defmt::info;
+use embassy_rp::peripherals::I2C0;
+use embassy_rp::{gpio, i2c};
+
+use crate::buttons::ButtonSource;
+use crate::error::Error;
+use crate::selftest::Outcome;
+use crate::{define_peripheral_set, dormant, Irqs};
+
 pub const ADDR: u8 = 0x20; // default addr
 
 macro_rules! tcaregs {
@@ -29,3 +38,62 @@ tcaregs! {
     CONF0: 0x06,
     CONF1: 0x07,
 }
+
+#[macro_export]
+macro_rules! button_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            ButtonPeripherals,
+            sda: PIN_4,
+            scl: PIN_5,
+            i2c0: I2C0,
+            button_int: PIN_3 => Input(None),
+        }
+    };
+}
+
+button_peripherals!(define_peripheral_set);
+
+/// Buttons to ground on the expander's inputs, its interrupt line goes low when one changes.
+pub struct Expander<'d> {
+    i2c: i2c::I2c<'d, I2C0, i2c::Async>,
+    button_int: gpio::Input<'d>,
+}
+
+pub fn source(p: ButtonPeripherals) -> Expander<'static> {
+    info!("set up i2c");
+    let i2c = i2c::I2c::new_async(p.i2c0, p.scl, p.sda, Irqs, i2c::Config::with_frequency(400_000));
+    Expander { i2c, button_int: p.button_int }
+}
+
+impl ButtonSource for Expander<'_> {
+    async fn read(&mut self) -> Result<u16, Error> {
+        use embedded_hal_async::i2c::I2c;
+        let mut port0 = [0; 2];
+        self.i2c.write_read(ADDR, &[INPORT0], &mut port0).await?;
+        Ok(u16::from_le_bytes(port0))
+    }
+
+    async fn wait_for_change(&mut self) {
+        self.button_int.wait_for_low().await;
+    }
+
+    /// The configuration registers are never written, so they read back as all inputs.
+    async fn self_test(&mut self) -> Outcome {
+        use embedded_hal_async::i2c::I2c;
+        let mut conf = [0; 2];
+        self.i2c
+            .write_read(ADDR, &[CONF0], &mut conf)
+            .await
+            .map_err(|_| "no answer")?;
+        if conf != [0xFF; 2] {
+            return Err("unexpected port configuration");
+        }
+        Ok(())
+    }
+
+    /// A press already pending wakes right away and is read as usual.
+    fn enter_dormant(&mut self) {
+        dormant::enter(&mut self.button_int);
+    }
+}
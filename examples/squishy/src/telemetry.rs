@@ -0,0 +1,64 @@
+use defmt::{debug, warn};
+use embassy_rp::adc;
+use embassy_time::{Duration, Ticker};
+
+use crate::command::CommandSender;
+use crate::supply;
+use crate::{consts, define_peripheral_set, Irqs};
+
+/// Samples taken between reports, averaging out conversion noise.
+const SAMPLES_PER_REPORT: u32 = 12;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[macro_export]
+macro_rules! telemetry_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            TelemetryPeripherals,
+            adc: ADC,
+            sensor: ADC_TEMP_SENSOR,
+        }
+    };
+}
+
+telemetry_peripherals!(define_peripheral_set);
+
+/// Conversion from the RP2040 datasheet, T = 27 - (V - 0.706 V) / 1.721 mV, with a 3.3 V
+/// reference, in tenths of a degree.
+fn to_decicelsius(raw: u16) -> i32 {
+    let microvolts = raw as i32 * 3_300_000 / 4096;
+    270 - (microvolts - 706_000) * 10 / 1721
+}
+
+/// Sample the die temperature and the supply voltage, and write the averages to their helper
+/// entities about once a minute.
+#[embassy_executor::task]
+pub async fn telemetry_task(mut sender: CommandSender, p: TelemetryPeripherals) -> ! {
+    let mut adc = adc::Adc::new(p.adc, Irqs, adc::Config::default());
+    let mut channel = adc::Channel::new_temp_sensor(p.sensor);
+    let mut supply = supply::Monitor::default();
+    let mut ticker = Ticker::every(SAMPLE_INTERVAL);
+    loop {
+        let mut sum = 0;
+        let mut samples = 0;
+        for _ in 0..SAMPLES_PER_REPORT {
+            ticker.next().await;
+            match adc.read(&mut channel).await {
+                Ok(raw) => {
+                    sum += to_decicelsius(raw);
+                    samples += 1;
+                }
+                Err(_) => warn!("temperature sampling failed"),
+            }
+            supply.sample(&mut adc);
+        }
+        supply.report(&mut sender);
+        if samples == 0 {
+            continue;
+        }
+        let decicelsius = sum / samples;
+        debug!("die temperature {} dC", decicelsius);
+        // Rounded to whole degrees, the helper's step
+        sender.set_value(consts::TEMPERATURE_ENTITY, (decicelsius + 5).div_euclid(10));
+    }
+}
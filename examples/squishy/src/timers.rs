@@ -0,0 +1,115 @@
+use defmt::info;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use squishy::schedule;
+
+use crate::command::{CommandSender, BUTTON_COMMANDS};
+use crate::leds::LedSender;
+use crate::{clock, consts, instrument};
+
+/// Longest wait before the scheduled presses are looked at again, so a sync stepping the clock
+/// doesn't leave them waiting on the old time.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pads whose countdown was started or cancelled, from the button task.
+static TOGGLES: Channel<CriticalSectionRawMutex, usize, 4> = Channel::new();
+
+/// Start the countdown of pad `i`, or cancel it if it is already running.
+pub fn toggle_countdown(i: usize) {
+    TOGGLES.try_send(i).ok();
+}
+
+struct Timers {
+    sender: CommandSender,
+    led_sender: LedSender,
+    /// When each pad's countdown started and when it runs out.
+    countdowns: [Option<(Instant, Instant)>; 16],
+    /// Unix second each of `consts::TIMERS.at` is next due, worked out once the clock is set.
+    due: [Option<u64>; consts::TIMERS.at.len()],
+}
+
+impl Timers {
+    /// Same as pressing the pad, without the click.
+    fn fire(&mut self, i: usize) {
+        self.sender.on_button_pressed(i);
+        self.led_sender.on_button_pressed(i);
+    }
+
+    fn toggle(&mut self, i: usize) {
+        let countdown = match self.countdowns[i] {
+            Some(_) => {
+                info!("countdown of button {} cancelled", i);
+                None
+            }
+            None => {
+                let secs = BUTTON_COMMANDS[i].countdown_secs;
+                info!("button {} fires in {} seconds", i, secs);
+                let now = Instant::now();
+                Some((now, now + Duration::from_secs(secs as u64)))
+            }
+        };
+        self.countdowns[i] = countdown;
+        self.led_sender.show_countdown(i, countdown);
+    }
+
+    fn on_countdowns_due(&mut self) {
+        let now = Instant::now();
+        for i in 0..self.countdowns.len() {
+            if self.countdowns[i].is_some_and(|(_, end)| now >= end) {
+                info!("countdown of button {} ran out", i);
+                self.countdowns[i] = None;
+                self.led_sender.show_countdown(i, None);
+                self.fire(i);
+            }
+        }
+    }
+
+    /// Fire the scheduled presses that came due, returns when to look again.
+    fn on_schedule(&mut self) -> Instant {
+        let Some(now) = clock::unix_seconds() else {
+            return Instant::now() + RECHECK_INTERVAL;
+        };
+        let mut wait = RECHECK_INTERVAL.as_secs();
+        for (k, entry) in consts::TIMERS.at.iter().enumerate() {
+            let next = |after| schedule::next_occurrence(after, consts::TIMERS.utc_offset_mins, entry.minute_of_day);
+            let due = match self.due[k] {
+                Some(due) if due <= now => {
+                    info!("scheduled press of button {}", entry.button);
+                    self.fire(entry.button);
+                    next(now)
+                }
+                Some(due) => due,
+                None => next(now),
+            };
+            self.due[k] = Some(due);
+            wait = wait.min(due - now);
+        }
+        Instant::now() + Duration::from_secs(wait)
+    }
+
+    async fn run(&mut self) -> ! {
+        loop {
+            let next_check = self.on_schedule();
+            let next_countdown = self.countdowns.iter().flatten().map(|(_, end)| *end).min().unwrap_or(Instant::MAX);
+            match select(TOGGLES.receive(), Timer::at(next_check.min(next_countdown))).await {
+                Either::First(i) => self.toggle(i),
+                Either::Second(_) => self.on_countdowns_due(),
+            }
+        }
+    }
+}
+
+/// Press pads on their own: the ones with a `countdown` in `device.toml` once it runs out after
+/// they were pressed, and the ones in `timers.at` at their time of day once the clock is set.
+#[embassy_executor::task]
+pub async fn timer_task(sender: CommandSender, led_sender: LedSender) -> ! {
+    let mut timers = Timers {
+        sender,
+        led_sender,
+        countdowns: [None; 16],
+        due: [None; consts::TIMERS.at.len()],
+    };
+    instrument::measured("timers", timers.run()).await
+}
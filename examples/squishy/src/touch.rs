@@ -0,0 +1,132 @@
+use defmt::{debug, info};
+use embassy_rp::gpio::{Flex, Pull};
+use embassy_time::{block_for, Duration, Timer};
+use squishy::capsense::Channel;
+
+use crate::buttons::ButtonSource;
+use crate::command::BUTTON_COMMANDS;
+use crate::define_peripheral_set;
+use crate::error::Error;
+use crate::selftest::Outcome;
+
+#[cfg(feature = "dormant")]
+compile_error!("touch pads can't wake the pad from dormant, enable only one of the touch and dormant features");
+
+const NUM_TOUCH_PADS: usize = 8;
+const _: () = assert!(BUTTON_COMMANDS.len() <= NUM_TOUCH_PADS, "more buttons than touch pads");
+
+/// Time between scans of every pad.
+const SCAN_INTERVAL: Duration = Duration::from_millis(20);
+/// Long enough to drain a pad through the pin driver.
+const DISCHARGE_TIME: Duration = Duration::from_micros(10);
+/// Loop iterations before giving up on a pad charging, a few hundred µs and ten times what a
+/// touched pad takes.
+const MAX_CHARGE_COUNT: u32 = 4000;
+
+/// Copper pads behind the front panel, each on a GPIO with a 1 MΩ pull-up to 3V3, for pads built
+/// without the TCA9555 and mechanical switches. Pad `i` is button `i`.
+#[macro_export]
+macro_rules! button_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            ButtonPeripherals,
+            t0: PIN_0,
+            t1: PIN_1,
+            t2: PIN_2,
+            t3: PIN_3,
+            t4: PIN_4,
+            t5: PIN_5,
+            t6: PIN_20,
+            t7: PIN_21,
+        }
+    };
+}
+
+button_peripherals!(define_peripheral_set);
+
+/// Measures each pad by how long the pull-up takes to charge it from empty, which a finger
+/// lengthens.
+pub struct TouchPads<'d> {
+    pins: [Flex<'d>; NUM_TOUCH_PADS],
+    channels: [Channel; NUM_TOUCH_PADS],
+    /// States of the last scan, in the expander's convention.
+    states: u16,
+}
+
+pub fn source(p: ButtonPeripherals) -> TouchPads<'static> {
+    info!("set up touch pads");
+    let pins = [p.t0, p.t1, p.t2, p.t3, p.t4, p.t5, p.t6, p.t7].map(|pin| {
+        let mut pin = Flex::new(pin);
+        pin.set_pull(Pull::None);
+        pin
+    });
+    let mut pads = TouchPads { pins, channels: [const { Channel::new() }; NUM_TOUCH_PADS], states: 0 };
+    // The first reading is the baseline, with nobody touching the pads at boot
+    pads.states = pads.scan();
+    pads
+}
+
+/// Charge time of the pad on `pin` in loop iterations, `MAX_CHARGE_COUNT` if it never charged.
+fn charge_count(pin: &mut Flex<'_>) -> u32 {
+    pin.set_low();
+    pin.set_as_output();
+    block_for(DISCHARGE_TIME);
+    // An interrupt in the middle would read as a touch
+    cortex_m::interrupt::free(|_| {
+        pin.set_as_input();
+        let mut count = 0;
+        while pin.is_low() && count < MAX_CHARGE_COUNT {
+            count += 1;
+        }
+        count
+    })
+}
+
+impl TouchPads<'_> {
+    fn scan(&mut self) -> u16 {
+        let mut states = u16::MAX;
+        for (i, (pin, channel)) in self.pins.iter_mut().zip(&mut self.channels).enumerate() {
+            if channel.update(charge_count(pin)) {
+                states &= !(1 << i);
+            }
+        }
+        states
+    }
+}
+
+impl ButtonSource for TouchPads<'_> {
+    async fn read(&mut self) -> Result<u16, Error> {
+        Ok(self.states)
+    }
+
+    /// Scans until a pad changes, there's no interrupt to wait for.
+    async fn wait_for_change(&mut self) {
+        loop {
+            Timer::after(SCAN_INTERVAL).await;
+            let states = self.scan();
+            if states != self.states {
+                self.states = states;
+                return;
+            }
+        }
+    }
+
+    /// Pads charge in a few hundred iterations. One that reads high at once is shorted, one
+    /// that never does is missing its pull-up.
+    async fn self_test(&mut self) -> Outcome {
+        for (i, pin) in self.pins.iter_mut().enumerate().take(BUTTON_COMMANDS.len()) {
+            let count = charge_count(pin);
+            debug!("touch pad {} charge count {}", i, count);
+            if count == 0 {
+                return Err("a pad reads high at once, is it shorted to 3V3?");
+            }
+            if count >= MAX_CHARGE_COUNT {
+                return Err("a pad never charges, is its pull-up fitted?");
+            }
+        }
+        Ok(())
+    }
+
+    /// Never asked for, the `dormant` feature doesn't build with `touch`.
+    fn enter_dormant(&mut self) {}
+}
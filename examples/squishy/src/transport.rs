@@ -0,0 +1,28 @@
+use embassy_net::driver::Driver;
+use embassy_net::Stack;
+use crate::command::CommandSender;
+
+#[cfg(feature = "ethernet")]
+pub use crate::ethernet::{init, NetDevice, NetPeripherals};
+#[cfg(not(feature = "ethernet"))]
+pub use crate::wifi::{init, NetDevice, NetPeripherals};
+
+/// Network interface the application runs over: Wi-Fi on the cyw43 by default, or a wired SPI
+/// Ethernet module with the `ethernet-w5500` or `ethernet-enc28j60` feature. Each provides
+/// `init`, `NetDevice` and `NetPeripherals` for main to bring it up.
+pub trait Transport {
+    /// Bring the link up, retrying until it is.
+    async fn connect<D: Driver>(&mut self, stack: &Stack<D>);
+
+    /// Pass frames sent to the multicast `mac` up to the stack, `false` if the interface can't.
+    async fn add_multicast_address(&mut self, mac: [u8; 6]) -> bool;
+
+    /// Look after the interface while the link is up and resolve once it is lost.
+    async fn run_until_lost<D: Driver>(&mut self, stack: &Stack<D>, sender: &mut CommandSender);
+
+    /// Bring the link back after `run_until_lost` resolved and wait for a fresh DHCP lease.
+    async fn reconnect<D: Driver>(&mut self, stack: &Stack<D>);
+
+    /// Let go of the link before going dormant, `reconnect` brings it back after waking.
+    async fn suspend(&mut self);
+}
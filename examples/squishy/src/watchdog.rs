@@ -0,0 +1,66 @@
+use defmt::{warn, Format};
+use embassy_time::Duration;
+
+pub use board_support::watchdog::check_in;
+
+use crate::define_peripheral_set;
+use crate::link::{self, NetHealth};
+
+#[macro_export]
+macro_rules! watchdog_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            WatchdogPeripherals,
+            watchdog: WATCHDOG,
+        }
+    };
+}
+
+watchdog_peripherals!(define_peripheral_set);
+
+/// Parts of the firmware that have to check in within their window for the watchdog to be fed.
+#[derive(Copy, Clone, PartialEq, Format)]
+pub enum Subsystem {
+    /// The network interface and stack, on join attempts or link polls, answered gateway probes
+    /// and quality reports.
+    Net = 0,
+    /// The LED task on core1, on every pass of its loop.
+    Leds = 1,
+    /// The Home Assistant connection, on every received frame and connection attempt. Only
+    /// judged while the network is up, reconnecting is covered by `Net` and a reboot won't bring
+    /// back an unreachable Home Assistant host.
+    Websocket = 2,
+}
+
+impl board_support::watchdog::Subsystem for Subsystem {
+    const ALL: &'static [Self] = &[Subsystem::Net, Subsystem::Leds, Subsystem::Websocket];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn window(self) -> Duration {
+        match self {
+            // A rejoin and a fresh DHCP lease can take a while, a DHCP server that stays silent
+            // longer gets a reboot as its retry
+            Subsystem::Net => Duration::from_secs(300),
+            Subsystem::Leds => Duration::from_secs(5),
+            // Pongs come back every ping interval, failed connects retry within seconds
+            Subsystem::Websocket => Duration::from_secs(180),
+        }
+    }
+
+    fn judged(self) -> bool {
+        self != Subsystem::Websocket || link::net_health() == NetHealth::Up
+    }
+}
+
+/// Feed the hardware watchdog while every subsystem keeps checking in, so a lockup anywhere
+/// reboots the device.
+#[embassy_executor::task]
+pub async fn watchdog_task(p: WatchdogPeripherals) -> ! {
+    board_support::watchdog::run(p.watchdog, |subsystem: Option<Subsystem>| {
+        warn!("rebooted by the watchdog, {} had stalled", subsystem);
+    })
+    .await
+}
@@ -1,28 +1,65 @@
 #![allow(dead_code)]
 
-use defmt::{assert, debug, unwrap};
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+
+use defmt::{debug, warn};
 use edge_ws::FrameHeader;
 use embassy_futures::select;
-use embassy_net::tcp::{Error, TcpSocket};
+use embassy_net::tcp::{self, TcpSocket};
 use embassy_net::IpEndpoint;
-use embassy_time::{Duration, Instant, Timer};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embedded_io_async::Write;
+use heapless::String;
 use ufmt::uwrite;
 
-use crate::command::{CommandReceiver, HaCommand, ENTITIES_TO_SUBSCRIBE};
+#[cfg(feature = "buzzer")]
+use crate::buzzer::{self, Tone};
+use crate::ambient;
+use crate::command::{CommandReceiver, HaCommand, AMBIENT, ENTITIES_TO_SUBSCRIBE};
 use crate::consts::HA_CONSTS;
+use crate::crash;
+use crate::error::Error;
+use crate::frame;
+use crate::ha::{self, Message, Outside};
+use crate::header::{self, HeaderLines};
+use crate::identity;
 use crate::leds::LedSender;
+use crate::outbox::Outbox;
+use crate::power;
+use crate::settings;
+use crate::watchdog::{self, Subsystem};
 
 const PING_INTERVAL: u64 = 30;
-
-fn map_edge_ws_error<R>(result: Result<R, edge_ws::io::Error<Error>>) -> Result<R, Error> {
+/// Longest entity state kept for the status page, longer effect names are cut short.
+pub const MAX_ENTITY_STATE_LEN: usize = 32;
+/// TCP keep-alives every `KEEP_ALIVE_INTERVAL` of silence keep NAT mappings open and, with
+/// `SOCKET_TIMEOUT`, reset half-open connections even while a ping is stuck in the send buffer.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Longer than a ping round trip, so only a dead peer or path trips it.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(2 * PING_INTERVAL + 15);
+/// Commands are formatted into 256 bytes, behind a header of at most 4.
+const MAX_COMMAND_FRAME_LEN: usize = 256 + 4;
+/// How often a send buffer too full for the next queued command is looked at again.
+const SEND_ROOM_POLL: Duration = Duration::from_millis(50);
+
+fn map_edge_ws_error<R>(result: Result<R, edge_ws::io::Error<tcp::Error>>) -> Result<R, Error> {
     match result {
         Ok(r) => Ok(r),
-        Err(edge_ws::Error::Io(e)) => Err(e),
-        _ => Err(Error::ConnectionReset),
+        Err(edge_ws::Error::Io(e)) => Err(Error::Tcp(e)),
+        _ => Err(Error::Protocol),
     }
 }
 
+/// Give up on `write` once it took longer than `HA_CONSTS.write_timeout_secs`, the peer stopped
+/// draining its window and the connection is as good as dead.
+async fn timed_write<F: Future>(write: F) -> Result<F::Output, Error> {
+    let timeout = Duration::from_secs(HA_CONSTS.write_timeout_secs as u64);
+    with_timeout(timeout, write).await.map_err(|_| Error::Timeout)
+}
+
 enum ReadWsOk {
     Ok,
     Discard,
@@ -33,7 +70,7 @@ macro_rules! make_send_function {
         async fn $name(&mut self) -> Result<(), Error> {
             debug!($debug);
             let mut s = heapless::String::<256>::new();
-            uwrite!(s, $format, self.id).unwrap();
+            uwrite!(s, $format, self.id).map_err(|_| Error::Format)?;
             self.id += 1;
             self.send_text_payload(&s).await
         }
@@ -45,7 +82,7 @@ macro_rules! make_send_function_1parm {
         async fn $name(&mut self, parm: &str) -> Result<(), Error> {
             debug!($debug);
             let mut s = heapless::String::<256>::new();
-            uwrite!(s, $format, parm, self.id).unwrap();
+            uwrite!(s, $format, parm, self.id).map_err(|_| Error::Format)?;
             self.id += 1;
             self.send_text_payload(&s).await
         }
@@ -57,87 +94,124 @@ macro_rules! make_send_function_2parm {
         async fn $name(&mut self, parm1: &str, parm2: &str) -> Result<(), Error> {
             debug!($debug);
             let mut s = heapless::String::<256>::new();
-            uwrite!(s, $format, parm1, parm2, self.id).unwrap();
+            uwrite!(s, $format, parm1, parm2, self.id).map_err(|_| Error::Format)?;
             self.id += 1;
             self.send_text_payload(&s).await
         }
     };
 }
 
+/// Home Assistant connection state, for the status page.
+#[derive(Copy, Clone, PartialEq)]
+pub enum HaConnection {
+    Disconnected,
+    /// Upgraded to a websocket, waiting for the token to be accepted.
+    Connected,
+    Authenticated,
+}
+
+static CONNECTION: Mutex<CriticalSectionRawMutex, Cell<HaConnection>> = Mutex::new(Cell::new(HaConnection::Disconnected));
+
+pub fn connection() -> HaConnection {
+    CONNECTION.lock(|cell| cell.get())
+}
+
+const UNKNOWN_STATE: String<MAX_ENTITY_STATE_LEN> = String::new();
+
+/// Last reported effect or "off" of each of `ENTITIES_TO_SUBSCRIBE`, empty until one arrives.
+static ENTITY_STATES: Mutex<CriticalSectionRawMutex, RefCell<[String<MAX_ENTITY_STATE_LEN>; ENTITIES_TO_SUBSCRIBE.len()]>> =
+    Mutex::new(RefCell::new([UNKNOWN_STATE; ENTITIES_TO_SUBSCRIBE.len()]));
+
+pub fn entity_state(index: usize) -> String<MAX_ENTITY_STATE_LEN> {
+    ENTITY_STATES.lock(|states| states.borrow()[index].clone())
+}
+
+fn set_entity_state(entity_name: &str, state: &str) {
+    if let Some(index) = ENTITIES_TO_SUBSCRIBE.iter().position(|entity| *entity == entity_name) {
+        ENTITY_STATES.lock(|states| {
+            let stored = &mut states.borrow_mut()[index];
+            stored.clear();
+            for c in state.chars() {
+                if stored.push(c).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// A temperature in tenths of a degree as a decimal.
+fn write_tenths<const N: usize>(out: &mut String<N>, tenths: i32) -> Result<(), Error> {
+    let sign = if tenths < 0 { "-" } else { "" };
+    uwrite!(out, "{}{}.{}", sign, tenths.unsigned_abs() / 10, tenths.unsigned_abs() % 10).map_err(|_| Error::Format)
+}
+
+/// Setpoint of a climate entity kept as its state, "off" while it has none.
+fn set_setpoint(entity_name: &str, tenths: Option<i32>) {
+    let mut state = String::<MAX_ENTITY_STATE_LEN>::new();
+    match tenths {
+        Some(tenths) => write_tenths(&mut state, tenths).ok(),
+        None => state.push_str("off").ok(),
+    };
+    set_entity_state(entity_name, &state);
+}
+
+fn setpoint(entity_name: &str) -> Option<i32> {
+    let index = ENTITIES_TO_SUBSCRIBE.iter().position(|entity| *entity == entity_name)?;
+    ha::parse_tenths(&entity_state(index))
+}
+
 pub struct Websocket<'a, const PAYLOAD_BUF_LEN: usize> {
     socket: TcpSocket<'a>,
     payload_buffer: &'a mut heapless::Vec<u8, PAYLOAD_BUF_LEN>,
     id: i32,
     authenticated: bool,
     last_received_instant: Instant,
+    /// Request id of the last command sent from the pad's inputs, until its result arrives.
+    awaited_result: Option<i32>,
+    /// Commands received but not sent yet, kept for the next connection when this one drops.
+    outbox: &'a mut Outbox,
     receiver: &'a mut CommandReceiver,
     led_sender: &'a mut LedSender,
 }
 
 impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
     pub fn new(
-        socket: TcpSocket<'a>,
+        mut socket: TcpSocket<'a>,
         payload_buffer: &'a mut heapless::Vec<u8, PAYLOAD_BUF_LEN>,
+        outbox: &'a mut Outbox,
         receiver: &'a mut CommandReceiver,
         led_sender: &'a mut LedSender,
     ) -> Self {
+        socket.set_keep_alive(Some(KEEP_ALIVE_INTERVAL));
+        socket.set_timeout(Some(SOCKET_TIMEOUT));
         Self {
             socket,
             payload_buffer,
             id: 1,
             authenticated: false,
             last_received_instant: Instant::MIN,
+            awaited_result: None,
+            outbox,
             receiver,
             led_sender,
         }
     }
 
-    async fn read_each_http_header_line<F: Fn(&str)>(&mut self, f: F) -> Result<(), Error> {
-        let mut concat_vec = heapless::Vec::<u8, 512>::new();
-        let mut cr = false;
-
-        while self
-            .socket
-            .read_with(|bytes| {
-                let mut line_start = 0_usize;
-                for (i, elem) in bytes.iter().enumerate() {
-                    match *elem {
-                        b'\n' => {
-                            assert!(cr);
-
-                            let line_end = i - 1;
-                            if line_start == line_end {
-                                return (i + 1, false);
-                            }
-
-                            if !concat_vec.is_empty() {
-                                unwrap!(concat_vec.extend_from_slice(&bytes[line_start..line_end]));
-                                f(core::str::from_utf8(concat_vec.as_slice()).unwrap());
-                                concat_vec.clear();
-                            } else {
-                                f(core::str::from_utf8(&bytes[line_start..line_end]).unwrap());
-                            }
-
-                            line_start = i + 1;
-                            cr = false;
-                        }
-                        b'\r' => {
-                            assert!(!cr);
-                            cr = true;
-                        }
-                        _ => {
-                            assert!(!cr);
-                        }
-                    }
-                }
-
-                unwrap!(concat_vec.extend_from_slice(&bytes[line_start..]));
-                (bytes.len(), true)
-            })
-            .await?
-        {}
-
-        Ok(())
+    async fn read_each_http_header_line<F: FnMut(Option<&str>)>(&mut self, mut f: F) -> Result<(), Error> {
+        let mut lines = HeaderLines::new();
+        loop {
+            let done = self
+                .socket
+                .read_with(|bytes| match lines.feed(bytes, &mut f) {
+                    Ok((taken, done)) => (taken, Ok(done)),
+                    Err(_) => (bytes.len(), Err(Error::Protocol)),
+                })
+                .await??;
+            if done {
+                return Ok(());
+            }
+        }
     }
 
     async fn read_ws_payload(&mut self, header: &FrameHeader) -> Result<ReadWsOk, Error> {
@@ -146,16 +220,10 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         if payload_len == 0 {
             return Ok(ReadWsOk::Ok);
         }
-        if payload_len <= self.payload_buffer.capacity() {
+        if frame::payload_fits(header, self.payload_buffer.capacity()) {
             while self.payload_buffer.len() < payload_len {
                 self.socket
-                    .read_with(|bytes| {
-                        let read_size = usize::min(bytes.len(), payload_len - self.payload_buffer.len());
-                        let payload_buf_start = self.payload_buffer.len();
-                        unwrap!(self.payload_buffer.extend_from_slice(&bytes[0..read_size]));
-                        header.mask(&mut self.payload_buffer[payload_buf_start..], payload_buf_start);
-                        (read_size, ())
-                    })
+                    .read_with(|bytes| (frame::append_payload(header, self.payload_buffer, bytes), ()))
                     .await?;
             }
             Ok(ReadWsOk::Ok)
@@ -182,7 +250,7 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
             payload_len: 0,
             mask_key: None,
         };
-        map_edge_ws_error(PING_HEADER.send(&mut self.socket).await)?;
+        map_edge_ws_error(timed_write(PING_HEADER.send(&mut self.socket)).await?)?;
         self.last_received_instant = Instant::now();
         Ok(())
     }
@@ -194,22 +262,19 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
             payload_len: 0,
             mask_key: None,
         };
-        map_edge_ws_error(PONG_HEADER.send(&mut self.socket).await)
+        map_edge_ws_error(timed_write(PONG_HEADER.send(&mut self.socket)).await?)
     }
 
     async fn send_auth(&mut self) -> Result<(), Error> {
         debug!("sending auth");
-        const AUTH_HEADER: FrameHeader = FrameHeader {
+        let auth = settings::ha_auth();
+        let auth_header = FrameHeader {
             frame_type: edge_ws::FrameType::Text(false),
-            payload_len: HA_CONSTS.auth.len() as u64,
+            payload_len: auth.len() as u64,
             mask_key: None,
         };
-        map_edge_ws_error(AUTH_HEADER.send(&mut self.socket).await)?;
-        map_edge_ws_error(
-            AUTH_HEADER
-                .send_payload(&mut self.socket, HA_CONSTS.auth.as_bytes())
-                .await,
-        )
+        map_edge_ws_error(timed_write(auth_header.send(&mut self.socket)).await?)?;
+        map_edge_ws_error(timed_write(auth_header.send_payload(&mut self.socket, auth.as_bytes())).await?)
     }
 
     async fn send_text_payload<const N: usize>(&mut self, s: &heapless::String<N>) -> Result<(), Error> {
@@ -219,8 +284,8 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
             payload_len: s.len() as u64,
             mask_key: None,
         };
-        map_edge_ws_error(header.send(&mut self.socket).await)?;
-        map_edge_ws_error(header.send_payload(&mut self.socket, s.as_bytes()).await)
+        map_edge_ws_error(timed_write(header.send(&mut self.socket)).await?)?;
+        map_edge_ws_error(timed_write(header.send_payload(&mut self.socket, s.as_bytes())).await?)
     }
 
     make_send_function!(
@@ -228,6 +293,12 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         "sending event subscribe",
         r#"{{"type":"subscribe_events","event_type":"state_changed","id":{}}}"#
     );
+    #[cfg(feature = "accent")]
+    make_send_function!(
+        send_accent_subscribe,
+        "sending accent subscribe",
+        r#"{{"type":"subscribe_events","event_type":"squishy_accent","id":{}}}"#
+    );
     make_send_function_1parm!(
         send_entity_subscribe,
         "sending entity subscribe",
@@ -256,77 +327,207 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         r#"{{"type":"call_service","domain":"media_player","service":"media_play_pause","service_data":{{"entity_id":"{}"}},"id":{}}}"#
     );
 
+    make_send_function_1parm!(
+        send_volume_up,
+        "sending volume up",
+        r#"{{"type":"call_service","domain":"media_player","service":"volume_up","service_data":{{"entity_id":"{}"}},"id":{}}}"#
+    );
+    make_send_function_1parm!(
+        send_volume_down,
+        "sending volume down",
+        r#"{{"type":"call_service","domain":"media_player","service":"volume_down","service_data":{{"entity_id":"{}"}},"id":{}}}"#
+    );
+
+    make_send_function_2parm!(
+        send_lock,
+        "sending lock",
+        r#"{{"type":"call_service","domain":"lock","service":"{}","service_data":{{"entity_id":"{}"}},"id":{}}}"#
+    );
+
+    make_send_function_2parm!(
+        send_vacuum,
+        "sending vacuum",
+        r#"{{"type":"call_service","domain":"vacuum","service":"{}","service_data":{{"entity_id":"{}"}},"id":{}}}"#
+    );
+
+    make_send_function_2parm!(
+        send_notify,
+        "sending notify",
+        r#"{{"type":"call_service","domain":"notify","service":"{}","service_data":{{"message":"{}"}},"id":{}}}"#
+    );
+
+    async fn send_step_brightness(&mut self, entity_name: &str, percent: i32) -> Result<(), Error> {
+        debug!("sending step brightness");
+        let mut s = heapless::String::<256>::new();
+        uwrite!(
+            s,
+            r#"{{"type":"call_service","domain":"light","service":"turn_on","service_data":{{"entity_id":"{}","brightness_step_pct":{}}},"id":{}}}"#,
+            entity_name,
+            percent,
+            self.id
+        )
+        .map_err(|_| Error::Format)?;
+        self.id += 1;
+        self.send_text_payload(&s).await
+    }
+
+    async fn send_set_value(&mut self, entity_name: &str, value: i32) -> Result<(), Error> {
+        debug!("sending set value");
+        let mut s = heapless::String::<256>::new();
+        uwrite!(
+            s,
+            r#"{{"type":"call_service","domain":"input_number","service":"set_value","service_data":{{"entity_id":"{}","value":{}}},"id":{}}}"#,
+            entity_name,
+            value,
+            self.id
+        )
+        .map_err(|_| Error::Format)?;
+        self.id += 1;
+        self.send_text_payload(&s).await
+    }
+
+    async fn send_set_temperature(&mut self, entity_name: &str, tenths: i32) -> Result<(), Error> {
+        debug!("sending set temperature");
+        let mut temperature = heapless::String::<12>::new();
+        write_tenths(&mut temperature, tenths)?;
+        let mut s = heapless::String::<256>::new();
+        uwrite!(
+            s,
+            r#"{{"type":"call_service","domain":"climate","service":"set_temperature","service_data":{{"entity_id":"{}","temperature":{}}},"id":{}}}"#,
+            entity_name,
+            temperature.as_str(),
+            self.id
+        )
+        .map_err(|_| Error::Format)?;
+        self.id += 1;
+        self.send_text_payload(&s).await
+    }
+
+    /// Fire a `squishy_crash` event for a crash recorded before the last reset, automations can
+    /// pick it up from there.
+    async fn send_crash_report(&mut self, crash: &crash::Record) -> Result<(), Error> {
+        debug!("sending crash report");
+        let mut message = heapless::String::<{ crash::MAX_MESSAGE_LEN }>::new();
+        for c in crash.message().chars() {
+            // Replace anything that would need escaping in JSON
+            let c = if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '?' };
+            message.push(c).ok();
+        }
+        let mut stack = heapless::String::<{ crash::STACK_WORDS * 9 }>::new();
+        for (i, word) in crash.stack.iter().enumerate() {
+            uwrite!(stack, "{}{:08x}", if i == 0 { "" } else { " " }, *word).map_err(|_| Error::Format)?;
+        }
+        let mut s = heapless::String::<512>::new();
+        uwrite!(
+            s,
+            r#"{{"type":"fire_event","event_type":"squishy_crash","event_data":{{"core":{},"pc":"{:08x}","lr":"{:08x}","message":"{}","stack":"{}"}},"id":{}}}"#,
+            crash.core,
+            crash.pc,
+            crash.lr,
+            message.as_str(),
+            stack.as_str(),
+            self.id
+        )
+        .map_err(|_| Error::Format)?;
+        self.id += 1;
+        self.send_text_payload(&s).await
+    }
+
+    /// Fire a `squishy_motion` event, so automations can use the pad as a presence sensor.
+    async fn send_motion_event(&mut self) -> Result<(), Error> {
+        debug!("sending motion event");
+        let mut s = heapless::String::<256>::new();
+        uwrite!(
+            s,
+            r#"{{"type":"fire_event","event_type":"squishy_motion","event_data":{{"device":"{}"}},"id":{}}}"#,
+            identity::hostname(),
+            self.id
+        )
+        .map_err(|_| Error::Format)?;
+        self.id += 1;
+        self.send_text_payload(&s).await
+    }
+
     async fn connect_socket<T: Into<IpEndpoint>>(&mut self, endpoint: T, hostname: &str) -> Result<(), Error> {
-        self.socket
-            .connect(endpoint)
-            .await
-            .map_err(|_| Error::ConnectionReset)?;
+        self.socket.connect(endpoint).await?;
 
         debug!("sending request");
-        self.socket
-            .write_all(
-                "GET /api/websocket HTTP/1.1\r\n\
+        timed_write(self.socket.write_all(
+            "GET /api/websocket HTTP/1.1\r\n\
              Host: "
-                    .as_ref(),
-            )
-            .await?;
+                .as_ref(),
+        ))
+        .await??;
 
-        self.socket.write_all(hostname.as_ref()).await?;
+        timed_write(self.socket.write_all(hostname.as_ref())).await??;
 
-        self.socket
-            .write_all(
-                "\r\n\
+        timed_write(self.socket.write_all(
+            "\r\n\
              Upgrade: websocket\r\n\
              Connection: Upgrade\r\n\
              Sec-WebSocket-Key: x3JJHMbDL1EzLkh9GBhXDw==\r\n\
              Sec-WebSocket-Version: 13\r\n\
              \r\n"
-                    .as_ref(),
-            )
-            .await?;
-
-        self.read_each_http_header_line(|line| {
-            debug!("{}", line);
+                .as_ref(),
+        ))
+        .await??;
+
+        self.read_each_http_header_line(|line| match line {
+            Some(line) => debug!("{}", line),
+            // Long cookies and the like, nothing the upgrade needs
+            None => debug!("skipped header line over {} bytes", header::MAX_LINE_LEN),
         })
         .await
     }
 
-    fn try_to_parse_state(led_sender: &mut LedSender, str: &str) {
-        let mut parsed: Option<(&str, Option<&str>)> = None;
-        let mut try_parse_effect = |name_start: usize| {
-            if let Some(mut name_end) = str[name_start..].find('"') {
-                name_end += name_start;
-                let entity_name = &str[name_start..name_end];
-                if let Some(mut effect_key_start) = str[name_end..].find(r#""effect":""#) {
-                    effect_key_start += name_end;
-                    let effect_start = effect_key_start + 10;
-                    if let Some(mut effect_end) = str[effect_start..].find('"') {
-                        effect_end += effect_start;
-                        let effect_name = &str[effect_start..effect_end];
-                        parsed = Some((entity_name, Some(effect_name)))
-                    }
-                } else if let Some(_) = str[name_end..].find(r#""state":"off""#) {
-                    parsed = Some((entity_name, None))
-                }
+    fn on_state_change(led_sender: &mut LedSender, entity_name: &str, effect_name: Option<&str>) {
+        debug!("parsed state change {} {}", entity_name, effect_name);
+        if ENTITIES_TO_SUBSCRIBE.contains(&entity_name) {
+            // Every entity's state changes arrive, only ours mean someone is using the pad
+            power::note_activity();
+            set_entity_state(entity_name, effect_name.unwrap_or("off"));
+            if let Some(effect_name_str) = effect_name {
+                led_sender.on_effect_changed(entity_name, effect_name_str);
+            } else {
+                led_sender.on_turn_off(entity_name);
             }
-        };
-        if let Some(start) = str.find(r#""a":{""#) {
-            let name_start = start + 6;
-            try_parse_effect(name_start);
-        } else if let Some(start) = str.find(r#""new_state":{"entity_id":""#) {
-            let name_start = start + 26;
-            try_parse_effect(name_start);
         }
-        if let Some((entity_name, effect_name)) = parsed {
-            debug!("parsed state change {} {}", entity_name, effect_name);
-            if ENTITIES_TO_SUBSCRIBE.contains(&entity_name) {
-                if let Some(effect_name_str) = effect_name {
-                    led_sender.on_effect_changed(entity_name, effect_name_str);
-                } else {
-                    led_sender.on_turn_off(entity_name);
-                }
+    }
+
+    fn on_outside(led_sender: &mut LedSender, entity_name: &str, outside: Outside) {
+        // Every weather and sun entity's changes arrive, only the configured one tints the pads
+        if !AMBIENT.is_some_and(|ambient| ambient.entity_name == entity_name) {
+            return;
+        }
+        let tint = match outside {
+            Outside::Condition(condition) => {
+                debug!("parsed weather {} {}", entity_name, condition);
+                set_entity_state(entity_name, condition);
+                ambient::condition_tint(condition)
             }
+            Outside::Elevation(tenths) => {
+                debug!("parsed sun elevation {} {}", entity_name, tenths);
+                let mut state = String::<MAX_ENTITY_STATE_LEN>::new();
+                write_tenths(&mut state, tenths).ok();
+                set_entity_state(entity_name, &state);
+                Some(ambient::elevation_tint(tenths))
+            }
+        };
+        led_sender.set_ambient(tint);
+    }
+
+    /// Only the result of the last command from the inputs is fed back, earlier ones were
+    /// overtaken by it.
+    fn on_result(&mut self, id: i32, success: bool) {
+        if self.awaited_result != Some(id) {
+            return;
         }
+        self.awaited_result = None;
+        if !success {
+            warn!("command {} failed", id);
+        }
+        #[cfg(feature = "buzzer")]
+        buzzer::play(if success { Tone::Confirm } else { Tone::Error });
     }
 
     async fn websocket_read(&mut self) -> Result<bool, Error> {
@@ -355,20 +556,53 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         if let ReadWsOk::Ok = self.read_ws_payload(&header).await? {
             match header.frame_type {
                 edge_ws::FrameType::Text(false) => {
-                    let str = core::str::from_utf8(self.payload_buffer.as_slice()).unwrap();
+                    let str = core::str::from_utf8(self.payload_buffer.as_slice()).map_err(|_| Error::Protocol)?;
                     debug!("> {}", str);
 
-                    if str.starts_with(r#"{"type":"auth_required","#) {
-                        self.send_auth().await?;
-                    } else if str.starts_with(r#"{"type":"auth_ok","#) {
-                        debug!("authenticated");
-                        self.send_event_subscribe().await?;
-                        for entity in ENTITIES_TO_SUBSCRIBE {
-                            self.send_entity_subscribe(entity).await?;
+                    match ha::parse_message(str) {
+                        Message::AuthRequired => {
+                            self.send_auth().await?;
                         }
-                        self.authenticated = true;
-                    } else {
-                        Self::try_to_parse_state(self.led_sender, str);
+                        Message::AuthOk => {
+                            debug!("authenticated");
+                            self.send_event_subscribe().await?;
+                            #[cfg(feature = "accent")]
+                            self.send_accent_subscribe().await?;
+                            for entity in ENTITIES_TO_SUBSCRIBE {
+                                self.send_entity_subscribe(entity).await?;
+                            }
+                            if let Some(crash) = crash::pending() {
+                                match self.send_crash_report(&crash).await {
+                                    // It won't fit on the next connection either
+                                    Err(Error::Format) => warn!("crash report too long to send"),
+                                    result => result?,
+                                }
+                                crash::reported();
+                            }
+                            self.authenticated = true;
+                            CONNECTION.lock(|cell| cell.set(HaConnection::Authenticated));
+                        }
+                        Message::StateChange { entity, effect } => {
+                            Self::on_state_change(self.led_sender, entity, effect);
+                        }
+                        Message::Setpoint { entity, tenths } => {
+                            debug!("parsed setpoint {} {}", entity, tenths);
+                            set_setpoint(entity, tenths);
+                        }
+                        Message::Outside { entity, outside } => {
+                            Self::on_outside(self.led_sender, entity, outside);
+                        }
+                        Message::Result { id, success } => {
+                            self.on_result(id, success);
+                        }
+                        #[cfg(feature = "accent")]
+                        Message::Accent { color, seconds } => {
+                            self.led_sender.show_accent(color, seconds);
+                        }
+                        // Only subscribed to with the strip
+                        #[cfg(not(feature = "accent"))]
+                        Message::Accent { .. } => {}
+                        Message::Other => {}
                     }
                 }
                 edge_ws::FrameType::Ping => {
@@ -382,10 +616,15 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         }
 
         self.last_received_instant = Instant::now();
+        watchdog::check_in(Subsystem::Websocket);
         Ok(true)
     }
 
     async fn send_command(&mut self, command: &HaCommand) -> Result<(), Error> {
+        // Background reports don't need the latency
+        if !matches!(command, HaCommand::SetValue(_)) {
+            power::note_activity();
+        }
         match command {
             HaCommand::SetEffect(cmd) => {
                 self.send_set_effect(cmd.entity_name, cmd.effect_name).await?;
@@ -396,13 +635,99 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
             HaCommand::PlayPause(cmd) => {
                 self.send_play_pause(cmd.entity_name).await?;
             }
+            HaCommand::SetValue(cmd) => {
+                self.send_set_value(cmd.entity_name, cmd.value).await?;
+            }
+            HaCommand::StepBrightness(cmd) => {
+                self.send_step_brightness(cmd.entity_name, cmd.percent).await?;
+            }
+            HaCommand::StepVolume(cmd) => {
+                // Media players only step their volume by their own increment
+                for _ in 0..cmd.steps.unsigned_abs() {
+                    if cmd.steps > 0 {
+                        self.send_volume_up(cmd.entity_name).await?;
+                    } else {
+                        self.send_volume_down(cmd.entity_name).await?;
+                    }
+                }
+            }
+            HaCommand::Lock(cmd) => {
+                self.send_lock(cmd.action.service(), cmd.entity_name).await?;
+            }
+            HaCommand::Vacuum(cmd) => {
+                self.send_vacuum(cmd.action.service(), cmd.entity_name).await?;
+            }
+            HaCommand::ClimateTempStep(cmd) => {
+                let Some(setpoint) = setpoint(cmd.entity_name) else {
+                    // Until Home Assistant reports one, or while the thermostat is off
+                    warn!("no setpoint of {} to step from", cmd.entity_name);
+                    #[cfg(feature = "buzzer")]
+                    buzzer::play(Tone::Error);
+                    return Ok(());
+                };
+                let setpoint = setpoint + cmd.tenths as i32;
+                self.send_set_temperature(cmd.entity_name, setpoint).await?;
+                // Steps in a row add up before the new state comes back
+                set_setpoint(cmd.entity_name, Some(setpoint));
+            }
+            HaCommand::Notify(cmd) => {
+                self.send_notify(cmd.service, cmd.message).await?;
+            }
+            HaCommand::Motion => {
+                self.send_motion_event().await?;
+            }
+        }
+        if command.from_input() {
+            self.awaited_result = Some(self.id - 1);
+        }
+        Ok(())
+    }
+
+    fn queue_command(&mut self, command: HaCommand) {
+        if let Err(dropped) = self.outbox.push(command) {
+            let from_input = dropped.from_input();
+            warn!("outbox full, dropped a command from the {}", if from_input { "inputs" } else { "background" });
+            #[cfg(feature = "buzzer")]
+            if from_input {
+                buzzer::play(Tone::Error);
+            }
+        }
+    }
+
+    /// Send queued commands for as long as the send buffer has room for them, so writing them
+    /// never waits for the peer to drain its window.
+    async fn send_queued(&mut self) -> Result<(), Error> {
+        while let Some(command) = self.outbox.front() {
+            // Volume steps are a service call each
+            let frames = match command {
+                HaCommand::StepVolume(cmd) => cmd.steps.unsigned_abs() as usize,
+                _ => 1,
+            };
+            let needed = (frames * MAX_COMMAND_FRAME_LEN).min(self.socket.send_capacity());
+            if self.socket.send_capacity() - self.socket.send_queue() < needed {
+                break;
+            }
+            let Some(command) = self.outbox.pop() else {
+                break;
+            };
+            match self.send_command(&command).await {
+                // Nothing went out for a command that didn't fit, the connection is fine
+                Err(Error::Format) => {
+                    warn!("dropped a command too long to send");
+                    #[cfg(feature = "buzzer")]
+                    if command.from_input() {
+                        buzzer::play(Tone::Error);
+                    }
+                }
+                result => result?,
+            }
         }
         Ok(())
     }
 
     /// Future which is ready as long as something is present in the receive buffer.
     async fn poll_read(&mut self) -> Result<(), Error> {
-        self.socket.read_with(|_| (0, ())).await
+        Ok(self.socket.read_with(|_| (0, ())).await?)
     }
 
     async fn websocket_pump(&mut self) -> Result<bool, Error> {
@@ -412,19 +737,24 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
                 return Ok(false);
             }
         } else {
-            // Wait until we receive either socket data or an app command
-            match select::select(self.socket.read_with(|_| (0, ())), self.receiver.receive()).await {
-                select::Either::First(result) => {
+            self.send_queued().await?;
+            let send_retry = if self.outbox.is_empty() { Instant::MAX } else { Instant::now() + SEND_ROOM_POLL };
+            // Wait until we receive either socket data or an app command, or for room to send
+            match select::select3(self.socket.read_with(|_| (0, ())), self.receiver.receive(), Timer::at(send_retry))
+                .await
+            {
+                select::Either3::First(result) => {
                     // Socket has received at least one byte
                     result?;
                     if !self.websocket_read().await? {
                         return Ok(false);
                     }
                 }
-                select::Either::Second(command) => {
-                    // App command
-                    self.send_command(&command).await?;
+                select::Either3::Second(command) => {
+                    // App command, sent on the next pass if there's room
+                    self.queue_command(command);
                 }
+                select::Either3::Third(_) => {}
             }
         }
         Ok(true)
@@ -448,6 +778,9 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
 
     async fn close_socket(&mut self) {
         debug!("closing");
+        if !self.outbox.is_empty() {
+            debug!("keeping {} unsent commands for the next connection", self.outbox.len());
+        }
         self.authenticated = false;
         {
             const CLOSE_HEADER: FrameHeader = FrameHeader {
@@ -455,17 +788,14 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
                 payload_len: 2,
                 mask_key: None,
             };
-            if CLOSE_HEADER.send(&mut self.socket).await.is_ok() {
-                CLOSE_HEADER
-                    .send_payload(&mut self.socket, &1000_u16.to_be_bytes())
-                    .await
-                    .ok();
+            if matches!(timed_write(CLOSE_HEADER.send(&mut self.socket)).await, Ok(Ok(_))) {
+                timed_write(CLOSE_HEADER.send_payload(&mut self.socket, &1000_u16.to_be_bytes())).await.ok();
             }
         }
         self.socket.close();
         loop {
             match self.socket.read_with(|bytes| (bytes.len(), ())).await {
-                Err(Error::ConnectionReset) => {
+                Err(tcp::Error::ConnectionReset) => {
                     debug!("tcp closed");
                     break;
                 }
@@ -474,11 +804,30 @@ impl<'a, const PAYLOAD_BUF_LEN: usize> Websocket<'a, PAYLOAD_BUF_LEN> {
         }
     }
 
-    pub async fn run(&mut self, endpoint: IpEndpoint, hostname: &str) {
-        if let Ok(_) = self.connect_socket(endpoint, hostname).await {
-            self.websocket_loop().await.ok();
+    /// Returns whether the connection and websocket upgrade succeeded.
+    pub async fn run(&mut self, endpoint: IpEndpoint, hostname: &str) -> bool {
+        let connected = self.connect_socket(endpoint, hostname).await.is_ok();
+        if connected {
+            CONNECTION.lock(|cell| cell.set(HaConnection::Connected));
+            match self.websocket_loop().await {
+                Ok(()) => {}
+                // Closing would wait on the same full window, drop the connection instead
+                Err(Error::Timeout) => {
+                    warn!("websocket write timed out, aborting the connection");
+                    self.socket.abort();
+                }
+                Err(err) => debug!("connection ended: {}", err),
+            }
         }
 
         self.close_socket().await;
+        connected
+    }
+}
+
+impl<'a, const PAYLOAD_BUF_LEN: usize> Drop for Websocket<'a, PAYLOAD_BUF_LEN> {
+    fn drop(&mut self) {
+        // Also runs when a lost link drops the connection without closing it
+        CONNECTION.lock(|cell| cell.set(HaConnection::Disconnected));
     }
 }
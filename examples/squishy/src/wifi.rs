@@ -0,0 +1,202 @@
+use cyw43::LinkEvents;
+use defmt::{debug, info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, select3, Either3};
+use embassy_net::driver::Driver;
+use embassy_net::Stack;
+use embassy_time::{Duration, Instant, Timer};
+use net_setup::status_led::{self, LinkStatus, StatusLed};
+use net_setup::Firmware;
+use crate::command::CommandSender;
+use crate::link::{self, NetHealth};
+use crate::power::PowerManager;
+use crate::transport::Transport;
+use crate::watchdog::{self, Subsystem};
+use crate::{consts, kept_log, settings, Irqs};
+
+const REJOIN_DELAY: Duration = Duration::from_secs(5);
+/// How often signal strength and PHY rate are reported to Home Assistant while joined.
+const QUALITY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[macro_export]
+macro_rules! net_peripherals {
+    ($macro_name:ident $(,$arg:tt)*) => {
+        $macro_name!{$($arg,)*
+            NetPeripherals,
+            pwr: PIN_23,
+            cs: PIN_25,
+            pio: PIO0,
+            dio: PIN_24,
+            clk: PIN_29,
+            dma0: DMA_CH0,
+        }
+    };
+}
+
+pub use net_setup::{NetDevice, WifiPeripherals as NetPeripherals};
+
+/// Scan all channels, logging every network heard, and return the BSSID of `ssid` with the
+/// strongest signal.
+async fn strongest_bssid(control: &mut cyw43::Control<'_>, ssid: &str) -> Option<[u8; 6]> {
+    let mut scanner = control.scan(Default::default()).await;
+    let mut best: Option<([u8; 6], i16)> = None;
+    while let Some(bss) = scanner.next().await {
+        let (bssid, rssi, channel) = (bss.bssid, bss.rssi, bss.ctl_ch);
+        let name = &bss.ssid[..(bss.ssid_len as usize).min(32)];
+        info!("scan: {} {:02x} channel {} rssi {}", core::str::from_utf8(name).unwrap_or("?"), bssid, channel, rssi);
+        if name == ssid.as_bytes() && best.map_or(true, |(_, best_rssi)| rssi > best_rssi) {
+            best = Some((bssid, rssi));
+        }
+    }
+    best.map(|(bssid, _)| bssid)
+}
+
+/// Join through the strongest access point with the precomputed WPA2 PSK, falling back to
+/// WPA3 for networks that don't accept it.
+async fn join(control: &mut cyw43::Control<'_>) -> Result<(), cyw43::ControlError> {
+    // Without a pin the firmware sticks to whichever access point it finds first
+    let ssid = settings::wifi_ssid();
+    let bssid = strongest_bssid(control, ssid).await;
+    control.set_join_bssid(bssid);
+    let result = match settings::wifi_psk() {
+        Some(psk) => control.join_wpa2_psk(ssid, psk).await,
+        None if settings::wifi_passphrase().is_empty() => control.join_open(ssid).await,
+        // Credentials set from the console, the cyw43 derives the PSK from the passphrase
+        None => control.join_wpa2(ssid, settings::wifi_passphrase()).await,
+    };
+    #[cfg(feature = "wpa3")]
+    let result = match result {
+        Err(err) if err.is_security_mismatch() => {
+            info!("WPA2 join failed with status={}, trying WPA3", err.status);
+            control.join_wpa3(ssid, settings::wifi_passphrase()).await
+        }
+        result => result,
+    };
+    // Even a refused join shows the cyw43 is answering
+    watchdog::check_in(Subsystem::Net);
+    result
+}
+
+
+/// Resolve once the connection to the access point is lost, either reported by the cyw43 or
+/// detected by probing the gateway, as when the access point reboots.
+async fn wait_lost<D: Driver>(stack: &Stack<D>, link_events: &mut LinkEvents<'_>) {
+    // Left over from the previous join or leave
+    while link_events.try_next().is_some() {}
+    let link_down = async {
+        loop {
+            let event = link_events.next().await;
+            if !event.is_up() {
+                warn!("wifi link event {}", event);
+                link::set_net_health(NetHealth::Outage);
+                return;
+            }
+        }
+    };
+    select(link_down, link::probe_until_lost(stack)).await;
+}
+
+/// Report signal strength in dBm and transmit PHY rate in Mbit/s to their Home Assistant
+/// helper entities.
+async fn report_quality<D: Driver>(control: &mut cyw43::Control<'_>, stack: &Stack<D>, sender: &mut CommandSender) {
+    // The cyw43 rejects both requests while not associated
+    if stack.is_link_up() {
+        let rssi = control.rssi().await;
+        let tx_rate = (control.tx_rate().await / 2) as i32;
+        debug!("link quality rssi {} dBm, rate {} Mbit/s", rssi, tx_rate);
+        // Queued until the websocket is authenticated, dropped once the channel is full
+        sender.set_value(consts::RSSI_ENTITY, rssi);
+        sender.set_value(consts::TX_RATE_ENTITY, tx_rate);
+        watchdog::check_in(Subsystem::Net);
+    }
+}
+
+/// Drive the cyw43 while the link is up: report link quality every `QUALITY_INTERVAL`, switch
+/// power management with websocket activity and blink the status LED.
+async fn run_control<D: Driver>(control: &mut cyw43::Control<'_>, stack: &Stack<D>, sender: &mut CommandSender) {
+    let mut power = PowerManager::new();
+    control.set_power_management(power.mode()).await;
+    let mut next_report = Instant::now();
+    loop {
+        match select3(Timer::at(next_report), power.next_mode(), status_led::changed()).await {
+            Either3::First(_) => {
+                report_quality(control, stack, sender).await;
+                next_report = Instant::now() + QUALITY_INTERVAL;
+            }
+            Either3::Second(mode) => control.set_power_management(mode).await,
+            Either3::Third(on) => control.gpio_set(0, on).await,
+        }
+    }
+}
+
+/// Wait for the DHCP lease, or return right away with a static address, blinking the status LED
+/// meanwhile.
+async fn wait_config_up<D: Driver>(control: &mut cyw43::Control<'_>, stack: &Stack<D>) {
+    status_led::set_status(LinkStatus::WaitingDhcp);
+    select(stack.wait_config_up(), status_led::drive(&mut StatusLed::Cyw43, control)).await;
+    status_led::set_status(LinkStatus::Up);
+}
+
+pub struct WifiTransport {
+    control: cyw43::Control<'static>,
+    link_events: LinkEvents<'static>,
+}
+
+impl Transport for WifiTransport {
+    /// Also waits for the first DHCP lease, for the status LED.
+    async fn connect<D: Driver>(&mut self, stack: &Stack<D>) {
+        status_led::show_joining(&mut StatusLed::Cyw43, &mut self.control).await;
+        loop {
+            match join(&mut self.control).await {
+                Ok(_) => break,
+                Err(err) => {
+                    kept_log!(Info, "join failed with status={}", err.status);
+                }
+            }
+        }
+        wait_config_up(&mut self.control, stack).await;
+    }
+
+    async fn add_multicast_address(&mut self, mac: [u8; 6]) -> bool {
+        self.control.add_multicast_address(mac).await.is_ok()
+    }
+
+    async fn run_until_lost<D: Driver>(&mut self, stack: &Stack<D>, sender: &mut CommandSender) {
+        select(wait_lost(stack, &mut self.link_events), run_control(&mut self.control, stack, sender)).await;
+    }
+
+    /// Leave the network, join it again and wait for a fresh DHCP lease.
+    async fn reconnect<D: Driver>(&mut self, stack: &Stack<D>) {
+        info!("reconnecting to wifi");
+        self.control.leave().await;
+        status_led::show_joining(&mut StatusLed::Cyw43, &mut self.control).await;
+        loop {
+            match join(&mut self.control).await {
+                Ok(_) => break,
+                Err(err) => {
+                    kept_log!(Info, "rejoin failed with status={}", err.status);
+                    Timer::after(REJOIN_DELAY).await;
+                }
+            }
+        }
+        status_led::set_status(LinkStatus::WaitingDhcp);
+        select(link::renew_lease(stack), status_led::drive(&mut StatusLed::Cyw43, &mut self.control)).await;
+        status_led::set_status(LinkStatus::Up);
+    }
+
+    /// Leave the network, the access point would otherwise buffer frames for a station that
+    /// stopped listening. The cyw43 stays powered and initialized for a quick rejoin.
+    async fn suspend(&mut self) {
+        self.control.leave().await;
+        // Dark while dormant, it would stay lit at whatever it showed last
+        self.control.gpio_set(0, false).await;
+    }
+}
+
+/// Bring up the cyw43 and hand its network device to the stack.
+pub async fn init(spawner: Spawner, p: NetPeripherals) -> (NetDevice, WifiTransport) {
+    let (net_device, mut control) =
+        net_setup::init_cyw43(spawner, p, Irqs, Firmware::BUNDLED, cyw43::PowerManagementMode::PowerSave).await;
+    let link_events = unwrap!(control.link_events());
+    (net_device, WifiTransport { control, link_events })
+}